@@ -0,0 +1,63 @@
+//! Clock Source for ComLock
+//!
+//! Time-dependent logic (QR and invite expiry, exchange cleanup, contact
+//! timestamps) reads the time through the [`Clock`] trait so tests can
+//! control it without sleeping.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of the current time
+pub trait Clock: Send + Sync {
+    /// Current Unix time in seconds
+    fn now_unix(&self) -> i64;
+}
+
+/// The system clock.
+///
+/// A clock set before the epoch reads as 0 instead of panicking.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
+            .unwrap_or(0)
+    }
+}
+
+/// Current Unix time in seconds from the system clock
+pub fn now_unix() -> i64 {
+    SystemClock.now_unix()
+}
+
+/// A clock that only moves when told to
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: std::sync::atomic::AtomicI64,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// A clock reading `now`
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: std::sync::atomic::AtomicI64::new(now),
+        }
+    }
+
+    /// Move the clock forward by `seconds`
+    pub fn advance(&self, seconds: i64) {
+        self.now
+            .fetch_add(seconds, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_unix(&self) -> i64 {
+        self.now.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
@@ -3,12 +3,16 @@
 //! Provides secure, trace-free contact discovery via QR codes and invite blobs.
 //! All contacts are stored in memory only by default - no disk persistence.
 
-use rand::RngCore;
+use comlock_crypto::ct_eq;
+use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroize;
+
+use crate::clock::{Clock, SystemClock};
 
 // ============================================================================
 // CONTACT DATA MODEL
@@ -27,12 +31,97 @@ pub struct Contact {
     /// ML-KEM-1024 public key for post-quantum security
     #[serde(with = "hex_vec_serde")]
     pub kem_pubkey: Vec<u8>,
+    /// Commitment to the ML-KEM-1024 key still to be received, from a
+    /// compact QR payload (hex); see [`ContactStore::apply_committed_kem_key`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kem_commitment: Option<String>,
     /// Active ratchet session ID
     pub session_id: String,
     /// Timestamp when contact was added (can be randomized for deniability)
     pub added_at: i64,
-    /// Whether the initial handshake is complete
-    pub verified: bool,
+    /// How far the contact's keys are trusted
+    #[serde(alias = "verified", deserialize_with = "deserialize_verification")]
+    pub verification: VerificationStatus,
+    /// Mailbox ID of the invite this contact was imported from (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite_mailbox: Option<String>,
+    /// Ed25519 identity key that signs the contact's key updates (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    /// X25519 identity key of the contact, for the safety number (hex).
+    /// `public_key` holds an ephemeral key for QR and pairing-code contacts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_key: Option<String>,
+    /// `issued_at` of the last key update applied (0 if none)
+    #[serde(default)]
+    pub key_updated_at: i64,
+    /// Keys changed since the session was set up; pair again before use
+    #[serde(default)]
+    pub repair_required: bool,
+    /// Unix time of the last message sent or received (0 if none).
+    /// UI metadata only: never used in fingerprints or session derivation.
+    #[serde(default)]
+    pub last_activity: i64,
+    /// Inbound messages not yet marked read
+    #[serde(default)]
+    pub unread_count: u32,
+    /// Private note about the contact, sealed by [`ContactStore::set_note`]
+    /// under its own key; left out of default bundle exports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_notes: Option<Vec<u8>>,
+}
+
+impl Contact {
+    /// Whether the contact has been revoked (no new sessions allowed)
+    pub fn is_revoked(&self) -> bool {
+        self.verification == VerificationStatus::Revoked
+    }
+
+    /// The contact's X25519 identity key, if it is known
+    pub fn decode_identity_key(&self) -> Option<[u8; 32]> {
+        hex::decode(self.identity_key.as_deref()?)
+            .ok()?
+            .try_into()
+            .ok()
+    }
+}
+
+/// Trust state of a contact's keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerificationStatus {
+    /// Keys accepted on first use (e.g. imported from an invite)
+    #[default]
+    Unverified,
+    /// Invite handshake completed by a delivery receipt (ACK)
+    Acknowledged,
+    /// Keys confirmed in person by comparing the SAS
+    SasConfirmed,
+    /// Keys no longer trusted (e.g. after compromise); record kept, sessions blocked
+    Revoked,
+}
+
+/// A stored verification field: the status, or the `verified` flag of
+/// contacts saved before statuses existed
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredVerification {
+    Status(VerificationStatus),
+    Legacy(bool),
+}
+
+/// Read a contact's verification, mapping the legacy `verified` flag.
+///
+/// The flag only recorded a completed handshake, by SAS or by ACK, so
+/// `true` becomes `Acknowledged` rather than claiming a SAS check.
+fn deserialize_verification<'de, D>(deserializer: D) -> Result<VerificationStatus, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match StoredVerification::deserialize(deserializer)? {
+        StoredVerification::Status(status) => status,
+        StoredVerification::Legacy(true) => VerificationStatus::Acknowledged,
+        StoredVerification::Legacy(false) => VerificationStatus::Unverified,
+    })
 }
 
 /// Ephemeral X25519 keypair for key exchange (zeroized on drop)
@@ -44,8 +133,12 @@ pub struct EphemeralKeypair {
 impl EphemeralKeypair {
     /// Generate a new random ephemeral keypair using real X25519
     pub fn generate() -> Self {
-        use rand::rngs::OsRng;
-        let secret_key = x25519_dalek::StaticSecret::random_from_rng(OsRng);
+        Self::generate_with_rng(&mut rand::rngs::OsRng)
+    }
+
+    /// Generate a keypair from `rng` (e.g. a seeded RNG in tests)
+    pub fn generate_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let secret_key = x25519_dalek::StaticSecret::random_from_rng(rng);
         let public_key = x25519_dalek::PublicKey::from(&secret_key);
 
         Self {
@@ -54,11 +147,17 @@ impl EphemeralKeypair {
         }
     }
 
-    /// Compute shared secret with peer's public key using real X25519 ECDH
-    pub fn compute_shared_secret(&self, peer_public: &[u8; 32]) -> [u8; 32] {
+    /// Compute shared secret with peer's public key using real X25519 ECDH.
+    ///
+    /// A low-order peer key, which would force a predictable shared secret,
+    /// is rejected with `InvalidPublicKey`.
+    pub fn compute_shared_secret(&self, peer_public: &[u8; 32]) -> Result<[u8; 32], ContactError> {
         let peer_pk = x25519_dalek::PublicKey::from(*peer_public);
         let shared = self.secret_key.diffie_hellman(&peer_pk);
-        shared.to_bytes()
+        if !shared.was_contributory() {
+            return Err(ContactError::InvalidPublicKey);
+        }
+        Ok(shared.to_bytes())
     }
 
     /// Get the raw secret key bytes (for SAS generation)
@@ -79,16 +178,46 @@ impl Drop for EphemeralKeypair {
 // QR CODE PAYLOAD
 // ============================================================================
 
+/// Default byte budget for a QR payload that phones can scan reliably
+const MAX_QR_BYTES: usize = 1024;
+
+/// Length of the KEM key commitment carried by compact payloads
+const KEM_COMMITMENT_LEN: usize = 16;
+
+/// Length of an ML-KEM-1024 encapsulation key
+pub const ML_KEM_PUBKEY_LEN: usize = 1568;
+
+/// Most bytes any QR code can hold (version 40, low error correction), so
+/// no scanned payload is longer
+const MAX_QR_PAYLOAD_LEN: usize = 2953;
+
+/// Most base64url characters of an ML-KEM-1024 key in a QR payload
+const MAX_KPK_CHARS: usize = ML_KEM_PUBKEY_LEN.div_ceil(3) * 4;
+
+/// Default byte budget that a serialized QR payload should stay within
+pub fn max_qr_bytes() -> usize {
+    MAX_QR_BYTES
+}
+
 /// QR code payload for in-person key exchange
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QrPayload {
     /// Protocol version
     pub v: u8,
-    /// X25519 ephemeral public key (base64)
+    /// X25519 ephemeral public key (base64url)
     pub pk: String,
-    /// ML-KEM-1024 public key (base64, optional for size)
+    /// ML-KEM-1024 public key (base64url, optional for size)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kpk: Option<String>,
+    /// Commitment to the ML-KEM-1024 public key when `kpk` is omitted (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kh: Option<String>,
+    /// Ed25519 identity key that will sign our key updates (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sk: Option<String>,
+    /// X25519 identity key, for the safety number (base64url)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ik: Option<String>,
     /// Expiry timestamp (Unix seconds)
     pub exp: i64,
 }
@@ -96,26 +225,94 @@ pub struct QrPayload {
 impl QrPayload {
     /// Create a new QR payload with ephemeral keys
     pub fn new(public_key: &[u8; 32], kem_pubkey: Option<&[u8]>, ttl_seconds: i64) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        Self::new_with_clock(public_key, kem_pubkey, ttl_seconds, &SystemClock)
+    }
+
+    /// [`QrPayload::new`] expiring `ttl_seconds` after `clock`'s time
+    pub fn new_with_clock<C: Clock + ?Sized>(
+        public_key: &[u8; 32],
+        kem_pubkey: Option<&[u8]>,
+        ttl_seconds: i64,
+        clock: &C,
+    ) -> Self {
+        let now = clock.now_unix();
 
         Self {
             v: 1,
             pk: base64_encode(public_key),
             kpk: kem_pubkey.map(base64_encode),
+            kh: None,
+            sk: None,
+            ik: None,
             exp: now + ttl_seconds,
         }
     }
 
+    /// Announce the Ed25519 identity key that will sign our key updates, so
+    /// the peer can pin it when the exchange is confirmed
+    pub fn with_signing_key(mut self, verifying_key: &ed25519_dalek::VerifyingKey) -> Self {
+        self.sk = Some(base64_encode(verifying_key.as_bytes()));
+        self
+    }
+
+    /// Announce our X25519 identity key, which the SAS then covers
+    pub fn with_identity_key(mut self, identity_key: &[u8; 32]) -> Self {
+        self.ik = Some(base64_encode(identity_key));
+        self
+    }
+
+    /// Create a compact QR payload that commits to the KEM key instead of
+    /// embedding it.
+    ///
+    /// The full KEM key is fetched over the established channel after SAS
+    /// confirmation and checked against the commitment the contact keeps,
+    /// with [`ContactStore::apply_committed_kem_key`].
+    pub fn new_compact(public_key: &[u8; 32], kem_pubkey: &[u8], ttl_seconds: i64) -> Self {
+        Self::new_compact_with_clock(public_key, kem_pubkey, ttl_seconds, &SystemClock)
+    }
+
+    /// [`QrPayload::new_compact`] expiring `ttl_seconds` after `clock`'s time
+    pub fn new_compact_with_clock<C: Clock + ?Sized>(
+        public_key: &[u8; 32],
+        kem_pubkey: &[u8],
+        ttl_seconds: i64,
+        clock: &C,
+    ) -> Self {
+        let mut payload = Self::new_with_clock(public_key, None, ttl_seconds, clock);
+        payload.kh = Some(base64_encode(&kem_commitment(kem_pubkey)));
+        payload
+    }
+
+    /// Check a KEM public key received later against the payload's commitment
+    pub fn verify_kem_commitment(&self, kem_pubkey: &[u8]) -> bool {
+        let Some(kh) = &self.kh else {
+            return false;
+        };
+        match base64_decode(kh) {
+            Ok(expected) => ct_eq(&expected, &kem_commitment(kem_pubkey)),
+            Err(_) => false,
+        }
+    }
+
+    /// Decode the KEM key commitment of a compact payload, if any
+    pub fn decode_kem_commitment(&self) -> Result<Option<[u8; KEM_COMMITMENT_LEN]>, ContactError> {
+        let Some(kh) = &self.kh else {
+            return Ok(None);
+        };
+        base64_decode(kh)?
+            .try_into()
+            .map(Some)
+            .map_err(|_| ContactError::InvalidPayload)
+    }
+
     /// Check if the payload has expired
     pub fn is_expired(&self) -> bool {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-        now > self.exp
+        self.is_expired_with_clock(&SystemClock)
+    }
+
+    /// Check if the payload has expired by `clock`'s time
+    pub fn is_expired_with_clock<C: Clock + ?Sized>(&self, clock: &C) -> bool {
+        clock.now_unix() > self.exp
     }
 
     /// Decode the X25519 public key
@@ -124,12 +321,43 @@ impl QrPayload {
         bytes.try_into().map_err(|_| ContactError::InvalidPublicKey)
     }
 
-    /// Decode the KEM public key
+    /// Decode the announced Ed25519 identity key, if any
+    pub fn decode_signing_key(&self) -> Result<Option<ed25519_dalek::VerifyingKey>, ContactError> {
+        let Some(sk) = &self.sk else {
+            return Ok(None);
+        };
+        let bytes: [u8; 32] = base64_decode(sk)?
+            .try_into()
+            .map_err(|_| ContactError::InvalidPayload)?;
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|_| ContactError::InvalidPayload)
+    }
+
+    /// Decode the announced X25519 identity key, if any
+    pub fn decode_identity_key(&self) -> Result<Option<[u8; 32]>, ContactError> {
+        let Some(ik) = &self.ik else {
+            return Ok(None);
+        };
+        base64_decode(ik)?
+            .try_into()
+            .map(Some)
+            .map_err(|_| ContactError::InvalidPublicKey)
+    }
+
+    /// Decode the KEM public key, which is at most [`ML_KEM_PUBKEY_LEN`] bytes
     pub fn decode_kem_pubkey(&self) -> Result<Option<Vec<u8>>, ContactError> {
-        match &self.kpk {
-            Some(kpk) => Ok(Some(base64_decode(kpk)?)),
-            None => Ok(None),
+        let Some(kpk) = &self.kpk else {
+            return Ok(None);
+        };
+        if kpk.len() > MAX_KPK_CHARS {
+            return Err(ContactError::InvalidPayload);
         }
+        let kem_pubkey = base64_decode(kpk)?;
+        if kem_pubkey.len() > ML_KEM_PUBKEY_LEN {
+            return Err(ContactError::InvalidPayload);
+        }
+        Ok(Some(kem_pubkey))
     }
 
     /// Serialize to JSON for QR code
@@ -137,12 +365,38 @@ impl QrPayload {
         serde_json::to_string(self).map_err(|_| ContactError::SerializationFailed)
     }
 
-    /// Parse from JSON scanned from QR code
+    /// Parse from JSON scanned from QR code.
+    ///
+    /// Input longer than a QR code can hold, or carrying a KEM key longer
+    /// than ML-KEM-1024's, is rejected before it is parsed or decoded.
     pub fn from_json(json: &str) -> Result<Self, ContactError> {
-        serde_json::from_str(json).map_err(|_| ContactError::InvalidPayload)
+        if json.len() > MAX_QR_PAYLOAD_LEN {
+            return Err(ContactError::InvalidPayload);
+        }
+        let payload: Self = serde_json::from_str(json).map_err(|_| ContactError::InvalidPayload)?;
+        if payload
+            .kpk
+            .as_ref()
+            .is_some_and(|kpk| kpk.len() > MAX_KPK_CHARS)
+        {
+            return Err(ContactError::InvalidPayload);
+        }
+        Ok(payload)
     }
 }
 
+/// Hash commitment to a KEM public key for compact QR payloads
+fn kem_commitment(kem_pubkey: &[u8]) -> [u8; KEM_COMMITMENT_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"COMLOCK_KEM_COMMIT_V1");
+    hasher.update(kem_pubkey);
+    let hash = hasher.finalize();
+
+    let mut commitment = [0u8; KEM_COMMITMENT_LEN];
+    commitment.copy_from_slice(&hash[..KEM_COMMITMENT_LEN]);
+    commitment
+}
+
 // ============================================================================
 // SAS (SHORT AUTHENTICATION STRING)
 // ============================================================================
@@ -168,17 +422,289 @@ pub fn generate_sas(shared_secret: &[u8; 32]) -> String {
     format!("{}-{}-{:02}", word1, word2, num)
 }
 
+/// Secret the SAS of an exchange is computed from when both sides
+/// announced an identity key, so comparing the SAS authenticates the
+/// identity keys along with the ephemeral ones
+fn bind_identity_keys(shared_secret: &[u8; 32], ours: &[u8; 32], theirs: &[u8; 32]) -> [u8; 32] {
+    let (first, second) = if ours <= theirs {
+        (ours, theirs)
+    } else {
+        (theirs, ours)
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"COMLOCK_SAS_IDENTITIES_V1");
+    hasher.update(shared_secret);
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
 /// Verify that a SAS matches the expected value
 pub fn verify_sas(shared_secret: &[u8; 32], claimed_sas: &str) -> bool {
     let expected = generate_sas(shared_secret);
     // Constant-time comparison to prevent timing attacks
-    expected.len() == claimed_sas.len()
-        && expected
-            .as_bytes()
-            .iter()
-            .zip(claimed_sas.as_bytes())
-            .fold(0, |acc, (a, b)| acc | (a ^ b))
-            == 0
+    ct_eq(expected.as_bytes(), claimed_sas.as_bytes())
+}
+
+// ============================================================================
+// PAIRING CODES
+// ============================================================================
+
+// A pairing code is a short random token read out over a call, not the
+// key itself. The ephemeral public key is posted to a rendezvous mailbox
+// derived from the token, sealed under a key derived from it, and the
+// peer fetches it from there. The token only locates and hides the key:
+// the SAS comparison that follows authenticates it, so a guessed token
+// gives an attacker nothing the SAS check would not catch.
+
+/// Decimal digits in a pairing token, excluding the check digit
+const PAIRING_TOKEN_DIGITS: usize = 10;
+
+/// Digits per dash-separated group of a pairing code
+const PAIRING_GROUP_DIGITS: usize = 5;
+
+/// Domain label of the rendezvous mailbox for a pairing token
+const PAIRING_MAILBOX_LABEL: &[u8] = b"COMLOCK_PAIRING_MAILBOX_V1";
+
+/// HKDF info for the key sealing a pairing offer
+const PAIRING_KDF_INFO: &[u8] = b"COMLOCK_PAIRING_KEY_V1";
+
+/// Length of the AES-GCM nonce prefixed to a sealed pairing offer
+const PAIRING_NONCE_LEN: usize = 12;
+
+/// Damm algorithm quasigroup: a single check digit catches every
+/// single-digit error and every swap of adjacent digits
+const DAMM_TABLE: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+/// Damm check digit of `digits` (each 0-9); zero over a code including its
+/// own check digit means the code is intact
+fn damm_check(digits: &[u8]) -> u8 {
+    digits.iter().fold(0, |interim, &digit| {
+        DAMM_TABLE[interim as usize][digit as usize]
+    })
+}
+
+/// Encode a pairing token as a code that can be read out over a call: two
+/// five-digit groups and a check digit, dash separated (e.g. "04213-65535-7")
+fn encode_pairing_code(token: u64) -> String {
+    let digits = format!("{:0width$}", token, width = PAIRING_TOKEN_DIGITS);
+    let check = damm_check(&digits.bytes().map(|b| b - b'0').collect::<Vec<_>>());
+    let (first, second) = digits.split_at(PAIRING_GROUP_DIGITS);
+
+    format!("{first}-{second}-{check}")
+}
+
+/// Decode a pairing code back into the token it carries.
+///
+/// Spaces and dashes are ignored. A code whose check digit doesn't match,
+/// as after a mistyped digit, is rejected with `PairingCodeMismatch`.
+pub fn decode_pairing_code(code: &str) -> Result<u64, ContactError> {
+    let mut digits = Vec::with_capacity(PAIRING_TOKEN_DIGITS + 1);
+    for c in code.chars().filter(|c| !c.is_whitespace() && *c != '-') {
+        let digit = c.to_digit(10).ok_or(ContactError::InvalidPairingCode)?;
+        digits.push(digit as u8);
+    }
+    if digits.len() != PAIRING_TOKEN_DIGITS + 1 {
+        return Err(ContactError::InvalidPairingCode);
+    }
+    if damm_check(&digits) != 0 {
+        return Err(ContactError::PairingCodeMismatch);
+    }
+
+    Ok(digits[..PAIRING_TOKEN_DIGITS]
+        .iter()
+        .fold(0u64, |token, &digit| token * 10 + digit as u64))
+}
+
+/// Rendezvous mailbox a pairing offer is posted to and fetched from
+pub fn pairing_mailbox(code: &str) -> Result<[u8; 32], ContactError> {
+    let token = decode_pairing_code(code)?;
+    let mut hasher = Sha256::new();
+    hasher.update(PAIRING_MAILBOX_LABEL);
+    hasher.update(token.to_be_bytes());
+    Ok(hasher.finalize().into())
+}
+
+/// Derive the key sealing a pairing offer from its token
+fn pairing_key(token: u64) -> [u8; 32] {
+    let hk = hkdf::Hkdf::<Sha256>::new(None, &token.to_be_bytes());
+    let mut key = [0u8; 32];
+    hk.expand(PAIRING_KDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    key
+}
+
+/// Seal an ephemeral public key, and our identity key if there is one,
+/// under the code's token: `nonce (12) || AES-GCM(public_key || identity_key)`
+fn seal_pairing_offer(
+    code: &str,
+    public_key: &[u8; 32],
+    identity_key: Option<&[u8; 32]>,
+) -> Result<Vec<u8>, ContactError> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    let mailbox = pairing_mailbox(code)?;
+    let key = pairing_key(decode_pairing_code(code)?);
+    let mut nonce_bytes = [0u8; PAIRING_NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
+    let mut keys = public_key.to_vec();
+    if let Some(identity_key) = identity_key {
+        keys.extend_from_slice(identity_key);
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(&key).expect("AES-256 key is 32 bytes");
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce_bytes),
+            Payload {
+                msg: &keys,
+                aad: &mailbox,
+            },
+        )
+        .expect("AES-GCM encryption failed");
+
+    let mut offer = nonce_bytes.to_vec();
+    offer.extend_from_slice(&ciphertext);
+    Ok(offer)
+}
+
+/// Open a pairing offer fetched from the mailbox of `code`, returning the
+/// peer's ephemeral public key
+pub fn open_pairing_offer(code: &str, offer: &[u8]) -> Result<[u8; 32], ContactError> {
+    open_pairing_offer_keys(code, offer).map(|(public_key, _)| public_key)
+}
+
+/// Open a pairing offer, returning the peer's ephemeral public key and
+/// identity key, if the offer announces one
+fn open_pairing_offer_keys(
+    code: &str,
+    offer: &[u8],
+) -> Result<([u8; 32], Option<[u8; 32]>), ContactError> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    if offer.len() < PAIRING_NONCE_LEN {
+        return Err(ContactError::InvalidPayload);
+    }
+    let mailbox = pairing_mailbox(code)?;
+    let key = pairing_key(decode_pairing_code(code)?);
+    let (nonce_bytes, ciphertext) = offer.split_at(PAIRING_NONCE_LEN);
+
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| ContactError::InvalidPayload)?;
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(nonce_bytes),
+            Payload {
+                msg: ciphertext,
+                aad: &mailbox,
+            },
+        )
+        .map_err(|_| ContactError::InvalidPayload)?;
+    let (public_key, identity_key) = match plaintext.len() {
+        32 => (&plaintext[..], None),
+        64 => (&plaintext[..32], Some(&plaintext[32..])),
+        _ => return Err(ContactError::InvalidPublicKey),
+    };
+    let public_key = public_key
+        .try_into()
+        .map_err(|_| ContactError::InvalidPublicKey)?;
+    let identity_key = identity_key
+        .map(|key| key.try_into().map_err(|_| ContactError::InvalidPublicKey))
+        .transpose()?;
+    Ok((public_key, identity_key))
+}
+
+/// What the peer of a QR or pairing-code exchange announced
+#[derive(Default)]
+struct ExchangePeer {
+    /// Ephemeral X25519 public key
+    public_key: [u8; 32],
+    /// ML-KEM-1024 public key, empty if not announced
+    kem_pubkey: Vec<u8>,
+    /// Commitment to a KEM key still to be received (hex)
+    kem_commitment: Option<String>,
+    /// Ed25519 key that will sign the peer's key updates
+    signing_key: Option<ed25519_dalek::VerifyingKey>,
+    /// X25519 identity key
+    identity_key: Option<[u8; 32]>,
+}
+
+/// Our side of a pairing-code exchange
+#[derive(Debug, Clone)]
+pub struct PairingOffer {
+    /// ID of the pending exchange
+    pub exchange_id: String,
+    /// Code to read out to the peer
+    pub code: String,
+    /// Rendezvous mailbox to post `offer` to
+    pub mailbox_id: [u8; 32],
+    /// Our ephemeral public key, sealed under the code
+    pub offer: Vec<u8>,
+}
+
+/// Short fingerprint of a public key: 16 hex-encoded bytes of its hash,
+/// in groups of four characters
+pub fn key_fingerprint(public_key: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"COMLOCK_FINGERPRINT_V1");
+    hasher.update(public_key);
+    let hash = hasher.finalize();
+
+    hex::encode(&hash[..16])
+        .as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// ============================================================================
+// SAFETY NUMBERS
+// ============================================================================
+
+/// Number of 5-digit groups in a safety number (60 digits total)
+const SAFETY_NUMBER_GROUPS: usize = 12;
+
+/// Compute the long-term safety number for a pair of identity keys.
+///
+/// Unlike the SAS, this depends only on the identity keys, so it stays the
+/// same for the lifetime of the contact and can be re-checked at any time.
+/// The keys are sorted first, so both sides get the same number. Returned as
+/// 12 space-separated groups of 5 digits.
+pub fn safety_number(my_identity_pubkey: &[u8; 32], their_identity_pubkey: &[u8; 32]) -> String {
+    let (first, second) = if my_identity_pubkey <= their_identity_pubkey {
+        (my_identity_pubkey, their_identity_pubkey)
+    } else {
+        (their_identity_pubkey, my_identity_pubkey)
+    };
+
+    let mut hasher = Sha512::new();
+    hasher.update(b"COMLOCK_SAFETY_NUMBER_V1");
+    hasher.update(first);
+    hasher.update(second);
+    let hash = hasher.finalize();
+
+    hash.chunks_exact(5)
+        .take(SAFETY_NUMBER_GROUPS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 // ============================================================================
@@ -201,9 +727,37 @@ pub struct InviteBlob {
     pub mailbox_id: [u8; 32],
     /// Expiry timestamp (Unix seconds)
     pub expiry: i64,
-    /// Ed25519 signature over the blob (placeholder)
+    /// Ed25519 signature over the blob (zeroed when unsigned)
     #[serde(with = "hex_serde_64")]
     pub signature: [u8; 64],
+    /// Sender's Ed25519 verifying key (hex), present on signed invites
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_key: Option<String>,
+}
+
+/// Invite blob version understood by this build
+pub const INVITE_VERSION: u8 = 2;
+
+/// Domain separator for invite signatures
+const INVITE_CONTEXT: &[u8] = b"COMLOCK_INVITE_V2";
+
+/// Longest base64 invite accepted; a signed invite carrying a full
+/// ML-KEM-1024 key encodes to under 5 KB
+const MAX_INVITE_ENCODED_LEN: usize = 6144;
+
+/// What a validated invite would import, for a confirmation screen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitePreview {
+    /// Fingerprint of the sender's X25519 key
+    pub fingerprint: String,
+    /// Expiry timestamp (Unix seconds)
+    pub expiry: i64,
+    /// Whether the invite carries a valid signature (false if unsigned).
+    ///
+    /// A valid signature shows the keys were not altered after signing by
+    /// whoever holds the embedded signer key; it says nothing about who
+    /// that is. Identity still needs SAS verification.
+    pub signature_valid: bool,
 }
 
 impl InviteBlob {
@@ -219,82 +773,108 @@ impl InviteBlob {
         let mut mailbox_id = [0u8; 32];
         rand::thread_rng().fill_bytes(&mut mailbox_id);
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-        let expiry = now + ttl_seconds;
-
-        // Create message to sign: version || sender_pubkey || mailbox_id || expiry
-        let mut message = Vec::with_capacity(1 + 32 + 32 + 8);
-        message.push(1u8); // version
-        message.extend_from_slice(&sender_pubkey);
-        message.extend_from_slice(&mailbox_id);
-        message.extend_from_slice(&expiry.to_le_bytes());
-
-        // Sign with Ed25519
-        let sig = signing_key.sign(&message);
-        let signature: [u8; 64] = sig.to_bytes();
-
-        Self {
-            version: 1,
+        let mut invite = Self {
+            version: INVITE_VERSION,
             sender_pubkey,
             sender_kem_pk,
             mailbox_id,
-            expiry,
-            signature,
-        }
+            expiry: SystemClock.now_unix() + ttl_seconds,
+            signature: [0u8; 64],
+            signer_key: Some(hex::encode(signing_key.verifying_key().as_bytes())),
+        };
+        let message = invite.signed_message(&signing_key.verifying_key());
+        invite.signature = signing_key.sign(&message).to_bytes();
+        invite
     }
 
     /// Create a new invite blob (unsigned - for backwards compatibility)
     pub fn new(sender_pubkey: [u8; 32], sender_kem_pk: Vec<u8>, ttl_seconds: i64) -> Self {
+        Self::new_with_clock(sender_pubkey, sender_kem_pk, ttl_seconds, &SystemClock)
+    }
+
+    /// [`InviteBlob::new`] expiring `ttl_seconds` after `clock`'s time
+    pub fn new_with_clock<C: Clock + ?Sized>(
+        sender_pubkey: [u8; 32],
+        sender_kem_pk: Vec<u8>,
+        ttl_seconds: i64,
+        clock: &C,
+    ) -> Self {
         let mut mailbox_id = [0u8; 32];
         rand::thread_rng().fill_bytes(&mut mailbox_id);
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let now = clock.now_unix();
 
         // Unsigned blob (signature is zeroed)
         let signature = [0u8; 64];
 
         Self {
-            version: 1,
+            version: INVITE_VERSION,
             sender_pubkey,
             sender_kem_pk,
             mailbox_id,
             expiry: now + ttl_seconds,
             signature,
+            signer_key: None,
         }
     }
 
-    /// Verify the Ed25519 signature
-    pub fn verify_signature(&self, verifying_key: &ed25519_dalek::VerifyingKey) -> bool {
-        use ed25519_dalek::Verifier;
-
-        // Reconstruct message
-        let mut message = Vec::with_capacity(1 + 32 + 32 + 8);
+    /// Message covered by the signature: `context || version ||
+    /// sender_pubkey || H(sender_kem_pk) || mailbox_id || expiry ||
+    /// signer_key`, so every key the invite carries is covered
+    fn signed_message(&self, verifying_key: &ed25519_dalek::VerifyingKey) -> Vec<u8> {
+        let kem_hash = Sha256::digest(&self.sender_kem_pk);
+        let mut message = Vec::with_capacity(INVITE_CONTEXT.len() + 1 + 32 + 32 + 32 + 8 + 32);
+        message.extend_from_slice(INVITE_CONTEXT);
         message.push(self.version);
         message.extend_from_slice(&self.sender_pubkey);
+        message.extend_from_slice(&kem_hash);
         message.extend_from_slice(&self.mailbox_id);
         message.extend_from_slice(&self.expiry.to_le_bytes());
+        message.extend_from_slice(verifying_key.as_bytes());
+        message
+    }
 
-        // Verify signature
-        if let Ok(sig) = ed25519_dalek::Signature::from_slice(&self.signature) {
-            verifying_key.verify(&message, &sig).is_ok()
-        } else {
-            false
-        }
+    /// Verify the Ed25519 signature
+    pub fn verify_signature(&self, verifying_key: &ed25519_dalek::VerifyingKey) -> bool {
+        use ed25519_dalek::Verifier;
+
+        let sig = ed25519_dalek::Signature::from_bytes(&self.signature);
+        verifying_key
+            .verify(&self.signed_message(verifying_key), &sig)
+            .is_ok()
     }
 
     /// Check if the invite has expired
     pub fn is_expired(&self) -> bool {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-        now > self.expiry
+        self.is_expired_with_clock(&SystemClock)
+    }
+
+    /// Check if the invite has expired by `clock`'s time
+    pub fn is_expired_with_clock<C: Clock + ?Sized>(&self, clock: &C) -> bool {
+        clock.now_unix() > self.expiry
+    }
+
+    /// Check the signature against the embedded signer key.
+    ///
+    /// Returns `Ok(false)` for unsigned invites and an error if a signature
+    /// is present but does not verify.
+    fn check_signature(&self) -> Result<bool, ContactError> {
+        let Some(signer_key) = &self.signer_key else {
+            if self.signature.iter().all(|&b| b == 0) {
+                return Ok(false);
+            }
+            return Err(ContactError::NoSigningKey);
+        };
+
+        let verifying_key = hex::decode(signer_key)
+            .ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .and_then(|key| ed25519_dalek::VerifyingKey::from_bytes(&key).ok())
+            .ok_or(ContactError::InvalidPublicKey)?;
+        if !self.verify_signature(&verifying_key) {
+            return Err(ContactError::InvalidSignature);
+        }
+        Ok(true)
     }
 
     /// Serialize to base64 for sharing
@@ -303,12 +883,205 @@ impl InviteBlob {
         Ok(base64_encode(json.as_bytes()))
     }
 
-    /// Parse from base64 string
+    /// Parse from base64 string.
+    ///
+    /// Oversized input is rejected before decoding, and a KEM key longer
+    /// than ML-KEM-1024's after it.
     pub fn from_base64(encoded: &str) -> Result<Self, ContactError> {
+        if encoded.len() > MAX_INVITE_ENCODED_LEN {
+            return Err(ContactError::InvalidPayload);
+        }
         let json_bytes = base64_decode(encoded)?;
         let json = String::from_utf8(json_bytes).map_err(|_| ContactError::InvalidPayload)?;
-        serde_json::from_str(&json).map_err(|_| ContactError::InvalidPayload)
+        let invite: Self = serde_json::from_str(&json).map_err(|_| ContactError::InvalidPayload)?;
+        if invite.sender_kem_pk.len() > ML_KEM_PUBKEY_LEN {
+            return Err(ContactError::InvalidPayload);
+        }
+        Ok(invite)
+    }
+}
+
+// ============================================================================
+// KEY UPDATES
+// ============================================================================
+
+/// Domain separator for key update signatures
+const KEY_UPDATE_CONTEXT: &[u8] = b"COMLOCK_KEY_UPDATE_V1";
+
+/// A contact's announcement of new long-term keys, signed with its
+/// Ed25519 identity key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyUpdate {
+    /// Protocol version
+    pub version: u8,
+    /// New X25519 public key
+    #[serde(with = "hex_serde")]
+    pub new_public_key: [u8; 32],
+    /// New ML-KEM-1024 public key
+    #[serde(with = "hex_vec_serde")]
+    pub new_kem_pubkey: Vec<u8>,
+    /// When the update was issued (Unix seconds); later updates win
+    pub issued_at: i64,
+    /// Ed25519 signature over the fields above
+    #[serde(with = "hex_serde_64")]
+    pub signature: [u8; 64],
+}
+
+impl KeyUpdate {
+    /// Create a key update signed with our identity key
+    pub fn new_signed(
+        signing_key: &ed25519_dalek::SigningKey,
+        new_public_key: [u8; 32],
+        new_kem_pubkey: Vec<u8>,
+    ) -> Self {
+        use ed25519_dalek::Signer;
+
+        let mut update = Self {
+            version: 1,
+            new_public_key,
+            new_kem_pubkey,
+            issued_at: SystemClock.now_unix(),
+            signature: [0u8; 64],
+        };
+        update.signature = signing_key.sign(&update.signed_message()).to_bytes();
+        update
+    }
+
+    /// Message covered by the signature:
+    /// `context || version || new_public_key || H(new_kem_pubkey) || issued_at`
+    fn signed_message(&self) -> Vec<u8> {
+        let kem_hash = Sha256::digest(&self.new_kem_pubkey);
+        let mut message = Vec::with_capacity(KEY_UPDATE_CONTEXT.len() + 1 + 32 + 32 + 8);
+        message.extend_from_slice(KEY_UPDATE_CONTEXT);
+        message.push(self.version);
+        message.extend_from_slice(&self.new_public_key);
+        message.extend_from_slice(&kem_hash);
+        message.extend_from_slice(&self.issued_at.to_le_bytes());
+        message
     }
+
+    /// Verify the Ed25519 signature
+    pub fn verify_signature(&self, verifying_key: &ed25519_dalek::VerifyingKey) -> bool {
+        use ed25519_dalek::Verifier;
+
+        let sig = ed25519_dalek::Signature::from_bytes(&self.signature);
+        verifying_key.verify(&self.signed_message(), &sig).is_ok()
+    }
+
+    /// Serialize to base64 for sending
+    pub fn to_base64(&self) -> Result<String, ContactError> {
+        let json = serde_json::to_string(self).map_err(|_| ContactError::SerializationFailed)?;
+        Ok(base64_encode(json.as_bytes()))
+    }
+
+    /// Parse from base64 string
+    pub fn from_base64(encoded: &str) -> Result<Self, ContactError> {
+        let json_bytes = base64_decode(encoded)?;
+        serde_json::from_slice(&json_bytes).map_err(|_| ContactError::InvalidPayload)
+    }
+}
+
+// ============================================================================
+// INVITE ACKNOWLEDGMENT
+// ============================================================================
+
+/// Plaintext prefix of an invite ACK
+const ACK_MAGIC: &[u8] = b"COMLOCK_INVITE_ACK_V1";
+
+/// HKDF info prefix for the ACK key
+const ACK_KDF_INFO: &[u8] = b"COMLOCK_ACK_KEY_V3";
+
+/// ACK key label of the ACK the responder sends on importing an invite
+const ACK_ROLE_RESPONDER: &[u8] = b"responder";
+
+/// ACK key label of the reply the inviter sends on receiving that ACK
+const ACK_ROLE_INVITER: &[u8] = b"inviter";
+
+/// ACK header: mailbox_id (32) || responder X25519 key (32) || nonce (12)
+const ACK_HEADER_LEN: usize = 32 + 32 + 12;
+
+/// Result of processing an invite ACK
+#[derive(Debug, Clone)]
+pub enum AckOutcome {
+    /// Inviter side: the responder's ACK cleared our pending invite. The
+    /// reply goes to `mailbox_id` so the responder learns it arrived.
+    Replied {
+        mailbox_id: [u8; 32],
+        reply: Vec<u8>,
+    },
+    /// Responder side: the inviter's reply acknowledged the contact
+    Acknowledged(Box<Contact>),
+}
+
+/// X25519 DH between an identity secret and a peer key, rejecting
+/// low-order peer keys that would give a predictable shared secret
+fn contributory_dh(secret: &[u8; 32], peer_public: &[u8; 32]) -> Result<[u8; 32], ContactError> {
+    let secret = x25519_dalek::StaticSecret::from(*secret);
+    let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(*peer_public));
+    if !shared.was_contributory() {
+        return Err(ContactError::InvalidPublicKey);
+    }
+    Ok(shared.to_bytes())
+}
+
+/// Derive the ACK encryption key from the DH between the inviter's and
+/// responder's identity keys, so only the two of them can produce it.
+/// `role` names the sender, so neither side accepts its own ACK back.
+fn ack_key(shared_secret: &[u8; 32], mailbox_id: &[u8; 32], role: &[u8]) -> [u8; 32] {
+    let hk = hkdf::Hkdf::<Sha256>::new(Some(mailbox_id), shared_secret);
+    let mut key = [0u8; 32];
+    hk.expand_multi_info(&[ACK_KDF_INFO, role], &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    key
+}
+
+// ============================================================================
+// CONTACT BACKUP BUNDLE
+// ============================================================================
+
+/// Magic prefix of an exported contact bundle
+const BUNDLE_MAGIC: &[u8; 4] = b"CLCB";
+
+/// Current contact bundle format version
+const BUNDLE_VERSION: u8 = 1;
+
+/// Length of the Argon2id salt in a bundle
+const BUNDLE_SALT_LEN: usize = 16;
+
+/// Bundle header: magic || version || salt
+const BUNDLE_HEADER_LEN: usize = BUNDLE_MAGIC.len() + 1 + BUNDLE_SALT_LEN;
+
+/// Derive the bundle encryption key from a passphrase using Argon2id
+fn bundle_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("Argon2 hashing failed");
+    key
+}
+
+// ============================================================================
+// CONTACT NOTES
+// ============================================================================
+
+/// HKDF info prefix for per-contact note keys
+const NOTE_KDF_INFO: &[u8] = b"COMLOCK_CONTACT_NOTE_V1";
+
+/// Length of the AES-GCM nonce prefixed to an encrypted note
+const NOTE_NONCE_LEN: usize = 12;
+
+/// Derive the key sealing one contact's note from the notes key
+fn note_key(key: &[u8; 32], contact_id: &str) -> [u8; 32] {
+    let hk = hkdf::Hkdf::<Sha256>::new(None, key);
+    let mut info = Vec::with_capacity(NOTE_KDF_INFO.len() + 1 + contact_id.len());
+    info.extend_from_slice(NOTE_KDF_INFO);
+    info.push(0x00);
+    info.extend_from_slice(contact_id.as_bytes());
+
+    let mut note_key = [0u8; 32];
+    hk.expand(&info, &mut note_key)
+        .expect("32 bytes is a valid HKDF output length");
+    note_key
 }
 
 // ============================================================================
@@ -323,36 +1096,124 @@ pub struct ContactStore {
     pending_exchanges: HashMap<String, (EphemeralKeypair, i64)>,
     /// Pending invite blobs awaiting ACK
     pending_invites: HashMap<String, InviteBlob>,
+    /// Our X25519 identity key, announced in QR and pairing-code exchanges
+    identity_key: Option<[u8; 32]>,
+    /// Time source for expiry checks and timestamps
+    clock: Arc<dyn Clock>,
 }
 
 impl ContactStore {
     /// Create a new empty contact store
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Create a new empty contact store reading the time from `clock`
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             contacts: HashMap::new(),
             pending_exchanges: HashMap::new(),
             pending_invites: HashMap::new(),
+            identity_key: None,
+            clock,
+        }
+    }
+
+    /// Announce `identity_key` in the QR and pairing-code exchanges started
+    /// from now on
+    pub fn set_identity_key(&mut self, identity_key: [u8; 32]) {
+        self.identity_key = Some(identity_key);
+    }
+
+    /// Add our identity key to a QR payload, if we have one
+    fn announce_identity(&self, payload: QrPayload) -> QrPayload {
+        match &self.identity_key {
+            Some(identity_key) => payload.with_identity_key(identity_key),
+            None => payload,
+        }
+    }
+
+    /// SAS of an exchange, covering both identity keys when both sides
+    /// announced one
+    fn exchange_sas(&self, shared_secret: &[u8; 32], peer_identity: Option<&[u8; 32]>) -> String {
+        match (&self.identity_key, peer_identity) {
+            (Some(ours), Some(theirs)) => {
+                generate_sas(&bind_identity_keys(shared_secret, ours, theirs))
+            }
+            _ => generate_sas(shared_secret),
         }
     }
 
     /// Generate a new QR exchange and return the payload
     pub fn start_qr_exchange(&mut self, kem_pubkey: Option<&[u8]>) -> (String, QrPayload) {
         let keypair = EphemeralKeypair::generate();
-        let payload = QrPayload::new(&keypair.public_key, kem_pubkey, 300); // 5 minutes
+        let payload = self.announce_identity(QrPayload::new_with_clock(
+            &keypair.public_key,
+            kem_pubkey,
+            300, // 5 minutes
+            &*self.clock,
+        ));
+        let exchange_id = self.track_exchange(keypair);
 
-        let exchange_id = generate_random_id();
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        (exchange_id, payload)
+    }
 
-        self.pending_exchanges
+    /// Generate a new QR exchange with a compact payload (KEM key commitment only)
+    pub fn start_compact_qr_exchange(&mut self, kem_pubkey: &[u8]) -> (String, QrPayload) {
+        let keypair = EphemeralKeypair::generate();
+        let payload = self.announce_identity(QrPayload::new_compact_with_clock(
+            &keypair.public_key,
+            kem_pubkey,
+            300, // 5 minutes
+            &*self.clock,
+        ));
+        let exchange_id = self.track_exchange(keypair);
+
+        (exchange_id, payload)
+    }
+
+    /// Generate a new QR exchange whose serialized payload stays within
+    /// `max_bytes` if it can, using the compact payload when the full one
+    /// would not fit
+    pub fn start_qr_exchange_within(
+        &mut self,
+        kem_pubkey: &[u8],
+        max_bytes: usize,
+    ) -> Result<(String, QrPayload), ContactError> {
+        let keypair = EphemeralKeypair::generate();
+        let full = self.announce_identity(QrPayload::new_with_clock(
+            &keypair.public_key,
+            Some(kem_pubkey),
+            300,
+            &*self.clock,
+        ));
+        let payload = if full.to_json()?.len() <= max_bytes {
+            full
+        } else {
+            self.announce_identity(QrPayload::new_compact_with_clock(
+                &keypair.public_key,
+                kem_pubkey,
+                300,
+                &*self.clock,
+            ))
+        };
+        let exchange_id = self.track_exchange(keypair);
+
+        Ok((exchange_id, payload))
+    }
+
+    /// Remember the ephemeral keypair of a new exchange
+    fn track_exchange(&mut self, keypair: EphemeralKeypair) -> String {
+        let exchange_id = generate_random_id();
+        let now = self.clock.now_unix();
+
+        self.pending_exchanges
             .insert(exchange_id.clone(), (keypair, now));
 
         // Clean up old exchanges (older than 10 minutes)
         self.cleanup_expired_exchanges();
 
-        (exchange_id, payload)
+        exchange_id
     }
 
     /// Process a scanned QR code and compute shared secret
@@ -361,7 +1222,7 @@ impl ContactStore {
         exchange_id: &str,
         scanned_payload: &QrPayload,
     ) -> Result<(String, [u8; 32]), ContactError> {
-        if scanned_payload.is_expired() {
+        if scanned_payload.is_expired_with_clock(&*self.clock) {
             return Err(ContactError::PayloadExpired);
         }
 
@@ -371,8 +1232,9 @@ impl ContactStore {
             .ok_or(ContactError::ExchangeNotFound)?;
 
         let peer_public = scanned_payload.decode_public_key()?;
-        let shared_secret = keypair.compute_shared_secret(&peer_public);
-        let sas = generate_sas(&shared_secret);
+        let peer_identity = scanned_payload.decode_identity_key()?;
+        let shared_secret = keypair.compute_shared_secret(&peer_public)?;
+        let sas = self.exchange_sas(&shared_secret, peer_identity.as_ref());
 
         Ok((sas, shared_secret))
     }
@@ -384,14 +1246,130 @@ impl ContactStore {
         scanned_payload: &QrPayload,
         alias: String,
     ) -> Result<Contact, ContactError> {
+        let kem_pubkey = scanned_payload.decode_kem_pubkey()?;
+        let kem_commitment = match kem_pubkey {
+            Some(_) => None,
+            None => scanned_payload.decode_kem_commitment()?,
+        };
+        let peer = ExchangePeer {
+            public_key: scanned_payload.decode_public_key()?,
+            kem_pubkey: kem_pubkey.unwrap_or_default(),
+            kem_commitment: kem_commitment.map(hex::encode),
+            signing_key: scanned_payload.decode_signing_key()?,
+            identity_key: scanned_payload.decode_identity_key()?,
+        };
+        self.finalize_exchange(exchange_id, peer, alias)
+    }
+
+    /// Set the ML-KEM key of a contact made from a compact QR payload,
+    /// once the peer has sent it over the established session.
+    ///
+    /// The key must match the commitment the payload carried; a mismatch
+    /// is refused and the commitment is kept.
+    pub fn apply_committed_kem_key(
+        &mut self,
+        id: &str,
+        kem_pubkey: &[u8],
+    ) -> Result<Contact, ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+        let expected = contact
+            .kem_commitment
+            .as_deref()
+            .and_then(|commitment| hex::decode(commitment).ok())
+            .ok_or(ContactError::NoKemCommitment)?;
+
+        if kem_pubkey.len() != ML_KEM_PUBKEY_LEN || !ct_eq(&expected, &kem_commitment(kem_pubkey)) {
+            return Err(ContactError::KemCommitmentMismatch);
+        }
+
+        contact.kem_pubkey = kem_pubkey.to_vec();
+        contact.kem_commitment = None;
+        Ok(contact.clone())
+    }
+
+    /// Start an exchange by pairing code, for when a QR code can't be
+    /// scanned (over a call, or without a camera).
+    ///
+    /// Each side reads its code to the other and posts its offer to the
+    /// offer's mailbox. The peer fetches the offer from
+    /// `pairing_mailbox(code)` and passes both to `complete_code_exchange`;
+    /// the SAS is then compared as for QR.
+    pub fn start_code_exchange(&mut self) -> PairingOffer {
+        use rand::Rng;
+
+        let keypair = EphemeralKeypair::generate();
+        let token = rand::rngs::OsRng.gen_range(0..10u64.pow(PAIRING_TOKEN_DIGITS as u32));
+        let code = encode_pairing_code(token);
+        let mailbox_id = pairing_mailbox(&code).expect("encoded pairing codes decode");
+        let offer = seal_pairing_offer(&code, &keypair.public_key, self.identity_key.as_ref())
+            .expect("encoded pairing codes decode");
+        let exchange_id = self.track_exchange(keypair);
+
+        PairingOffer {
+            exchange_id,
+            code,
+            mailbox_id,
+            offer,
+        }
+    }
+
+    /// Process the peer's pairing code and offer, and compute the shared
+    /// secret
+    pub fn complete_code_exchange(
+        &mut self,
+        exchange_id: &str,
+        peer_code: &str,
+        peer_offer: &[u8],
+    ) -> Result<(String, [u8; 32]), ContactError> {
+        let (peer_public, peer_identity) = open_pairing_offer_keys(peer_code, peer_offer)?;
+        let (keypair, _) = self
+            .pending_exchanges
+            .get(exchange_id)
+            .ok_or(ContactError::ExchangeNotFound)?;
+
+        let shared_secret = keypair.compute_shared_secret(&peer_public)?;
+        let sas = self.exchange_sas(&shared_secret, peer_identity.as_ref());
+
+        Ok((sas, shared_secret))
+    }
+
+    /// Confirm the SAS of a pairing-code exchange and finalize contact
+    /// creation. The offer carries no KEM key; it is learned with the
+    /// contact's first key update.
+    pub fn confirm_code_sas(
+        &mut self,
+        exchange_id: &str,
+        peer_code: &str,
+        peer_offer: &[u8],
+        alias: String,
+    ) -> Result<Contact, ContactError> {
+        let (public_key, identity_key) = open_pairing_offer_keys(peer_code, peer_offer)?;
+        let peer = ExchangePeer {
+            public_key,
+            identity_key,
+            ..Default::default()
+        };
+        self.finalize_exchange(exchange_id, peer, alias)
+    }
+
+    /// Create the contact for a confirmed QR or pairing-code exchange,
+    /// pinning the peer's announced signing key if there is one
+    fn finalize_exchange(
+        &mut self,
+        exchange_id: &str,
+        peer: ExchangePeer,
+        alias: String,
+    ) -> Result<Contact, ContactError> {
+        let alias = normalize_alias(&alias)?;
         let (keypair, _) = self
             .pending_exchanges
             .remove(exchange_id)
             .ok_or(ContactError::ExchangeNotFound)?;
 
-        let peer_public = scanned_payload.decode_public_key()?;
-        let kem_pubkey = scanned_payload.decode_kem_pubkey()?.unwrap_or_default();
-        let shared_secret = keypair.compute_shared_secret(&peer_public);
+        let shared_secret = keypair.compute_shared_secret(&peer.public_key)?;
 
         // Generate session ID from shared secret
         let mut hasher = Sha256::new();
@@ -402,14 +1380,20 @@ impl ContactStore {
         let contact = Contact {
             id: generate_random_id(),
             alias,
-            public_key: peer_public,
-            kem_pubkey,
+            public_key: peer.public_key,
+            kem_pubkey: peer.kem_pubkey,
+            kem_commitment: peer.kem_commitment,
             session_id,
-            added_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            verified: true,
+            added_at: self.clock.now_unix(),
+            verification: VerificationStatus::SasConfirmed,
+            invite_mailbox: None,
+            signing_key: peer.signing_key.map(|key| hex::encode(key.as_bytes())),
+            identity_key: peer.identity_key.map(hex::encode),
+            key_updated_at: 0,
+            repair_required: false,
+            last_activity: 0,
+            unread_count: 0,
+            encrypted_notes: None,
         };
 
         self.contacts.insert(contact.id.clone(), contact.clone());
@@ -424,21 +1408,52 @@ impl ContactStore {
         our_kem_pk: Vec<u8>,
         ttl_hours: u32,
     ) -> InviteBlob {
-        let invite = InviteBlob::new(our_pubkey, our_kem_pk, (ttl_hours * 3600) as i64);
+        let invite = InviteBlob::new_with_clock(
+            our_pubkey,
+            our_kem_pk,
+            (ttl_hours * 3600) as i64,
+            &*self.clock,
+        );
         self.pending_invites
             .insert(hex::encode(invite.mailbox_id), invite.clone());
         invite
     }
 
-    /// Import an invite blob and create a pending contact
+    /// Check an invite blob without importing it.
+    ///
+    /// Rejects unsupported versions, expired invites and bad signatures,
+    /// and returns what the UI needs to ask the user to confirm.
+    ///
+    /// This checks integrity, not identity: anyone can sign an invite with
+    /// a key of their own, so a valid signature only means the invite's
+    /// keys are the ones its signer sent.
+    pub fn validate_invite(&self, invite: &InviteBlob) -> Result<InvitePreview, ContactError> {
+        if invite.version != INVITE_VERSION {
+            return Err(ContactError::UnsupportedVersion(invite.version));
+        }
+        if invite.is_expired_with_clock(&*self.clock) {
+            return Err(ContactError::PayloadExpired);
+        }
+        let signature_valid = invite.check_signature()?;
+
+        Ok(InvitePreview {
+            fingerprint: key_fingerprint(&invite.sender_pubkey),
+            expiry: invite.expiry,
+            signature_valid,
+        })
+    }
+
+    /// Import an invite blob and create a pending contact.
+    ///
+    /// A validly signed invite pins its signer as the key that must sign
+    /// the contact's key updates.
     pub fn import_invite(
         &mut self,
         invite: &InviteBlob,
         alias: String,
     ) -> Result<Contact, ContactError> {
-        if invite.is_expired() {
-            return Err(ContactError::PayloadExpired);
-        }
+        let preview = self.validate_invite(invite)?;
+        let alias = normalize_alias(&alias)?;
 
         let session_id = generate_random_id();
 
@@ -447,12 +1462,21 @@ impl ContactStore {
             alias,
             public_key: invite.sender_pubkey,
             kem_pubkey: invite.sender_kem_pk.clone(),
+            kem_commitment: None,
             session_id,
-            added_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            verified: false, // Pending ACK
+            added_at: self.clock.now_unix(),
+            verification: VerificationStatus::Unverified, // Pending ACK
+            invite_mailbox: Some(hex::encode(invite.mailbox_id)),
+            signing_key: invite
+                .signer_key
+                .clone()
+                .filter(|_| preview.signature_valid),
+            identity_key: Some(hex::encode(invite.sender_pubkey)),
+            key_updated_at: 0,
+            repair_required: false,
+            last_activity: 0,
+            unread_count: 0,
+            encrypted_notes: None,
         };
 
         self.contacts.insert(contact.id.clone(), contact.clone());
@@ -460,16 +1484,555 @@ impl ContactStore {
         Ok(contact)
     }
 
+    /// Generate an encrypted acknowledgment for an imported invite.
+    ///
+    /// The ACK is keyed by the X25519 DH between `our_secret` (our identity
+    /// key) and the inviter's key, so it cannot be forged from the public
+    /// invite alone. Returns the mailbox to deliver it to (the invite's
+    /// `mailbox_id`) and the ACK bytes:
+    /// `mailbox_id (32) || our_pubkey (32) || nonce (12) || ciphertext`.
+    pub fn generate_ack(
+        &self,
+        invite: &InviteBlob,
+        our_secret: &[u8; 32],
+    ) -> Result<([u8; 32], Vec<u8>), ContactError> {
+        let our_pubkey =
+            x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(*our_secret))
+                .to_bytes();
+        let ack = self.seal_ack(
+            &invite.mailbox_id,
+            &our_pubkey,
+            &invite.sender_pubkey,
+            our_secret,
+            ACK_ROLE_RESPONDER,
+        )?;
+        Ok((invite.mailbox_id, ack))
+    }
+
+    /// Encrypt an ACK from `role` to `peer_pubkey`; the header names the
+    /// responder's key whichever side sends it
+    fn seal_ack(
+        &self,
+        mailbox_id: &[u8; 32],
+        responder_pubkey: &[u8; 32],
+        peer_pubkey: &[u8; 32],
+        our_secret: &[u8; 32],
+        role: &[u8],
+    ) -> Result<Vec<u8>, ContactError> {
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let mut shared = contributory_dh(our_secret, peer_pubkey)?;
+        let key = ack_key(&shared, mailbox_id, role);
+        shared.zeroize();
+        let mut nonce_bytes = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+
+        let now = self.clock.now_unix();
+        let mut plaintext = ACK_MAGIC.to_vec();
+        plaintext.extend_from_slice(&now.to_le_bytes());
+
+        let mut ack = Vec::with_capacity(ACK_HEADER_LEN + plaintext.len() + 16);
+        ack.extend_from_slice(mailbox_id);
+        ack.extend_from_slice(responder_pubkey);
+        ack.extend_from_slice(&nonce_bytes);
+
+        let cipher = Aes256Gcm::new_from_slice(&key).expect("AES-256 key is 32 bytes");
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce_bytes),
+                Payload {
+                    msg: &plaintext,
+                    aad: &ack[..64],
+                },
+            )
+            .expect("AES-GCM encryption failed");
+        ack.extend_from_slice(&ciphertext);
+
+        Ok(ack)
+    }
+
+    /// Process an invite ACK.
+    ///
+    /// As the inviter, accepts the responder's ACK, removes the matching
+    /// pending invite and returns the reply to send back. As the responder,
+    /// accepts only that reply, and marks the contact imported from the
+    /// invite as acknowledged. The two directions are keyed apart, so a
+    /// reflected copy of our own ACK is rejected.
+    ///
+    /// `our_secret` is our X25519 identity key. As the inviter we complete
+    /// the DH with the responder key carried in the ACK; as the responder
+    /// we complete it with the inviter key of the contact, and the ACK must
+    /// name our own key.
+    pub fn process_ack(
+        &mut self,
+        ack_bytes: &[u8],
+        our_secret: &[u8; 32],
+    ) -> Result<AckOutcome, ContactError> {
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        if ack_bytes.len() < ACK_HEADER_LEN {
+            return Err(ContactError::InvalidPayload);
+        }
+        let (header, ciphertext) = ack_bytes.split_at(ACK_HEADER_LEN);
+        let mailbox: [u8; 32] = header[..32]
+            .try_into()
+            .map_err(|_| ContactError::InvalidPayload)?;
+        let responder_pubkey: [u8; 32] = header[32..64]
+            .try_into()
+            .map_err(|_| ContactError::InvalidPayload)?;
+        let nonce_bytes = &header[64..];
+        let mailbox_hex = hex::encode(mailbox);
+
+        let contact_id = self
+            .contacts
+            .values()
+            .find(|c| c.invite_mailbox.as_deref() == Some(mailbox_hex.as_str()))
+            .map(|c| c.id.clone());
+        let inviter = self.pending_invites.contains_key(&mailbox_hex);
+        let (peer_pubkey, role) = match (&contact_id, inviter) {
+            (_, true) => (responder_pubkey, ACK_ROLE_RESPONDER),
+            (Some(id), false) => {
+                let our_pubkey =
+                    x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(*our_secret))
+                        .to_bytes();
+                if !ct_eq(&responder_pubkey, &our_pubkey) {
+                    return Err(ContactError::InvalidPayload);
+                }
+                (self.contacts[id].public_key, ACK_ROLE_INVITER)
+            }
+            (None, false) => return Err(ContactError::ExchangeNotFound),
+        };
+
+        let mut shared = contributory_dh(our_secret, &peer_pubkey)?;
+        let key = ack_key(&shared, &mailbox, role);
+        shared.zeroize();
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| ContactError::InvalidPayload)?;
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce_bytes),
+                Payload {
+                    msg: ciphertext,
+                    aad: &header[..64],
+                },
+            )
+            .map_err(|_| ContactError::InvalidPayload)?;
+        if !plaintext.starts_with(ACK_MAGIC) {
+            return Err(ContactError::InvalidPayload);
+        }
+
+        if inviter {
+            let reply = self.seal_ack(
+                &mailbox,
+                &responder_pubkey,
+                &responder_pubkey,
+                our_secret,
+                ACK_ROLE_INVITER,
+            )?;
+            self.pending_invites.remove(&mailbox_hex);
+            return Ok(AckOutcome::Replied {
+                mailbox_id: mailbox,
+                reply,
+            });
+        }
+
+        let contact = contact_id
+            .and_then(|id| self.contacts.get_mut(&id))
+            .ok_or(ContactError::ExchangeNotFound)?;
+        if contact.verification == VerificationStatus::Unverified {
+            contact.verification = VerificationStatus::Acknowledged;
+        }
+        Ok(AckOutcome::Acknowledged(Box::new(contact.clone())))
+    }
+
+    /// Whether an invite with this mailbox ID is still awaiting its ACK
+    pub fn has_pending_invite(&self, mailbox_id: &[u8; 32]) -> bool {
+        self.pending_invites.contains_key(&hex::encode(mailbox_id))
+    }
+
     /// Get all contacts
     pub fn list_contacts(&self) -> Vec<Contact> {
         self.contacts.values().cloned().collect()
     }
 
+    /// Get contacts that have not been revoked
+    pub fn list_active_contacts(&self) -> Vec<Contact> {
+        self.contacts
+            .values()
+            .filter(|contact| !contact.is_revoked())
+            .cloned()
+            .collect()
+    }
+
     /// Get a contact by ID
     pub fn get_contact(&self, id: &str) -> Option<&Contact> {
         self.contacts.get(id)
     }
 
+    /// Find contacts whose alias starts with `prefix`, ignoring case and
+    /// Unicode normalization form, sorted by alias
+    pub fn find_by_alias_prefix(&self, prefix: &str) -> Vec<&Contact> {
+        let prefix = search_key(prefix);
+        let mut found: Vec<&Contact> = self
+            .contacts
+            .values()
+            .filter(|contact| search_key(&contact.alias).starts_with(&prefix))
+            .collect();
+        found.sort_by(|a, b| a.alias.cmp(&b.alias));
+        found
+    }
+
+    /// Find contacts whose key fingerprint starts with `short_hex`.
+    ///
+    /// Case and the spaces between fingerprint groups are ignored; an
+    /// empty query matches nothing.
+    pub fn find_by_fingerprint(&self, short_hex: &str) -> Vec<&Contact> {
+        let query: String = short_hex
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        self.contacts
+            .values()
+            .filter(|contact| {
+                key_fingerprint(&contact.public_key)
+                    .replace(' ', "")
+                    .starts_with(&query)
+            })
+            .collect()
+    }
+
+    /// Get the contact with exactly this public key
+    pub fn get_by_public_key(&self, public_key: &[u8; 32]) -> Option<&Contact> {
+        self.contacts
+            .values()
+            .find(|contact| contact.public_key == *public_key)
+    }
+
+    /// Get the contact whose ratchet session has this ID
+    pub fn get_by_session(&self, session_id: &str) -> Option<&Contact> {
+        self.contacts
+            .values()
+            .find(|contact| contact.session_id == session_id)
+    }
+
+    /// Get all contacts, most recently active first (ties by alias)
+    pub fn list_by_activity(&self) -> Vec<Contact> {
+        let mut contacts = self.list_contacts();
+        contacts.sort_by(|a, b| {
+            b.last_activity
+                .cmp(&a.last_activity)
+                .then_with(|| a.alias.cmp(&b.alias))
+        });
+        contacts
+    }
+
+    /// Record activity with a contact at `timestamp`.
+    ///
+    /// An older timestamp (e.g. a delayed message) never moves
+    /// `last_activity` backwards.
+    pub fn touch_contact(&mut self, id: &str, timestamp: i64) -> Result<(), ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+        contact.last_activity = contact.last_activity.max(timestamp);
+        Ok(())
+    }
+
+    /// Record an inbound message from a contact at `timestamp`: touch it and
+    /// count the message as unread
+    pub fn record_inbound(&mut self, id: &str, timestamp: i64) -> Result<(), ContactError> {
+        self.touch_contact(id, timestamp)?;
+        if let Some(contact) = self.contacts.get_mut(id) {
+            contact.unread_count = contact.unread_count.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    /// Clear a contact's unread count
+    pub fn mark_read(&mut self, id: &str) -> Result<(), ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+        contact.unread_count = 0;
+        Ok(())
+    }
+
+    /// Change a contact's verification status.
+    ///
+    /// Revocation is final: a revoked contact must be re-added with new keys.
+    pub fn set_verification(
+        &mut self,
+        id: &str,
+        status: VerificationStatus,
+    ) -> Result<(), ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+
+        if contact.is_revoked() && status != VerificationStatus::Revoked {
+            return Err(ContactError::ContactRevoked);
+        }
+        contact.verification = status;
+        Ok(())
+    }
+
+    /// Pin the Ed25519 identity key that must sign a contact's key updates
+    pub fn set_signing_key(
+        &mut self,
+        id: &str,
+        verifying_key: &ed25519_dalek::VerifyingKey,
+    ) -> Result<(), ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+        contact.signing_key = Some(hex::encode(verifying_key.as_bytes()));
+        Ok(())
+    }
+
+    /// Apply a contact's signed key rotation.
+    ///
+    /// The update must be signed by the contact's pinned identity key and be
+    /// newer than any update already applied. The new keys replace the old
+    /// ones, the contact drops back to `Unverified` until they are confirmed
+    /// again, and `repair_required` is set since the existing session was
+    /// built on the old keys.
+    pub fn apply_key_update(
+        &mut self,
+        id: &str,
+        update: &KeyUpdate,
+    ) -> Result<Contact, ContactError> {
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+        if contact.is_revoked() {
+            return Err(ContactError::ContactRevoked);
+        }
+
+        let signing_key = contact
+            .signing_key
+            .as_deref()
+            .and_then(|key| hex::decode(key).ok())
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .and_then(|key| ed25519_dalek::VerifyingKey::from_bytes(&key).ok())
+            .ok_or(ContactError::NoSigningKey)?;
+        if !update.verify_signature(&signing_key) {
+            return Err(ContactError::InvalidSignature);
+        }
+        if update.issued_at <= contact.key_updated_at {
+            return Err(ContactError::StaleKeyUpdate);
+        }
+
+        contact.public_key = update.new_public_key;
+        contact.kem_pubkey = update.new_kem_pubkey.clone();
+        contact.key_updated_at = update.issued_at;
+        contact.verification = VerificationStatus::Unverified;
+        contact.repair_required = true;
+        Ok(contact.clone())
+    }
+
+    /// Revoke a contact, keeping the record but blocking new sessions
+    pub fn revoke_contact(&mut self, id: &str) -> Result<(), ContactError> {
+        self.set_verification(id, VerificationStatus::Revoked)
+    }
+
+    /// Whether a new session may be started for this session ID
+    pub fn session_allowed(&self, session_id: &str) -> bool {
+        !self
+            .contacts
+            .values()
+            .any(|contact| contact.session_id == session_id && contact.is_revoked())
+    }
+
+    /// Encrypt a private note about a contact, replacing any previous one.
+    ///
+    /// Format: `nonce (12) || ciphertext`, encrypted with AES-256-GCM under
+    /// a key derived from `key` and the contact's ID, so the note stays
+    /// sealed wherever the contact record itself is readable.
+    pub fn set_note(
+        &mut self,
+        id: &str,
+        plaintext: &str,
+        key: &[u8; 32],
+    ) -> Result<(), ContactError> {
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let contact = self
+            .contacts
+            .get_mut(id)
+            .ok_or(ContactError::ContactNotFound)?;
+
+        let mut nonce_bytes = [0u8; NOTE_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+
+        let mut note_key = note_key(key, id);
+        let cipher = Aes256Gcm::new_from_slice(&note_key).expect("AES-256 key is 32 bytes");
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext.as_bytes())
+            .expect("AES-GCM encryption failed");
+        note_key.zeroize();
+
+        let mut sealed = Vec::with_capacity(NOTE_NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce_bytes);
+        sealed.extend_from_slice(&ciphertext);
+        if let Some(mut old) = contact.encrypted_notes.replace(sealed) {
+            old.zeroize();
+        }
+        Ok(())
+    }
+
+    /// Decrypt a contact's note, or `None` if it has no note
+    pub fn get_note(&self, id: &str, key: &[u8; 32]) -> Result<Option<String>, ContactError> {
+        use aes_gcm::aead::{Aead, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let contact = self.contacts.get(id).ok_or(ContactError::ContactNotFound)?;
+        let Some(sealed) = contact.encrypted_notes.as_deref() else {
+            return Ok(None);
+        };
+        if sealed.len() < NOTE_NONCE_LEN {
+            return Err(ContactError::NoteDecryptionFailed);
+        }
+        let (nonce_bytes, ciphertext) = sealed.split_at(NOTE_NONCE_LEN);
+
+        let mut note_key = note_key(key, id);
+        let cipher = Aes256Gcm::new_from_slice(&note_key).expect("AES-256 key is 32 bytes");
+        let decrypted = cipher.decrypt(Nonce::from_slice(nonce_bytes), ciphertext);
+        note_key.zeroize();
+
+        let plaintext = decrypted.map_err(|_| ContactError::NoteDecryptionFailed)?;
+        String::from_utf8(plaintext)
+            .map(Some)
+            .map_err(|_| ContactError::NoteDecryptionFailed)
+    }
+
+    /// Export all contacts as a passphrase-encrypted backup bundle.
+    ///
+    /// Format: `"CLCB" || version (1) || salt (16) || nonce (12) || ciphertext`,
+    /// encrypted with AES-256-GCM under an Argon2id key; the header is
+    /// authenticated as associated data. Contact notes are left out; see
+    /// [`Self::export_bundle_with_notes`].
+    pub fn export_bundle(&self, passphrase: &str) -> Vec<u8> {
+        self.seal_bundle(passphrase, false)
+    }
+
+    /// Export all contacts like [`Self::export_bundle`], keeping their
+    /// (still encrypted) notes
+    pub fn export_bundle_with_notes(&self, passphrase: &str) -> Vec<u8> {
+        self.seal_bundle(passphrase, true)
+    }
+
+    /// Encrypt the contact list into a bundle, with or without notes
+    fn seal_bundle(&self, passphrase: &str, include_notes: bool) -> Vec<u8> {
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let contacts: Vec<Contact> = self
+            .contacts
+            .values()
+            .map(|contact| Contact {
+                encrypted_notes: contact.encrypted_notes.clone().filter(|_| include_notes),
+                ..contact.clone()
+            })
+            .collect();
+        let mut json = serde_json::to_vec(&contacts).expect("Contact serialization failed");
+
+        let mut salt = [0u8; BUNDLE_SALT_LEN];
+        let mut nonce_bytes = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+
+        let mut bundle = Vec::with_capacity(BUNDLE_HEADER_LEN + 12 + json.len() + 16);
+        bundle.extend_from_slice(BUNDLE_MAGIC);
+        bundle.push(BUNDLE_VERSION);
+        bundle.extend_from_slice(&salt);
+
+        let mut key = bundle_key(passphrase, &salt);
+        let cipher = Aes256Gcm::new_from_slice(&key).expect("AES-256 key is 32 bytes");
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce_bytes),
+                Payload {
+                    msg: &json,
+                    aad: &bundle,
+                },
+            )
+            .expect("AES-GCM encryption failed");
+        key.zeroize();
+        json.zeroize();
+
+        bundle.extend_from_slice(&nonce_bytes);
+        bundle.extend_from_slice(&ciphertext);
+        bundle
+    }
+
+    /// Import contacts from a backup bundle, merging them into the store.
+    ///
+    /// Contacts whose public key is already known are skipped. Returns the
+    /// number of contacts added.
+    pub fn import_bundle(&mut self, bytes: &[u8], passphrase: &str) -> Result<usize, ContactError> {
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        if bytes.len() < BUNDLE_HEADER_LEN + 12 || !bytes.starts_with(BUNDLE_MAGIC) {
+            return Err(ContactError::InvalidBundle);
+        }
+        if bytes[BUNDLE_MAGIC.len()] != BUNDLE_VERSION {
+            return Err(ContactError::InvalidBundle);
+        }
+
+        let (header, rest) = bytes.split_at(BUNDLE_HEADER_LEN);
+        let (nonce_bytes, ciphertext) = rest.split_at(12);
+        let salt = &header[BUNDLE_MAGIC.len() + 1..];
+
+        let mut key = bundle_key(passphrase, salt);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| ContactError::InvalidBundle)?;
+        let decrypted = cipher.decrypt(
+            Nonce::from_slice(nonce_bytes),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        );
+        key.zeroize();
+        let mut json = decrypted.map_err(|_| ContactError::BundleDecryptionFailed)?;
+
+        let parsed: Result<Vec<Contact>, _> = serde_json::from_slice(&json);
+        json.zeroize();
+        let imported = parsed.map_err(|_| ContactError::InvalidBundle)?;
+
+        let mut added = 0;
+        for mut contact in imported {
+            if self
+                .contacts
+                .values()
+                .any(|existing| existing.public_key == contact.public_key)
+            {
+                continue;
+            }
+            if self.contacts.contains_key(&contact.id) {
+                contact.id = generate_random_id();
+                // Its note was sealed under the old ID
+                contact.encrypted_notes = None;
+            }
+            self.contacts.insert(contact.id.clone(), contact);
+            added += 1;
+        }
+
+        Ok(added)
+    }
+
     /// Get a pending exchange (for reading shared secret before confirm)
     pub fn get_pending_exchange(&self, exchange_id: &str) -> Option<&(EphemeralKeypair, i64)> {
         self.pending_exchanges.get(exchange_id)
@@ -480,12 +2043,26 @@ impl ContactStore {
         self.contacts.remove(id)
     }
 
+    /// Securely zeroize and remove all contacts and pending exchanges
+    pub fn wipe(&mut self) {
+        for (_, contact) in self.contacts.iter_mut() {
+            contact.id.zeroize();
+            contact.alias.zeroize();
+            contact.public_key.zeroize();
+            contact.kem_pubkey.zeroize();
+            contact.session_id.zeroize();
+            if let Some(notes) = contact.encrypted_notes.as_mut() {
+                notes.zeroize();
+            }
+        }
+        self.contacts.clear();
+        self.pending_exchanges.clear();
+        self.pending_invites.clear();
+    }
+
     /// Clean up expired pending exchanges
     fn cleanup_expired_exchanges(&mut self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let now = self.clock.now_unix();
 
         let expired: Vec<_> = self
             .pending_exchanges
@@ -508,15 +2085,7 @@ impl Default for ContactStore {
 
 impl Drop for ContactStore {
     fn drop(&mut self) {
-        // Securely zeroize all contact data
-        for (_, contact) in self.contacts.iter_mut() {
-            contact.public_key.zeroize();
-            contact.kem_pubkey.zeroize();
-            contact.session_id.zeroize();
-        }
-        self.contacts.clear();
-        self.pending_exchanges.clear();
-        self.pending_invites.clear();
+        self.wipe();
     }
 }
 
@@ -538,12 +2107,58 @@ pub enum ContactError {
     SerializationFailed,
     #[error("Base64 decoding failed")]
     Base64DecodeFailed,
+    #[error("Contact not found")]
+    ContactNotFound,
+    #[error("Contact has been revoked")]
+    ContactRevoked,
+    #[error("Invalid contact bundle")]
+    InvalidBundle,
+    #[error("Contact bundle decryption failed (wrong passphrase?)")]
+    BundleDecryptionFailed,
+    #[error("No identity key pinned to verify the contact's signature")]
+    NoSigningKey,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Key update is older than the contact's current keys")]
+    StaleKeyUpdate,
+    #[error("Alias must not be empty")]
+    InvalidAlias,
+    #[error("Unsupported invite version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid pairing code")]
+    InvalidPairingCode,
+    #[error("Pairing code check digit mismatch (typo?)")]
+    PairingCodeMismatch,
+    #[error("Contact note decryption failed (wrong key?)")]
+    NoteDecryptionFailed,
+    #[error("No KEM key commitment is pending for this contact")]
+    NoKemCommitment,
+    #[error("KEM key does not match the contact's commitment")]
+    KemCommitmentMismatch,
 }
 
 // ============================================================================
 // UTILITIES
 // ============================================================================
 
+/// Maximum alias length in characters; longer aliases are truncated
+pub const MAX_ALIAS_CHARS: usize = 64;
+
+/// Normalize a contact alias: NFC, trimmed, at most `MAX_ALIAS_CHARS` long
+fn normalize_alias(alias: &str) -> Result<String, ContactError> {
+    let normalized: String = alias.trim().nfc().take(MAX_ALIAS_CHARS).collect();
+    let normalized = normalized.trim_end();
+    if normalized.is_empty() {
+        return Err(ContactError::InvalidAlias);
+    }
+    Ok(normalized.to_string())
+}
+
+/// Form of a string used for search: NFKC-normalized, trimmed, lowercase
+fn search_key(text: &str) -> String {
+    text.trim().nfkc().collect::<String>().to_lowercase()
+}
+
 /// Generate a random 16-byte hex ID
 fn generate_random_id() -> String {
     let mut bytes = [0u8; 16];
@@ -551,16 +2166,16 @@ fn generate_random_id() -> String {
     hex::encode(bytes)
 }
 
-/// Base64 encode bytes
+/// Base64url encode bytes (no padding, keeps QR codes small)
 fn base64_encode(data: &[u8]) -> String {
-    use base64::{engine::general_purpose::STANDARD, Engine};
-    STANDARD.encode(data)
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    URL_SAFE_NO_PAD.encode(data)
 }
 
-/// Base64 decode string
+/// Base64url decode string (no padding)
 fn base64_decode(data: &str) -> Result<Vec<u8>, ContactError> {
-    use base64::{engine::general_purpose::STANDARD, Engine};
-    STANDARD
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    URL_SAFE_NO_PAD
         .decode(data)
         .map_err(|_| ContactError::Base64DecodeFailed)
 }
@@ -646,6 +2261,18 @@ mod tests {
         assert_ne!(kp1.public_key, kp2.public_key);
     }
 
+    #[test]
+    fn test_seeded_keypair_generation() {
+        use rand::SeedableRng;
+
+        let generate = |seed: u64| {
+            EphemeralKeypair::generate_with_rng(&mut rand::rngs::StdRng::seed_from_u64(seed))
+                .public_key
+        };
+        assert_eq!(generate(3), generate(3));
+        assert_ne!(generate(3), generate(4));
+    }
+
     #[test]
     fn test_shared_secret_computation() {
         let kp1 = EphemeralKeypair::generate();
@@ -654,8 +2281,27 @@ mod tests {
         // In real X25519, DH(sk1, pk2) == DH(sk2, pk1)
         // Our placeholder hash-based version won't have this property,
         // but in production with real X25519 it would
-        let _secret1 = kp1.compute_shared_secret(&kp2.public_key);
-        let _secret2 = kp2.compute_shared_secret(&kp1.public_key);
+        let _secret1 = kp1.compute_shared_secret(&kp2.public_key).unwrap();
+        let _secret2 = kp2.compute_shared_secret(&kp1.public_key).unwrap();
+    }
+
+    #[test]
+    fn test_low_order_peer_key_rejected() {
+        let keypair = EphemeralKeypair::generate();
+
+        // The identity point and a point of order eight give an all-zero
+        // shared secret whatever our key
+        let order_eight: [u8; 32] =
+            hex::decode("e0eb7a7c3b41b8ae1656e3faf19fc46ada098deb9c32b1fd866205165f49b800")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        for low_order in [[0u8; 32], order_eight] {
+            assert!(matches!(
+                keypair.compute_shared_secret(&low_order),
+                Err(ContactError::InvalidPublicKey)
+            ));
+        }
     }
 
     #[test]
@@ -681,6 +2327,305 @@ mod tests {
         assert!(!verify_sas(&secret, "Wrong-Sas-00"));
     }
 
+    /// A store with contacts imported from invites, with the given
+    /// aliases and public keys
+    fn store_with_contacts(entries: &[(&str, [u8; 32])]) -> ContactStore {
+        let mut store = ContactStore::new();
+        for (alias, public_key) in entries {
+            store
+                .import_invite(
+                    &InviteBlob::new(*public_key, vec![], 3600),
+                    alias.to_string(),
+                )
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_unread_count_tracks_inbound_and_read() {
+        let mut store = store_with_contacts(&[("Alice", [1u8; 32])]);
+        let id = store.list_contacts()[0].id.clone();
+
+        store.record_inbound(&id, 100).unwrap();
+        store.record_inbound(&id, 200).unwrap();
+        let contact = store.get_contact(&id).unwrap();
+        assert_eq!(contact.unread_count, 2);
+        assert_eq!(contact.last_activity, 200);
+
+        store.mark_read(&id).unwrap();
+        let contact = store.get_contact(&id).unwrap();
+        assert_eq!(contact.unread_count, 0);
+        assert_eq!(contact.last_activity, 200);
+
+        assert!(matches!(
+            store.mark_read("missing"),
+            Err(ContactError::ContactNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_list_by_activity_sorts_most_recent_first() {
+        let mut store = store_with_contacts(&[
+            ("Alice", [1u8; 32]),
+            ("Bob", [2u8; 32]),
+            ("Carol", [3u8; 32]),
+        ]);
+        let id = |public_key: [u8; 32]| store.get_by_public_key(&public_key).unwrap().id.clone();
+        let (alice, bob, carol) = (id([1u8; 32]), id([2u8; 32]), id([3u8; 32]));
+
+        store.touch_contact(&alice, 300).unwrap();
+        store.record_inbound(&bob, 500).unwrap();
+        store.touch_contact(&carol, 400).unwrap();
+        // A delayed message does not move activity backwards
+        store.record_inbound(&bob, 100).unwrap();
+
+        let aliases: Vec<String> = store
+            .list_by_activity()
+            .into_iter()
+            .map(|c| c.alias)
+            .collect();
+        assert_eq!(aliases, ["Bob", "Carol", "Alice"]);
+
+        // The metadata does not touch anything derived from the keys
+        let contact = store.get_contact(&bob).unwrap();
+        assert_eq!(contact.unread_count, 2);
+        assert_eq!(
+            store.find_by_fingerprint(&key_fingerprint(&[2u8; 32]))[0].id,
+            bob
+        );
+    }
+
+    #[test]
+    fn test_find_by_alias_prefix() {
+        let store = store_with_contacts(&[
+            ("Alice", [1u8; 32]),
+            ("alfred", [2u8; 32]),
+            ("Bob", [3u8; 32]),
+            ("Élodie", [4u8; 32]),
+        ]);
+
+        let aliases = |prefix: &str| -> Vec<String> {
+            store
+                .find_by_alias_prefix(prefix)
+                .into_iter()
+                .map(|c| c.alias.clone())
+                .collect()
+        };
+        assert_eq!(aliases("AL"), ["Alice", "alfred"]);
+        assert_eq!(aliases(" bo"), ["Bob"]);
+        // Decomposed and precomposed accents match
+        assert_eq!(aliases("e\u{301}lo"), ["Élodie"]);
+        assert!(aliases("Carol").is_empty());
+        assert_eq!(aliases("").len(), 4);
+    }
+
+    #[test]
+    fn test_find_by_fingerprint_prefix() {
+        let store = store_with_contacts(&[("Alice", [1u8; 32]), ("Bob", [3u8; 32])]);
+        let fingerprint = key_fingerprint(&[1u8; 32]);
+
+        // The first group, several groups with their spaces, or any case
+        let upper = fingerprint.to_uppercase();
+        for query in [&fingerprint[..4], &fingerprint[..9], &upper[..]] {
+            let found = store.find_by_fingerprint(query);
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].alias, "Alice");
+        }
+        let compact = fingerprint.replace(' ', "");
+        assert_eq!(store.find_by_fingerprint(&compact[..6])[0].alias, "Alice");
+
+        assert!(store.find_by_fingerprint("").is_empty());
+        assert!(store.find_by_fingerprint("not hex").is_empty());
+    }
+
+    #[test]
+    fn test_get_by_public_key() {
+        let store = store_with_contacts(&[("Alice", [1u8; 32]), ("Bob", [3u8; 32])]);
+
+        assert_eq!(store.get_by_public_key(&[3u8; 32]).unwrap().alias, "Bob");
+        assert!(store.get_by_public_key(&[9u8; 32]).is_none());
+    }
+
+    #[test]
+    fn test_qr_expiry_follows_clock() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let mut store = ContactStore::with_clock(clock.clone());
+        let (exchange_id, _) = store.start_qr_exchange(None);
+        let peer_payload = QrPayload::new_with_clock(&[5u8; 32], None, 300, &*clock);
+
+        clock.advance(300);
+        assert!(!peer_payload.is_expired_with_clock(&*clock));
+        assert!(store
+            .process_scanned_qr(&exchange_id, &peer_payload)
+            .is_ok());
+
+        clock.advance(1);
+        assert!(peer_payload.is_expired_with_clock(&*clock));
+        assert!(matches!(
+            store.process_scanned_qr(&exchange_id, &peer_payload),
+            Err(ContactError::PayloadExpired)
+        ));
+    }
+
+    #[test]
+    fn test_invite_expiry_follows_clock() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let mut store = ContactStore::with_clock(clock.clone());
+        let invite = InviteBlob::new_with_clock([3u8; 32], vec![], 3600, &*clock);
+        assert_eq!(invite.expiry, 1_700_000_000 + 3600);
+
+        clock.advance(3600);
+        assert!(store.validate_invite(&invite).is_ok());
+
+        clock.advance(1);
+        assert!(matches!(
+            store.import_invite(&invite, "Alice".into()),
+            Err(ContactError::PayloadExpired)
+        ));
+
+        // Pending exchanges are dropped once the clock passes ten minutes
+        let (exchange_id, _) = store.start_qr_exchange(None);
+        clock.advance(601);
+        store.start_qr_exchange(None);
+        assert!(store.get_pending_exchange(&exchange_id).is_none());
+    }
+
+    #[test]
+    fn test_pairing_code_exchange_roundtrip() {
+        let mut alice = ContactStore::new();
+        let mut bob = ContactStore::new();
+        let alice_offer = alice.start_code_exchange();
+        let bob_offer = bob.start_code_exchange();
+        assert_eq!(alice_offer.code.len(), 13);
+
+        // Each side finds the other's offer at the mailbox of the code heard;
+        // transcription tolerates spaces in place of dashes
+        let heard = alice_offer.code.replace('-', " ");
+        assert_eq!(pairing_mailbox(&heard).unwrap(), alice_offer.mailbox_id);
+        let (bob_sas, bob_secret) = bob
+            .complete_code_exchange(&bob_offer.exchange_id, &heard, &alice_offer.offer)
+            .unwrap();
+        let (alice_sas, alice_secret) = alice
+            .complete_code_exchange(&alice_offer.exchange_id, &bob_offer.code, &bob_offer.offer)
+            .unwrap();
+        assert_eq!(alice_secret, bob_secret);
+        assert_eq!(alice_sas, bob_sas);
+
+        // Identity keys travel in the offers and are covered by the SAS
+        let mut carol = ContactStore::new();
+        carol.set_identity_key([0x11u8; 32]);
+        let mut dave = ContactStore::new();
+        dave.set_identity_key([0x22u8; 32]);
+        let carol_offer = carol.start_code_exchange();
+        let dave_offer = dave.start_code_exchange();
+        let (carol_sas, _) = carol
+            .complete_code_exchange(
+                &carol_offer.exchange_id,
+                &dave_offer.code,
+                &dave_offer.offer,
+            )
+            .unwrap();
+        let (dave_sas, _) = dave
+            .complete_code_exchange(
+                &dave_offer.exchange_id,
+                &carol_offer.code,
+                &carol_offer.offer,
+            )
+            .unwrap();
+        assert_eq!(carol_sas, dave_sas);
+        let dave_contact = carol
+            .confirm_code_sas(
+                &carol_offer.exchange_id,
+                &dave_offer.code,
+                &dave_offer.offer,
+                "Dave".into(),
+            )
+            .unwrap();
+        assert_eq!(dave_contact.decode_identity_key(), Some([0x22u8; 32]));
+
+        let contact = alice
+            .confirm_code_sas(
+                &alice_offer.exchange_id,
+                &bob_offer.code,
+                &bob_offer.offer,
+                "Bob".into(),
+            )
+            .unwrap();
+        assert_eq!(contact.verification, VerificationStatus::SasConfirmed);
+        assert_eq!(
+            contact.public_key,
+            open_pairing_offer(&bob_offer.code, &bob_offer.offer).unwrap()
+        );
+        assert!(alice
+            .get_pending_exchange(&alice_offer.exchange_id)
+            .is_none());
+
+        // An offer does not open under another code
+        assert!(matches!(
+            open_pairing_offer(&alice_offer.code, &bob_offer.offer),
+            Err(ContactError::InvalidPayload)
+        ));
+    }
+
+    #[test]
+    fn test_pairing_code_typo_rejected() {
+        let code = encode_pairing_code(4_213_065_535);
+        assert_eq!(decode_pairing_code(&code).unwrap(), 4_213_065_535);
+
+        // Every single-digit change is caught by the check digit
+        for (i, c) in code.char_indices().filter(|(_, c)| c.is_ascii_digit()) {
+            let digit = c.to_digit(10).unwrap();
+            for wrong in (0..10).filter(|&d| d != digit) {
+                let mut typo = code.clone();
+                typo.replace_range(i..i + 1, &wrong.to_string());
+                assert!(matches!(
+                    decode_pairing_code(&typo),
+                    Err(ContactError::PairingCodeMismatch)
+                ));
+            }
+        }
+
+        // Dropped digits and stray letters are malformed, not mismatched
+        assert!(matches!(
+            decode_pairing_code(&code[1..]),
+            Err(ContactError::InvalidPairingCode)
+        ));
+        let mut letter = code.clone();
+        letter.replace_range(0..1, "O");
+        assert!(matches!(
+            decode_pairing_code(&letter),
+            Err(ContactError::InvalidPairingCode)
+        ));
+    }
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = [1u8; 32];
+        let bob = [2u8; 32];
+        let number = safety_number(&alice, &bob);
+
+        assert_eq!(number, safety_number(&bob, &alice));
+        assert_eq!(number.chars().filter(|c| c.is_ascii_digit()).count(), 60);
+        assert_eq!(number.split(' ').count(), 12);
+    }
+
+    #[test]
+    fn test_safety_number_changes_with_either_key() {
+        let alice = [1u8; 32];
+        let bob = [2u8; 32];
+        let mut other = bob;
+        other[31] ^= 1;
+        let number = safety_number(&alice, &bob);
+
+        assert_ne!(number, safety_number(&alice, &other));
+        assert_ne!(number, safety_number(&other, &bob));
+    }
+
     #[test]
     fn test_qr_payload_roundtrip() {
         let pk = [1u8; 32];
@@ -695,6 +2640,96 @@ mod tests {
         assert_eq!(parsed.decode_kem_pubkey().unwrap().unwrap(), kem);
     }
 
+    #[test]
+    fn test_compact_qr_payload_fits_budget() {
+        let pk = [1u8; 32];
+        let kem = vec![2u8; 1568];
+
+        let full = QrPayload::new(&pk, Some(&kem), 300).to_json().unwrap();
+        let compact = QrPayload::new_compact(&pk, &kem, 300);
+        let compact_json = compact.to_json().unwrap();
+
+        assert!(full.len() > max_qr_bytes());
+        assert!(compact_json.len() < max_qr_bytes());
+        assert!(!compact_json.contains('='));
+
+        let parsed = QrPayload::from_json(&compact_json).unwrap();
+        assert_eq!(parsed.decode_public_key().unwrap(), pk);
+        assert!(parsed.decode_kem_pubkey().unwrap().is_none());
+        assert!(parsed.verify_kem_commitment(&kem));
+        assert!(!parsed.verify_kem_commitment(&[3u8; 1568]));
+    }
+
+    #[test]
+    fn test_qr_exchange_within_budget_falls_back_to_compact() {
+        let mut store = ContactStore::new();
+        let kem = vec![2u8; ML_KEM_PUBKEY_LEN];
+
+        let (_, payload) = store
+            .start_qr_exchange_within(&kem, max_qr_bytes())
+            .unwrap();
+        assert!(payload.decode_kem_pubkey().unwrap().is_none());
+        assert!(payload.verify_kem_commitment(&kem));
+
+        let (_, payload) = store
+            .start_qr_exchange_within(&kem, MAX_QR_PAYLOAD_LEN)
+            .unwrap();
+        assert_eq!(payload.decode_kem_pubkey().unwrap().unwrap(), kem);
+        assert!(payload.to_json().unwrap().len() <= MAX_QR_PAYLOAD_LEN);
+    }
+
+    #[test]
+    fn test_oversized_qr_payload_rejected() {
+        let huge = "A".repeat(1_000_000);
+        assert!(matches!(
+            QrPayload::from_json(&huge),
+            Err(ContactError::InvalidPayload)
+        ));
+
+        // A kpk longer than an ML-KEM-1024 key, in a payload that still
+        // fits in a QR code
+        let mut payload = QrPayload::new(&[1u8; 32], None, 300);
+        payload.kpk = Some(base64_encode(&[2u8; ML_KEM_PUBKEY_LEN + 3]));
+        let json = payload.to_json().unwrap();
+        assert!(json.len() <= MAX_QR_PAYLOAD_LEN);
+        assert!(matches!(
+            QrPayload::from_json(&json),
+            Err(ContactError::InvalidPayload)
+        ));
+        assert!(matches!(
+            payload.decode_kem_pubkey(),
+            Err(ContactError::InvalidPayload)
+        ));
+
+        let full = QrPayload::new(&[1u8; 32], Some(&[2u8; ML_KEM_PUBKEY_LEN]), 300);
+        let parsed = QrPayload::from_json(&full.to_json().unwrap()).unwrap();
+        assert_eq!(
+            parsed.decode_kem_pubkey().unwrap().unwrap().len(),
+            ML_KEM_PUBKEY_LEN
+        );
+    }
+
+    #[test]
+    fn test_oversized_invite_rejected() {
+        let huge = "A".repeat(1_000_000);
+        assert!(matches!(
+            InviteBlob::from_base64(&huge),
+            Err(ContactError::InvalidPayload)
+        ));
+
+        let oversized = InviteBlob::new([3u8; 32], vec![4u8; ML_KEM_PUBKEY_LEN + 1], 3600);
+        let encoded = oversized.to_base64().unwrap();
+        assert!(encoded.len() <= MAX_INVITE_ENCODED_LEN);
+        assert!(matches!(
+            InviteBlob::from_base64(&encoded),
+            Err(ContactError::InvalidPayload)
+        ));
+
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let full = InviteBlob::new_signed(&signer, [3u8; 32], vec![4u8; ML_KEM_PUBKEY_LEN], 3600);
+        assert!(InviteBlob::from_base64(&full.to_base64().unwrap()).is_ok());
+    }
+
     #[test]
     fn test_invite_blob_roundtrip() {
         let pk = [3u8; 32];
@@ -704,17 +2739,95 @@ mod tests {
         let encoded = invite.to_base64().unwrap();
         let decoded = InviteBlob::from_base64(&encoded).unwrap();
 
-        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.version, INVITE_VERSION);
         assert_eq!(decoded.sender_pubkey, pk);
         assert_eq!(decoded.sender_kem_pk, kem);
     }
 
+    #[test]
+    fn test_validate_signed_invite() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let invite = InviteBlob::new_signed(&signer, [3u8; 32], vec![4u8; 64], 3600);
+        let invite = InviteBlob::from_base64(&invite.to_base64().unwrap()).unwrap();
+        let store = ContactStore::new();
+
+        let preview = store.validate_invite(&invite).unwrap();
+        assert!(preview.signature_valid);
+        assert_eq!(preview.expiry, invite.expiry);
+        assert_eq!(preview.fingerprint, key_fingerprint(&[3u8; 32]));
+
+        // Unsigned invites validate, but are reported as such
+        let unsigned = InviteBlob::new([3u8; 32], vec![], 3600);
+        assert!(!store.validate_invite(&unsigned).unwrap().signature_valid);
+    }
+
+    #[test]
+    fn test_signed_invite_covers_every_key() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let invite = InviteBlob::new_signed(&signer, [3u8; 32], vec![4u8; 64], 3600);
+        let store = ContactStore::new();
+
+        let mut swapped_kem = invite.clone();
+        swapped_kem.sender_kem_pk = vec![5u8; 64];
+        assert!(matches!(
+            store.validate_invite(&swapped_kem),
+            Err(ContactError::InvalidSignature)
+        ));
+
+        let mut stripped_kem = invite.clone();
+        stripped_kem.sender_kem_pk.clear();
+        assert!(matches!(
+            store.validate_invite(&stripped_kem),
+            Err(ContactError::InvalidSignature)
+        ));
+
+        let mut swapped_x25519 = invite;
+        swapped_x25519.sender_pubkey = [6u8; 32];
+        assert!(matches!(
+            store.validate_invite(&swapped_x25519),
+            Err(ContactError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_validate_invite_rejects_before_import() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let mut store = ContactStore::new();
+
+        let mut expired = InviteBlob::new([3u8; 32], vec![], 3600);
+        expired.expiry = 0;
+        assert!(matches!(
+            store.validate_invite(&expired),
+            Err(ContactError::PayloadExpired)
+        ));
+
+        let mut tampered = InviteBlob::new_signed(&signer, [3u8; 32], vec![], 3600);
+        tampered.sender_pubkey = [9u8; 32];
+        assert!(matches!(
+            store.validate_invite(&tampered),
+            Err(ContactError::InvalidSignature)
+        ));
+
+        let mut future = InviteBlob::new([3u8; 32], vec![], 3600);
+        future.version = INVITE_VERSION + 1;
+        assert!(matches!(
+            store.validate_invite(&future),
+            Err(ContactError::UnsupportedVersion(v)) if v == INVITE_VERSION + 1
+        ));
+
+        // import_invite runs the same checks and leaves the store untouched
+        for invite in [&expired, &tampered, &future] {
+            assert!(store.import_invite(invite, "Mallory".into()).is_err());
+        }
+        assert!(store.list_contacts().is_empty());
+    }
+
     #[test]
     fn test_contact_store_qr_exchange_flow() {
         let mut store = ContactStore::new();
 
         // Start exchange
-        let (exchange_id, payload) = store.start_qr_exchange(None);
+        let (exchange_id, _payload) = store.start_qr_exchange(None);
         assert!(!exchange_id.is_empty());
 
         // Simulate peer's QR code
@@ -735,12 +2848,96 @@ mod tests {
             .confirm_sas(&exchange_id2, &peer_payload, "Alice".into())
             .unwrap();
         assert_eq!(contact.alias, "Alice");
-        assert!(contact.verified);
+        assert_eq!(contact.verification, VerificationStatus::SasConfirmed);
 
         // Contact should be in store
         assert_eq!(store.list_contacts().len(), 1);
     }
 
+    #[test]
+    fn test_qr_pairing_yields_matching_safety_numbers() {
+        let (alice_ik, bob_ik) = ([0x11u8; 32], [0x22u8; 32]);
+        let mut alice = ContactStore::new();
+        alice.set_identity_key(alice_ik);
+        let mut bob = ContactStore::new();
+        bob.set_identity_key(bob_ik);
+
+        let (alice_exchange, alice_qr) = alice.start_qr_exchange(None);
+        let (bob_exchange, bob_qr) = bob.start_qr_exchange(None);
+        let (alice_sas, _) = alice.process_scanned_qr(&alice_exchange, &bob_qr).unwrap();
+        let (bob_sas, _) = bob.process_scanned_qr(&bob_exchange, &alice_qr).unwrap();
+        assert_eq!(alice_sas, bob_sas);
+
+        let bob_contact = alice
+            .confirm_sas(&alice_exchange, &bob_qr, "Bob".into())
+            .unwrap();
+        let alice_contact = bob
+            .confirm_sas(&bob_exchange, &alice_qr, "Alice".into())
+            .unwrap();
+        assert_eq!(bob_contact.decode_identity_key(), Some(bob_ik));
+        assert_eq!(alice_contact.decode_identity_key(), Some(alice_ik));
+
+        // The ephemeral keys differ, but both sides compute the same number
+        assert_ne!(bob_contact.public_key, bob_ik);
+        assert_eq!(
+            safety_number(&alice_ik, &bob_contact.decode_identity_key().unwrap()),
+            safety_number(&bob_ik, &alice_contact.decode_identity_key().unwrap())
+        );
+
+        // A swapped identity key changes the SAS
+        let (mallory_exchange, _) = alice.start_qr_exchange(None);
+        let forged = bob_qr.clone().with_identity_key(&[0x33u8; 32]);
+        let (forged_sas, _) = alice
+            .process_scanned_qr(&mallory_exchange, &forged)
+            .unwrap();
+        let (honest_sas, _) = alice
+            .process_scanned_qr(&mallory_exchange, &bob_qr)
+            .unwrap();
+        assert_ne!(forged_sas, honest_sas);
+    }
+
+    #[test]
+    fn test_empty_alias_is_rejected() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([6u8; 32], vec![], 3600);
+        assert!(matches!(
+            store.import_invite(&invite, "   \t\n".into()),
+            Err(ContactError::InvalidAlias)
+        ));
+
+        // A rejected alias leaves the pending exchange usable
+        let (exchange_id, _) = store.start_qr_exchange(None);
+        let peer_payload = QrPayload::new(&[5u8; 32], None, 300);
+        assert!(matches!(
+            store.confirm_sas(&exchange_id, &peer_payload, String::new()),
+            Err(ContactError::InvalidAlias)
+        ));
+        let contact = store
+            .confirm_sas(&exchange_id, &peer_payload, "  Alice  ".into())
+            .unwrap();
+        assert_eq!(contact.alias, "Alice");
+        assert_eq!(store.list_contacts().len(), 1);
+    }
+
+    #[test]
+    fn test_long_alias_is_truncated() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([6u8; 32], vec![], 3600);
+        let contact = store.import_invite(&invite, "é".repeat(1_000_000)).unwrap();
+        assert_eq!(contact.alias.chars().count(), MAX_ALIAS_CHARS);
+    }
+
+    #[test]
+    fn test_alias_is_nfc_normalized() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([6u8; 32], vec![], 3600);
+        // "e" + combining acute accent composes to a single "é"
+        let contact = store
+            .import_invite(&invite, "Ame\u{301}lie".into())
+            .unwrap();
+        assert_eq!(contact.alias, "Am\u{e9}lie");
+    }
+
     #[test]
     fn test_contact_store_invite_flow() {
         let mut store = ContactStore::new();
@@ -753,11 +2950,372 @@ mod tests {
         // Import invite (simulating receiver)
         let contact = store.import_invite(&invite, "Bob".into()).unwrap();
         assert_eq!(contact.alias, "Bob");
-        assert!(!contact.verified); // Pending ACK
+        assert_eq!(contact.verification, VerificationStatus::Unverified); // Pending ACK
 
         assert_eq!(store.list_contacts().len(), 1);
     }
 
+    /// An X25519 identity secret and its public key
+    fn identity_key(byte: u8) -> ([u8; 32], [u8; 32]) {
+        let secret = [byte; 32];
+        let public =
+            x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(secret)).to_bytes();
+        (secret, public)
+    }
+
+    #[test]
+    fn test_invite_ack_marks_contact_acknowledged() {
+        let (inviter_secret, inviter_pk) = identity_key(6);
+        let (responder_secret, _) = identity_key(9);
+        let mut inviter = ContactStore::new();
+        let mut responder = ContactStore::new();
+
+        // Invite -> import
+        let invite = inviter.generate_invite(inviter_pk, vec![7u8; 150], 24);
+        let contact = responder.import_invite(&invite, "Bob".into()).unwrap();
+        assert_eq!(contact.verification, VerificationStatus::Unverified);
+        assert!(inviter.has_pending_invite(&invite.mailbox_id));
+
+        // ACK -> the inviter's pending invite is cleared
+        let (target, ack) = responder.generate_ack(&invite, &responder_secret).unwrap();
+        assert_eq!(target, invite.mailbox_id);
+        let AckOutcome::Replied { mailbox_id, reply } =
+            inviter.process_ack(&ack, &inviter_secret).unwrap()
+        else {
+            panic!("the inviter replies to the ACK");
+        };
+        assert_eq!(mailbox_id, invite.mailbox_id);
+        assert!(!inviter.has_pending_invite(&invite.mailbox_id));
+
+        // The inviter's reply marks the responder's contact acknowledged
+        let AckOutcome::Acknowledged(acked) =
+            responder.process_ack(&reply, &responder_secret).unwrap()
+        else {
+            panic!("the responder acknowledges the contact");
+        };
+        assert_eq!(acked.id, contact.id);
+        assert_eq!(
+            responder.get_contact(&contact.id).unwrap().verification,
+            VerificationStatus::Acknowledged
+        );
+    }
+
+    #[test]
+    fn test_reflected_ack_rejected() {
+        let (inviter_secret, inviter_pk) = identity_key(6);
+        let (responder_secret, _) = identity_key(9);
+        let mut inviter = ContactStore::new();
+        let mut responder = ContactStore::new();
+        let invite = inviter.generate_invite(inviter_pk, vec![], 24);
+        let contact = responder.import_invite(&invite, "Bob".into()).unwrap();
+
+        // The responder's own ACK, reflected back, does not acknowledge it
+        let (_, ack) = responder.generate_ack(&invite, &responder_secret).unwrap();
+        assert!(matches!(
+            responder.process_ack(&ack, &responder_secret),
+            Err(ContactError::InvalidPayload)
+        ));
+        assert_eq!(
+            responder.get_contact(&contact.id).unwrap().verification,
+            VerificationStatus::Unverified
+        );
+
+        // Nor does the inviter accept its own reply in place of an ACK
+        let AckOutcome::Replied { reply, .. } = inviter.process_ack(&ack, &inviter_secret).unwrap()
+        else {
+            panic!("the inviter replies to the ACK");
+        };
+        inviter
+            .pending_invites
+            .insert(hex::encode(invite.mailbox_id), invite.clone());
+        assert!(matches!(
+            inviter.process_ack(&reply, &inviter_secret),
+            Err(ContactError::InvalidPayload)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_ack_rejected() {
+        let (inviter_secret, inviter_pk) = identity_key(6);
+        let (responder_secret, _) = identity_key(9);
+        let mut inviter = ContactStore::new();
+        let invite = inviter.generate_invite(inviter_pk, vec![], 24);
+
+        let (_, mut ack) = ContactStore::new()
+            .generate_ack(&invite, &responder_secret)
+            .unwrap();
+        let last = ack.len() - 1;
+        ack[last] ^= 0xFF;
+
+        assert!(matches!(
+            inviter.process_ack(&ack, &inviter_secret),
+            Err(ContactError::InvalidPayload)
+        ));
+        assert!(inviter.has_pending_invite(&invite.mailbox_id));
+
+        // An ACK for an unknown invite is not found
+        let other = InviteBlob::new(identity_key(1).1, vec![], 3600);
+        let (_, ack) = ContactStore::new()
+            .generate_ack(&other, &responder_secret)
+            .unwrap();
+        assert!(matches!(
+            inviter.process_ack(&ack, &inviter_secret),
+            Err(ContactError::ExchangeNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_ack_forged_from_public_invite_rejected() {
+        let (_, inviter_pk) = identity_key(6);
+        let (responder_secret, responder_pk) = identity_key(9);
+        let (attacker_secret, _) = identity_key(13);
+        let invite = ContactStore::new().generate_invite(inviter_pk, vec![], 24);
+        let mut responder = ContactStore::new();
+        let contact = responder.import_invite(&invite, "Bob".into()).unwrap();
+
+        // An observer of the invite keys the ACK with their own secret
+        let (_, forged) = ContactStore::new()
+            .generate_ack(&invite, &attacker_secret)
+            .unwrap();
+        assert!(matches!(
+            responder.process_ack(&forged, &responder_secret),
+            Err(ContactError::InvalidPayload)
+        ));
+
+        // Naming the responder's key does not help without its secret
+        let mut relabelled = forged.clone();
+        relabelled[32..64].copy_from_slice(&responder_pk);
+        assert!(matches!(
+            responder.process_ack(&relabelled, &responder_secret),
+            Err(ContactError::InvalidPayload)
+        ));
+        assert_eq!(
+            responder.get_contact(&contact.id).unwrap().verification,
+            VerificationStatus::Unverified
+        );
+
+        // A low-order inviter key is refused outright
+        let weak = InviteBlob::new([0u8; 32], vec![], 3600);
+        assert!(matches!(
+            ContactStore::new().generate_ack(&weak, &responder_secret),
+            Err(ContactError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_revoked_contact_excluded_from_active() {
+        let mut store = ContactStore::new();
+
+        let alice = store
+            .import_invite(&InviteBlob::new([1u8; 32], vec![], 3600), "Alice".into())
+            .unwrap();
+        let bob = store
+            .import_invite(&InviteBlob::new([2u8; 32], vec![], 3600), "Bob".into())
+            .unwrap();
+
+        store.revoke_contact(&alice.id).unwrap();
+
+        // The record is kept but filtered out and blocked from new sessions
+        assert_eq!(store.list_contacts().len(), 2);
+        let active = store.list_active_contacts();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].id, bob.id);
+        assert!(!store.session_allowed(&alice.session_id));
+        assert!(store.session_allowed(&bob.session_id));
+
+        // Revocation cannot be undone
+        assert!(matches!(
+            store.set_verification(&alice.id, VerificationStatus::SasConfirmed),
+            Err(ContactError::ContactRevoked)
+        ));
+        assert!(matches!(
+            store.revoke_contact("missing"),
+            Err(ContactError::ContactNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_verification_status_serde_roundtrip() {
+        let mut store = ContactStore::new();
+        let contact = store
+            .import_invite(&InviteBlob::new([1u8; 32], vec![], 3600), "Alice".into())
+            .unwrap();
+
+        for status in [
+            VerificationStatus::Unverified,
+            VerificationStatus::Acknowledged,
+            VerificationStatus::SasConfirmed,
+            VerificationStatus::Revoked,
+        ] {
+            store.set_verification(&contact.id, status).unwrap();
+            let json = serde_json::to_string(store.get_contact(&contact.id).unwrap()).unwrap();
+            let parsed: Contact = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed.verification, status);
+        }
+    }
+
+    #[test]
+    fn test_legacy_verified_flag_maps_to_status() {
+        let mut store = ContactStore::new();
+        let contact = store
+            .import_invite(&InviteBlob::new([1u8; 32], vec![], 3600), "Alice".into())
+            .unwrap();
+        let mut json = serde_json::to_value(&contact).unwrap();
+        json.as_object_mut().unwrap().remove("verification");
+
+        for (verified, status) in [
+            (true, VerificationStatus::Acknowledged),
+            (false, VerificationStatus::Unverified),
+        ] {
+            json["verified"] = verified.into();
+            let parsed: Contact = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(parsed.verification, status);
+        }
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([3u8; 32], vec![4u8; 64], 3600);
+        let contact = store.import_invite(&invite, "Alice".into()).unwrap();
+        store.revoke_contact(&contact.id).unwrap();
+        let bundle = store.export_bundle("correct horse");
+
+        let mut other = ContactStore::new();
+        assert_eq!(other.import_bundle(&bundle, "correct horse").unwrap(), 1);
+
+        let restored = other.get_contact(&contact.id).unwrap();
+        assert_eq!(restored.alias, "Alice");
+        assert_eq!(restored.public_key, [3u8; 32]);
+        assert_eq!(restored.kem_pubkey, vec![4u8; 64]);
+        assert!(restored.is_revoked());
+    }
+
+    #[test]
+    fn test_bundle_wrong_passphrase_rejected() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([3u8; 32], vec![], 3600);
+        store.import_invite(&invite, "Alice".into()).unwrap();
+        let mut bundle = store.export_bundle("correct horse");
+
+        let mut other = ContactStore::new();
+        assert!(matches!(
+            other.import_bundle(&bundle, "battery staple"),
+            Err(ContactError::BundleDecryptionFailed)
+        ));
+        assert!(other.list_contacts().is_empty());
+
+        // The header is authenticated too
+        bundle[BUNDLE_MAGIC.len() + 1] ^= 0xFF;
+        assert!(other.import_bundle(&bundle, "correct horse").is_err());
+        assert!(matches!(
+            other.import_bundle(b"not a bundle", "correct horse"),
+            Err(ContactError::InvalidBundle)
+        ));
+    }
+
+    #[test]
+    fn test_bundle_import_skips_duplicates() {
+        let mut store = ContactStore::new();
+        for (i, alias) in ["Alice", "Bob"].into_iter().enumerate() {
+            let invite = InviteBlob::new([i as u8 + 1; 32], vec![], 3600);
+            store.import_invite(&invite, alias.into()).unwrap();
+        }
+        let bundle = store.export_bundle("pass");
+
+        // The target already knows Alice's key under another alias
+        let mut other = ContactStore::new();
+        let invite = InviteBlob::new([1u8; 32], vec![], 3600);
+        other
+            .import_invite(&invite, "Alice (phone)".into())
+            .unwrap();
+
+        assert_eq!(other.import_bundle(&bundle, "pass").unwrap(), 1);
+        let contacts = other.list_contacts();
+        assert_eq!(contacts.len(), 2);
+        assert!(contacts.iter().any(|c| c.alias == "Alice (phone)"));
+        assert!(contacts.iter().any(|c| c.alias == "Bob"));
+
+        // Importing again adds nothing
+        assert_eq!(other.import_bundle(&bundle, "pass").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_contact_note_roundtrip() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([3u8; 32], vec![], 3600);
+        let contact = store.import_invite(&invite, "Alice".into()).unwrap();
+        let key = [5u8; 32];
+
+        assert_eq!(store.get_note(&contact.id, &key).unwrap(), None);
+        store
+            .set_note(&contact.id, "Met at the conference, SAS checked", &key)
+            .unwrap();
+        assert_eq!(
+            store.get_note(&contact.id, &key).unwrap().as_deref(),
+            Some("Met at the conference, SAS checked")
+        );
+
+        // The record only carries ciphertext
+        let json = serde_json::to_string(store.get_contact(&contact.id).unwrap()).unwrap();
+        assert!(!json.contains("conference"));
+        assert!(matches!(
+            store.set_note("missing", "note", &key),
+            Err(ContactError::ContactNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_contact_note_wrong_key_rejected() {
+        let mut store = ContactStore::new();
+        for (i, alias) in ["Alice", "Bob"].into_iter().enumerate() {
+            let invite = InviteBlob::new([i as u8 + 1; 32], vec![], 3600);
+            let contact = store.import_invite(&invite, alias.into()).unwrap();
+            store.set_note(&contact.id, "private", &[5u8; 32]).unwrap();
+        }
+        let contacts = store.list_contacts();
+        let (first, second) = (&contacts[0], &contacts[1]);
+
+        assert!(matches!(
+            store.get_note(&first.id, &[6u8; 32]),
+            Err(ContactError::NoteDecryptionFailed)
+        ));
+
+        // Each contact's note has its own key: a note moved to another
+        // contact does not open
+        let moved = first.encrypted_notes.clone();
+        store.contacts.get_mut(&second.id).unwrap().encrypted_notes = moved;
+        assert!(matches!(
+            store.get_note(&second.id, &[5u8; 32]),
+            Err(ContactError::NoteDecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_contact_notes_left_out_of_default_export() {
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new([3u8; 32], vec![], 3600);
+        let contact = store.import_invite(&invite, "Alice".into()).unwrap();
+        store.set_note(&contact.id, "private", &[5u8; 32]).unwrap();
+
+        let mut other = ContactStore::new();
+        other
+            .import_bundle(&store.export_bundle("pass"), "pass")
+            .unwrap();
+        let restored = other.get_contact(&contact.id).unwrap();
+        assert!(restored.encrypted_notes.is_none());
+        assert_eq!(other.get_note(&contact.id, &[5u8; 32]).unwrap(), None);
+
+        let mut other = ContactStore::new();
+        other
+            .import_bundle(&store.export_bundle_with_notes("pass"), "pass")
+            .unwrap();
+        assert_eq!(
+            other.get_note(&contact.id, &[5u8; 32]).unwrap().as_deref(),
+            Some("private")
+        );
+    }
+
     #[test]
     fn test_contact_deletion() {
         let mut store = ContactStore::new();
@@ -771,4 +3329,154 @@ mod tests {
         store.delete_contact(&contact.id);
         assert_eq!(store.list_contacts().len(), 0);
     }
+
+    /// Contact imported from an invite, with `signer` pinned as its identity key
+    fn contact_with_signer(
+        store: &mut ContactStore,
+        signer: &ed25519_dalek::SigningKey,
+    ) -> Contact {
+        let invite = InviteBlob::new([3u8; 32], vec![4u8; 64], 3600);
+        let contact = store.import_invite(&invite, "Alice".into()).unwrap();
+        store
+            .set_signing_key(&contact.id, &signer.verifying_key())
+            .unwrap();
+        store
+            .set_verification(&contact.id, VerificationStatus::SasConfirmed)
+            .unwrap();
+        contact
+    }
+
+    #[test]
+    fn test_key_update_rotates_keys() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let mut store = ContactStore::new();
+        let contact = contact_with_signer(&mut store, &signer);
+
+        let update = KeyUpdate::new_signed(&signer, [9u8; 32], vec![8u8; 64]);
+        let update = KeyUpdate::from_base64(&update.to_base64().unwrap()).unwrap();
+        let updated = store.apply_key_update(&contact.id, &update).unwrap();
+
+        assert_eq!(updated.public_key, [9u8; 32]);
+        assert_eq!(updated.kem_pubkey, vec![8u8; 64]);
+        assert_eq!(updated.session_id, contact.session_id);
+
+        // The same update cannot be replayed
+        assert!(matches!(
+            store.apply_key_update(&contact.id, &update),
+            Err(ContactError::StaleKeyUpdate)
+        ));
+    }
+
+    #[test]
+    fn test_forged_key_update_rejected() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let forger = ed25519_dalek::SigningKey::from_bytes(&[6u8; 32]);
+        let mut store = ContactStore::new();
+        let contact = contact_with_signer(&mut store, &signer);
+
+        let forged = KeyUpdate::new_signed(&forger, [9u8; 32], vec![8u8; 64]);
+        assert!(matches!(
+            store.apply_key_update(&contact.id, &forged),
+            Err(ContactError::InvalidSignature)
+        ));
+
+        let mut tampered = KeyUpdate::new_signed(&signer, [9u8; 32], vec![8u8; 64]);
+        tampered.new_public_key = [10u8; 32];
+        assert!(matches!(
+            store.apply_key_update(&contact.id, &tampered),
+            Err(ContactError::InvalidSignature)
+        ));
+
+        let unchanged = store.get_contact(&contact.id).unwrap();
+        assert_eq!(unchanged.public_key, [3u8; 32]);
+        assert!(!unchanged.repair_required);
+    }
+
+    #[test]
+    fn test_key_update_requires_repairing() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let mut store = ContactStore::new();
+        let contact = contact_with_signer(&mut store, &signer);
+
+        let update = KeyUpdate::new_signed(&signer, [9u8; 32], vec![8u8; 64]);
+        store.apply_key_update(&contact.id, &update).unwrap();
+
+        let updated = store.get_contact(&contact.id).unwrap();
+        assert!(updated.repair_required);
+        assert_eq!(updated.verification, VerificationStatus::Unverified);
+
+        // Without a pinned identity key nothing can be verified
+        let other = store
+            .import_invite(&InviteBlob::new([5u8; 32], vec![], 3600), "Bob".into())
+            .unwrap();
+        assert!(matches!(
+            store.apply_key_update(&other.id, &update),
+            Err(ContactError::NoSigningKey)
+        ));
+    }
+
+    #[test]
+    fn test_signed_invite_pins_signing_key() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let mut store = ContactStore::new();
+        let invite = InviteBlob::new_signed(&signer, [3u8; 32], vec![4u8; 64], 3600);
+        let contact = store.import_invite(&invite, "Alice".into()).unwrap();
+
+        let update = KeyUpdate::new_signed(&signer, [9u8; 32], vec![8u8; 64]);
+        let updated = store.apply_key_update(&contact.id, &update).unwrap();
+        assert_eq!(updated.public_key, [9u8; 32]);
+    }
+
+    #[test]
+    fn test_compact_qr_contact_checks_fetched_kem_key() {
+        let kem = vec![2u8; ML_KEM_PUBKEY_LEN];
+        let mut store = ContactStore::new();
+        let (exchange_id, _) = store.start_qr_exchange(None);
+        let peer = EphemeralKeypair::generate();
+        let peer_payload = QrPayload::new_compact(&peer.public_key, &kem, 300);
+        let peer_payload = QrPayload::from_json(&peer_payload.to_json().unwrap()).unwrap();
+
+        let contact = store
+            .confirm_sas(&exchange_id, &peer_payload, "Alice".into())
+            .unwrap();
+        assert!(contact.kem_pubkey.is_empty());
+        assert!(contact.kem_commitment.is_some());
+
+        let mut forged = kem.clone();
+        forged[0] ^= 1;
+        assert!(matches!(
+            store.apply_committed_kem_key(&contact.id, &forged),
+            Err(ContactError::KemCommitmentMismatch)
+        ));
+        assert!(store
+            .get_contact(&contact.id)
+            .unwrap()
+            .kem_pubkey
+            .is_empty());
+
+        let updated = store.apply_committed_kem_key(&contact.id, &kem).unwrap();
+        assert_eq!(updated.kem_pubkey, kem);
+        assert!(updated.kem_commitment.is_none());
+        assert!(matches!(
+            store.apply_committed_kem_key(&contact.id, &kem),
+            Err(ContactError::NoKemCommitment)
+        ));
+    }
+
+    #[test]
+    fn test_qr_exchange_pins_signing_key() {
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let mut store = ContactStore::new();
+        let (exchange_id, _) = store.start_qr_exchange(None);
+        let peer = EphemeralKeypair::generate();
+        let peer_payload =
+            QrPayload::new(&peer.public_key, None, 300).with_signing_key(&signer.verifying_key());
+        let peer_payload = QrPayload::from_json(&peer_payload.to_json().unwrap()).unwrap();
+
+        let contact = store
+            .confirm_sas(&exchange_id, &peer_payload, "Alice".into())
+            .unwrap();
+        let update = KeyUpdate::new_signed(&signer, [9u8; 32], vec![8u8; 64]);
+        store.apply_key_update(&contact.id, &update).unwrap();
+    }
 }
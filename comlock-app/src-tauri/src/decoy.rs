@@ -2,8 +2,14 @@
 //!
 //! Pre-generated innocent content displayed after duress wipe.
 //! This creates plausible deniability by showing "normal" app usage.
+//!
+//! Users can author their own decoy conversations, stored encrypted under
+//! the duress PIN; the built-in content is only shown when there are none.
 
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::storage::{SecureStorage, StorageError};
 
 // ============================================================================
 // DECOY DATA STRUCTURES
@@ -217,6 +223,48 @@ impl DecoyVault {
         }
     }
 
+    /// Encrypt the vault under `pin` and write it to `path`
+    pub fn save_encrypted(&self, path: &Path, pin: &str) -> Result<(), StorageError> {
+        let json = serde_json::to_vec(self).map_err(|_| StorageError::SerializationFailed)?;
+        SecureStorage::write_encrypted(path, pin, &json)
+    }
+
+    /// Load a vault written by `save_encrypted`
+    pub fn load_encrypted(path: &Path, pin: &str) -> Result<Self, StorageError> {
+        let json = SecureStorage::read_encrypted(path, pin)?;
+        serde_json::from_slice(&json).map_err(|_| StorageError::CorruptedData)
+    }
+
+    /// Load the user's vault, or the built-in content if there is none.
+    ///
+    /// Any failure also falls back, so duress mode always has something
+    /// believable to show.
+    pub fn load_or_default(path: &Path, pin: &str) -> Self {
+        match Self::load_encrypted(path, pin) {
+            Ok(vault) if !vault.conversations.is_empty() => vault,
+            _ => Self::load_default(),
+        }
+    }
+
+    /// Add a conversation, replacing any with the same contact id
+    pub fn upsert_conversation(&mut self, conversation: DecoyConversation) {
+        match self
+            .conversations
+            .iter_mut()
+            .find(|c| c.contact.id == conversation.contact.id)
+        {
+            Some(existing) => *existing = conversation,
+            None => self.conversations.push(conversation),
+        }
+    }
+
+    /// Remove the conversation with a contact; returns whether it existed
+    pub fn remove_conversation(&mut self, contact_id: &str) -> bool {
+        let before = self.conversations.len();
+        self.conversations.retain(|c| c.contact.id != contact_id);
+        self.conversations.len() != before
+    }
+
     /// Get all decoy contacts for display
     pub fn get_contacts(&self) -> Vec<DecoyContact> {
         self.conversations
@@ -276,4 +324,74 @@ mod tests {
 
         assert!(messages.is_empty());
     }
+
+    fn temp_vault_path() -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("comlock_decoy_test_{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("decoy.enc")
+    }
+
+    fn user_conversation(id: &str, name: &str) -> DecoyConversation {
+        DecoyConversation {
+            contact: DecoyContact {
+                id: id.into(),
+                name: name.into(),
+                avatar_letter: 'J',
+                last_message: "See you at practice".into(),
+                last_message_time: "7:10 PM".into(),
+            },
+            messages: vec![DecoyMessage {
+                id: "j1".into(),
+                text: "See you at practice".into(),
+                sent: false,
+                time: "7:10 PM".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let path = temp_vault_path();
+        let mut vault = DecoyVault::default();
+        vault.upsert_conversation(user_conversation("user_1", "Jordan"));
+        vault.save_encrypted(&path, "9999").unwrap();
+
+        let loaded = DecoyVault::load_encrypted(&path, "9999").unwrap();
+        assert_eq!(loaded.get_contacts()[0].name, "Jordan");
+        assert_eq!(loaded.get_messages("user_1").len(), 1);
+        assert!(DecoyVault::load_encrypted(&path, "0000").is_err());
+
+        // The user vault replaces the built-in content
+        let shown = DecoyVault::load_or_default(&path, "9999");
+        assert_eq!(shown.conversations.len(), 1);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_missing_vault_falls_back_to_default() {
+        let path = temp_vault_path();
+        assert!(matches!(
+            DecoyVault::load_encrypted(&path, "9999"),
+            Err(StorageError::NotFound)
+        ));
+
+        let shown = DecoyVault::load_or_default(&path, "9999");
+        assert!(shown.get_contacts().iter().any(|c| c.name == "Mom"));
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_upsert_and_remove_conversation() {
+        let mut vault = DecoyVault::default();
+        vault.upsert_conversation(user_conversation("user_1", "Jordan"));
+        vault.upsert_conversation(user_conversation("user_1", "Jordan B."));
+        assert_eq!(vault.conversations.len(), 1);
+        assert_eq!(vault.conversations[0].contact.name, "Jordan B.");
+
+        assert!(vault.remove_conversation("user_1"));
+        assert!(!vault.remove_conversation("user_1"));
+    }
 }
@@ -0,0 +1,580 @@
+//! Identity Store for ComLock
+//!
+//! Holds multiple independent identities (personas) on one device.
+//! Each identity owns its own contacts and ratchet sessions, so switching
+//! the active identity hides everything belonging to the others.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use comlock_crypto::RatchetState;
+use serde::Serialize;
+use zeroize::Zeroize;
+
+use crate::contacts::ContactStore;
+use crate::storage::{SecureStorage, SessionArchive, StorageError};
+use crate::Identity;
+
+// ============================================================================
+// SESSIONS
+// ============================================================================
+
+/// A ratchet session and when it was last used
+struct Session {
+    ratchet: RatchetState,
+    last_used: Instant,
+}
+
+/// Ratchet sessions by session ID.
+///
+/// Sessions left unused for too long are evicted by
+/// [`SessionStore::cleanup_idle_sessions`]. With a [`SessionArchive`]
+/// attached they are sealed to disk and reloaded transparently on their
+/// next use, for as long as the process runs; without one they are gone.
+#[derive(Default)]
+pub struct SessionStore {
+    /// Sessions held in memory
+    sessions: HashMap<String, Session>,
+    /// Where evicted sessions are kept, if anywhere
+    archive: Option<SessionArchive>,
+}
+
+impl SessionStore {
+    /// Create an empty session store without an archive
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep sessions evicted from now on in `archive`
+    pub fn set_archive(&mut self, archive: SessionArchive) {
+        self.archive = Some(archive);
+    }
+
+    /// Whether evicted sessions are archived rather than dropped
+    pub fn has_archive(&self) -> bool {
+        self.archive.is_some()
+    }
+
+    /// Add or replace a session
+    pub fn insert(&mut self, session_id: String, ratchet: RatchetState) {
+        if let Some(archive) = &self.archive {
+            let _ = archive.remove(&session_id);
+        }
+        let session = Session {
+            ratchet,
+            last_used: Instant::now(),
+        };
+        if let Some(mut old) = self.sessions.insert(session_id, session) {
+            old.ratchet.zeroize();
+        }
+    }
+
+    /// Get a session for use, reloading it from the archive if it was
+    /// evicted
+    pub fn get_mut(&mut self, session_id: &str) -> Option<&mut RatchetState> {
+        if !self.sessions.contains_key(session_id) {
+            let ratchet = self.archive.as_ref()?.take(session_id).ok()??;
+            self.insert(session_id.to_string(), ratchet);
+        }
+        let session = self.sessions.get_mut(session_id)?;
+        session.last_used = Instant::now();
+        Some(&mut session.ratchet)
+    }
+
+    /// Zeroize and remove a session, archived or not
+    pub fn remove(&mut self, session_id: &str) {
+        if let Some(mut session) = self.sessions.remove(session_id) {
+            session.ratchet.zeroize();
+        }
+        if let Some(archive) = &self.archive {
+            let _ = archive.remove(session_id);
+        }
+    }
+
+    /// Evict every session unused for longer than `max_idle`, returning how
+    /// many were evicted.
+    ///
+    /// Each one is archived if there is an archive, then zeroized in
+    /// memory. A session the archive fails to store stays in memory, to be
+    /// tried again on the next cleanup, rather than being lost.
+    pub fn cleanup_idle_sessions(&mut self, max_idle: Duration) -> usize {
+        let idle: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.last_used.elapsed() > max_idle)
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+
+        idle.iter()
+            .filter(|session_id| self.evict(session_id).is_some())
+            .count()
+    }
+
+    /// Archive a session if there is an archive and remove it from memory,
+    /// returning the zeroized session, or `None` if it is not held or the
+    /// archive failed to store it
+    fn evict(&mut self, session_id: &str) -> Option<Session> {
+        let session = self.sessions.get(session_id)?;
+        if let Some(archive) = &self.archive {
+            archive.store(session_id, &session.ratchet).ok()?;
+        }
+        let mut session = self.sessions.remove(session_id)?;
+        session.ratchet.zeroize();
+        Some(session)
+    }
+
+    /// Number of sessions held in memory
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Whether no session is held in memory
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Zeroize every session and drop the archive with its files
+    pub fn wipe(&mut self) {
+        for (_, mut session) in self.sessions.drain() {
+            session.ratchet.zeroize();
+        }
+        self.archive = None;
+    }
+}
+
+// ============================================================================
+// PERSONA
+// ============================================================================
+
+/// An identity together with the data scoped to it
+pub struct Persona {
+    /// User-chosen label (e.g. "Work", "Personal")
+    pub label: String,
+    /// The identity key material
+    pub identity: Identity,
+    /// Contacts known to this identity
+    pub contacts: ContactStore,
+    /// Active ratchet sessions by session ID
+    pub sessions: SessionStore,
+}
+
+impl Persona {
+    /// Create a persona with no contacts or sessions
+    pub fn new(label: String, identity: Identity) -> Self {
+        let mut contacts = ContactStore::new();
+        contacts.set_identity_key(identity.x25519_public_key());
+        Self {
+            label,
+            identity,
+            contacts,
+            sessions: SessionStore::new(),
+        }
+    }
+
+    /// Zeroize the identity keys, sessions and contacts
+    pub fn wipe(&mut self) {
+        self.sessions.wipe();
+        self.identity.zeroize();
+        self.label.zeroize();
+        self.contacts.wipe();
+    }
+}
+
+/// Public summary of an identity (safe to send to the UI)
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentitySummary {
+    pub public_id: String,
+    pub label: String,
+    pub active: bool,
+}
+
+// ============================================================================
+// IDENTITY STORE (Memory-Only)
+// ============================================================================
+
+/// In-memory collection of identities with a single active one
+#[derive(Default)]
+pub struct IdentityStore {
+    /// Personas indexed by public ID
+    personas: HashMap<String, Persona>,
+    /// Public ID of the active persona
+    active: Option<String>,
+}
+
+impl IdentityStore {
+    /// Create a new empty identity store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an identity and make it active.
+    ///
+    /// If an identity with the same public ID already exists, its key
+    /// material and label are replaced but its contacts and sessions are kept.
+    pub fn insert(&mut self, label: String, identity: Identity) -> String {
+        let public_id = identity.public_id.clone();
+
+        match self.personas.get_mut(&public_id) {
+            Some(persona) => {
+                persona.label = label;
+                persona
+                    .contacts
+                    .set_identity_key(identity.x25519_public_key());
+                persona.identity = identity;
+            }
+            None => {
+                self.personas
+                    .insert(public_id.clone(), Persona::new(label, identity));
+            }
+        }
+
+        self.active = Some(public_id.clone());
+        public_id
+    }
+
+    /// List all identities, sorted by label
+    pub fn list(&self) -> Vec<IdentitySummary> {
+        let mut summaries: Vec<IdentitySummary> = self
+            .personas
+            .iter()
+            .map(|(public_id, persona)| IdentitySummary {
+                public_id: public_id.clone(),
+                label: persona.label.clone(),
+                active: self.active.as_deref() == Some(public_id.as_str()),
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.label.cmp(&b.label).then(a.public_id.cmp(&b.public_id)));
+        summaries
+    }
+
+    /// Make another identity active
+    pub fn switch(&mut self, public_id: &str) -> Result<(), IdentityError> {
+        if !self.personas.contains_key(public_id) {
+            return Err(IdentityError::NotFound);
+        }
+        self.active = Some(public_id.to_string());
+        Ok(())
+    }
+
+    /// Remove an identity along with its contacts and sessions.
+    ///
+    /// If the removed identity was active, no identity is active afterwards.
+    pub fn delete(&mut self, public_id: &str) -> Option<Persona> {
+        let removed = self.personas.remove(public_id)?;
+        if self.active.as_deref() == Some(public_id) {
+            self.active = None;
+        }
+        Some(removed)
+    }
+
+    /// Get the active persona
+    pub fn active(&self) -> Option<&Persona> {
+        self.active.as_ref().and_then(|id| self.personas.get(id))
+    }
+
+    /// Get the active persona mutably
+    pub fn active_mut(&mut self) -> Option<&mut Persona> {
+        let id = self.active.as_ref()?;
+        self.personas.get_mut(id)
+    }
+
+    /// Get the active persona, or an error if none is active
+    pub fn require_active(&self) -> Result<&Persona, IdentityError> {
+        self.active().ok_or(IdentityError::NoActiveIdentity)
+    }
+
+    /// Get the active persona mutably, or an error if none is active
+    pub fn require_active_mut(&mut self) -> Result<&mut Persona, IdentityError> {
+        self.active_mut().ok_or(IdentityError::NoActiveIdentity)
+    }
+
+    /// Give every identity without one an archive in `storage` for its
+    /// evicted sessions
+    pub fn attach_session_archives(&mut self, storage: &SecureStorage) -> Result<(), StorageError> {
+        for (public_id, persona) in self.personas.iter_mut() {
+            if !persona.sessions.has_archive() {
+                persona
+                    .sessions
+                    .set_archive(SessionArchive::open(storage, public_id)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evict the sessions of every identity unused for longer than
+    /// `max_idle`, returning how many were evicted
+    pub fn cleanup_idle_sessions(&mut self, max_idle: Duration) -> usize {
+        self.personas
+            .values_mut()
+            .map(|persona| persona.sessions.cleanup_idle_sessions(max_idle))
+            .sum()
+    }
+
+    /// Zeroize and remove every identity with its contacts and sessions
+    pub fn wipe(&mut self) {
+        for (_, mut persona) in self.personas.drain() {
+            persona.wipe();
+        }
+        self.active = None;
+    }
+
+    /// Number of stored identities
+    pub fn len(&self) -> usize {
+        self.personas.len()
+    }
+
+    /// Whether no identities are stored
+    pub fn is_empty(&self) -> bool {
+        self.personas.is_empty()
+    }
+}
+
+// ============================================================================
+// ERROR TYPES
+// ============================================================================
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum IdentityError {
+    #[error("Identity not found")]
+    NotFound,
+    #[error("No active identity")]
+    NoActiveIdentity,
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contacts::InviteBlob;
+
+    fn test_identity(seed: u8) -> Identity {
+        Identity {
+            mnemonic: vec![],
+            root_key: [seed; 32],
+            public_id: format!("id_{}", seed),
+            kem_decap_key: vec![seed; 8],
+            kem_encap_key: vec![seed.wrapping_add(1); 8],
+            x25519_secret: [0u8; 32],
+        }
+    }
+
+    #[test]
+    fn test_insert_makes_identity_active() {
+        let mut store = IdentityStore::new();
+        assert!(store.active().is_none());
+
+        let id = store.insert("Work".into(), test_identity(1));
+        assert_eq!(id, "id_1");
+        assert_eq!(store.active().unwrap().label, "Work");
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_switch_scopes_contacts_and_kem_keys() {
+        let mut store = IdentityStore::new();
+        let work = store.insert("Work".into(), test_identity(1));
+
+        let invite = InviteBlob::new([9u8; 32], vec![], 3600);
+        store
+            .require_active_mut()
+            .unwrap()
+            .contacts
+            .import_invite(&invite, "Colleague".into())
+            .unwrap();
+
+        let personal = store.insert("Personal".into(), test_identity(2));
+
+        // New identity starts with no contacts and its own KEM keys
+        let active = store.active().unwrap();
+        assert!(active.contacts.list_contacts().is_empty());
+        assert_eq!(active.identity.kem_encap_key, vec![3u8; 8]);
+
+        store.switch(&work).unwrap();
+        let active = store.active().unwrap();
+        let contacts = active.contacts.list_contacts();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].alias, "Colleague");
+        assert_eq!(active.identity.kem_encap_key, vec![2u8; 8]);
+
+        store.switch(&personal).unwrap();
+        assert!(store.active().unwrap().contacts.list_contacts().is_empty());
+    }
+
+    #[test]
+    fn test_switch_unknown_identity_fails() {
+        let mut store = IdentityStore::new();
+        store.insert("Work".into(), test_identity(1));
+
+        assert!(matches!(
+            store.switch("missing"),
+            Err(IdentityError::NotFound)
+        ));
+        assert_eq!(store.active().unwrap().identity.public_id, "id_1");
+    }
+
+    #[test]
+    fn test_list_marks_active() {
+        let mut store = IdentityStore::new();
+        store.insert("Work".into(), test_identity(1));
+        store.insert("Personal".into(), test_identity(2));
+
+        let list = store.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].label, "Personal");
+        assert!(list[0].active);
+        assert!(!list[1].active);
+    }
+
+    #[test]
+    fn test_delete_active_clears_selection() {
+        let mut store = IdentityStore::new();
+        let work = store.insert("Work".into(), test_identity(1));
+
+        assert!(store.delete(&work).is_some());
+        assert!(store.active().is_none());
+        assert!(store.is_empty());
+        assert!(matches!(
+            store.require_active(),
+            Err(IdentityError::NoActiveIdentity)
+        ));
+    }
+
+    #[test]
+    fn test_persona_wipe_zeroizes_keys() {
+        let mut persona = Persona::new("Work".into(), test_identity(1));
+        persona
+            .sessions
+            .insert("s1".into(), RatchetState::new([7u8; 32], true).unwrap());
+        let invite = InviteBlob::new([9u8; 32], vec![], 3600);
+        persona
+            .contacts
+            .import_invite(&invite, "Colleague".into())
+            .unwrap();
+
+        persona.wipe();
+
+        assert!(persona.sessions.is_empty());
+        assert!(persona.contacts.list_contacts().is_empty());
+        assert_eq!(persona.identity.root_key, [0u8; 32]);
+        assert!(persona.identity.kem_decap_key.is_empty());
+        assert!(persona.identity.mnemonic.is_empty());
+    }
+
+    /// Pretend `session_id` was last used `idle` ago
+    fn backdate(sessions: &mut SessionStore, session_id: &str, idle: Duration) {
+        let session = sessions.sessions.get_mut(session_id).unwrap();
+        session.last_used = Instant::now().checked_sub(idle).unwrap();
+    }
+
+    #[test]
+    fn test_idle_session_evicted_active_kept() {
+        let mut sessions = SessionStore::new();
+        sessions.insert("idle".into(), RatchetState::new([7u8; 32], true).unwrap());
+        sessions.insert("active".into(), RatchetState::new([8u8; 32], true).unwrap());
+        backdate(&mut sessions, "idle", Duration::from_secs(120));
+        backdate(&mut sessions, "active", Duration::from_secs(120));
+        assert!(sessions.get_mut("active").is_some());
+
+        assert_eq!(sessions.cleanup_idle_sessions(Duration::from_secs(60)), 1);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.get_mut("active").is_some());
+        // Without an archive the evicted session is gone for good
+        assert!(sessions.get_mut("idle").is_none());
+    }
+
+    #[test]
+    fn test_evicted_session_is_zeroized() {
+        let mut sessions = SessionStore::new();
+        sessions.insert("s1".into(), RatchetState::new([7u8; 32], true).unwrap());
+        assert!(sessions
+            .get_mut("s1")
+            .unwrap()
+            .our_kem_public_key()
+            .is_some());
+
+        let evicted = sessions.evict("s1").unwrap();
+        assert!(evicted.ratchet.our_kem_public_key().is_none());
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn test_session_kept_when_archive_fails() {
+        let dir = std::env::temp_dir().join(format!("comlock_test_{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = SecureStorage::new(dir.clone());
+
+        let mut sessions = SessionStore::new();
+        sessions.set_archive(SessionArchive::open(&storage, "owner").unwrap());
+        sessions.insert("s1".into(), RatchetState::new([7u8; 32], true).unwrap());
+
+        // The archive directory disappears, so nothing can be stored
+        std::fs::remove_dir_all(dir.join("sessions")).unwrap();
+        assert_eq!(sessions.cleanup_idle_sessions(Duration::ZERO), 0);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions
+            .get_mut("s1")
+            .unwrap()
+            .our_kem_public_key()
+            .is_some());
+
+        sessions.wipe();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_evicted_session_reloads_from_archive() {
+        let dir = std::env::temp_dir().join(format!("comlock_test_{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = SecureStorage::new(dir.clone());
+
+        let mut store = IdentityStore::new();
+        store.insert("Work".into(), test_identity(1));
+        store.attach_session_archives(&storage).unwrap();
+
+        let root_key = [7u8; 32];
+        let mut peer = RatchetState::new(root_key, false).unwrap();
+        let sessions = &mut store.require_active_mut().unwrap().sessions;
+        sessions.insert("s1".into(), RatchetState::new(root_key, true).unwrap());
+        let first =
+            comlock_crypto::encrypt_message(b"before", sessions.get_mut("s1").unwrap()).unwrap();
+        comlock_crypto::decrypt_message(&first, &mut peer).unwrap();
+
+        assert_eq!(store.cleanup_idle_sessions(Duration::ZERO), 1);
+        let sessions = &mut store.require_active_mut().unwrap().sessions;
+        assert!(sessions.is_empty());
+
+        // The next use picks the session up where it left off
+        let second =
+            comlock_crypto::encrypt_message(b"after", sessions.get_mut("s1").unwrap()).unwrap();
+        assert_eq!(
+            comlock_crypto::decrypt_message(&second, &mut peer).unwrap(),
+            b"after"
+        );
+        assert_eq!(sessions.len(), 1);
+
+        store.wipe();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reinsert_keeps_contacts() {
+        let mut store = IdentityStore::new();
+        store.insert("Work".into(), test_identity(1));
+
+        let invite = InviteBlob::new([9u8; 32], vec![], 3600);
+        store
+            .require_active_mut()
+            .unwrap()
+            .contacts
+            .import_invite(&invite, "Colleague".into())
+            .unwrap();
+
+        store.insert("Work (recovered)".into(), test_identity(1));
+        let active = store.active().unwrap();
+        assert_eq!(active.label, "Work (recovered)");
+        assert_eq!(active.contacts.list_contacts().len(), 1);
+    }
+}
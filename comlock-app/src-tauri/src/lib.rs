@@ -3,37 +3,51 @@
 //! This module provides the mobile entry point and Tauri commands
 //! for cryptographic operations using the comlock-crypto crate.
 
+pub mod clock;
 pub mod contacts;
 pub mod decoy;
+pub mod identities;
 pub mod security;
 pub mod storage;
 
-use std::collections::HashMap;
 use std::sync::Mutex;
+use std::time::Duration;
 
-use comlock_crypto::{decrypt_message, encrypt_message, RatchetState};
+use comlock_crypto::{
+    ct_eq, decrypt_message, decrypt_typed_message, encrypt_message, encrypt_typed_message,
+    forward_message, ContentType, RatchetState, RatchetStatus,
+};
 // Transport layer types - imported for future async integration
 // use comlock_transport::{MixClient, MixClientConfig, Mailbox, MixNode, NodeId};
-use contacts::{Contact, ContactStore, InviteBlob, QrPayload};
-use decoy::{DecoyContact, DecoyMessage, DecoyVault};
-use security::{verify_pin, PinResult, SecurityConfig, WipeReason, WipeState};
+use contacts::{AckOutcome, Contact, InviteBlob, InvitePreview, KeyUpdate, QrPayload};
+use decoy::{DecoyContact, DecoyConversation, DecoyMessage, DecoyVault};
+use identities::{IdentityStore, IdentitySummary};
+use security::{
+    verify_pin, DuressAction, GestureEvent, PanicGestureConfig, PanicGestureDetector, PinResult,
+    SecurityConfig, WipeReason, WipeState,
+};
 use serde::{Deserialize, Serialize};
-use tauri::State;
+use storage::SecureStorage;
+use tauri::{Manager, State};
+use zeroize::{Zeroize, Zeroizing};
 
-/// Application state holding active ratchet sessions.
+/// Application state holding identities and their active ratchet sessions.
 pub struct AppState {
-    /// Map of session ID to ratchet state.
-    sessions: Mutex<HashMap<String, RatchetState>>,
-    /// The user's identity (mnemonic-derived root key).
-    identity: Mutex<Option<Identity>>,
-    /// In-memory contact store (no disk persistence).
-    contacts: Mutex<ContactStore>,
+    /// The user's identities, each with its own contacts and sessions
+    /// (in memory only, no disk persistence).
+    identities: Mutex<IdentityStore>,
     /// Security configuration.
     security_config: Mutex<SecurityConfig>,
     /// Current wipe state (for decoy mode).
     wipe_state: Mutex<WipeState>,
+    /// Recognizes the panic gesture from raw UI events.
+    panic_gesture: Mutex<PanicGestureDetector>,
     /// Decoy vault for duress mode.
     decoy_vault: Mutex<DecoyVault>,
+    /// On-disk storage, available once the app data directory is known.
+    storage: Mutex<Option<SecureStorage>>,
+    /// Ciphertexts queued for delivery, by session ID.
+    outbox: Mutex<Vec<(String, Vec<u8>)>>,
     // Transport layer will be added when async integration is complete:
     // mix_client: Mutex<MixClient>,
     // mailbox: Mutex<Option<Mailbox>>,
@@ -42,18 +56,161 @@ pub struct AppState {
 impl Default for AppState {
     fn default() -> Self {
         Self {
-            sessions: Mutex::new(HashMap::new()),
-            identity: Mutex::new(None),
-            contacts: Mutex::new(ContactStore::new()),
+            identities: Mutex::new(IdentityStore::new()),
             security_config: Mutex::new(SecurityConfig::default()),
             wipe_state: Mutex::new(WipeState::default()),
+            panic_gesture: Mutex::new(PanicGestureDetector::default()),
             decoy_vault: Mutex::new(DecoyVault::load_default()),
+            storage: Mutex::new(None),
+            outbox: Mutex::new(Vec::new()),
         }
     }
 }
 
+impl AppState {
+    /// Lock the real identities, contacts and sessions for a command.
+    ///
+    /// Refused in decoy mode, so nothing the UI asks for while showing the
+    /// decoy vault can reveal or touch the real data.
+    fn real_identities(&self) -> Result<std::sync::MutexGuard<'_, IdentityStore>, String> {
+        if self
+            .wipe_state
+            .lock()
+            .map_err(|e| e.to_string())?
+            .should_show_decoy()
+        {
+            return Err("Not available".into());
+        }
+        self.identities.lock().map_err(|e| e.to_string())
+    }
+
+    /// Hand every queued ciphertext to the caller for delivery, emptying
+    /// the outbox.
+    fn take_outbox(&self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(
+            &mut *self
+                .outbox
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+
+    /// Trigger a wipe: switch to decoy mode, zeroize every in-memory
+    /// identity, contact and session, and delete the on-disk data.
+    ///
+    /// Best effort by design: a poisoned lock or a failed file deletion
+    /// must not stop the rest of the wipe or reveal that one happened.
+    fn wipe(&self, wipe_state: &mut WipeState, reason: WipeReason) {
+        wipe_state.trigger(reason);
+
+        let mut identities = self
+            .identities
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        identities.wipe();
+        drop(identities);
+
+        let storage = self
+            .storage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(storage) = storage.as_ref() {
+            let _ = storage.wipe_all_data();
+        }
+    }
+
+    /// Queue a duress alarm for a trusted contact over their session.
+    ///
+    /// Best effort and silent: a missing contact or session is ignored so
+    /// the unlock screen behaves exactly as for any other duress PIN.
+    fn queue_silent_alarm(&self, contact_id: &str) {
+        let mut identities = self
+            .identities
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(persona) = identities.active_mut() else {
+            return;
+        };
+        let Some(session_id) = persona
+            .contacts
+            .get_contact(contact_id)
+            .map(|contact| contact.session_id.clone())
+        else {
+            return;
+        };
+        let Some(ratchet) = persona.sessions.get_mut(&session_id) else {
+            return;
+        };
+        if let Ok(ciphertext) = encrypt_message(DURESS_ALARM_MESSAGE, ratchet) {
+            self.outbox
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push((session_id, ciphertext));
+        }
+    }
+
+    /// Show the user's decoy vault (encrypted under the duress PIN) in
+    /// decoy mode, falling back to the built-in content.
+    fn load_decoy_vault(&self, duress_pin: &str) {
+        let storage = self
+            .storage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let vault = match storage.as_ref() {
+            Some(storage) => DecoyVault::load_or_default(&storage.decoy_path(), duress_pin),
+            None => DecoyVault::load_default(),
+        };
+        drop(storage);
+
+        *self
+            .decoy_vault
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = vault;
+    }
+
+    /// Load, modify and re-save the user's decoy vault.
+    ///
+    /// Only allowed outside decoy mode and with the correct duress PIN, which
+    /// the vault is encrypted under.
+    fn edit_decoy_vault<T>(
+        &self,
+        duress_pin: &str,
+        edit: impl FnOnce(&mut DecoyVault) -> T,
+    ) -> Result<T, String> {
+        if self
+            .wipe_state
+            .lock()
+            .map_err(|e| e.to_string())?
+            .should_show_decoy()
+        {
+            return Err("Not available".into());
+        }
+
+        let config = self.security_config.lock().map_err(|e| e.to_string())?;
+        if !matches!(verify_pin(duress_pin, &config), PinResult::Duress(_)) {
+            return Err("Invalid duress PIN".into());
+        }
+        drop(config);
+
+        let storage = self.storage.lock().map_err(|e| e.to_string())?;
+        let path = storage.as_ref().ok_or("Storage unavailable")?.decoy_path();
+        let mut vault = match DecoyVault::load_encrypted(&path, duress_pin) {
+            Ok(vault) => vault,
+            Err(storage::StorageError::NotFound) => DecoyVault::default(),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let result = edit(&mut vault);
+        vault
+            .save_encrypted(&path, duress_pin)
+            .map_err(|e| e.to_string())?;
+        Ok(result)
+    }
+}
+
 /// User identity bundle.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "StoredIdentity")]
 pub struct Identity {
     /// 24-word mnemonic (BIP-39).
     pub mnemonic: Vec<String>,
@@ -67,6 +224,171 @@ pub struct Identity {
     /// ML-KEM-1024 encapsulation key (public, 1568 bytes).
     #[serde(default)]
     pub kem_encap_key: Vec<u8>,
+    /// X25519 identity secret key.
+    pub x25519_secret: [u8; 32],
+}
+
+/// An identity as saved on disk. Identities saved before the X25519 key
+/// was added lack `x25519_secret`; it is re-derived from the mnemonic.
+#[derive(Deserialize)]
+struct StoredIdentity {
+    mnemonic: Vec<String>,
+    root_key: [u8; 32],
+    public_id: String,
+    #[serde(default)]
+    kem_decap_key: Vec<u8>,
+    #[serde(default)]
+    kem_encap_key: Vec<u8>,
+    x25519_secret: Option<[u8; 32]>,
+}
+
+impl TryFrom<StoredIdentity> for Identity {
+    type Error = String;
+
+    fn try_from(mut stored: StoredIdentity) -> Result<Self, String> {
+        if let Some(x25519_secret) = stored.x25519_secret {
+            return Ok(Self {
+                mnemonic: stored.mnemonic,
+                root_key: stored.root_key,
+                public_id: stored.public_id,
+                kem_decap_key: stored.kem_decap_key,
+                kem_encap_key: stored.kem_encap_key,
+                x25519_secret,
+            });
+        }
+
+        // Legacy identity: re-derive every key from the mnemonic, and refuse
+        // it if the mnemonic does not reproduce the stored root key
+        let derived = Self::from_words(&stored.mnemonic);
+        let matches = derived
+            .as_ref()
+            .is_ok_and(|identity| ct_eq(&identity.root_key, &stored.root_key));
+        stored.mnemonic.zeroize();
+        stored.root_key.zeroize();
+        stored.kem_decap_key.zeroize();
+        if !matches {
+            return Err(
+                "Legacy identity lacks an X25519 key and its mnemonic does not match".into(),
+            );
+        }
+        derived
+    }
+}
+
+/// HKDF info prefix for keys derived from the mnemonic seed.
+const IDENTITY_KDF_INFO: &[u8] = b"ComLock-Identity-v1";
+
+/// Derive a 32-byte key for `label` from the BIP-39 seed.
+fn derive_seed_key(seed: &[u8], label: &[u8]) -> [u8; 32] {
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(None, seed);
+    let mut info = Vec::with_capacity(IDENTITY_KDF_INFO.len() + 1 + label.len());
+    info.extend_from_slice(IDENTITY_KDF_INFO);
+    info.push(0x00);
+    info.extend_from_slice(label);
+
+    let mut key = [0u8; 32];
+    hk.expand(&info, &mut key).expect("HKDF expansion failed");
+    key
+}
+
+impl Identity {
+    /// Derive an identity from a BIP-39 mnemonic.
+    ///
+    /// The root key comes from the mnemonic seed, and the ML-KEM-1024 and
+    /// X25519 keys are derived from the seed with HKDF, so recovering from
+    /// the same mnemonic always reproduces the same keys.
+    fn from_mnemonic(mnemonic: &bip39::Mnemonic) -> Self {
+        let words: Vec<String> = mnemonic.words().map(|s| s.to_string()).collect();
+
+        // Derive root key from mnemonic seed (using BIP-39 seed derivation)
+        let mut seed = Zeroizing::new(mnemonic.to_seed("")); // Empty passphrase for simplicity
+        Self::from_seed(words, &mut seed)
+    }
+
+    /// Derive the identity keys from a BIP-39 seed, zeroizing the seed and
+    /// every intermediate key buffer before returning.
+    fn from_seed(words: Vec<String>, seed: &mut [u8; 64]) -> Self {
+        use ml_kem::{EncodedSizeUser, KemCore, MlKem1024, B32};
+        use sha2::{Digest, Sha256};
+
+        let mut root_key = [0u8; 32];
+        root_key.copy_from_slice(&seed[..32]);
+
+        // Create public ID (hash of root key)
+        let mut hasher = Sha256::new();
+        hasher.update(root_key);
+        let hash = hasher.finalize();
+        let public_id = hex::encode(&hash[..8]);
+
+        // Derive the ML-KEM-1024 keypair from the seed
+        let mut d = derive_seed_key(seed, b"mlkem_d");
+        let mut z = derive_seed_key(seed, b"mlkem_z");
+        let (dk, ek) = MlKem1024::generate_deterministic(&B32::from(d), &B32::from(z));
+        d.zeroize();
+        z.zeroize();
+        let mut dk_bytes = dk.as_bytes();
+        let kem_decap_key = dk_bytes.to_vec();
+        dk_bytes.as_mut_slice().zeroize();
+
+        let x25519_secret = derive_seed_key(seed, b"x25519");
+        seed.zeroize();
+
+        Self {
+            mnemonic: words,
+            root_key,
+            public_id,
+            kem_decap_key,
+            kem_encap_key: ek.as_bytes().to_vec(),
+            x25519_secret,
+        }
+    }
+
+    /// X25519 identity public key, as shared in invites.
+    pub fn x25519_public_key(&self) -> [u8; 32] {
+        let secret = x25519_dalek::StaticSecret::from(self.x25519_secret);
+        x25519_dalek::PublicKey::from(&secret).to_bytes()
+    }
+
+    /// X25519 Diffie-Hellman between our identity key and a peer's public key.
+    pub fn x25519_diffie_hellman(&self, their_public: &[u8; 32]) -> [u8; 32] {
+        let secret = x25519_dalek::StaticSecret::from(self.x25519_secret);
+        secret
+            .diffie_hellman(&x25519_dalek::PublicKey::from(*their_public))
+            .to_bytes()
+    }
+
+    /// Recover an identity from its 24 mnemonic words.
+    fn from_words(words: &[String]) -> Result<Self, String> {
+        if words.len() != 24 {
+            return Err("Mnemonic must be 24 words".into());
+        }
+
+        // Join words and parse as BIP-39 mnemonic
+        let phrase = Zeroizing::new(words.join(" "));
+        let mnemonic = bip39::Mnemonic::parse(phrase.as_str())
+            .map_err(|e| format!("Invalid mnemonic: {}", e))?;
+
+        Ok(Self::from_mnemonic(&mnemonic))
+    }
+}
+
+impl Zeroize for Identity {
+    fn zeroize(&mut self) {
+        self.mnemonic.zeroize();
+        self.root_key.zeroize();
+        self.public_id.zeroize();
+        self.kem_decap_key.zeroize();
+        self.kem_encap_key.zeroize();
+        self.x25519_secret.zeroize();
+    }
+}
+
+/// A discarded identity (replaced, deleted or dropped on exit) leaves no
+/// key material behind.
+impl Drop for Identity {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
 /// Result of creating a new identity.
@@ -89,6 +411,21 @@ pub struct DecryptResult {
     pub plaintext: String,
 }
 
+/// Raw message decryption result, for payloads that aren't text.
+#[derive(Debug, Serialize)]
+pub struct DecryptBytesResult {
+    pub plaintext: Vec<u8>,
+    pub plaintext_hex: String,
+}
+
+/// Startup self-test result; an error is `None` when that layer passed.
+#[derive(Debug, Serialize)]
+pub struct SelfTestResult {
+    pub passed: bool,
+    pub crypto_error: Option<String>,
+    pub transport_error: Option<String>,
+}
+
 // ============================================================================
 // IDENTITY COMMANDS
 // ============================================================================
@@ -96,105 +433,67 @@ pub struct DecryptResult {
 /// Create a new identity with a random mnemonic.
 #[tauri::command]
 fn create_identity(state: State<AppState>) -> Result<CreateIdentityResult, String> {
+    create_identity_named("Default".into(), state)
+}
+
+/// Create a new labelled identity with a random mnemonic and make it active.
+#[tauri::command]
+fn create_identity_named(
+    label: String,
+    state: State<AppState>,
+) -> Result<CreateIdentityResult, String> {
     use bip39::Mnemonic;
     use rand::RngCore;
-    use sha2::{Digest, Sha256};
 
     // Generate 32 bytes of entropy for 24-word mnemonic
-    let mut entropy = [0u8; 32];
-    rand::thread_rng().fill_bytes(&mut entropy);
+    let mut entropy = Zeroizing::new([0u8; 32]);
+    rand::thread_rng().fill_bytes(entropy.as_mut());
 
     // Create mnemonic from entropy using BIP-39
-    let mnemonic = Mnemonic::from_entropy(&entropy)
+    let mnemonic = Mnemonic::from_entropy(entropy.as_ref())
         .map_err(|e| format!("Failed to generate mnemonic: {}", e))?;
 
-    let words: Vec<String> = mnemonic.word_iter().map(|s| s.to_string()).collect();
-
-    // Derive root key from mnemonic seed (using BIP-39 seed derivation)
-    let seed = mnemonic.to_seed(""); // Empty passphrase for simplicity
-    let mut root_key = [0u8; 32];
-    root_key.copy_from_slice(&seed[..32]);
-
-    // Create public ID (hash of root key)
-    let mut hasher = Sha256::new();
-    hasher.update(root_key);
-    let hash = hasher.finalize();
-    let public_id = hex::encode(&hash[..8]);
-
-    // Generate ML-KEM-1024 keypair for post-quantum key encapsulation
-    use ml_kem::{EncodedSizeUser, KemCore, MlKem1024};
-    let mut rng = rand::thread_rng();
-    let (dk, ek) = MlKem1024::generate(&mut rng);
-
-    // Serialize keypair for storage using as_bytes()
-    let kem_decap_key = dk.as_bytes().to_vec();
-    let kem_encap_key = ek.as_bytes().to_vec();
-
-    let identity = Identity {
-        mnemonic: words.clone(),
-        root_key,
-        public_id: public_id.clone(),
-        kem_decap_key,
-        kem_encap_key,
+    let identity = Identity::from_mnemonic(&mnemonic);
+    let result = CreateIdentityResult {
+        mnemonic: identity.mnemonic.clone(),
+        public_id: identity.public_id.clone(),
     };
 
     // Store identity
-    let mut id_lock = state.identity.lock().map_err(|e| e.to_string())?;
-    *id_lock = Some(identity);
+    let mut identities = state.real_identities()?;
+    identities.insert(label, identity);
 
-    Ok(CreateIdentityResult {
-        mnemonic: words,
-        public_id,
-    })
+    Ok(result)
 }
 
 /// Recover identity from mnemonic.
 #[tauri::command]
 fn recover_identity(mnemonic: Vec<String>, state: State<AppState>) -> Result<String, String> {
-    use bip39::Mnemonic;
-    use sha2::{Digest, Sha256};
-
-    if mnemonic.len() != 24 {
-        return Err("Mnemonic must be 24 words".into());
-    }
-
-    // Join words and parse as BIP-39 mnemonic
-    let phrase = mnemonic.join(" ");
-    let bip39_mnemonic =
-        Mnemonic::parse(&phrase).map_err(|e| format!("Invalid mnemonic: {}", e))?;
-
-    // Derive root key from mnemonic seed
-    let seed = bip39_mnemonic.to_seed("");
-    let mut root_key = [0u8; 32];
-    root_key.copy_from_slice(&seed[..32]);
-
-    // Create public ID
-    let mut hasher = Sha256::new();
-    hasher.update(root_key);
-    let hash = hasher.finalize();
-    let public_id = hex::encode(&hash[..8]);
-
-    // Generate ML-KEM-1024 keypair for post-quantum key encapsulation
-    use ml_kem::{EncodedSizeUser, KemCore, MlKem1024};
-    let mut rng = rand::thread_rng();
-    let (dk, ek) = MlKem1024::generate(&mut rng);
-
-    // Serialize keypair for storage using as_bytes()
-    let kem_decap_key = dk.as_bytes().to_vec();
-    let kem_encap_key = ek.as_bytes().to_vec();
-
-    let identity = Identity {
-        mnemonic,
-        root_key,
-        public_id: public_id.clone(),
-        kem_decap_key,
-        kem_encap_key,
-    };
+    let identity = Identity::from_words(&mnemonic)?;
+
+    let mut identities = state.real_identities()?;
+    Ok(identities.insert("Recovered".into(), identity))
+}
+
+/// List all identities on this device.
+#[tauri::command]
+fn list_identities(state: State<AppState>) -> Result<Vec<IdentitySummary>, String> {
+    let identities = state.real_identities()?;
+    Ok(identities.list())
+}
 
-    let mut id_lock = state.identity.lock().map_err(|e| e.to_string())?;
-    *id_lock = Some(identity);
+/// Switch the active identity, hiding the other identities' contacts and sessions.
+#[tauri::command]
+fn switch_identity(public_id: String, state: State<AppState>) -> Result<(), String> {
+    let mut identities = state.real_identities()?;
+    identities.switch(&public_id).map_err(|e| e.to_string())
+}
 
-    Ok(public_id)
+/// Delete an identity together with its contacts and sessions.
+#[tauri::command]
+fn delete_identity(public_id: String, state: State<AppState>) -> Result<bool, String> {
+    let mut identities = state.real_identities()?;
+    Ok(identities.delete(&public_id).is_some())
 }
 
 // ============================================================================
@@ -214,10 +513,15 @@ fn init_session(
         .try_into()
         .map_err(|_| "Shared secret must be 32 bytes")?;
 
-    let ratchet = RatchetState::new(shared_secret, is_initiator);
+    let ratchet =
+        RatchetState::new_checked(shared_secret, is_initiator).map_err(|e| e.to_string())?;
 
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    sessions.insert(session_id, ratchet);
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    if !persona.contacts.session_allowed(&session_id) {
+        return Err("Contact has been revoked".to_string());
+    }
+    persona.sessions.insert(session_id, ratchet);
 
     Ok(())
 }
@@ -225,11 +529,58 @@ fn init_session(
 /// Trigger KEM ratchet advancement for a session.
 #[tauri::command]
 fn trigger_kem(session_id: String, state: State<AppState>) -> Result<(), String> {
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    let ratchet = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let ratchet = persona
+        .sessions
+        .get_mut(&session_id)
+        .ok_or("Session not found")?;
+
+    ratchet.trigger_kem_advancement().map_err(|e| e.to_string())
+}
 
-    ratchet.trigger_kem_advancement();
-    Ok(())
+/// Get message counters and post-quantum status for a session.
+#[tauri::command]
+fn get_session_status(session_id: String, state: State<AppState>) -> Result<RatchetStatus, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let ratchet = persona
+        .sessions
+        .get_mut(&session_id)
+        .ok_or("Session not found")?;
+
+    Ok(ratchet.status())
+}
+
+/// Evict sessions of every identity left unused for longer than
+/// `max_idle_secs` (default: the configured session idle timeout).
+///
+/// Call it on a timer, and with 0 when the app locks. Evicted sessions are
+/// archived in encrypted storage when it is available and reloaded on their
+/// next use. Returns how many sessions were evicted.
+#[tauri::command]
+fn cleanup_idle_sessions(
+    max_idle_secs: Option<u64>,
+    state: State<AppState>,
+) -> Result<usize, String> {
+    let max_idle_secs = match max_idle_secs {
+        Some(secs) => secs,
+        None => {
+            let config = state.security_config.lock().map_err(|e| e.to_string())?;
+            config.session_idle_secs
+        }
+    };
+
+    let mut identities = state.real_identities()?;
+    let storage = state.storage.lock().map_err(|e| e.to_string())?;
+    if let Some(storage) = storage.as_ref() {
+        identities
+            .attach_session_archives(storage)
+            .map_err(|e| e.to_string())?;
+    }
+    drop(storage);
+
+    Ok(identities.cleanup_idle_sessions(std::time::Duration::from_secs(max_idle_secs)))
 }
 
 // ============================================================================
@@ -243,10 +594,7 @@ fn encrypt(
     plaintext: String,
     state: State<AppState>,
 ) -> Result<EncryptResult, String> {
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    let ratchet = sessions.get_mut(&session_id).ok_or("Session not found")?;
-
-    let ciphertext = encrypt_message(plaintext.as_bytes(), ratchet).map_err(|e| e.to_string())?;
+    let ciphertext = encrypt_session_message(&state, &session_id, plaintext.as_bytes())?;
 
     Ok(EncryptResult {
         ciphertext_hex: hex::encode(&ciphertext),
@@ -254,25 +602,135 @@ fn encrypt(
     })
 }
 
-/// Decrypt a message for a session.
+/// Decrypt a text message for a session.
+///
+/// A payload that isn't valid UTF-8 is an error unless `lossy` is set, in
+/// which case invalid sequences are replaced. Either way the message is
+/// consumed, so use `decrypt_bytes` for payloads that may not be text.
 #[tauri::command]
 fn decrypt(
     session_id: String,
     ciphertext_hex: String,
+    lossy: Option<bool>,
     state: State<AppState>,
 ) -> Result<DecryptResult, String> {
-    let ciphertext = hex::decode(&ciphertext_hex).map_err(|e| e.to_string())?;
+    let plaintext_bytes = decrypt_session_message(&state, &session_id, &ciphertext_hex)?;
+    decode_plaintext(plaintext_bytes, lossy.unwrap_or(false))
+}
+
+/// Decrypt a message for a session, returning the raw bytes.
+#[tauri::command]
+fn decrypt_bytes(
+    session_id: String,
+    ciphertext_hex: String,
+    state: State<AppState>,
+) -> Result<DecryptBytesResult, String> {
+    let plaintext = decrypt_session_message(&state, &session_id, &ciphertext_hex)?;
 
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    let ratchet = sessions.get_mut(&session_id).ok_or("Session not found")?;
+    Ok(DecryptBytesResult {
+        plaintext_hex: hex::encode(&plaintext),
+        plaintext,
+    })
+}
 
-    let plaintext_bytes = decrypt_message(&ciphertext, ratchet).map_err(|e| e.to_string())?;
+/// Encrypt `plaintext` with the active identity's session.
+fn encrypt_session_message(
+    state: &AppState,
+    session_id: &str,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let ratchet = persona
+        .sessions
+        .get_mut(session_id)
+        .ok_or("Session not found")?;
+
+    let ciphertext = encrypt_message(plaintext, ratchet).map_err(|e| e.to_string())?;
+
+    if let Some(contact_id) = persona
+        .contacts
+        .get_by_session(session_id)
+        .map(|contact| contact.id.clone())
+    {
+        persona
+            .contacts
+            .touch_contact(&contact_id, clock::now_unix())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(ciphertext)
+}
 
-    let plaintext = String::from_utf8(plaintext_bytes).map_err(|e| e.to_string())?;
+/// Decrypt `ciphertext_hex` with the active identity's session.
+fn decrypt_session_message(
+    state: &AppState,
+    session_id: &str,
+    ciphertext_hex: &str,
+) -> Result<Vec<u8>, String> {
+    let ciphertext = hex::decode(ciphertext_hex).map_err(|e| e.to_string())?;
+
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let ratchet = persona
+        .sessions
+        .get_mut(session_id)
+        .ok_or("Session not found")?;
+
+    let plaintext =
+        decrypt_message(&ciphertext, ratchet).map_err(|e| format!("Decryption failed: {e}"))?;
+
+    if let Some(contact_id) = persona
+        .contacts
+        .get_by_session(session_id)
+        .map(|contact| contact.id.clone())
+    {
+        persona
+            .contacts
+            .record_inbound(&contact_id, clock::now_unix())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(plaintext)
+}
+
+/// Turn decrypted bytes into text, replacing invalid UTF-8 only if `lossy`.
+fn decode_plaintext(plaintext_bytes: Vec<u8>, lossy: bool) -> Result<DecryptResult, String> {
+    let plaintext = if lossy {
+        String::from_utf8_lossy(&plaintext_bytes).into_owned()
+    } else {
+        String::from_utf8(plaintext_bytes)
+            .map_err(|e| format!("Plaintext is not valid UTF-8: {}", e.utf8_error()))?
+    };
 
     Ok(DecryptResult { plaintext })
 }
 
+/// Forward a decrypted message to another session.
+///
+/// The message is re-encrypted for the target session only; with
+/// `strip_attribution` its length is also hidden by padding.
+#[tauri::command]
+fn forward(
+    plaintext: String,
+    target_session_id: String,
+    strip_attribution: bool,
+    state: State<AppState>,
+) -> Result<EncryptResult, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let ratchet = persona
+        .sessions
+        .get_mut(&target_session_id)
+        .ok_or("Session not found")?;
+
+    let ciphertext = forward_message(plaintext.as_bytes(), ratchet, strip_attribution)
+        .map_err(|e| e.to_string())?;
+
+    Ok(EncryptResult {
+        ciphertext_hex: hex::encode(&ciphertext),
+        ciphertext,
+    })
+}
+
 // ============================================================================
 // TRANSPORT LAYER COMMANDS
 // ============================================================================
@@ -294,8 +752,8 @@ pub struct ReceivedMessage {
 }
 
 /// Send an encrypted message through the mixnet.
-/// Note: Currently queues the message for delivery. Actual mixnet
-/// delivery will be implemented when the transport layer is fully connected.
+/// Note: Currently queues the message in the outbox, which the transport
+/// drains with `take_outbox`.
 #[tauri::command]
 fn send_via_mixnet(
     session_id: String,
@@ -304,19 +762,15 @@ fn send_via_mixnet(
     state: State<AppState>,
 ) -> Result<SendMessageResult, String> {
     // Encrypt the message first
-    let ciphertext = {
-        let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-        let ratchet = sessions.get_mut(&session_id).ok_or("Session not found")?;
-        encrypt_message(plaintext.as_bytes(), ratchet).map_err(|e| e.to_string())?
-    };
+    let ciphertext = encrypt_session_message(&state, &session_id, plaintext.as_bytes())?;
 
     // Generate message ID
     let message_id = format!(
         "msg_{}",
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
+            .map(|d| d.as_millis())
+            .unwrap_or_default()
     );
 
     // Log for now - actual mixnet delivery will be implemented
@@ -327,6 +781,11 @@ fn send_via_mixnet(
         recipient_mailbox_id,
         ciphertext.len()
     );
+    state
+        .outbox
+        .lock()
+        .map_err(|e| e.to_string())?
+        .push((session_id, ciphertext));
 
     Ok(SendMessageResult {
         message_id,
@@ -346,16 +805,40 @@ fn poll_messages(_state: State<AppState>) -> Result<Vec<ReceivedMessage>, String
 
 /// Get transport layer status.
 #[tauri::command]
-fn get_transport_status(_state: State<AppState>) -> Result<TransportStatus, String> {
+fn get_transport_status(state: State<AppState>) -> Result<TransportStatus, String> {
     Ok(TransportStatus {
         connected: false,
         gateway_address: None,
         mailbox_id: None,
-        messages_queued: 0,
+        messages_queued: u32::try_from(state.outbox.lock().map_err(|e| e.to_string())?.len())
+            .unwrap_or(u32::MAX),
         messages_received: 0,
     })
 }
 
+/// A ciphertext waiting in the outbox.
+#[derive(Debug, Serialize)]
+pub struct OutboxMessage {
+    pub session_id: String,
+    pub ciphertext_hex: String,
+}
+
+/// Take every queued ciphertext for delivery, emptying the outbox.
+///
+/// Works in decoy mode too, so a silent alarm queued by a duress PIN still
+/// leaves the device.
+#[tauri::command]
+fn take_outbox(state: State<AppState>) -> Vec<OutboxMessage> {
+    state
+        .take_outbox()
+        .into_iter()
+        .map(|(session_id, ciphertext)| OutboxMessage {
+            session_id,
+            ciphertext_hex: hex::encode(ciphertext),
+        })
+        .collect()
+}
+
 /// Transport layer status.
 #[derive(Debug, Serialize)]
 pub struct TransportStatus {
@@ -377,6 +860,17 @@ pub struct QrExchangeResult {
     pub qr_payload: String,
 }
 
+/// Result of starting a pairing-code exchange
+#[derive(Debug, Serialize)]
+pub struct CodeExchangeResult {
+    pub exchange_id: String,
+    pub code: String,
+    /// Mailbox to post the offer to (hex)
+    pub mailbox_id: String,
+    /// Sealed offer carrying our ephemeral key (hex)
+    pub offer_hex: String,
+}
+
 /// Result of processing a scanned QR code
 #[derive(Debug, Serialize)]
 pub struct ScanResult {
@@ -392,18 +886,34 @@ pub struct ConfirmSasResult {
 }
 
 /// Generate a QR payload for in-person key exchange.
+/// With `compact`, the KEM key is replaced by a commitment to keep the QR code small.
+/// Without it, the compact payload is used only if the full one exceeds
+/// `max_qr_bytes` (default [`contacts::max_qr_bytes`]). A contact made
+/// from a compact payload gets the KEM key with `receive_kem_key`, after
+/// the peer sends it with `share_kem_key`.
 #[tauri::command]
-fn generate_qr_payload(state: State<AppState>) -> Result<QrExchangeResult, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
-
-    // Get KEM pubkey from identity if available
-    let identity = state.identity.lock().map_err(|e| e.to_string())?;
-    let kem_pubkey: Option<Vec<u8>> = identity.as_ref().map(|id| {
-        // Use the real ML-KEM-1024 encapsulation key from identity
-        id.kem_encap_key.clone()
-    });
-
-    let (exchange_id, payload) = contacts.start_qr_exchange(kem_pubkey.as_deref());
+fn generate_qr_payload(
+    compact: Option<bool>,
+    max_qr_bytes: Option<usize>,
+    state: State<AppState>,
+) -> Result<QrExchangeResult, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+
+    // Use the real ML-KEM-1024 encapsulation key from the active identity
+    let kem_pubkey = persona.identity.kem_encap_key.clone();
+
+    let (exchange_id, payload) = match compact {
+        Some(true) => persona.contacts.start_compact_qr_exchange(&kem_pubkey),
+        Some(false) => persona.contacts.start_qr_exchange(Some(&kem_pubkey)),
+        None => persona
+            .contacts
+            .start_qr_exchange_within(
+                &kem_pubkey,
+                max_qr_bytes.unwrap_or_else(contacts::max_qr_bytes),
+            )
+            .map_err(|e| e.to_string())?,
+    };
     let qr_json = payload.to_json().map_err(|e| e.to_string())?;
 
     Ok(QrExchangeResult {
@@ -419,10 +929,12 @@ fn process_scanned_qr(
     qr_json: String,
     state: State<AppState>,
 ) -> Result<ScanResult, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
     let payload = QrPayload::from_json(&qr_json).map_err(|e| e.to_string())?;
 
-    let (sas, _shared_secret) = contacts
+    let (sas, _shared_secret) = persona
+        .contacts
         .process_scanned_qr(&exchange_id, &payload)
         .map_err(|e| e.to_string())?;
 
@@ -438,29 +950,36 @@ fn confirm_sas(
     alias: String,
     state: State<AppState>,
 ) -> Result<ConfirmSasResult, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
     let payload = QrPayload::from_json(&qr_json).map_err(|e| e.to_string())?;
 
     // Get the shared secret before consuming the exchange
     let peer_public = payload.decode_public_key().map_err(|e| e.to_string())?;
     let shared_secret = {
-        let (keypair, _) = contacts
+        let (keypair, _) = persona
+            .contacts
             .get_pending_exchange(&exchange_id)
             .ok_or("Exchange not found")?;
-        keypair.compute_shared_secret(&peer_public)
+        keypair
+            .compute_shared_secret(&peer_public)
+            .map_err(|e| e.to_string())?
     };
 
+    // Refuse a degenerate secret before consuming the exchange. We're the
+    // scanner, so we're initiator
+    let ratchet = RatchetState::new_checked(shared_secret, true).map_err(|e| e.to_string())?;
+
     // Create the contact
-    let contact = contacts
+    let contact = persona
+        .contacts
         .confirm_sas(&exchange_id, &payload, alias)
         .map_err(|e| e.to_string())?;
 
     // Auto-initialize the ratchet session with the shared secret
     let session_id = contact.session_id.clone();
-    let ratchet = RatchetState::new(shared_secret, true); // We're the scanner, so we're initiator
 
-    let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
-    sessions.insert(session_id.clone(), ratchet);
+    persona.sessions.insert(session_id.clone(), ratchet);
 
     Ok(ConfirmSasResult {
         contact,
@@ -469,30 +988,197 @@ fn confirm_sas(
     })
 }
 
-/// Generate a one-time invite blob for remote contact exchange.
+/// Encrypt the active identity's ML-KEM key for a contact, over the
+/// contact's session, for a peer that scanned our compact QR payload.
 #[tauri::command]
-fn generate_invite(ttl_hours: Option<u32>, state: State<AppState>) -> Result<String, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
-    let identity = state.identity.lock().map_err(|e| e.to_string())?;
+fn share_kem_key(contact_id: String, state: State<AppState>) -> Result<EncryptResult, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let session_id = persona
+        .contacts
+        .get_contact(&contact_id)
+        .ok_or("Contact not found")?
+        .session_id
+        .clone();
+    let ratchet = persona
+        .sessions
+        .get_mut(&session_id)
+        .ok_or("Session not found")?;
+
+    let ciphertext = encrypt_typed_message(
+        &persona.identity.kem_encap_key,
+        ContentType::KeyUpdate,
+        ratchet,
+    )
+    .map_err(|e| e.to_string())?;
 
-    let identity = identity.as_ref().ok_or("No identity created yet")?;
+    Ok(EncryptResult {
+        ciphertext_hex: hex::encode(&ciphertext),
+        ciphertext,
+    })
+}
 
-    // Derive X25519 public key from root key (placeholder)
-    let mut hasher = sha2::Sha256::new();
-    use sha2::Digest;
-    hasher.update(b"COMLOCK_X25519_PK");
-    hasher.update(identity.root_key);
-    let hash = hasher.finalize();
-    let mut our_pubkey = [0u8; 32];
-    our_pubkey.copy_from_slice(&hash);
+/// Receive the ML-KEM key a contact sent with `share_kem_key`, and check
+/// it against the commitment from their compact QR payload.
+///
+/// A key that does not match the commitment is refused.
+#[tauri::command]
+fn receive_kem_key(
+    contact_id: String,
+    ciphertext_hex: String,
+    state: State<AppState>,
+) -> Result<Contact, String> {
+    let ciphertext = hex::decode(ciphertext_hex).map_err(|e| e.to_string())?;
+
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let session_id = persona
+        .contacts
+        .get_contact(&contact_id)
+        .ok_or("Contact not found")?
+        .session_id
+        .clone();
+    let ratchet = persona
+        .sessions
+        .get_mut(&session_id)
+        .ok_or("Session not found")?;
+
+    let message = decrypt_typed_message(&ciphertext, ratchet)
+        .map_err(|e| format!("Decryption failed: {e}"))?;
+    if message.content_type != ContentType::KeyUpdate {
+        return Err("Not a KEM key message".into());
+    }
+    persona
+        .contacts
+        .apply_committed_kem_key(&contact_id, &message.plaintext)
+        .map_err(|e| e.to_string())
+}
+
+/// Start a pairing-code exchange, for when a QR code can't be scanned.
+#[tauri::command]
+fn start_code_exchange(state: State<AppState>) -> Result<CodeExchangeResult, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+
+    let offer = persona.contacts.start_code_exchange();
+
+    Ok(CodeExchangeResult {
+        exchange_id: offer.exchange_id,
+        code: offer.code,
+        mailbox_id: hex::encode(offer.mailbox_id),
+        offer_hex: hex::encode(offer.offer),
+    })
+}
+
+/// Mailbox to fetch the peer's pairing offer from, given the code heard.
+#[tauri::command]
+fn pairing_code_mailbox(peer_code: String) -> Result<String, String> {
+    contacts::pairing_mailbox(&peer_code)
+        .map(hex::encode)
+        .map_err(|e| e.to_string())
+}
+
+/// Process the peer's pairing code and return the SAS for verification.
+#[tauri::command]
+fn complete_code_exchange(
+    exchange_id: String,
+    peer_code: String,
+    peer_offer_hex: String,
+    state: State<AppState>,
+) -> Result<ScanResult, String> {
+    let peer_offer = hex::decode(&peer_offer_hex).map_err(|e| e.to_string())?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+
+    let (sas, _shared_secret) = persona
+        .contacts
+        .complete_code_exchange(&exchange_id, &peer_code, &peer_offer)
+        .map_err(|e| e.to_string())?;
 
-    // Use real ML-KEM-1024 encapsulation key from identity
+    Ok(ScanResult { sas })
+}
+
+/// Confirm the SAS of a pairing-code exchange and finalize contact creation.
+/// Also initializes the ratchet session automatically.
+#[tauri::command]
+fn confirm_code_sas(
+    exchange_id: String,
+    peer_code: String,
+    peer_offer_hex: String,
+    alias: String,
+    state: State<AppState>,
+) -> Result<ConfirmSasResult, String> {
+    let peer_offer = hex::decode(&peer_offer_hex).map_err(|e| e.to_string())?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+
+    // Get the shared secret and our role before consuming the exchange
+    let peer_public =
+        contacts::open_pairing_offer(&peer_code, &peer_offer).map_err(|e| e.to_string())?;
+    let (shared_secret, is_initiator) = {
+        let (keypair, _) = persona
+            .contacts
+            .get_pending_exchange(&exchange_id)
+            .ok_or("Exchange not found")?;
+        // Both sides act alike, so the lower public key takes the initiator role
+        (
+            keypair
+                .compute_shared_secret(&peer_public)
+                .map_err(|e| e.to_string())?,
+            keypair.public_key < peer_public,
+        )
+    };
+
+    // Refuse a degenerate secret before consuming the exchange
+    let ratchet =
+        RatchetState::new_checked(shared_secret, is_initiator).map_err(|e| e.to_string())?;
+
+    let contact = persona
+        .contacts
+        .confirm_code_sas(&exchange_id, &peer_code, &peer_offer, alias)
+        .map_err(|e| e.to_string())?;
+
+    let session_id = contact.session_id.clone();
+
+    persona.sessions.insert(session_id.clone(), ratchet);
+
+    Ok(ConfirmSasResult {
+        contact,
+        session_id,
+        session_initialized: true,
+    })
+}
+
+/// Generate a one-time invite blob for remote contact exchange.
+#[tauri::command]
+fn generate_invite(ttl_hours: Option<u32>, state: State<AppState>) -> Result<String, String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.active_mut().ok_or("No identity created yet")?;
+    let identity = &persona.identity;
+
+    // Real X25519 and ML-KEM-1024 public keys from the identity
+    let our_pubkey = identity.x25519_public_key();
     let our_kem_pk = identity.kem_encap_key.clone();
 
-    let invite = contacts.generate_invite(our_pubkey, our_kem_pk, ttl_hours.unwrap_or(24));
+    let invite = persona
+        .contacts
+        .generate_invite(our_pubkey, our_kem_pk, ttl_hours.unwrap_or(24));
     invite.to_base64().map_err(|e| e.to_string())
 }
 
+/// Check an invite blob without importing it, for a confirmation screen.
+#[tauri::command]
+fn validate_invite(invite_b64: String, state: State<AppState>) -> Result<InvitePreview, String> {
+    let identities = state.real_identities()?;
+    let persona = identities.require_active().map_err(|e| e.to_string())?;
+    let invite = InviteBlob::from_base64(&invite_b64).map_err(|e| e.to_string())?;
+
+    persona
+        .contacts
+        .validate_invite(&invite)
+        .map_err(|e| e.to_string())
+}
+
 /// Import an invite blob and create a pending contact.
 #[tauri::command]
 fn import_invite(
@@ -500,38 +1186,269 @@ fn import_invite(
     alias: String,
     state: State<AppState>,
 ) -> Result<Contact, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
     let invite = InviteBlob::from_base64(&invite_b64).map_err(|e| e.to_string())?;
 
-    contacts
+    persona
+        .contacts
         .import_invite(&invite, alias)
         .map_err(|e| e.to_string())
 }
 
-/// List all contacts in memory.
+/// Result of acknowledging an imported invite
+#[derive(Debug, Serialize)]
+pub struct InviteAckResult {
+    /// Mailbox to deliver the ACK to (hex)
+    pub mailbox_id: String,
+    /// Encrypted ACK (hex)
+    pub ack_hex: String,
+}
+
+/// Generate the delivery receipt (ACK) for an imported invite.
+#[tauri::command]
+fn generate_invite_ack(
+    invite_b64: String,
+    state: State<AppState>,
+) -> Result<InviteAckResult, String> {
+    let identities = state.real_identities()?;
+    let persona = identities.require_active().map_err(|e| e.to_string())?;
+    let invite = InviteBlob::from_base64(&invite_b64).map_err(|e| e.to_string())?;
+
+    let (mailbox_id, ack) = persona
+        .contacts
+        .generate_ack(&invite, &persona.identity.x25519_secret)
+        .map_err(|e| e.to_string())?;
+    Ok(InviteAckResult {
+        mailbox_id: hex::encode(mailbox_id),
+        ack_hex: hex::encode(ack),
+    })
+}
+
+/// Result of processing an invite ACK
+#[derive(Debug, Serialize)]
+pub struct InviteAckProcessed {
+    /// Responder side: the contact the inviter's reply acknowledged
+    pub contact: Option<Contact>,
+    /// Inviter side: the reply to deliver back to the responder
+    pub reply: Option<InviteAckResult>,
+}
+
+/// Process an invite ACK received via the mixnet.
+#[tauri::command]
+fn process_invite_ack(
+    ack_hex: String,
+    state: State<AppState>,
+) -> Result<InviteAckProcessed, String> {
+    let ack = hex::decode(&ack_hex).map_err(|e| e.to_string())?;
+
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let outcome = persona
+        .contacts
+        .process_ack(&ack, &persona.identity.x25519_secret)
+        .map_err(|e| e.to_string())?;
+    Ok(match outcome {
+        AckOutcome::Replied { mailbox_id, reply } => InviteAckProcessed {
+            contact: None,
+            reply: Some(InviteAckResult {
+                mailbox_id: hex::encode(mailbox_id),
+                ack_hex: hex::encode(reply),
+            }),
+        },
+        AckOutcome::Acknowledged(contact) => InviteAckProcessed {
+            contact: Some(*contact),
+            reply: None,
+        },
+    })
+}
+
+/// List all contacts in memory, most recently active first.
 #[tauri::command]
 fn list_contacts(state: State<AppState>) -> Result<Vec<Contact>, String> {
-    let contacts = state.contacts.lock().map_err(|e| e.to_string())?;
-    Ok(contacts.list_contacts())
+    let identities = state.real_identities()?;
+    Ok(identities
+        .active()
+        .map(|persona| persona.contacts.list_by_activity())
+        .unwrap_or_default())
+}
+
+/// Clear a contact's unread count once its conversation has been viewed.
+#[tauri::command]
+fn mark_contact_read(contact_id: String, state: State<AppState>) -> Result<(), String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    persona
+        .contacts
+        .mark_read(&contact_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Save a private note about a contact, replacing any previous one.
+///
+/// The note is sealed under a key derived from the storage key, which
+/// `pin` unlocks.
+#[tauri::command]
+fn set_contact_note(
+    contact_id: String,
+    note: String,
+    pin: String,
+    state: State<AppState>,
+) -> Result<(), String> {
+    with_contact_notes(&state, &pin, |contacts, key| {
+        contacts.set_note(&contact_id, &note, key)
+    })
+}
+
+/// Read the private note about a contact, if it has one.
+#[tauri::command]
+fn get_contact_note(
+    contact_id: String,
+    pin: String,
+    state: State<AppState>,
+) -> Result<Option<String>, String> {
+    with_contact_notes(&state, &pin, |contacts, key| {
+        contacts.get_note(&contact_id, key)
+    })
+}
+
+/// Run `f` on the active contacts with the key sealing contact notes, from
+/// the storage key unlocked by `pin`.
+///
+/// Decoy mode is checked before the storage is touched, so a wipe cannot be
+/// told apart by the error.
+fn with_contact_notes<T>(
+    state: &AppState,
+    pin: &str,
+    f: impl FnOnce(&mut contacts::ContactStore, &[u8; 32]) -> Result<T, contacts::ContactError>,
+) -> Result<T, String> {
+    let mut identities = state.real_identities()?;
+    let key = {
+        let storage = state.storage.lock().map_err(|e| e.to_string())?;
+        storage
+            .as_ref()
+            .ok_or("Storage unavailable")?
+            .notes_key(pin)
+            .map_err(|e| e.to_string())?
+    };
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    f(&mut persona.contacts, &key).map_err(|e| e.to_string())
 }
 
 /// Delete a contact and securely zeroize its data.
 #[tauri::command]
 fn delete_contact(contact_id: String, state: State<AppState>) -> Result<bool, String> {
-    let mut contacts = state.contacts.lock().map_err(|e| e.to_string())?;
-    Ok(contacts.delete_contact(&contact_id).is_some())
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    Ok(persona.contacts.delete_contact(&contact_id).is_some())
+}
+
+/// Revoke a contact: keep the record but end and block its sessions.
+#[tauri::command]
+fn revoke_contact(contact_id: String, state: State<AppState>) -> Result<(), String> {
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    persona
+        .contacts
+        .revoke_contact(&contact_id)
+        .map_err(|e| e.to_string())?;
+
+    if let Some(contact) = persona.contacts.get_contact(&contact_id) {
+        persona.sessions.remove(&contact.session_id);
+    }
+    Ok(())
+}
+
+/// Apply a contact's signed key rotation (base64 `KeyUpdate`).
+///
+/// The session built on the old keys is dropped; the contact must be
+/// paired again before messaging resumes.
+#[tauri::command]
+fn apply_key_update(
+    contact_id: String,
+    update: String,
+    state: State<AppState>,
+) -> Result<Contact, String> {
+    let update = KeyUpdate::from_base64(&update).map_err(|e| e.to_string())?;
+
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    let contact = persona
+        .contacts
+        .apply_key_update(&contact_id, &update)
+        .map_err(|e| e.to_string())?;
+    persona.sessions.remove(&contact.session_id);
+    Ok(contact)
+}
+
+/// Safety number for a contact, to compare out of band at any time.
+#[tauri::command]
+fn get_safety_number(contact_id: String, state: State<AppState>) -> Result<String, String> {
+    let identities = state.real_identities()?;
+    let persona = identities.require_active().map_err(|e| e.to_string())?;
+    let contact = persona
+        .contacts
+        .get_contact(&contact_id)
+        .ok_or("Contact not found")?;
+    let their_identity = contact
+        .decode_identity_key()
+        .ok_or("Contact's identity key is unknown; pair again to compare safety numbers")?;
+    Ok(contacts::safety_number(
+        &persona.identity.x25519_public_key(),
+        &their_identity,
+    ))
+}
+
+/// Export all contacts as a passphrase-encrypted backup bundle (hex).
+///
+/// Contact notes are only included when `include_notes` is set.
+#[tauri::command]
+fn export_contacts_bundle(
+    passphrase: String,
+    include_notes: Option<bool>,
+    state: State<AppState>,
+) -> Result<String, String> {
+    let identities = state.real_identities()?;
+    let persona = identities.require_active().map_err(|e| e.to_string())?;
+    let bundle = if include_notes.unwrap_or(false) {
+        persona.contacts.export_bundle_with_notes(&passphrase)
+    } else {
+        persona.contacts.export_bundle(&passphrase)
+    };
+    Ok(hex::encode(bundle))
+}
+
+/// Import contacts from a backup bundle, returning how many were added.
+#[tauri::command]
+fn import_contacts_bundle(
+    bundle_hex: String,
+    passphrase: String,
+    state: State<AppState>,
+) -> Result<usize, String> {
+    let bundle = hex::decode(&bundle_hex).map_err(|e| e.to_string())?;
+
+    let mut identities = state.real_identities()?;
+    let persona = identities.require_active_mut().map_err(|e| e.to_string())?;
+    persona
+        .contacts
+        .import_bundle(&bundle, &passphrase)
+        .map_err(|e| e.to_string())
 }
 
 // ============================================================================
 // SECURITY COMMANDS
 // ============================================================================
 
+/// Plaintext sent to a trusted contact by a silent-alarm duress PIN.
+const DURESS_ALARM_MESSAGE: &[u8] = b"COMLOCK_DURESS_ALARM";
+
 /// Security status result
 #[derive(Debug, Serialize)]
 pub struct SecurityStatus {
     pub security_enabled: bool,
     pub has_pin: bool,
     pub has_duress_pin: bool,
+    pub duress_pin_count: usize,
     pub dead_man_days: u32,
     pub days_until_wipe: Option<i64>,
     pub panic_gesture_enabled: bool,
@@ -556,7 +1473,8 @@ fn get_security_status(state: State<AppState>) -> Result<SecurityStatus, String>
     Ok(SecurityStatus {
         security_enabled: config.security_enabled,
         has_pin: config.pin_hash.is_some(),
-        has_duress_pin: config.duress_pin_hash.is_some(),
+        has_duress_pin: !config.duress_entries.is_empty(),
+        duress_pin_count: config.duress_entries.len(),
         dead_man_days: config.dead_man_days,
         days_until_wipe: security::days_until_wipe(&config),
         panic_gesture_enabled: config.panic_gesture_enabled,
@@ -574,30 +1492,35 @@ fn setup_pin(pin: String, state: State<AppState>) -> Result<(), String> {
         return Err("PIN must be at least 4 characters".into());
     }
 
-    config.pin_hash = Some(security::set_pin(&pin));
+    let pin_hash = security::set_pin(&pin);
+    if config.is_duress_pin_hash(&pin_hash) {
+        return Err("PIN must be different from every duress PIN".into());
+    }
+
+    config.pin_hash = Some(pin_hash);
     config.security_enabled = true;
     config.update_access();
 
     Ok(())
 }
 
-/// Set up the duress PIN (different from normal PIN).
+/// Add a duress PIN (different from normal PIN) and the action it triggers;
+/// defaults to wiping and showing the decoy.
 #[tauri::command]
-fn setup_duress_pin(duress_pin: String, state: State<AppState>) -> Result<(), String> {
+fn setup_duress_pin(
+    duress_pin: String,
+    action: Option<DuressAction>,
+    state: State<AppState>,
+) -> Result<(), String> {
     let mut config = state.security_config.lock().map_err(|e| e.to_string())?;
 
-    let normal_hash = config.pin_hash.ok_or("Set normal PIN first")?;
-
     if duress_pin.len() < 4 {
         return Err("Duress PIN must be at least 4 characters".into());
     }
 
-    let duress_hash = security::set_duress_pin(&duress_pin, &normal_hash)
-        .ok_or("Duress PIN must be different from normal PIN")?;
-
-    config.duress_pin_hash = Some(duress_hash);
-
-    Ok(())
+    config
+        .add_duress_pin(&duress_pin, action.unwrap_or_default())
+        .map_err(|e| e.to_string())
 }
 
 /// Verify PIN and handle unlock/duress/wipe scenarios.
@@ -608,7 +1531,7 @@ fn verify_unlock(pin: String, state: State<AppState>) -> Result<UnlockResult, St
 
     // Check dead man's switch first
     if config.is_dead_man_triggered() {
-        wipe_state.trigger(WipeReason::DeadManSwitch);
+        state.wipe(&mut wipe_state, WipeReason::DeadManSwitch);
         return Ok(UnlockResult {
             success: true,
             is_decoy: true,
@@ -627,8 +1550,16 @@ fn verify_unlock(pin: String, state: State<AppState>) -> Result<UnlockResult, St
                 reason: "authenticated".into(),
             })
         }
-        PinResult::Duress => {
-            wipe_state.trigger(WipeReason::DuressPin);
+        PinResult::Duress(action) => {
+            match action {
+                DuressAction::DecoyOnly => wipe_state.enter_decoy(WipeReason::DuressPin),
+                DuressAction::WipeThenDecoy => state.wipe(&mut wipe_state, WipeReason::DuressPin),
+                DuressAction::SilentAlarm { contact_id } => {
+                    state.queue_silent_alarm(&contact_id);
+                    wipe_state.enter_decoy(WipeReason::DuressPin);
+                }
+            }
+            state.load_decoy_vault(&pin);
             Ok(UnlockResult {
                 success: true,
                 is_decoy: true,
@@ -638,7 +1569,7 @@ fn verify_unlock(pin: String, state: State<AppState>) -> Result<UnlockResult, St
         PinResult::Invalid => {
             let should_wipe = config.record_failed_attempt();
             if should_wipe {
-                wipe_state.trigger(WipeReason::MaxAttempts);
+                state.wipe(&mut wipe_state, WipeReason::MaxAttempts);
                 return Ok(UnlockResult {
                     success: true,
                     is_decoy: true,
@@ -659,7 +1590,7 @@ fn verify_unlock(pin: String, state: State<AppState>) -> Result<UnlockResult, St
             })
         }
         PinResult::MaxAttemptsExceeded => {
-            wipe_state.trigger(WipeReason::MaxAttempts);
+            state.wipe(&mut wipe_state, WipeReason::MaxAttempts);
             Ok(UnlockResult {
                 success: true,
                 is_decoy: true,
@@ -678,6 +1609,14 @@ fn configure_dead_man(days: u32, state: State<AppState>) -> Result<(), String> {
     Ok(())
 }
 
+/// Set how long a session may sit unused before it is evicted.
+#[tauri::command]
+fn configure_session_idle(secs: u64, state: State<AppState>) -> Result<(), String> {
+    let mut config = state.security_config.lock().map_err(|e| e.to_string())?;
+    config.session_idle_secs = secs;
+    Ok(())
+}
+
 /// Toggle panic gesture.
 #[tauri::command]
 fn toggle_panic_gesture(enabled: bool, state: State<AppState>) -> Result<(), String> {
@@ -696,11 +1635,106 @@ fn trigger_panic(state: State<AppState>) -> Result<(), String> {
     }
 
     let mut wipe_state = state.wipe_state.lock().map_err(|e| e.to_string())?;
-    wipe_state.trigger(WipeReason::PanicGesture);
+    state.wipe(&mut wipe_state, WipeReason::PanicGesture);
 
     Ok(())
 }
 
+/// Set the panic gesture pattern, cancel window and debounce.
+#[tauri::command]
+fn configure_panic_gesture(
+    config: PanicGestureConfig,
+    state: State<AppState>,
+) -> Result<(), String> {
+    let mut detector = state.panic_gesture.lock().map_err(|e| e.to_string())?;
+    detector.set_config(config);
+    Ok(())
+}
+
+/// Feed a raw gesture event to the panic gesture detector.
+///
+/// Wipes once the gesture is confirmed or its cancel window has run out;
+/// a backend timer enforces the window, so the wipe happens even if the
+/// UI never polls again. Returns whether a wipe is armed, so the UI can
+/// offer to cancel it.
+#[tauri::command]
+fn feed_panic_gesture(
+    event: GestureEvent,
+    app: tauri::AppHandle,
+    state: State<AppState>,
+) -> Result<bool, String> {
+    let mut detector = state.panic_gesture.lock().map_err(|e| e.to_string())?;
+    let was_armed = detector.is_armed();
+    detector.feed_event(event);
+    wipe_on_panic_gesture(&mut detector, event.at_ms(), &state)?;
+    if detector.is_armed() && !was_armed {
+        spawn_panic_gesture_timer(app, event.at_ms());
+    }
+    Ok(detector.is_armed())
+}
+
+/// Poll the detector at the armed wipe's deadline until it is committed
+/// or cancelled.
+///
+/// `now_ms` is the time of the arming event on the UI's clock; the timer
+/// follows that clock by sleeping for the remaining window. The deadline
+/// is re-read after each sleep, in case the window was reconfigured.
+fn spawn_panic_gesture_timer(app: tauri::AppHandle, mut now_ms: u64) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let deadline = {
+                let state = app.state::<AppState>();
+                let Ok(detector) = state.panic_gesture.lock() else {
+                    return;
+                };
+                match detector.deadline_ms() {
+                    Some(deadline) => deadline,
+                    None => return,
+                }
+            };
+            let wait = deadline.saturating_sub(now_ms);
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+            now_ms = now_ms.max(deadline);
+
+            let state = app.state::<AppState>();
+            let Ok(mut detector) = state.panic_gesture.lock() else {
+                return;
+            };
+            if wipe_on_panic_gesture(&mut detector, now_ms, &state).unwrap_or(true) {
+                return;
+            }
+        }
+    });
+}
+
+/// Check the panic gesture detector at `now_ms` (same clock as the events).
+///
+/// Returns whether a wipe was carried out.
+#[tauri::command]
+fn poll_panic_gesture(now_ms: u64, state: State<AppState>) -> Result<bool, String> {
+    let mut detector = state.panic_gesture.lock().map_err(|e| e.to_string())?;
+    wipe_on_panic_gesture(&mut detector, now_ms, &state)
+}
+
+/// Wipe if the detector has committed a wipe and the gesture is enabled.
+fn wipe_on_panic_gesture(
+    detector: &mut PanicGestureDetector,
+    now_ms: u64,
+    state: &AppState,
+) -> Result<bool, String> {
+    if !detector.poll_should_wipe(now_ms) {
+        return Ok(false);
+    }
+    let config = state.security_config.lock().map_err(|e| e.to_string())?;
+    if !config.panic_gesture_enabled {
+        return Ok(false);
+    }
+
+    let mut wipe_state = state.wipe_state.lock().map_err(|e| e.to_string())?;
+    state.wipe(&mut wipe_state, WipeReason::PanicGesture);
+    Ok(true)
+}
+
 /// Get decoy contacts (for decoy mode).
 #[tauri::command]
 fn get_decoy_contacts(state: State<AppState>) -> Result<Vec<DecoyContact>, String> {
@@ -718,6 +1752,35 @@ fn get_decoy_messages(
     Ok(vault.get_messages(&contact_id))
 }
 
+/// List the user's own decoy conversations (normal mode only).
+#[tauri::command]
+fn list_user_decoy_conversations(
+    duress_pin: String,
+    state: State<AppState>,
+) -> Result<Vec<DecoyConversation>, String> {
+    state.edit_decoy_vault(&duress_pin, |vault| vault.conversations.clone())
+}
+
+/// Add or replace a user decoy conversation (normal mode only).
+#[tauri::command]
+fn save_decoy_conversation(
+    conversation: DecoyConversation,
+    duress_pin: String,
+    state: State<AppState>,
+) -> Result<(), String> {
+    state.edit_decoy_vault(&duress_pin, |vault| vault.upsert_conversation(conversation))
+}
+
+/// Remove a user decoy conversation (normal mode only).
+#[tauri::command]
+fn remove_decoy_conversation(
+    contact_id: String,
+    duress_pin: String,
+    state: State<AppState>,
+) -> Result<bool, String> {
+    state.edit_decoy_vault(&duress_pin, |vault| vault.remove_conversation(&contact_id))
+}
+
 /// Check if in decoy mode.
 #[tauri::command]
 fn is_decoy_mode(state: State<AppState>) -> Result<bool, String> {
@@ -725,6 +1788,24 @@ fn is_decoy_mode(state: State<AppState>) -> Result<bool, String> {
     Ok(wipe_state.should_show_decoy())
 }
 
+// ============================================================================
+// SELF-TEST
+// ============================================================================
+
+/// Check the crypto and transport primitives before they are trusted.
+///
+/// The UI runs this on launch and should refuse to continue if it fails.
+#[tauri::command]
+fn run_self_test() -> SelfTestResult {
+    let crypto_error = comlock_crypto::self_test().err().map(|e| e.to_string());
+    let transport_error = comlock_transport::self_test().err().map(|e| e.to_string());
+    SelfTestResult {
+        passed: crypto_error.is_none() && transport_error.is_none(),
+        crypto_error,
+        transport_error,
+    }
+}
+
 // ============================================================================
 // ENTRY POINT
 // ============================================================================
@@ -734,38 +1815,80 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(AppState::default())
+        .setup(|app| {
+            let app_data_dir = app.path().app_data_dir()?;
+            let state = app.state::<AppState>();
+            *state.storage.lock().map_err(|e| e.to_string())? =
+                Some(SecureStorage::new(app_data_dir));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
+            // Self-test
+            run_self_test,
             // Identity
             create_identity,
+            create_identity_named,
             recover_identity,
+            list_identities,
+            switch_identity,
+            delete_identity,
             // Sessions
             init_session,
             trigger_kem,
+            get_session_status,
+            cleanup_idle_sessions,
             // Crypto
             encrypt,
             decrypt,
+            decrypt_bytes,
+            forward,
             // Transport Layer
             send_via_mixnet,
             poll_messages,
             get_transport_status,
+            take_outbox,
             // Contact Exchange
             generate_qr_payload,
             process_scanned_qr,
             confirm_sas,
+            share_kem_key,
+            receive_kem_key,
+            start_code_exchange,
+            complete_code_exchange,
+            pairing_code_mailbox,
+            confirm_code_sas,
             generate_invite,
+            validate_invite,
             import_invite,
+            generate_invite_ack,
+            process_invite_ack,
             list_contacts,
+            mark_contact_read,
+            set_contact_note,
+            get_contact_note,
             delete_contact,
+            revoke_contact,
+            apply_key_update,
+            get_safety_number,
+            export_contacts_bundle,
+            import_contacts_bundle,
             // Security
             get_security_status,
             setup_pin,
             setup_duress_pin,
             verify_unlock,
             configure_dead_man,
+            configure_session_idle,
             toggle_panic_gesture,
+            configure_panic_gesture,
             trigger_panic,
+            feed_panic_gesture,
+            poll_panic_gesture,
             get_decoy_contacts,
             get_decoy_messages,
+            list_user_decoy_conversations,
+            save_decoy_conversation,
+            remove_decoy_conversation,
             is_decoy_mode,
         ])
         .run(tauri::generate_context!())
@@ -776,6 +1899,7 @@ pub fn run() {
 // WORD LIST (Simplified - use full BIP-39 in production)
 // ============================================================================
 
+#[allow(dead_code)]
 const WORD_LIST: &[&str] = &[
     "abandon", "ability", "able", "about", "above", "absent", "absorb", "abstract", "absurd",
     "abuse", "access", "accident", "account", "accuse", "achieve", "acid", "acquire", "across",
@@ -805,3 +1929,310 @@ const WORD_LIST: &[&str] = &[
     "cabin", "cable", "cactus", "cage", "cake", "call", "calm", "camera", "camp", "can", "canal",
     "cancel", "candy",
 ];
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_self_test_passes() {
+        let result = run_self_test();
+        assert!(result.passed, "{:?}", result);
+        assert!(result.crypto_error.is_none());
+        assert!(result.transport_error.is_none());
+    }
+
+    #[test]
+    fn test_recovery_reproduces_identity_keys() {
+        let words: Vec<String> = bip39::Mnemonic::from_entropy(&[0x5au8; 32])
+            .unwrap()
+            .words()
+            .map(str::to_string)
+            .collect();
+
+        let first = Identity::from_words(&words).unwrap();
+        let second = Identity::from_words(&words).unwrap();
+
+        assert_eq!(first.public_id, second.public_id);
+        assert_eq!(first.kem_encap_key, second.kem_encap_key);
+        assert_eq!(first.kem_decap_key, second.kem_decap_key);
+        assert_eq!(first.x25519_secret, second.x25519_secret);
+        assert_eq!(first.kem_encap_key.len(), 1568);
+
+        let other: Vec<String> = bip39::Mnemonic::from_entropy(&[0xa5u8; 32])
+            .unwrap()
+            .words()
+            .map(str::to_string)
+            .collect();
+        let other = Identity::from_words(&other).unwrap();
+        assert_ne!(first.kem_encap_key, other.kem_encap_key);
+        assert_ne!(first.x25519_secret, other.x25519_secret);
+    }
+
+    #[test]
+    fn test_identity_derivation_zeroizes_seed() {
+        let mnemonic = bip39::Mnemonic::from_entropy(&[0x33u8; 32]).unwrap();
+        let words: Vec<String> = mnemonic.words().map(str::to_string).collect();
+
+        let mut seed = mnemonic.to_seed("");
+        let identity = Identity::from_seed(words, &mut seed);
+        assert_eq!(seed, [0u8; 64]);
+
+        let expected = Identity::from_mnemonic(&mnemonic);
+        assert_eq!(identity.root_key, expected.root_key);
+        assert_eq!(identity.kem_decap_key, expected.kem_decap_key);
+        assert_ne!(identity.root_key, [0u8; 32]);
+
+        let mut discarded = identity.clone();
+        discarded.zeroize();
+        assert!(discarded.kem_decap_key.is_empty());
+        assert_eq!(discarded.x25519_secret, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_legacy_identity_rederives_x25519_key() {
+        let mnemonic = bip39::Mnemonic::from_entropy(&[0x11u8; 32]).unwrap();
+        let identity = Identity::from_mnemonic(&mnemonic);
+
+        // Saved before the X25519 key existed
+        let mut json: serde_json::Value = serde_json::to_value(&identity).unwrap();
+        json.as_object_mut().unwrap().remove("x25519_secret");
+        let loaded: Identity = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(loaded.x25519_secret, identity.x25519_secret);
+        assert_ne!(loaded.x25519_secret, [0u8; 32]);
+
+        // A mnemonic that does not match the stored root key fails loudly
+        json["root_key"] = serde_json::to_value([0u8; 32]).unwrap();
+        assert!(serde_json::from_value::<Identity>(json).is_err());
+    }
+
+    #[test]
+    fn test_invite_carries_usable_x25519_key() {
+        let mnemonic = bip39::Mnemonic::from_entropy(&[0x11u8; 32]).unwrap();
+        let identity = Identity::from_mnemonic(&mnemonic);
+        let mut contacts = contacts::ContactStore::new();
+
+        let invite = contacts.generate_invite(
+            identity.x25519_public_key(),
+            identity.kem_encap_key.clone(),
+            1,
+        );
+        let invite = InviteBlob::from_base64(&invite.to_base64().unwrap()).unwrap();
+
+        // The invite key is the public half of the stored identity secret
+        let secret = x25519_dalek::StaticSecret::from(identity.x25519_secret);
+        assert_eq!(
+            invite.sender_pubkey,
+            x25519_dalek::PublicKey::from(&secret).to_bytes()
+        );
+
+        // A peer completes the same DH as the identity
+        let peer = x25519_dalek::StaticSecret::random_from_rng(rand::thread_rng());
+        let peer_public = x25519_dalek::PublicKey::from(&peer).to_bytes();
+        let peer_shared = peer
+            .diffie_hellman(&x25519_dalek::PublicKey::from(invite.sender_pubkey))
+            .to_bytes();
+        let our_shared = identity.x25519_diffie_hellman(&peer_public);
+        assert_eq!(peer_shared, our_shared);
+        assert_ne!(our_shared, [0u8; 32]);
+    }
+
+    /// An app state whose active identity holds a "send" and a matching
+    /// "receive" session.
+    fn state_with_session_pair() -> AppState {
+        let state = AppState::default();
+        let mnemonic = bip39::Mnemonic::from_entropy(&[4u8; 32]).unwrap();
+        let mut identities = state.identities.lock().unwrap();
+        identities.insert("Work".into(), Identity::from_mnemonic(&mnemonic));
+        let persona = identities.require_active_mut().unwrap();
+        persona
+            .sessions
+            .insert("send".into(), RatchetState::new([8u8; 32], true).unwrap());
+        persona.sessions.insert(
+            "receive".into(),
+            RatchetState::new([8u8; 32], false).unwrap(),
+        );
+        drop(identities);
+        state
+    }
+
+    fn encrypt_for_pair(state: &AppState, plaintext: &[u8]) -> String {
+        let mut identities = state.identities.lock().unwrap();
+        let persona = identities.require_active_mut().unwrap();
+        let ratchet = persona.sessions.get_mut("send").unwrap();
+        hex::encode(encrypt_message(plaintext, ratchet).unwrap())
+    }
+
+    #[test]
+    fn test_decrypt_text_message() {
+        let state = state_with_session_pair();
+        let ciphertext_hex = encrypt_for_pair(&state, "héllo ✓".as_bytes());
+
+        let plaintext = decrypt_session_message(&state, "receive", &ciphertext_hex).unwrap();
+        let result = decode_plaintext(plaintext, false).unwrap();
+        assert_eq!(result.plaintext, "héllo ✓");
+    }
+
+    #[test]
+    fn test_decrypt_binary_payload() {
+        let state = state_with_session_pair();
+        let payload = [0xff, 0xfe, 0x00, 0x80];
+        let first = encrypt_for_pair(&state, &payload);
+        let second = encrypt_for_pair(&state, &payload);
+
+        // The bytes path returns the payload as is
+        let plaintext = decrypt_session_message(&state, "receive", &first).unwrap();
+        assert_eq!(plaintext, payload);
+
+        // The text path names the UTF-8 failure, or replaces it if asked
+        let plaintext = decrypt_session_message(&state, "receive", &second).unwrap();
+        let err = decode_plaintext(plaintext.clone(), false).unwrap_err();
+        assert!(err.contains("UTF-8"));
+        let lossy = decode_plaintext(plaintext, true).unwrap();
+        assert!(lossy.plaintext.contains(char::REPLACEMENT_CHARACTER));
+
+        // A tampered message is a decryption error, not a UTF-8 one
+        let mut tampered = hex::decode(encrypt_for_pair(&state, &payload)).unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let err = decrypt_session_message(&state, "receive", &hex::encode(tampered)).unwrap_err();
+        assert!(err.starts_with("Decryption failed"));
+    }
+
+    #[test]
+    fn test_sending_touches_the_contact() {
+        let state = state_with_session_pair();
+        let session_id = {
+            let mut identities = state.identities.lock().unwrap();
+            let persona = identities.require_active_mut().unwrap();
+            let invite = InviteBlob::new([6u8; 32], vec![], 3600);
+            let contact = persona
+                .contacts
+                .import_invite(&invite, "Alice".into())
+                .unwrap();
+            persona.sessions.insert(
+                contact.session_id.clone(),
+                RatchetState::new([9u8; 32], true).unwrap(),
+            );
+            contact.session_id
+        };
+
+        encrypt_session_message(&state, &session_id, b"hi").unwrap();
+
+        let identities = state.identities.lock().unwrap();
+        let contact = identities
+            .active()
+            .unwrap()
+            .contacts
+            .get_by_session(&session_id)
+            .unwrap();
+        assert!(contact.last_activity > 0);
+        assert_eq!(contact.unread_count, 0);
+    }
+
+    #[test]
+    fn test_duress_wipe_clears_memory() {
+        let state = AppState::default();
+        {
+            let mut identities = state.identities.lock().unwrap();
+            let mnemonic = bip39::Mnemonic::from_entropy(&[3u8; 32]).unwrap();
+            let identity = Identity::from_mnemonic(&mnemonic);
+            identities.insert("Work".into(), identity);
+            let persona = identities.require_active_mut().unwrap();
+            persona.sessions.insert(
+                "session".into(),
+                RatchetState::new([7u8; 32], true).unwrap(),
+            );
+        }
+
+        let mut wipe_state = WipeState::default();
+        state.wipe(&mut wipe_state, WipeReason::DuressPin);
+
+        assert!(wipe_state.should_show_decoy());
+        assert_eq!(wipe_state.reason, WipeReason::DuressPin);
+        let identities = state.identities.lock().unwrap();
+        assert!(identities.is_empty());
+        assert!(identities.active().is_none());
+    }
+
+    #[test]
+    fn test_decoy_mode_refuses_real_data() {
+        let state = state_with_session_pair();
+        let ciphertext_hex = encrypt_for_pair(&state, b"real");
+        state
+            .outbox
+            .lock()
+            .unwrap()
+            .push(("send".into(), vec![1, 2, 3]));
+
+        state
+            .wipe_state
+            .lock()
+            .unwrap()
+            .enter_decoy(WipeReason::DuressPin);
+        assert_eq!(
+            decrypt_session_message(&state, "receive", &ciphertext_hex).unwrap_err(),
+            "Not available"
+        );
+        assert!(state.real_identities().is_err());
+
+        // Queued ciphertexts still drain, and only once
+        assert_eq!(
+            state.take_outbox(),
+            vec![("send".to_string(), vec![1, 2, 3])]
+        );
+        assert!(state.take_outbox().is_empty());
+    }
+
+    #[test]
+    fn test_contact_notes_without_prior_setup() {
+        let state = state_with_session_pair();
+        let dir = std::env::temp_dir().join(format!("comlock_notes_{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let params = storage::Argon2Params {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        *state.storage.lock().unwrap() = Some(SecureStorage::with_params(dir, params));
+        let contact_id = {
+            let mut identities = state.identities.lock().unwrap();
+            let persona = identities.require_active_mut().unwrap();
+            let invite = InviteBlob::new([6u8; 32], vec![], 3600);
+            persona
+                .contacts
+                .import_invite(&invite, "Bob".into())
+                .unwrap()
+                .id
+        };
+
+        // The storage key is created on first use
+        with_contact_notes(&state, "1234", |contacts, key| {
+            contacts.set_note(&contact_id, "met at the station", key)
+        })
+        .unwrap();
+        let note = with_contact_notes(&state, "1234", |contacts, key| {
+            contacts.get_note(&contact_id, key)
+        })
+        .unwrap();
+        assert_eq!(note.as_deref(), Some("met at the station"));
+
+        // In decoy mode the error is the same whether storage is there or not
+        state
+            .wipe_state
+            .lock()
+            .unwrap()
+            .enter_decoy(WipeReason::DuressPin);
+        let read = || with_contact_notes(&state, "1234", |_, _| Ok(()));
+        assert_eq!(read().unwrap_err(), "Not available");
+        let storage = state.storage.lock().unwrap().take().unwrap();
+        assert_eq!(read().unwrap_err(), "Not available");
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+}
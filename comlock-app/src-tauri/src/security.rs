@@ -1,14 +1,15 @@
 //! Security Module for ComLock
 //!
 //! Implements panic-layer security features including:
-//! - Duress PIN (triggers silent wipe and decoy mode)
+//! - Duress PINs (decoy mode, optionally after a silent wipe or alarm)
 //! - Dead Man's Switch (auto-wipe after inactivity)
+//! - Panic gesture (wipe on a deliberate hold, with a window to cancel)
 //! - Secure deletion with memory zeroization
 
+use comlock_crypto::ct_eq;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::time::{SystemTime, UNIX_EPOCH};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
 // ============================================================================
@@ -21,9 +22,13 @@ pub struct SecurityConfig {
     /// SHA-256 hash of the normal unlock PIN
     #[serde(with = "option_hex_32")]
     pub pin_hash: Option<[u8; 32]>,
-    /// SHA-256 hash of the duress PIN (triggers wipe)
-    #[serde(with = "option_hex_32")]
-    pub duress_pin_hash: Option<[u8; 32]>,
+    /// Duress PINs and what each one triggers
+    #[serde(
+        default,
+        alias = "duress_pin_hash",
+        deserialize_with = "duress_entries::deserialize"
+    )]
+    pub duress_entries: Vec<DuressEntry>,
     /// Days until auto-wipe (0 = disabled)
     pub dead_man_days: u32,
     /// Last time the app was accessed (Unix timestamp)
@@ -36,19 +41,29 @@ pub struct SecurityConfig {
     pub max_failed_attempts: u32,
     /// Whether security is enabled at all
     pub security_enabled: bool,
+    /// Seconds a ratchet session may sit unused before it is evicted from
+    /// memory
+    #[serde(default = "default_session_idle_secs")]
+    pub session_idle_secs: u64,
+}
+
+/// Default session idle timeout: 15 minutes
+fn default_session_idle_secs() -> u64 {
+    15 * 60
 }
 
 impl Default for SecurityConfig {
     fn default() -> Self {
         Self {
             pin_hash: None,
-            duress_pin_hash: None,
+            duress_entries: Vec::new(),
             dead_man_days: 0,
             last_accessed: current_timestamp(),
             panic_gesture_enabled: true,
             failed_attempts: 0,
             max_failed_attempts: 10,
             security_enabled: false,
+            session_idle_secs: default_session_idle_secs(),
         }
     }
 }
@@ -71,6 +86,40 @@ impl SecurityConfig {
         self.failed_attempts = 0;
     }
 
+    /// Add a duress PIN, or change the action of an existing one.
+    ///
+    /// The normal PIN must be set first and the duress PIN must differ
+    /// from it.
+    pub fn add_duress_pin(
+        &mut self,
+        pin: &str,
+        action: DuressAction,
+    ) -> Result<(), DuressPinError> {
+        let normal_hash = self.pin_hash.ok_or(DuressPinError::NoNormalPin)?;
+        let hash = set_duress_pin(pin, &normal_hash).ok_or(DuressPinError::MatchesNormalPin)?;
+
+        if let Some(entry) = self
+            .duress_entries
+            .iter_mut()
+            .find(|e| ct_eq(&e.hash, &hash))
+        {
+            entry.action = action;
+            return Ok(());
+        }
+        if self.duress_entries.len() >= MAX_DURESS_PINS {
+            return Err(DuressPinError::TooMany);
+        }
+        self.duress_entries.push(DuressEntry { hash, action });
+        Ok(())
+    }
+
+    /// Whether `pin_hash` belongs to one of the duress PINs
+    pub fn is_duress_pin_hash(&self, pin_hash: &[u8; 32]) -> bool {
+        self.duress_entries
+            .iter()
+            .any(|entry| ct_eq(&entry.hash, pin_hash))
+    }
+
     /// Record a failed PIN attempt
     pub fn record_failed_attempt(&mut self) -> bool {
         self.failed_attempts += 1;
@@ -81,6 +130,47 @@ impl SecurityConfig {
     }
 }
 
+// ============================================================================
+// DURESS PINS
+// ============================================================================
+
+/// Maximum number of duress PINs
+pub const MAX_DURESS_PINS: usize = 8;
+
+/// What entering a duress PIN does
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DuressAction {
+    /// Show the decoy vault, leaving the real data in place
+    DecoyOnly,
+    /// Wipe all real data, then show the decoy vault
+    #[default]
+    WipeThenDecoy,
+    /// Show the decoy vault and quietly alert a trusted contact
+    SilentAlarm { contact_id: String },
+}
+
+/// A duress PIN and the action it triggers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuressEntry {
+    /// SHA-256 hash of the duress PIN
+    #[serde(with = "hex_32")]
+    pub hash: [u8; 32],
+    /// What entering this PIN does
+    pub action: DuressAction,
+}
+
+/// Why a duress PIN could not be added
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DuressPinError {
+    #[error("Set normal PIN first")]
+    NoNormalPin,
+    #[error("Duress PIN must be different from normal PIN")]
+    MatchesNormalPin,
+    #[error("At most {MAX_DURESS_PINS} duress PINs can be set")]
+    TooMany,
+}
+
 // ============================================================================
 // PIN VERIFICATION
 // ============================================================================
@@ -90,8 +180,8 @@ impl SecurityConfig {
 pub enum PinResult {
     /// Correct normal PIN - proceed to app
     Normal,
-    /// Duress PIN entered - trigger silent wipe
-    Duress,
+    /// Duress PIN entered - carry out its action
+    Duress(DuressAction),
     /// Wrong PIN - increment failure counter
     Invalid,
     /// No PIN is set - proceed to app
@@ -120,11 +210,31 @@ impl Pin {
     /// Constant-time comparison of PIN hash
     pub fn verify(&self, expected_hash: &[u8; 32]) -> bool {
         let hash = self.hash();
-        constant_time_eq(&hash, expected_hash)
+        ct_eq(&hash, expected_hash)
     }
 }
 
-/// Verify a PIN against the security config
+#[cfg(test)]
+thread_local! {
+    /// PIN hash comparisons made by `verify_pin` on this thread
+    static PIN_COMPARISONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Constant-time comparison of an entered PIN's hash against a stored one
+fn compare_pin_hash(hash: &[u8; 32], expected_hash: &[u8; 32]) -> bool {
+    #[cfg(test)]
+    PIN_COMPARISONS.with(|count| count.set(count.get() + 1));
+    ct_eq(hash, expected_hash)
+}
+
+/// Verify a PIN against the security config.
+///
+/// The work done does not depend on which PIN was entered: the PIN is
+/// compared against every duress slot (up to `MAX_DURESS_PINS`, padding
+/// with a dummy hash) and the normal PIN hash, with no early return, and
+/// only then are the results folded into a `PinResult`. The response time
+/// therefore does not reveal whether a duress, normal or wrong PIN was
+/// entered.
 pub fn verify_pin(pin: &str, config: &SecurityConfig) -> PinResult {
     // If security is not enabled, allow access
     if !config.security_enabled {
@@ -136,26 +246,29 @@ pub fn verify_pin(pin: &str, config: &SecurityConfig) -> PinResult {
         return PinResult::MaxAttemptsExceeded;
     }
 
-    let pin = Pin::new(pin.to_string());
+    let hash = Pin::new(pin.to_string()).hash();
+    let dummy_hash = [0u8; 32];
 
-    // Check duress PIN first (if set)
-    if let Some(duress_hash) = &config.duress_pin_hash {
-        if pin.verify(duress_hash) {
-            return PinResult::Duress;
+    // Compare against every duress slot, keeping the first match
+    let mut duress_action = None;
+    for slot in 0..MAX_DURESS_PINS.max(config.duress_entries.len()) {
+        let entry = config.duress_entries.get(slot);
+        let matched = compare_pin_hash(&hash, entry.map_or(&dummy_hash, |entry| &entry.hash));
+        if matched & entry.is_some() & duress_action.is_none() {
+            duress_action = entry.map(|entry| entry.action.clone());
         }
     }
 
-    // Check normal PIN
-    if let Some(pin_hash) = &config.pin_hash {
-        if pin.verify(pin_hash) {
-            return PinResult::Normal;
-        }
-    } else {
+    // Compare against the normal PIN even if a duress PIN matched
+    let normal = compare_pin_hash(&hash, config.pin_hash.as_ref().unwrap_or(&dummy_hash));
+
+    match (duress_action, &config.pin_hash) {
+        (Some(action), _) => PinResult::Duress(action),
         // No PIN set but security enabled means we just need any PIN
-        return PinResult::NoPinSet;
+        (None, None) => PinResult::NoPinSet,
+        (None, Some(_)) if normal => PinResult::Normal,
+        (None, Some(_)) => PinResult::Invalid,
     }
-
-    PinResult::Invalid
 }
 
 /// Set the normal unlock PIN
@@ -170,7 +283,7 @@ pub fn set_duress_pin(pin: &str, normal_pin_hash: &[u8; 32]) -> Option<[u8; 32]>
     let hash = pin.hash();
 
     // Ensure duress PIN is different from normal PIN
-    if constant_time_eq(&hash, normal_pin_hash) {
+    if ct_eq(&hash, normal_pin_hash) {
         return None;
     }
 
@@ -186,6 +299,8 @@ pub fn set_duress_pin(pin: &str, normal_pin_hash: &[u8; 32]) -> Option<[u8; 32]>
 pub struct WipeState {
     /// Whether a wipe has been triggered
     pub wiped: bool,
+    /// Whether decoy mode was entered without wiping
+    pub decoy: bool,
     /// Reason for wipe
     pub reason: WipeReason,
 }
@@ -208,9 +323,15 @@ impl WipeState {
         self.reason = reason;
     }
 
+    /// Show the decoy without wiping anything
+    pub fn enter_decoy(&mut self, reason: WipeReason) {
+        self.decoy = true;
+        self.reason = reason;
+    }
+
     /// Check if app should show decoy
     pub fn should_show_decoy(&self) -> bool {
-        self.wiped
+        self.wiped || self.decoy
     }
 }
 
@@ -236,24 +357,178 @@ pub fn days_until_wipe(config: &SecurityConfig) -> Option<i64> {
     Some(days_left.max(0))
 }
 
+// ============================================================================
+// PANIC GESTURE
+// ============================================================================
+
+/// The gesture that arms a panic wipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanicPattern {
+    /// Touch points (or hardware buttons) held down together
+    pub fingers: u8,
+    /// Shortest hold that counts, in milliseconds
+    pub hold_ms: u64,
+}
+
+impl Default for PanicPattern {
+    /// A 3-finger hold of 2 seconds
+    fn default() -> Self {
+        Self {
+            fingers: 3,
+            hold_ms: 2000,
+        }
+    }
+}
+
+/// Settings for [`PanicGestureDetector`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanicGestureConfig {
+    /// Gesture that arms the wipe
+    pub pattern: PanicPattern,
+    /// How long an armed wipe can still be cancelled, in milliseconds
+    pub confirm_window_ms: u64,
+    /// Matching holds this soon after an accepted one are ignored as
+    /// bounce, in milliseconds
+    pub debounce_ms: u64,
+}
+
+impl Default for PanicGestureConfig {
+    fn default() -> Self {
+        Self {
+            pattern: PanicPattern::default(),
+            confirm_window_ms: 3000,
+            debounce_ms: 500,
+        }
+    }
+}
+
+/// A raw gesture event, timestamped in milliseconds on a monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GestureEvent {
+    /// `fingers` points were held for `duration_ms` and released at `at_ms`
+    Hold {
+        fingers: u8,
+        duration_ms: u64,
+        at_ms: u64,
+    },
+    /// The user confirmed the armed wipe
+    Confirm { at_ms: u64 },
+    /// The user cancelled the armed wipe
+    Cancel { at_ms: u64 },
+}
+
+impl GestureEvent {
+    /// When the event happened
+    pub fn at_ms(&self) -> u64 {
+        match *self {
+            GestureEvent::Hold { at_ms, .. }
+            | GestureEvent::Confirm { at_ms }
+            | GestureEvent::Cancel { at_ms } => at_ms,
+        }
+    }
+}
+
+/// Turns raw gesture events into a decision to wipe.
+///
+/// A hold matching the pattern arms the wipe. It commits when the user
+/// confirms or when the confirm window runs out, whichever comes first;
+/// cancelling within the window disarms it. An accidental pocket press
+/// can so be undone, while a user under duress has nothing more to do
+/// after the gesture.
+#[derive(Debug, Clone, Default)]
+pub struct PanicGestureDetector {
+    config: PanicGestureConfig,
+    /// When the pending wipe was armed
+    armed_at: Option<u64>,
+    /// When the last matching hold was accepted, for debouncing
+    last_hold_at: Option<u64>,
+    /// The wipe was committed but not yet polled
+    committed: bool,
+}
+
+impl PanicGestureDetector {
+    /// A detector using `config`
+    pub fn new(config: PanicGestureConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Process one gesture event
+    pub fn feed_event(&mut self, event: GestureEvent) {
+        self.expire(event.at_ms());
+        match event {
+            GestureEvent::Hold {
+                fingers,
+                duration_ms,
+                at_ms,
+            } => {
+                let pattern = self.config.pattern;
+                if fingers != pattern.fingers || duration_ms < pattern.hold_ms {
+                    return;
+                }
+                let bounced = self
+                    .last_hold_at
+                    .is_some_and(|last| at_ms.saturating_sub(last) < self.config.debounce_ms);
+                if bounced || self.committed {
+                    return;
+                }
+                self.last_hold_at = Some(at_ms);
+                self.armed_at.get_or_insert(at_ms);
+            }
+            GestureEvent::Confirm { .. } => {
+                if self.armed_at.take().is_some() {
+                    self.committed = true;
+                }
+            }
+            GestureEvent::Cancel { .. } => {
+                self.armed_at = None;
+            }
+        }
+    }
+
+    /// Replace the settings; a wipe already armed keeps its arming time
+    pub fn set_config(&mut self, config: PanicGestureConfig) {
+        self.config = config;
+    }
+
+    /// Whether a wipe is armed and can still be cancelled
+    pub fn is_armed(&self) -> bool {
+        self.armed_at.is_some()
+    }
+
+    /// When the armed wipe commits unless cancelled first
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.armed_at
+            .map(|armed_at| armed_at.saturating_add(self.config.confirm_window_ms))
+    }
+
+    /// Whether the wipe should happen now; true once per committed wipe
+    pub fn poll_should_wipe(&mut self, now_ms: u64) -> bool {
+        self.expire(now_ms);
+        std::mem::take(&mut self.committed)
+    }
+
+    /// Commit an armed wipe whose confirm window has run out
+    fn expire(&mut self, now_ms: u64) {
+        if let Some(armed_at) = self.armed_at {
+            if now_ms.saturating_sub(armed_at) >= self.config.confirm_window_ms {
+                self.armed_at = None;
+                self.committed = true;
+            }
+        }
+    }
+}
+
 // ============================================================================
 // UTILITIES
 // ============================================================================
 
 /// Get current Unix timestamp
 fn current_timestamp() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64
-}
-
-/// Constant-time byte comparison
-fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
-    a.iter()
-        .zip(b.iter())
-        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
-        == 0
+    crate::clock::now_unix()
 }
 
 /// Generate a random salt
@@ -263,6 +538,58 @@ pub fn generate_salt() -> [u8; 16] {
     salt
 }
 
+// Custom serde for [u8; 32]
+mod hex_32 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&hex::encode(value))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex::decode(&s).map_err(serde::de::Error::custom)?;
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("invalid length"))
+    }
+}
+
+// Duress entries, also accepting the single `duress_pin_hash` of older configs
+mod duress_entries {
+    use super::{DuressAction, DuressEntry};
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Entries(Vec<DuressEntry>),
+        Legacy(#[serde(with = "super::option_hex_32")] Option<[u8; 32]>),
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<DuressEntry>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Entries(entries) => entries,
+            Stored::Legacy(hash) => hash
+                .map(|hash| DuressEntry {
+                    hash,
+                    action: DuressAction::WipeThenDecoy,
+                })
+                .into_iter()
+                .collect(),
+        })
+    }
+}
+
 // Custom serde for Option<[u8; 32]>
 mod option_hex_32 {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -332,6 +659,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_verify_pin_normal() {
         let mut config = SecurityConfig::default();
         config.security_enabled = true;
@@ -342,17 +670,53 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_verify_pin_duress() {
         let mut config = SecurityConfig::default();
         config.security_enabled = true;
         config.pin_hash = Some(set_pin("1234"));
-        config.duress_pin_hash = set_duress_pin("9999", &config.pin_hash.unwrap());
+        config
+            .add_duress_pin("9999", DuressAction::default())
+            .unwrap();
 
         assert_eq!(verify_pin("1234", &config), PinResult::Normal);
-        assert_eq!(verify_pin("9999", &config), PinResult::Duress);
+        assert_eq!(
+            verify_pin("9999", &config),
+            PinResult::Duress(DuressAction::WipeThenDecoy)
+        );
         assert_eq!(verify_pin("wrong", &config), PinResult::Invalid);
     }
 
+    #[test]
+    fn test_verify_pin_does_constant_work() {
+        let mut config = SecurityConfig {
+            security_enabled: true,
+            pin_hash: Some(set_pin("1234")),
+            ..Default::default()
+        };
+        config
+            .add_duress_pin("9999", DuressAction::default())
+            .unwrap();
+
+        let comparisons = |pin: &str, config: &SecurityConfig| {
+            PIN_COMPARISONS.with(|count| count.set(0));
+            verify_pin(pin, config);
+            PIN_COMPARISONS.with(|count| count.get())
+        };
+
+        // Every duress slot and the normal PIN, whichever PIN is entered
+        let expected = MAX_DURESS_PINS + 1;
+        assert_eq!(comparisons("1234", &config), expected);
+        assert_eq!(comparisons("9999", &config), expected);
+        assert_eq!(comparisons("wrong", &config), expected);
+
+        // Nor does the number of duress PINs configured change the work
+        config.duress_entries.clear();
+        assert_eq!(comparisons("1234", &config), expected);
+        config.pin_hash = None;
+        assert_eq!(comparisons("1234", &config), expected);
+    }
+
     #[test]
     fn test_duress_pin_must_be_different() {
         let normal_hash = set_pin("1234");
@@ -364,6 +728,90 @@ mod tests {
         assert!(set_duress_pin("5678", &normal_hash).is_some());
     }
 
+    #[test]
+    fn test_duress_pins_dispatch_their_actions() {
+        let mut config = SecurityConfig {
+            security_enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.add_duress_pin("1111", DuressAction::DecoyOnly),
+            Err(DuressPinError::NoNormalPin)
+        );
+        config.pin_hash = Some(set_pin("1234"));
+
+        let alarm = DuressAction::SilentAlarm {
+            contact_id: "trusted".into(),
+        };
+        config
+            .add_duress_pin("1111", DuressAction::DecoyOnly)
+            .unwrap();
+        config
+            .add_duress_pin("2222", DuressAction::WipeThenDecoy)
+            .unwrap();
+        config.add_duress_pin("3333", alarm.clone()).unwrap();
+        assert_eq!(
+            config.add_duress_pin("1234", DuressAction::DecoyOnly),
+            Err(DuressPinError::MatchesNormalPin)
+        );
+
+        assert_eq!(verify_pin("1234", &config), PinResult::Normal);
+        assert_eq!(
+            verify_pin("1111", &config),
+            PinResult::Duress(DuressAction::DecoyOnly)
+        );
+        assert_eq!(
+            verify_pin("2222", &config),
+            PinResult::Duress(DuressAction::WipeThenDecoy)
+        );
+        assert_eq!(verify_pin("3333", &config), PinResult::Duress(alarm));
+        assert!(!config.is_duress_pin_hash(&set_pin("1234")));
+
+        // Re-adding a PIN changes its action instead of adding an entry
+        config
+            .add_duress_pin("1111", DuressAction::WipeThenDecoy)
+            .unwrap();
+        assert_eq!(config.duress_entries.len(), 3);
+        assert_eq!(
+            verify_pin("1111", &config),
+            PinResult::Duress(DuressAction::WipeThenDecoy)
+        );
+    }
+
+    #[test]
+    fn test_duress_pin_count_is_capped() {
+        let mut config = SecurityConfig {
+            pin_hash: Some(set_pin("1234")),
+            ..Default::default()
+        };
+        for i in 0..MAX_DURESS_PINS {
+            config
+                .add_duress_pin(&format!("9{i:03}"), DuressAction::DecoyOnly)
+                .unwrap();
+        }
+        assert_eq!(
+            config.add_duress_pin("8888", DuressAction::DecoyOnly),
+            Err(DuressPinError::TooMany)
+        );
+    }
+
+    #[test]
+    fn test_legacy_duress_pin_hash_is_migrated() {
+        let hash = set_pin("9999");
+        let json = format!(
+            r#"{{"pin_hash":null,"duress_pin_hash":"{}","dead_man_days":0,"last_accessed":0,"panic_gesture_enabled":true,"failed_attempts":0,"max_failed_attempts":10,"security_enabled":true}}"#,
+            hex::encode(hash)
+        );
+        let config: SecurityConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.duress_entries.len(), 1);
+        assert_eq!(config.duress_entries[0].hash, hash);
+        assert_eq!(config.duress_entries[0].action, DuressAction::WipeThenDecoy);
+
+        let roundtrip: SecurityConfig =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(roundtrip.duress_entries[0].hash, hash);
+    }
+
     #[test]
     fn test_dead_man_switch_disabled() {
         let config = SecurityConfig {
@@ -421,6 +869,97 @@ mod tests {
         assert_eq!(days, Some(4));
     }
 
+    fn hold(fingers: u8, duration_ms: u64, at_ms: u64) -> GestureEvent {
+        GestureEvent::Hold {
+            fingers,
+            duration_ms,
+            at_ms,
+        }
+    }
+
+    #[test]
+    fn test_panic_pattern_triggers_after_window() {
+        let mut detector = PanicGestureDetector::default();
+        let window = PanicGestureConfig::default().confirm_window_ms;
+
+        detector.feed_event(hold(3, 2000, 10_000));
+        assert!(detector.is_armed());
+        // A bounce of the same hold does not restart the window
+        detector.feed_event(hold(3, 2000, 10_100));
+        assert!(!detector.poll_should_wipe(10_000 + window - 1));
+
+        assert!(detector.poll_should_wipe(10_000 + window));
+        assert!(!detector.is_armed());
+        assert!(!detector.poll_should_wipe(10_000 + window + 1));
+
+        // Confirming commits without waiting for the window
+        detector.feed_event(hold(3, 2500, 20_000));
+        detector.feed_event(GestureEvent::Confirm { at_ms: 20_100 });
+        assert!(detector.poll_should_wipe(20_100));
+    }
+
+    #[test]
+    fn test_deadline_follows_reconfigured_window() {
+        let mut detector = PanicGestureDetector::default();
+        assert_eq!(detector.deadline_ms(), None);
+
+        detector.feed_event(hold(3, 2000, 10_000));
+        assert_eq!(detector.deadline_ms(), Some(13_000));
+
+        detector.set_config(PanicGestureConfig {
+            confirm_window_ms: 10_000,
+            ..Default::default()
+        });
+        assert_eq!(detector.deadline_ms(), Some(20_000));
+        assert!(!detector.poll_should_wipe(13_000));
+        assert!(detector.poll_should_wipe(20_000));
+        assert_eq!(detector.deadline_ms(), None);
+    }
+
+    #[test]
+    fn test_too_short_or_wrong_hold_does_not_trigger() {
+        let mut detector = PanicGestureDetector::default();
+
+        detector.feed_event(hold(3, 1999, 10_000));
+        detector.feed_event(hold(2, 5000, 20_000));
+        detector.feed_event(GestureEvent::Confirm { at_ms: 20_100 });
+        assert!(!detector.is_armed());
+        assert!(!detector.poll_should_wipe(60_000));
+
+        // A custom pattern replaces the default one
+        let mut detector = PanicGestureDetector::new(PanicGestureConfig {
+            pattern: PanicPattern {
+                fingers: 1,
+                hold_ms: 5000,
+            },
+            ..Default::default()
+        });
+        detector.feed_event(hold(3, 2000, 10_000));
+        assert!(!detector.is_armed());
+        detector.feed_event(hold(1, 5000, 20_000));
+        assert!(detector.is_armed());
+    }
+
+    #[test]
+    fn test_cancel_within_window_aborts_wipe() {
+        let mut detector = PanicGestureDetector::default();
+        let window = PanicGestureConfig::default().confirm_window_ms;
+
+        detector.feed_event(hold(3, 2000, 10_000));
+        detector.feed_event(GestureEvent::Cancel {
+            at_ms: 10_000 + window - 1,
+        });
+        assert!(!detector.is_armed());
+        assert!(!detector.poll_should_wipe(10_000 + 10 * window));
+
+        // Too late to cancel once the window has run out
+        detector.feed_event(hold(3, 2000, 50_000));
+        detector.feed_event(GestureEvent::Cancel {
+            at_ms: 50_000 + window,
+        });
+        assert!(detector.poll_should_wipe(50_000 + window));
+    }
+
     #[test]
     fn test_wipe_state() {
         let mut state = WipeState::default();
@@ -2,19 +2,221 @@
 //!
 //! Encrypted local storage for security configuration.
 //! Uses AES-256-GCM for encryption with PIN-derived key.
+//!
+//! File format: `"CLS3" || m_cost || t_cost || p_cost (u32 LE each) ||
+//! salt (16) || nonce (12) || ciphertext`. The Argon2 parameters are not
+//! secret; storing them lets any device open a file with the cost it was
+//! written with. Files in the older `"CLS2" || salt || nonce || ciphertext`
+//! format (default parameters) and the original `nonce || ciphertext`
+//! format (fixed salt) are still readable and are upgraded on their next save.
+//!
+//! ## Crypto-shredding
+//!
+//! Overwriting a file in place does not reliably erase it from flash
+//! storage: wear-leveling remaps writes, so old blocks can survive. Files
+//! written through a `SecureStorage` instance are therefore sealed in the
+//! `"CLS4"` format, `"CLS4" || params || salt || key nonce (12) ||
+//! wrapped content key (48) || nonce (12) || ciphertext`. Each file gets a
+//! random content key, wrapped under a key derived from both the PIN and a
+//! 32-byte key-encryption key (KEK) held in one small file. Deleting the
+//! KEK makes every such file undecryptable, even with the right PIN, no
+//! matter what copies of the ciphertext the disk still holds. The decoy
+//! vault stays in the PIN-only `"CLS3"` format, since a wipe must leave it
+//! readable.
+//!
+//! The KEK file is itself sealed under the PIN in the `"CLS3"` format, so
+//! a copy of the data directory, KEK included, still needs the PIN. KEK
+//! files written before this in plain form are resealed on first use.
 
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
+use comlock_crypto::RatchetState;
 use rand::RngCore;
-use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
 use crate::security::SecurityConfig;
 
+/// Marks files that carry their own Argon2 parameters and salt
+const FILE_MAGIC: &[u8; 4] = b"CLS3";
+
+/// Marks files whose content key is wrapped under the PIN and the KEK
+const WRAPPED_FILE_MAGIC: &[u8; 4] = b"CLS4";
+
+/// Marks files that carry their own salt but use default Argon2 parameters
+const FILE_MAGIC_V2: &[u8; 4] = b"CLS2";
+
+/// Length of the encoded Argon2 parameters
+const PARAMS_LEN: usize = 12;
+
+/// Length of the per-file Argon2 salt
+const SALT_LEN: usize = 16;
+
+/// AES-GCM nonce length
+const NONCE_LEN: usize = 12;
+
+/// AES-GCM authentication tag length
+const TAG_LEN: usize = 16;
+
+/// Length of the key-encryption key and of each file's content key
+const KEY_LEN: usize = 32;
+
+/// Length of a content key wrapped under AES-GCM
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+
+/// HKDF info binding the PIN-derived key to the KEK
+const WRAP_KEY_INFO: &[u8] = b"comlock-storage-wrap-v1";
+
+/// HKDF info for the contact notes key derived from the KEK
+const NOTES_KEY_INFO: &[u8] = b"comlock-storage-notes-v1";
+
+/// Key-encryption key file, relative to the app data dir
+const KEK_FILE: &str = "storage.kek";
+
+/// Prefix shared by every versioned file magic (`"CLS" || version digit`)
+const MAGIC_PREFIX: &[u8; 3] = b"CLS";
+
+/// Salt used by files written before per-file salts
+const LEGACY_SALT: &[u8] = b"comlock_storage_salt_v2!";
+
+/// Encrypted files covered by PIN rotation, relative to the app data dir
+const ENCRYPTED_FILES: [&str; 4] = [
+    "security.enc",
+    "contacts.enc",
+    "identity.enc",
+    MESSAGE_CACHE_FILE,
+];
+
+/// User-authored decoy vault, encrypted under the duress PIN. Deliberately
+/// not removed by `wipe_all_data`, since it is what duress mode shows.
+const DECOY_FILE: &str = "decoy.enc";
+
+/// Encrypted cache of inbound ciphertexts awaiting delivery
+const MESSAGE_CACHE_FILE: &str = "messages.cache";
+
+/// Default cap on the ciphertext bytes held by a `MessageCache` (8 MiB)
+pub const DEFAULT_CACHE_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Directory of idle sessions moved out of memory, relative to the app data dir
+const SESSION_ARCHIVE_DIR: &str = "sessions";
+
+// ============================================================================
+// ARGON2 PARAMETERS
+// ============================================================================
+
+/// Largest accepted memory cost (1 GiB, in KiB)
+const MAX_M_COST: u32 = 1024 * 1024;
+
+/// Largest accepted iteration count
+const MAX_T_COST: u32 = 16;
+
+/// Largest accepted parallelism
+const MAX_P_COST: u32 = 8;
+
+/// Argon2id cost parameters for PIN-derived keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Check the parameters are usable and bounded, so a crafted file
+    /// cannot make key derivation hang or exhaust memory
+    pub fn validate(&self) -> Result<(), StorageError> {
+        let valid = (1..=MAX_P_COST).contains(&self.p_cost)
+            && (1..=MAX_T_COST).contains(&self.t_cost)
+            && (8 * self.p_cost..=MAX_M_COST).contains(&self.m_cost);
+        if valid {
+            Ok(())
+        } else {
+            Err(StorageError::InvalidParameters)
+        }
+    }
+
+    /// Pick a memory cost so one derivation takes roughly `target` here.
+    ///
+    /// Times a single derivation with the default parameters and scales the
+    /// memory cost accordingly. The result is never weaker than the
+    /// defaults: a fast device gets more memory cost, a slow one keeps the
+    /// default.
+    pub fn calibrate(target: Duration) -> Self {
+        let base = Self::default();
+        let started = Instant::now();
+        let mut key = base.derive(b"calibration", &[0u8; SALT_LEN]);
+        let elapsed = started.elapsed().max(Duration::from_millis(1));
+        key.fill(0);
+
+        let scale = target.as_secs_f64() / elapsed.as_secs_f64();
+        let m_cost = (base.m_cost as f64 * scale) as u32;
+        Self {
+            m_cost: m_cost.clamp(base.m_cost, MAX_M_COST),
+            ..base
+        }
+    }
+
+    /// Derive a 32-byte key from `secret` and `salt` with these parameters
+    fn derive(&self, secret: &[u8], salt: &[u8]) -> [u8; 32] {
+        use argon2::{Algorithm, Argon2, Params, Version};
+
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .expect("Argon2 parameters validated");
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(secret, salt, &mut key)
+            .expect("Argon2 hashing failed");
+        key
+    }
+
+    /// Encode as three little-endian u32s
+    fn to_bytes(self) -> [u8; PARAMS_LEN] {
+        let mut bytes = [0u8; PARAMS_LEN];
+        bytes[0..4].copy_from_slice(&self.m_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.t_cost.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.p_cost.to_le_bytes());
+        bytes
+    }
+
+    /// Decode and validate parameters from a file header
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StorageError> {
+        let field = |i: usize| -> Result<u32, StorageError> {
+            bytes
+                .get(i * 4..i * 4 + 4)
+                .and_then(|b| b.try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or(StorageError::CorruptedData)
+        };
+        let params = Self {
+            m_cost: field(0)?,
+            t_cost: field(1)?,
+            p_cost: field(2)?,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
 // ============================================================================
 // SECURE STORAGE
 // ============================================================================
@@ -23,85 +225,382 @@ use crate::security::SecurityConfig;
 pub struct SecureStorage {
     /// Path to the config file
     config_path: PathBuf,
+    /// Argon2 parameters for files written by this instance
+    params: Argon2Params,
 }
 
 impl SecureStorage {
     /// Create a new secure storage instance
     pub fn new(app_data_dir: PathBuf) -> Self {
+        Self::with_params(app_data_dir, Argon2Params::default())
+    }
+
+    /// Create a storage instance that writes files with `params`.
+    ///
+    /// Files are always read with the parameters recorded in them.
+    pub fn with_params(app_data_dir: PathBuf, params: Argon2Params) -> Self {
         let config_path = app_data_dir.join("security.enc");
-        Self { config_path }
+        Self {
+            config_path,
+            params,
+        }
     }
 
-    /// Derive encryption key from PIN using Argon2id
-    fn derive_key(pin: &str) -> [u8; 32] {
-        use argon2::Argon2;
+    /// Path of the user's decoy vault
+    pub fn decoy_path(&self) -> PathBuf {
+        self.config_path.with_file_name(DECOY_FILE)
+    }
 
-        // Fixed salt for deterministic key derivation
-        // Note: In production, consider using random salts stored with ciphertext
-        let salt = b"comlock_storage_salt_v2!";
+    /// Path of the key-encryption key file
+    fn kek_path(&self) -> PathBuf {
+        self.config_path.with_file_name(KEK_FILE)
+    }
 
-        let mut key = [0u8; 32];
-        Argon2::default()
-            .hash_password_into(pin.as_bytes(), salt, &mut key)
-            .expect("Argon2 hashing failed");
+    /// Encrypt `plaintext` under a fresh content key wrapped with `pin` and
+    /// the KEK (created on first use)
+    fn seal(&self, pin: &str, plaintext: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let kek = Self::load_or_create_kek(&self.kek_path(), &self.params, pin)?;
+        Self::seal_wrapped(&self.params, pin, &kek, plaintext)
+    }
+
+    /// Decrypt file contents produced by `seal`, or by `seal_with` and the
+    /// older formats
+    fn open_sealed(&self, pin: &str, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        Self::open_wrapped(pin, data, &self.kek_path())
+    }
+
+    /// Read the KEK at `path`, or create and store a new one if none
+    /// exists. A KEK still in plain form is resealed under `pin`.
+    fn load_or_create_kek(
+        path: &Path,
+        params: &Argon2Params,
+        pin: &str,
+    ) -> Result<Zeroizing<[u8; KEY_LEN]>, StorageError> {
+        if path.exists() {
+            let (kek, sealed) = Self::read_kek(path, pin)?;
+            if !sealed {
+                Self::write_kek(path, params, pin, &kek)?;
+            }
+            return Ok(kek);
+        }
+        let mut kek = Zeroizing::new([0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(kek.as_mut());
+        Self::write_kek(path, params, pin, &kek)?;
+        Ok(kek)
+    }
+
+    /// Read the KEK at `path`, opening it with `pin`, and whether it was
+    /// sealed rather than in the original plain form. A missing file means
+    /// the KEK was destroyed.
+    fn read_kek(path: &Path, pin: &str) -> Result<(Zeroizing<[u8; KEY_LEN]>, bool), StorageError> {
+        let data = Zeroizing::new(fs::read(path).map_err(|_| StorageError::KeyDestroyed)?);
+        let sealed = data.len() != KEY_LEN;
+        let kek = if sealed {
+            Zeroizing::new(Self::open(pin, &data)?)
+        } else {
+            data
+        };
+        let kek: [u8; KEY_LEN] = kek
+            .as_slice()
+            .try_into()
+            .map_err(|_| StorageError::CorruptedData)?;
+        Ok((Zeroizing::new(kek), sealed))
+    }
+
+    /// Store `kek` at `path`, sealed under `pin`
+    fn write_kek(
+        path: &Path,
+        params: &Argon2Params,
+        pin: &str,
+        kek: &[u8; KEY_LEN],
+    ) -> Result<(), StorageError> {
+        Self::write_atomic(path, &Self::seal_with(params, pin, kek)?)
+    }
+
+    /// Key wrapping a file's content key, bound to both the PIN and the KEK
+    fn wrap_key(
+        params: &Argon2Params,
+        pin: &str,
+        salt: &[u8],
+        kek: &[u8; KEY_LEN],
+    ) -> Zeroizing<[u8; KEY_LEN]> {
+        let pin_key = Zeroizing::new(params.derive(pin.as_bytes(), salt));
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(kek), pin_key.as_ref())
+            .expand(WRAP_KEY_INFO, key.as_mut())
+            .expect("32 bytes is a valid HKDF output length");
         key
     }
 
-    /// Save security config encrypted with PIN
-    pub fn save_config(&self, config: &SecurityConfig, pin: &str) -> Result<(), StorageError> {
-        // Serialize config to JSON
-        let json = serde_json::to_string(config).map_err(|_| StorageError::SerializationFailed)?;
+    /// Encrypt `plaintext` under a random content key, wrapped with `pin`
+    /// and `kek`
+    fn seal_wrapped(
+        params: &Argon2Params,
+        pin: &str,
+        kek: &[u8; KEY_LEN],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, StorageError> {
+        params.validate()?;
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut key_nonce = [0u8; NONCE_LEN];
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        let mut content_key = Zeroizing::new([0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut key_nonce);
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+        rand::thread_rng().fill_bytes(content_key.as_mut());
+
+        let wrap_key = Self::wrap_key(params, pin, &salt, kek);
+        let wrapped_key = Aes256Gcm::new_from_slice(wrap_key.as_ref())
+            .map_err(|_| StorageError::EncryptionFailed)?
+            .encrypt(Nonce::from_slice(&key_nonce), content_key.as_ref())
+            .map_err(|_| StorageError::EncryptionFailed)?;
+        let ciphertext = Aes256Gcm::new_from_slice(content_key.as_ref())
+            .map_err(|_| StorageError::EncryptionFailed)?
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+            .map_err(|_| StorageError::EncryptionFailed)?;
 
-        // Derive encryption key
-        let key = Self::derive_key(pin);
+        let mut data = Vec::with_capacity(
+            WRAPPED_FILE_MAGIC.len()
+                + PARAMS_LEN
+                + SALT_LEN
+                + NONCE_LEN
+                + WRAPPED_KEY_LEN
+                + NONCE_LEN
+                + ciphertext.len(),
+        );
+        data.extend_from_slice(WRAPPED_FILE_MAGIC);
+        data.extend_from_slice(&params.to_bytes());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&key_nonce);
+        data.extend_from_slice(&wrapped_key);
+        data.extend_from_slice(&nonce_bytes);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypt file contents produced by `seal_wrapped`, reading the KEK
+    /// from `kek_path`. Files in the PIN-only formats go through `open`.
+    ///
+    /// A missing KEK is reported as `KeyDestroyed` before any key
+    /// derivation.
+    fn open_wrapped(pin: &str, data: &[u8], kek_path: &Path) -> Result<Vec<u8>, StorageError> {
+        if !data.starts_with(WRAPPED_FILE_MAGIC) {
+            return Self::open(pin, data);
+        }
+
+        let header_len = WRAPPED_FILE_MAGIC.len()
+            + PARAMS_LEN
+            + SALT_LEN
+            + NONCE_LEN
+            + WRAPPED_KEY_LEN
+            + NONCE_LEN;
+        if data.len() < header_len + TAG_LEN {
+            return Err(StorageError::TruncatedFile);
+        }
+        let (params, rest) = data[WRAPPED_FILE_MAGIC.len()..].split_at(PARAMS_LEN);
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (key_nonce, rest) = rest.split_at(NONCE_LEN);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
+        let params = Argon2Params::from_bytes(params)?;
+        let (kek, _) = Self::read_kek(kek_path, pin)?;
+
+        let wrap_key = Self::wrap_key(&params, pin, salt, &kek);
+        let content_key = Zeroizing::new(
+            Aes256Gcm::new_from_slice(wrap_key.as_ref())
+                .map_err(|_| StorageError::DecryptionFailed)?
+                .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+                .map_err(|_| StorageError::DecryptionFailed)?,
+        );
+        Aes256Gcm::new_from_slice(&content_key)
+            .map_err(|_| StorageError::DecryptionFailed)?
+            .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+            .map_err(|_| StorageError::DecryptionFailed)
+    }
 
-        // Generate random nonce
-        let mut nonce_bytes = [0u8; 12];
+    /// Encrypt `plaintext` with explicit Argon2 parameters
+    fn seal_with(
+        params: &Argon2Params,
+        pin: &str,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, StorageError> {
+        params.validate()?;
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
         rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
 
-        // Encrypt
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::EncryptionFailed)?;
+        let key = Zeroizing::new(params.derive(pin.as_bytes(), &salt));
+        let cipher =
+            Aes256Gcm::new_from_slice(key.as_ref()).map_err(|_| StorageError::EncryptionFailed)?;
         let ciphertext = cipher
-            .encrypt(nonce, json.as_bytes())
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
             .map_err(|_| StorageError::EncryptionFailed)?;
 
-        // Write: nonce (12 bytes) + ciphertext
-        let mut file = File::create(&self.config_path).map_err(|_| StorageError::IoError)?;
-        file.write_all(&nonce_bytes)
-            .map_err(|_| StorageError::IoError)?;
-        file.write_all(&ciphertext)
-            .map_err(|_| StorageError::IoError)?;
+        let mut data = Vec::with_capacity(
+            FILE_MAGIC.len() + PARAMS_LEN + SALT_LEN + NONCE_LEN + ciphertext.len(),
+        );
+        data.extend_from_slice(FILE_MAGIC);
+        data.extend_from_slice(&params.to_bytes());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce_bytes);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
 
-        Ok(())
+    /// Decrypt file contents produced by `seal` (or an older format).
+    ///
+    /// Structural problems are reported before any key derivation:
+    /// `VersionMismatch` for a format this build does not know and
+    /// `TruncatedFile` when the data cannot even hold a header and tag.
+    /// `DecryptionFailed` therefore only ever means the AEAD tag did not
+    /// verify (wrong PIN, or tampering).
+    fn open(pin: &str, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let v3_header_len = FILE_MAGIC.len() + PARAMS_LEN + SALT_LEN + NONCE_LEN;
+        let v2_header_len = FILE_MAGIC_V2.len() + SALT_LEN + NONCE_LEN;
+
+        let (params, salt, rest) = if data.starts_with(FILE_MAGIC) {
+            if data.len() < v3_header_len + TAG_LEN {
+                return Err(StorageError::TruncatedFile);
+            }
+            let (params, rest) = data[FILE_MAGIC.len()..].split_at(PARAMS_LEN);
+            let (salt, rest) = rest.split_at(SALT_LEN);
+            (Argon2Params::from_bytes(params)?, salt, rest)
+        } else if data.starts_with(FILE_MAGIC_V2) {
+            if data.len() < v2_header_len + TAG_LEN {
+                return Err(StorageError::TruncatedFile);
+            }
+            let (salt, rest) = data[FILE_MAGIC_V2.len()..].split_at(SALT_LEN);
+            (Argon2Params::default(), salt, rest)
+        } else if let Some(version) = Self::unknown_version(data) {
+            return Err(StorageError::VersionMismatch(version));
+        } else if data.len() >= NONCE_LEN + TAG_LEN {
+            (Argon2Params::default(), LEGACY_SALT, data)
+        } else {
+            return Err(StorageError::TruncatedFile);
+        };
+
+        let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
+        let key = Zeroizing::new(params.derive(pin.as_bytes(), salt));
+        let cipher =
+            Aes256Gcm::new_from_slice(key.as_ref()).map_err(|_| StorageError::DecryptionFailed)?;
+        cipher
+            .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+            .map_err(|_| StorageError::DecryptionFailed)
     }
 
-    /// Load and decrypt security config
-    pub fn load_config(&self, pin: &str) -> Result<SecurityConfig, StorageError> {
-        // Read file
-        let mut file = File::open(&self.config_path).map_err(|_| StorageError::NotFound)?;
+    /// Version digit of a `"CLS<n>"` magic this build cannot read
+    fn unknown_version(data: &[u8]) -> Option<u8> {
+        let version = *data.strip_prefix(MAGIC_PREFIX)?.first()?;
+        version.is_ascii_digit().then(|| version - b'0')
+    }
+
+    /// Parse decrypted JSON; the AEAD tag already verified, so a parse
+    /// failure means the plaintext itself is bad, not the PIN
+    fn parse_json<T: serde::de::DeserializeOwned>(plaintext: &[u8]) -> Result<T, StorageError> {
+        serde_json::from_slice(plaintext).map_err(|_| StorageError::CorruptedData)
+    }
+
+    /// Encrypt `plaintext` with default parameters and write it to `path`
+    pub(crate) fn write_encrypted(
+        path: &Path,
+        pin: &str,
+        plaintext: &[u8],
+    ) -> Result<(), StorageError> {
+        let data = Self::seal_with(&Argon2Params::default(), pin, plaintext)?;
+        Self::write_atomic(path, &data)
+    }
+
+    /// Read and decrypt a file written by `write_encrypted`
+    pub(crate) fn read_encrypted(path: &Path, pin: &str) -> Result<Vec<u8>, StorageError> {
+        Self::open(pin, &Self::read_file(path)?)
+    }
+
+    /// Read a whole file into memory
+    fn read_file(path: &Path) -> Result<Vec<u8>, StorageError> {
         let mut data = Vec::new();
-        file.read_to_end(&mut data)
+        File::open(path)
+            .map_err(|_| StorageError::NotFound)?
+            .read_to_end(&mut data)
             .map_err(|_| StorageError::IoError)?;
+        Ok(data)
+    }
+
+    /// Save security config encrypted with PIN
+    pub fn save_config(&self, config: &SecurityConfig, pin: &str) -> Result<(), StorageError> {
+        // Serialize config to JSON
+        let json = serde_json::to_string(config).map_err(|_| StorageError::SerializationFailed)?;
 
-        if data.len() < 12 {
-            return Err(StorageError::CorruptedData);
+        // Encrypt under a fresh salt
+        let data = self.seal(pin, json.as_bytes())?;
+
+        // Write: magic + salt + nonce + ciphertext
+        Self::write_atomic(&self.config_path, &data)?;
+
+        Ok(())
+    }
+
+    /// Temporary sibling used while a file is being replaced
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        path.with_file_name(name)
+    }
+
+    /// Crash-safe write of encrypted file contents to `path`.
+    ///
+    /// Writes to a temporary sibling, syncs it, then renames it over the
+    /// destination (atomic on the same filesystem), so a crash mid-write
+    /// never leaves a truncated file in place of the previous one. If the
+    /// rename is not supported, falls back to writing the destination directly.
+    fn write_atomic(path: &Path, data: &[u8]) -> Result<(), StorageError> {
+        let temp_path = Self::write_temp(path, data)?;
+        Self::replace_with_temp(&temp_path, path, data)
+    }
+
+    /// Write `data` to the temporary sibling of `path` and sync it,
+    /// leaving `path` itself untouched
+    fn write_temp(path: &Path, data: &[u8]) -> Result<PathBuf, StorageError> {
+        let temp_path = Self::temp_path(path);
+
+        let written = File::create(&temp_path).and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        });
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(StorageError::IoError);
         }
+        Ok(temp_path)
+    }
 
-        // Extract nonce and ciphertext
-        let nonce = Nonce::from_slice(&data[..12]);
-        let ciphertext = &data[12..];
+    /// Move a file written by `write_temp` over `path`, whose new contents
+    /// are `data`
+    fn replace_with_temp(temp_path: &Path, path: &Path, data: &[u8]) -> Result<(), StorageError> {
+        if fs::rename(temp_path, path).is_err() {
+            // Fallback: overwrite in place
+            let _ = fs::remove_file(temp_path);
+            let mut file = File::create(path).map_err(|_| StorageError::IoError)?;
+            file.write_all(data).map_err(|_| StorageError::IoError)?;
+            file.sync_all().map_err(|_| StorageError::IoError)?;
+        }
+
+        Ok(())
+    }
+
+    /// Load and decrypt security config
+    pub fn load_config(&self, pin: &str) -> Result<SecurityConfig, StorageError> {
+        // Read file
+        let data = Self::read_file(&self.config_path)?;
 
         // Derive key and decrypt
-        let key = Self::derive_key(pin);
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::DecryptionFailed)?;
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|_| StorageError::DecryptionFailed)?;
+        let plaintext = self.open_sealed(pin, &data)?;
 
         // Deserialize
-        let json = String::from_utf8(plaintext).map_err(|_| StorageError::CorruptedData)?;
-        serde_json::from_str(&json).map_err(|_| StorageError::CorruptedData)
+        Self::parse_json(&plaintext)
     }
 
     /// Check if config file exists
@@ -109,7 +608,32 @@ impl SecureStorage {
         self.config_path.exists()
     }
 
-    /// Securely delete the config file
+    /// Destroy the key-encryption key.
+    ///
+    /// Every file sealed under it becomes undecryptable, even with the PIN
+    /// and even if the flash controller kept stale copies of the
+    /// ciphertext. The KEK file is also overwritten, on a best-effort basis.
+    pub fn destroy_kek(&self) -> Result<(), StorageError> {
+        let kek_path = self.kek_path();
+        if kek_path.exists() {
+            Self::secure_delete_file(&kek_path)?;
+        }
+
+        // Delete leftovers of interrupted writes
+        let kek_temp = Self::temp_path(&kek_path);
+        if kek_temp.exists() {
+            Self::secure_delete_file(&kek_temp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Securely delete the config file.
+    ///
+    /// Only the config file is removed; the KEK, shared by every other
+    /// sealed file, is left alone. The overwrite is best-effort, since
+    /// wear-leveling on SSDs and flash can leave the old blocks intact;
+    /// use `destroy_kek` or `wipe_all_data` to make data unrecoverable.
     pub fn secure_delete(&self) -> Result<(), StorageError> {
         if !self.config_path.exists() {
             return Ok(());
@@ -145,11 +669,20 @@ impl SecureStorage {
         // Get app data directory from config path
         let app_dir = self.config_path.parent();
 
+        // Crypto-shred everything sealed under the KEK
+        self.destroy_kek()?;
+
         // Delete config file securely
         if self.config_path.exists() {
             self.secure_delete()?;
         }
 
+        // Delete leftovers of interrupted writes
+        let config_temp = Self::temp_path(&self.config_path);
+        if config_temp.exists() {
+            Self::secure_delete_file(&config_temp)?;
+        }
+
         // Delete other sensitive files in app directory
         if let Some(dir) = app_dir {
             // Securely delete contacts database
@@ -159,7 +692,7 @@ impl SecureStorage {
             }
 
             // Securely delete message cache
-            let messages_file = dir.join("messages.cache");
+            let messages_file = dir.join(MESSAGE_CACHE_FILE);
             if messages_file.exists() {
                 Self::secure_delete_file(&messages_file)?;
             }
@@ -181,6 +714,20 @@ impl SecureStorage {
             if mailbox_file.exists() {
                 Self::secure_delete_file(&mailbox_file)?;
             }
+
+            // Delete archived idle sessions
+            let sessions_dir = dir.join(SESSION_ARCHIVE_DIR);
+            if sessions_dir.exists() {
+                SessionArchive::delete_dir(&sessions_dir)?;
+            }
+
+            // Delete leftovers of interrupted writes
+            for name in ["contacts.enc", "identity.enc", MESSAGE_CACHE_FILE] {
+                let temp_file = Self::temp_path(&dir.join(name));
+                if temp_file.exists() {
+                    Self::secure_delete_file(&temp_file)?;
+                }
+            }
         }
 
         Ok(())
@@ -188,7 +735,7 @@ impl SecureStorage {
 
     /// Securely delete a specific file by overwriting with zeros
     fn secure_delete_file(path: &std::path::Path) -> Result<(), StorageError> {
-        use std::fs::{File, OpenOptions};
+        use std::fs::OpenOptions;
         use std::io::Write;
 
         if let Ok(metadata) = std::fs::metadata(path) {
@@ -225,21 +772,9 @@ impl SecureStorage {
         let json =
             serde_json::to_string(contacts).map_err(|_| StorageError::SerializationFailed)?;
 
-        let key = Self::derive_key(pin);
-        let mut nonce_bytes = [0u8; 12];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        let data = self.seal(pin, json.as_bytes())?;
 
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::EncryptionFailed)?;
-        let ciphertext = cipher
-            .encrypt(nonce, json.as_bytes())
-            .map_err(|_| StorageError::EncryptionFailed)?;
-
-        let mut file = File::create(&contacts_path).map_err(|_| StorageError::IoError)?;
-        file.write_all(&nonce_bytes)
-            .map_err(|_| StorageError::IoError)?;
-        file.write_all(&ciphertext)
-            .map_err(|_| StorageError::IoError)?;
+        Self::write_atomic(&contacts_path, &data)?;
 
         Ok(())
     }
@@ -256,29 +791,10 @@ impl SecureStorage {
             return Ok(Vec::new()); // No saved contacts
         }
 
-        let mut data = Vec::new();
-        File::open(&contacts_path)
-            .map_err(|_| StorageError::NotFound)?
-            .read_to_end(&mut data)
-            .map_err(|_| StorageError::IoError)?;
-
-        if data.len() < 12 {
-            return Err(StorageError::CorruptedData);
-        }
-
-        let (nonce_bytes, ciphertext) = data.split_at(12);
-        let nonce = Nonce::from_slice(nonce_bytes);
-
-        let key = Self::derive_key(pin);
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::DecryptionFailed)?;
-        let json = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|_| StorageError::DecryptionFailed)?;
-
-        let contacts: Vec<crate::contacts::Contact> =
-            serde_json::from_slice(&json).map_err(|_| StorageError::CorruptedData)?;
+        let data = Self::read_file(&contacts_path)?;
+        let json = self.open_sealed(pin, &data)?;
 
-        Ok(contacts)
+        Self::parse_json(&json)
     }
 
     /// Delete contacts file securely
@@ -310,21 +826,9 @@ impl SecureStorage {
         let json =
             serde_json::to_string(identity).map_err(|_| StorageError::SerializationFailed)?;
 
-        let key = Self::derive_key(pin);
-        let mut nonce_bytes = [0u8; 12];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        let data = self.seal(pin, json.as_bytes())?;
 
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::EncryptionFailed)?;
-        let ciphertext = cipher
-            .encrypt(nonce, json.as_bytes())
-            .map_err(|_| StorageError::EncryptionFailed)?;
-
-        let mut file = File::create(&identity_path).map_err(|_| StorageError::IoError)?;
-        file.write_all(&nonce_bytes)
-            .map_err(|_| StorageError::IoError)?;
-        file.write_all(&ciphertext)
-            .map_err(|_| StorageError::IoError)?;
+        Self::write_atomic(&identity_path, &data)?;
 
         Ok(())
     }
@@ -341,29 +845,10 @@ impl SecureStorage {
             return Ok(None); // No saved identity
         }
 
-        let mut data = Vec::new();
-        File::open(&identity_path)
-            .map_err(|_| StorageError::NotFound)?
-            .read_to_end(&mut data)
-            .map_err(|_| StorageError::IoError)?;
-
-        if data.len() < 12 {
-            return Err(StorageError::CorruptedData);
-        }
-
-        let (nonce_bytes, ciphertext) = data.split_at(12);
-        let nonce = Nonce::from_slice(nonce_bytes);
+        let data = Self::read_file(&identity_path)?;
+        let json = self.open_sealed(pin, &data)?;
 
-        let key = Self::derive_key(pin);
-        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| StorageError::DecryptionFailed)?;
-        let json = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|_| StorageError::DecryptionFailed)?;
-
-        let identity: crate::Identity =
-            serde_json::from_slice(&json).map_err(|_| StorageError::CorruptedData)?;
-
-        Ok(Some(identity))
+        Self::parse_json(&json).map(Some)
     }
 
     /// Check if identity file exists
@@ -373,6 +858,341 @@ impl SecureStorage {
             .map(|p| p.join("identity.enc").exists())
             .unwrap_or(false)
     }
+
+    // ========================================================================
+    // PIN ROTATION
+    // ========================================================================
+
+    /// Re-encrypt every stored file under a new PIN.
+    ///
+    /// Each existing file is decrypted with `old_pin` first; if any of them
+    /// fails, nothing is written. The files are then re-encrypted under a
+    /// key derived from `new_pin` with a fresh salt (and this instance's
+    /// Argon2 parameters).
+    ///
+    /// Every new file, the resealed KEK last, is written to a temporary
+    /// sibling first. Only once all of them are on disk are they renamed
+    /// over the originals, so a failed write leaves the old files intact.
+    pub fn rotate_pin(&self, old_pin: &str, new_pin: &str) -> Result<(), StorageError> {
+        let dir = self.config_path.parent().ok_or(StorageError::IoError)?;
+
+        let mut decrypted = Vec::new();
+        for name in ENCRYPTED_FILES {
+            let path = dir.join(name);
+            if !path.exists() {
+                continue;
+            }
+            let data = Self::read_file(&path)?;
+            decrypted.push((path, Zeroizing::new(self.open_sealed(old_pin, &data)?)));
+        }
+
+        let kek_path = self.kek_path();
+        let kek = Self::load_or_create_kek(&kek_path, &self.params, old_pin)?;
+        let mut resealed = Vec::with_capacity(decrypted.len() + 1);
+        for (path, plaintext) in &decrypted {
+            resealed.push((
+                path.clone(),
+                Self::seal_wrapped(&self.params, new_pin, &kek, plaintext)?,
+            ));
+        }
+        resealed.push((
+            kek_path.clone(),
+            Self::seal_with(&self.params, new_pin, &kek[..])?,
+        ));
+
+        let mut staged = Vec::with_capacity(resealed.len());
+        for (path, data) in &resealed {
+            match Self::write_temp(path, data) {
+                Ok(temp_path) => staged.push(temp_path),
+                Err(e) => {
+                    for temp_path in &staged {
+                        let _ = fs::remove_file(temp_path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        for (temp_path, (path, data)) in staged.iter().zip(&resealed) {
+            Self::replace_with_temp(temp_path, path, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Key for sealing contact notes, derived from the KEK opened with `pin`.
+    ///
+    /// It survives PIN rotation, which keeps the KEK, and is lost with the
+    /// KEK like every other sealed file. The KEK is created on first use,
+    /// so notes sealed before it was destroyed no longer open.
+    pub fn notes_key(&self, pin: &str) -> Result<Zeroizing<[u8; KEY_LEN]>, StorageError> {
+        let kek = Self::load_or_create_kek(&self.kek_path(), &self.params, pin)?;
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        hkdf::Hkdf::<sha2::Sha256>::new(None, kek.as_ref())
+            .expand(NOTES_KEY_INFO, key.as_mut())
+            .expect("32 bytes is a valid HKDF output length");
+        Ok(key)
+    }
+}
+
+// ============================================================================
+// ENCRYPTED MESSAGE CACHE
+// ============================================================================
+
+/// An inbound ciphertext held for later delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedMessage {
+    session_id: String,
+    ciphertext: Vec<u8>,
+    /// Unix seconds after which the message is dropped
+    expires_at: u64,
+}
+
+/// Inbound ciphertexts held until their session is ready to decrypt them.
+///
+/// Only ciphertexts are cached, and the cache file is itself encrypted under
+/// the PIN and the storage KEK. Every message has a TTL, and the total ciphertext size is capped:
+/// adding a message beyond the cap evicts the oldest ones first.
+pub struct MessageCache {
+    /// Path to the cache file
+    path: PathBuf,
+    /// PIN the cache file is encrypted under
+    pin: Zeroizing<String>,
+    /// Path to the key-encryption key the cache file is sealed under
+    kek_path: PathBuf,
+    /// Argon2 parameters for writing the cache file
+    params: Argon2Params,
+    /// Cap on the total ciphertext bytes held
+    max_bytes: usize,
+    /// Cached messages, oldest first
+    entries: VecDeque<CachedMessage>,
+}
+
+impl MessageCache {
+    /// Open the message cache next to `storage`'s files, loading any
+    /// messages already cached
+    pub fn open(storage: &SecureStorage, pin: &str) -> Result<Self, StorageError> {
+        let path = storage.config_path.with_file_name(MESSAGE_CACHE_FILE);
+        let kek_path = storage.kek_path();
+        let entries = match SecureStorage::read_file(&path)
+            .and_then(|data| SecureStorage::open_wrapped(pin, &data, &kek_path))
+        {
+            Ok(json) => SecureStorage::parse_json(&json)?,
+            Err(StorageError::NotFound) => VecDeque::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            pin: Zeroizing::new(pin.to_string()),
+            kek_path,
+            params: storage.params,
+            max_bytes: DEFAULT_CACHE_MAX_BYTES,
+            entries,
+        })
+    }
+
+    /// Cap the total ciphertext bytes held (applies from the next insert)
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Cache an inbound ciphertext for `session_id` for up to `ttl`.
+    ///
+    /// Expired messages are dropped first, then the oldest messages until
+    /// the new one fits.
+    pub fn cache_message(
+        &mut self,
+        session_id: &str,
+        ciphertext: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        if ciphertext.len() > self.max_bytes {
+            return Err(StorageError::MessageTooLarge);
+        }
+
+        self.drop_expired();
+        while self.total_bytes() + ciphertext.len() > self.max_bytes {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(CachedMessage {
+            session_id: session_id.to_string(),
+            ciphertext,
+            expires_at: Self::now().saturating_add(ttl.as_secs()),
+        });
+        self.persist()
+    }
+
+    /// Remove and return the unexpired ciphertexts cached for a session,
+    /// oldest first
+    pub fn take_pending(&mut self, session_id: &str) -> Result<Vec<Vec<u8>>, StorageError> {
+        self.drop_expired();
+        let (pending, rest) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|entry| entry.session_id == session_id);
+        self.entries = rest;
+        self.persist()?;
+        Ok(pending.into_iter().map(|entry| entry.ciphertext).collect())
+    }
+
+    /// Drop expired messages, returning how many were removed
+    pub fn prune_expired(&mut self) -> Result<usize, StorageError> {
+        let removed = self.drop_expired();
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    /// Number of cached messages
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache holds no messages
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total ciphertext bytes held
+    pub fn total_bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|entry| entry.ciphertext.len())
+            .sum()
+    }
+
+    /// Remove expired messages from memory
+    fn drop_expired(&mut self) -> usize {
+        let now = Self::now();
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.expires_at > now);
+        before - self.entries.len()
+    }
+
+    /// Write the cache back to disk, encrypted
+    fn persist(&self) -> Result<(), StorageError> {
+        let json =
+            serde_json::to_vec(&self.entries).map_err(|_| StorageError::SerializationFailed)?;
+        let kek = SecureStorage::load_or_create_kek(&self.kek_path, &self.params, &self.pin)?;
+        let data = SecureStorage::seal_wrapped(&self.params, &self.pin, &kek, &json)?;
+        SecureStorage::write_atomic(&self.path, &data)
+    }
+
+    /// Current Unix time in seconds
+    fn now() -> u64 {
+        crate::clock::now_unix() as u64
+    }
+}
+
+// ============================================================================
+// IDLE SESSION ARCHIVE
+// ============================================================================
+
+/// Ratchet sessions moved out of memory while idle, kept on disk until
+/// they are used again.
+///
+/// Each session is written as a ratchet backup (AES-256-GCM) under a random
+/// key that only this archive holds, so the files are unreadable once it is
+/// dropped. Loading a session deletes its file.
+///
+/// The archive therefore lasts only as long as the process: it is not a
+/// way to keep sessions across restarts, and files an earlier process left
+/// behind are deleted when the archive is opened.
+pub struct SessionArchive {
+    /// Directory holding this archive's session files
+    dir: PathBuf,
+    /// Key the session backups are encrypted under
+    key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl SessionArchive {
+    /// Open an archive for `owner`'s sessions (e.g. an identity's public ID)
+    /// next to `storage`'s files
+    pub fn open(storage: &SecureStorage, owner: &str) -> Result<Self, StorageError> {
+        let dir = storage
+            .config_path
+            .with_file_name(SESSION_ARCHIVE_DIR)
+            .join(Self::file_stem(owner));
+        // Files from an earlier process are sealed under a key now lost
+        if dir.exists() {
+            Self::delete_dir(&dir)?;
+        }
+        fs::create_dir_all(&dir).map_err(|_| StorageError::IoError)?;
+
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(key.as_mut());
+        Ok(Self { dir, key })
+    }
+
+    /// Write `ratchet` to disk, encrypted, under `session_id`
+    pub fn store(&self, session_id: &str, ratchet: &RatchetState) -> Result<(), StorageError> {
+        let backup = ratchet
+            .export_backup(&self.key)
+            .map_err(|_| StorageError::EncryptionFailed)?;
+        SecureStorage::write_atomic(&self.path(session_id), &backup)
+    }
+
+    /// Load the session archived under `session_id` and delete its file, or
+    /// `None` if there is none
+    pub fn take(&self, session_id: &str) -> Result<Option<RatchetState>, StorageError> {
+        let path = self.path(session_id);
+        let backup = match SecureStorage::read_file(&path) {
+            Ok(backup) => backup,
+            Err(StorageError::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let ratchet = RatchetState::import_backup(&backup, &self.key)
+            .map_err(|_| StorageError::DecryptionFailed)?;
+        SecureStorage::secure_delete_file(&path)?;
+        Ok(Some(ratchet))
+    }
+
+    /// Delete the session archived under `session_id`, if any
+    pub fn remove(&self, session_id: &str) -> Result<(), StorageError> {
+        let path = self.path(session_id);
+        if path.exists() {
+            SecureStorage::secure_delete_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a session is archived under `session_id`
+    pub fn contains(&self, session_id: &str) -> bool {
+        self.path(session_id).exists()
+    }
+
+    /// Path of the file for `session_id`
+    fn path(&self, session_id: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.enc", Self::file_stem(session_id)))
+    }
+
+    /// File name for an arbitrary ID, safe on any filesystem
+    fn file_stem(id: &str) -> String {
+        use sha2::Digest;
+        hex::encode(&sha2::Sha256::digest(id.as_bytes())[..16])
+    }
+
+    /// Securely delete every file under `dir`, then `dir` itself
+    fn delete_dir(dir: &Path) -> Result<(), StorageError> {
+        for entry in fs::read_dir(dir).map_err(|_| StorageError::IoError)? {
+            let path = entry.map_err(|_| StorageError::IoError)?.path();
+            if path.is_dir() {
+                Self::delete_dir(&path)?;
+            } else {
+                SecureStorage::secure_delete_file(&path)?;
+            }
+        }
+        fs::remove_dir(dir).map_err(|_| StorageError::IoError)
+    }
+}
+
+impl Drop for SessionArchive {
+    fn drop(&mut self) {
+        // The files cannot be opened without the key anyway
+        let _ = Self::delete_dir(&self.dir);
+    }
 }
 
 // ============================================================================
@@ -388,6 +1208,15 @@ pub enum StorageError {
     EncryptionFailed,
     DecryptionFailed,
     CorruptedData,
+    InvalidParameters,
+    /// The file uses a format version this build cannot read
+    VersionMismatch(u8),
+    /// The file is too short to hold a header and authentication tag
+    TruncatedFile,
+    /// The message is larger than the whole cache
+    MessageTooLarge,
+    /// The key-encryption key was destroyed, so the file is unrecoverable
+    KeyDestroyed,
 }
 
 impl std::fmt::Display for StorageError {
@@ -399,6 +1228,13 @@ impl std::fmt::Display for StorageError {
             StorageError::EncryptionFailed => write!(f, "Encryption failed"),
             StorageError::DecryptionFailed => write!(f, "Decryption failed (wrong PIN?)"),
             StorageError::CorruptedData => write!(f, "Data corrupted"),
+            StorageError::InvalidParameters => write!(f, "Invalid key derivation parameters"),
+            StorageError::VersionMismatch(version) => {
+                write!(f, "Unsupported storage format version {}", version)
+            }
+            StorageError::TruncatedFile => write!(f, "File truncated"),
+            StorageError::MessageTooLarge => write!(f, "Message too large to cache"),
+            StorageError::KeyDestroyed => write!(f, "Storage key destroyed; data is unrecoverable"),
         }
     }
 }
@@ -469,6 +1305,187 @@ mod tests {
         let _ = storage.secure_delete();
     }
 
+    #[test]
+    fn test_interrupted_write_keeps_original() {
+        let storage = temp_storage();
+
+        let config = SecurityConfig {
+            dead_man_days: 3,
+            ..Default::default()
+        };
+        storage.save_config(&config, "pin").unwrap();
+        let temp_path = SecureStorage::temp_path(&storage.config_path);
+        assert!(!temp_path.exists());
+
+        // Simulate a crash partway through writing the replacement
+        fs::write(&temp_path, [0xAB; 7]).unwrap();
+
+        let loaded = storage.load_config("pin").unwrap();
+        assert_eq!(loaded.dead_man_days, 3);
+
+        // The next save replaces both the stale temp file and the original
+        let updated = SecurityConfig {
+            dead_man_days: 9,
+            ..Default::default()
+        };
+        storage.save_config(&updated, "pin").unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(storage.load_config("pin").unwrap().dead_man_days, 9);
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_rotate_pin_reencrypts_all_files() {
+        let storage = temp_storage();
+
+        let config = SecurityConfig {
+            dead_man_days: 5,
+            ..Default::default()
+        };
+        let identity = crate::Identity {
+            mnemonic: vec!["word".into()],
+            root_key: [4u8; 32],
+            public_id: "id_rotate".into(),
+            kem_decap_key: vec![1, 2, 3],
+            kem_encap_key: vec![4, 5, 6],
+            x25519_secret: [0u8; 32],
+        };
+        storage.save_config(&config, "old").unwrap();
+        storage.save_contacts(&[], "old").unwrap();
+        storage.save_identity(&identity, "old").unwrap();
+
+        storage.rotate_pin("old", "new").unwrap();
+
+        assert_eq!(storage.load_config("new").unwrap().dead_man_days, 5);
+        assert!(storage.load_contacts("new").unwrap().is_empty());
+        assert_eq!(
+            storage.load_identity("new").unwrap().unwrap().public_id,
+            "id_rotate"
+        );
+
+        assert!(storage.load_config("old").is_err());
+        assert!(storage.load_contacts("old").is_err());
+        assert!(storage.load_identity("old").is_err());
+
+        let dir = storage.config_path.parent().unwrap();
+        for entry in fs::read_dir(dir).unwrap() {
+            let name = entry.unwrap().file_name();
+            assert!(!name.to_string_lossy().ends_with(".tmp"), "{name:?}");
+        }
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_notes_key_follows_the_kek() {
+        let storage = fast_storage();
+
+        // Nothing has been sealed yet, so the first notes key creates the KEK
+        let key = storage.notes_key("old").unwrap();
+        assert_eq!(*storage.notes_key("old").unwrap(), *key);
+        assert!(matches!(
+            storage.notes_key("wrong"),
+            Err(StorageError::DecryptionFailed)
+        ));
+
+        storage.rotate_pin("old", "new").unwrap();
+        assert_eq!(*storage.notes_key("new").unwrap(), *key);
+
+        storage.destroy_kek().unwrap();
+        assert_ne!(*storage.notes_key("new").unwrap(), *key);
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_rotate_pin_with_wrong_pin_changes_nothing() {
+        let storage = temp_storage();
+
+        storage
+            .save_config(&SecurityConfig::default(), "old")
+            .unwrap();
+        let before = fs::read(&storage.config_path).unwrap();
+
+        assert!(matches!(
+            storage.rotate_pin("wrong", "new"),
+            Err(StorageError::DecryptionFailed)
+        ));
+        assert_eq!(fs::read(&storage.config_path).unwrap(), before);
+        assert!(storage.load_config("old").is_ok());
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_truncated_file_detected() {
+        let storage = fast_storage();
+        storage
+            .save_config(&SecurityConfig::default(), "pin")
+            .unwrap();
+        let data = fs::read(&storage.config_path).unwrap();
+
+        for len in [
+            0,
+            3,
+            20,
+            FILE_MAGIC.len() + PARAMS_LEN + SALT_LEN + NONCE_LEN + 5,
+        ] {
+            fs::write(&storage.config_path, &data[..len]).unwrap();
+            assert!(matches!(
+                storage.load_config("pin"),
+                Err(StorageError::TruncatedFile)
+            ));
+        }
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_unknown_version_detected() {
+        let storage = fast_storage();
+        storage
+            .save_config(&SecurityConfig::default(), "pin")
+            .unwrap();
+        let mut data = fs::read(&storage.config_path).unwrap();
+        data[3] = b'9';
+        fs::write(&storage.config_path, &data).unwrap();
+
+        assert!(matches!(
+            storage.load_config("pin"),
+            Err(StorageError::VersionMismatch(9))
+        ));
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_garbled_json_is_not_a_wrong_pin() {
+        let storage = fast_storage();
+
+        // Authentic ciphertext around plaintext that is not a config
+        let data = storage.seal("pin", b"{\"security_enabled\": tru").unwrap();
+        SecureStorage::write_atomic(&storage.config_path, &data).unwrap();
+
+        assert!(matches!(
+            storage.load_config("pin"),
+            Err(StorageError::CorruptedData)
+        ));
+        assert!(matches!(
+            storage.load_config("wrong"),
+            Err(StorageError::DecryptionFailed)
+        ));
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
     #[test]
     fn test_secure_delete() {
         let storage = temp_storage();
@@ -482,4 +1499,254 @@ mod tests {
 
         assert!(!storage.config_exists());
     }
+
+    #[test]
+    fn test_destroyed_kek_makes_files_unrecoverable() {
+        let storage = fast_storage();
+        let contacts_path = storage.config_path.with_file_name("contacts.enc");
+
+        storage
+            .save_config(&SecurityConfig::default(), "pin")
+            .unwrap();
+        storage.save_contacts(&[], "pin").unwrap();
+        assert!(storage.kek_path().exists());
+        let ciphertext = fs::read(&contacts_path).unwrap();
+        assert!(ciphertext.starts_with(WRAPPED_FILE_MAGIC));
+
+        // Deleting one file leaves the others readable
+        storage.secure_delete().unwrap();
+        assert!(storage.kek_path().exists());
+        assert!(storage.load_contacts("pin").is_ok());
+
+        storage.destroy_kek().unwrap();
+        assert!(!storage.kek_path().exists());
+
+        // Even a surviving copy of the ciphertext cannot be opened with the PIN
+        fs::write(&contacts_path, &ciphertext).unwrap();
+        assert!(matches!(
+            storage.load_contacts("pin"),
+            Err(StorageError::KeyDestroyed)
+        ));
+
+        // Nor under a fresh KEK created by a later save
+        storage
+            .save_config(&SecurityConfig::default(), "pin")
+            .unwrap();
+        assert!(matches!(
+            storage.load_contacts("pin"),
+            Err(StorageError::DecryptionFailed)
+        ));
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+        assert!(!storage.kek_path().exists());
+    }
+
+    #[test]
+    fn test_kek_file_is_sealed_under_pin() {
+        let storage = fast_storage();
+        storage.save_contacts(&[], "pin").unwrap();
+
+        // The KEK on disk is not the raw key, and needs the PIN
+        let sealed = fs::read(storage.kek_path()).unwrap();
+        assert!(sealed.starts_with(FILE_MAGIC));
+        assert!(matches!(
+            SecureStorage::read_kek(&storage.kek_path(), "wrong"),
+            Err(StorageError::DecryptionFailed)
+        ));
+        let (kek, was_sealed) = SecureStorage::read_kek(&storage.kek_path(), "pin").unwrap();
+        assert!(was_sealed);
+
+        // A KEK in the old plain form still opens, and is resealed on use
+        fs::write(storage.kek_path(), &kek[..]).unwrap();
+        assert!(storage.load_contacts("pin").is_ok());
+        storage.save_contacts(&[], "pin").unwrap();
+        assert!(fs::read(storage.kek_path())
+            .unwrap()
+            .starts_with(FILE_MAGIC));
+        assert!(storage.load_contacts("pin").is_ok());
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    /// Cheap parameters so tests stay fast
+    const FAST_PARAMS: Argon2Params = Argon2Params {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn fast_storage() -> SecureStorage {
+        let dir = temp_storage().config_path.parent().unwrap().to_path_buf();
+        SecureStorage::with_params(dir, FAST_PARAMS)
+    }
+
+    #[test]
+    fn test_file_records_its_argon2_params() {
+        let writer = temp_storage();
+        let dir = writer.config_path.parent().unwrap().to_path_buf();
+        let writer = SecureStorage::with_params(dir.clone(), FAST_PARAMS);
+
+        let config = SecurityConfig {
+            dead_man_days: 11,
+            ..Default::default()
+        };
+        writer.save_config(&config, "pin").unwrap();
+
+        // A reader with different (default) parameters uses the stored ones
+        let reader = SecureStorage::new(dir);
+        assert_ne!(reader.params, FAST_PARAMS);
+        assert_eq!(reader.load_config("pin").unwrap().dead_man_days, 11);
+
+        // Cleanup
+        let _ = reader.wipe_all_data();
+    }
+
+    #[test]
+    fn test_absurd_params_rejected_on_load() {
+        let storage = temp_storage();
+        let storage = SecureStorage::with_params(
+            storage.config_path.parent().unwrap().to_path_buf(),
+            FAST_PARAMS,
+        );
+        storage
+            .save_config(&SecurityConfig::default(), "pin")
+            .unwrap();
+
+        // Rewrite the stored iteration count to something that would hang
+        let mut data = fs::read(&storage.config_path).unwrap();
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&storage.config_path, &data).unwrap();
+
+        assert!(matches!(
+            storage.load_config("pin"),
+            Err(StorageError::InvalidParameters)
+        ));
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_calibrate_stays_in_bounds() {
+        let params = Argon2Params::calibrate(Duration::from_millis(1));
+        assert!(params.validate().is_ok());
+        assert_eq!(params, Argon2Params::default());
+    }
+
+    #[test]
+    fn test_message_cache_roundtrip_is_encrypted() {
+        let storage = fast_storage();
+        let mut cache = MessageCache::open(&storage, "pin").unwrap();
+        let ttl = Duration::from_secs(3600);
+        cache
+            .cache_message("session_a", b"ciphertext one".to_vec(), ttl)
+            .unwrap();
+        cache
+            .cache_message("session_b", b"ciphertext two".to_vec(), ttl)
+            .unwrap();
+        cache
+            .cache_message("session_a", b"ciphertext three".to_vec(), ttl)
+            .unwrap();
+
+        let on_disk = fs::read(storage.config_path.with_file_name(MESSAGE_CACHE_FILE)).unwrap();
+        assert!(on_disk.starts_with(WRAPPED_FILE_MAGIC));
+        assert!(!on_disk
+            .windows(b"ciphertext".len())
+            .any(|w| w == b"ciphertext"));
+        assert!(matches!(
+            MessageCache::open(&storage, "wrong"),
+            Err(StorageError::DecryptionFailed)
+        ));
+
+        let mut reopened = MessageCache::open(&storage, "pin").unwrap();
+        assert_eq!(
+            reopened.take_pending("session_a").unwrap(),
+            vec![b"ciphertext one".to_vec(), b"ciphertext three".to_vec()]
+        );
+        assert!(reopened.take_pending("session_a").unwrap().is_empty());
+        assert_eq!(MessageCache::open(&storage, "pin").unwrap().len(), 1);
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+        assert!(!storage
+            .config_path
+            .with_file_name(MESSAGE_CACHE_FILE)
+            .exists());
+    }
+
+    #[test]
+    fn test_message_cache_survives_pin_rotation() {
+        let storage = fast_storage();
+        let mut cache = MessageCache::open(&storage, "old").unwrap();
+        cache
+            .cache_message(
+                "session_a",
+                b"ciphertext".to_vec(),
+                Duration::from_secs(3600),
+            )
+            .unwrap();
+        drop(cache);
+
+        storage.rotate_pin("old", "new").unwrap();
+
+        let mut reopened = MessageCache::open(&storage, "new").unwrap();
+        assert_eq!(
+            reopened.take_pending("session_a").unwrap(),
+            vec![b"ciphertext".to_vec()]
+        );
+        assert!(MessageCache::open(&storage, "old").is_err());
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_message_cache_ttl_expiry() {
+        let storage = fast_storage();
+        let mut cache = MessageCache::open(&storage, "pin").unwrap();
+        cache
+            .cache_message("session", b"fresh".to_vec(), Duration::from_secs(3600))
+            .unwrap();
+        cache
+            .cache_message("session", b"stale".to_vec(), Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(cache.prune_expired().unwrap(), 1);
+        assert_eq!(MessageCache::open(&storage, "pin").unwrap().len(), 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(
+            cache.take_pending("session").unwrap(),
+            vec![b"fresh".to_vec()]
+        );
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
+
+    #[test]
+    fn test_message_cache_evicts_oldest_over_cap() {
+        let storage = fast_storage();
+        let mut cache = MessageCache::open(&storage, "pin")
+            .unwrap()
+            .with_max_bytes(100);
+        let ttl = Duration::from_secs(3600);
+        for i in 0..5u8 {
+            cache.cache_message("session", vec![i; 30], ttl).unwrap();
+        }
+
+        assert_eq!(cache.len(), 3);
+        assert!(cache.total_bytes() <= 100);
+        let pending = cache.take_pending("session").unwrap();
+        assert_eq!(pending, vec![vec![2u8; 30], vec![3u8; 30], vec![4u8; 30]]);
+
+        assert!(matches!(
+            cache.cache_message("session", vec![0u8; 101], ttl),
+            Err(StorageError::MessageTooLarge)
+        ));
+
+        // Cleanup
+        let _ = storage.wipe_all_data();
+    }
 }
@@ -1,6 +1,7 @@
 use comlock_app_lib::security::{verify_pin, Pin, PinResult, SecurityConfig};
 
 #[test]
+#[allow(clippy::field_reassign_with_default)]
 fn test_timing_attack_resistance_audit() {
     // This test serves as an executable audit record for timing attack resistance.
     //
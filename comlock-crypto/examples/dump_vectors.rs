@@ -0,0 +1,7 @@
+//! Print the published key-schedule vectors as JSON.
+//!
+//! `cargo run --example dump_vectors --features vector-dump > test-vectors/kdf_v2.json`
+
+fn main() {
+    println!("{}", comlock_crypto::test_vectors::dump_json());
+}
@@ -0,0 +1,264 @@
+//! # ComLock Crypto - Content Types
+//!
+//! Typed messages carry one byte naming what the plaintext is, ahead of
+//! the plaintext itself:
+//!
+//! ```text
+//! [content_type: u8][plaintext]
+//! ```
+//!
+//! The byte is encrypted and authenticated with the rest of the message,
+//! so the relay cannot see or change it. A type byte this version does not
+//! know decodes to [`ContentType::Unknown`] rather than failing, so newer
+//! peers can add types without breaking older ones.
+//!
+//! Typed messages set the header's `typed_content` flag, which the AEAD
+//! authenticates. The typed decrypt functions refuse a message without it
+//! and [`decrypt_message`](crate::decrypt_message) one with it, so a type
+//! byte is never mistaken for message content or the reverse.
+
+use alloc::vec::Vec;
+
+use rand::{CryptoRng, RngCore};
+
+use crate::{
+    ComLockError, HeaderEncoding, MAX_PLAINTEXT_SIZE, NONCE_SIZE, PaddingScheme, RatchetState,
+    Result, check_plaintext_size, open_message, seal,
+};
+
+/// Size of the content type prefix in bytes.
+pub const CONTENT_TYPE_SIZE: usize = 1;
+
+/// What a typed message's plaintext contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    /// UTF-8 text
+    Text,
+    /// Arbitrary binary data, such as a file
+    Binary,
+    /// An encoded image
+    Image,
+    /// A key update control message
+    KeyUpdate,
+    /// A delivery or read receipt
+    Receipt,
+    /// A typing indicator
+    Typing,
+    /// A type this version does not know; only
+    /// [`from_byte`](ContentType::from_byte) makes one, so it never holds
+    /// the byte of a known type
+    Unknown(UnknownContentType),
+}
+
+/// The raw byte of a content type this version does not know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnknownContentType(u8);
+
+impl UnknownContentType {
+    /// The byte sent on the wire.
+    pub fn byte(self) -> u8 {
+        self.0
+    }
+}
+
+impl ContentType {
+    /// The byte sent on the wire for this type.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Text => 0,
+            Self::Binary => 1,
+            Self::Image => 2,
+            Self::KeyUpdate => 3,
+            Self::Receipt => 4,
+            Self::Typing => 5,
+            Self::Unknown(unknown) => unknown.0,
+        }
+    }
+
+    /// The type named by a wire byte; unassigned bytes give `Unknown`.
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::Text,
+            1 => Self::Binary,
+            2 => Self::Image,
+            3 => Self::KeyUpdate,
+            4 => Self::Receipt,
+            5 => Self::Typing,
+            other => Self::Unknown(UnknownContentType(other)),
+        }
+    }
+}
+
+/// A decrypted typed message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptedMessage {
+    /// What the plaintext contains
+    pub content_type: ContentType,
+    /// The message itself, without the type byte
+    pub plaintext: Vec<u8>,
+}
+
+/// Encrypt a message tagged with `content_type`.
+///
+/// Same as [`encrypt_message`](crate::encrypt_message), with the type byte
+/// counted against [`MAX_PLAINTEXT_SIZE`].
+#[cfg(feature = "std")]
+pub fn encrypt_typed_message(
+    msg: &[u8],
+    content_type: ContentType,
+    state: &mut RatchetState,
+) -> Result<Vec<u8>> {
+    encrypt_typed_message_with_rng(msg, content_type, state, &mut rand::thread_rng())
+}
+
+/// [`encrypt_typed_message`] drawing the nonce and any new ratchet keys
+/// from `rng`.
+pub fn encrypt_typed_message_with_rng<R: RngCore + CryptoRng>(
+    msg: &[u8],
+    content_type: ContentType,
+    state: &mut RatchetState,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    check_plaintext_size(msg, MAX_PLAINTEXT_SIZE - CONTENT_TYPE_SIZE)?;
+
+    let mut tagged = Vec::with_capacity(CONTENT_TYPE_SIZE + msg.len());
+    tagged.push(content_type.to_byte());
+    tagged.extend_from_slice(msg);
+
+    let ratchet_output = state.step_typed_with_rng(rng)?;
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    rng.try_fill_bytes(&mut nonce_bytes)
+        .map_err(|_| ComLockError::RngFailure)?;
+
+    seal(
+        &tagged,
+        PaddingScheme::None,
+        &ratchet_output,
+        nonce_bytes,
+        HeaderEncoding::Binary,
+        state,
+    )
+}
+
+/// Decrypt a message written by [`encrypt_typed_message`].
+///
+/// # Errors
+/// As [`decrypt_message`](crate::decrypt_message), except that
+/// `ContentTypeMismatch` means the message is untyped, and
+/// `MessageTooShort` if the plaintext has no type byte.
+#[cfg(feature = "std")]
+pub fn decrypt_typed_message(
+    ciphertext: &[u8],
+    state: &mut RatchetState,
+) -> Result<DecryptedMessage> {
+    decrypt_typed_message_with_rng(ciphertext, state, &mut rand::thread_rng())
+}
+
+/// [`decrypt_typed_message`] drawing any new KEM keypair from `rng`.
+pub fn decrypt_typed_message_with_rng<R: RngCore + CryptoRng>(
+    ciphertext: &[u8],
+    state: &mut RatchetState,
+    rng: &mut R,
+) -> Result<DecryptedMessage> {
+    let mut plaintext = open_message(ciphertext, state, true, rng)?;
+    let &type_byte = plaintext.first().ok_or(ComLockError::MessageTooShort)?;
+    plaintext.remove(0);
+    Ok(DecryptedMessage {
+        content_type: ContentType::from_byte(type_byte),
+        plaintext,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decrypt_message, encrypt_message};
+
+    #[test]
+    fn test_content_type_round_trips() {
+        let root_key = [7u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        for content_type in [
+            ContentType::Text,
+            ContentType::Binary,
+            ContentType::Image,
+            ContentType::KeyUpdate,
+            ContentType::Receipt,
+            ContentType::Typing,
+        ] {
+            assert_eq!(ContentType::from_byte(content_type.to_byte()), content_type);
+
+            let ciphertext = encrypt_typed_message(b"payload", content_type, &mut alice).unwrap();
+            let decrypted = decrypt_typed_message(&ciphertext, &mut bob).unwrap();
+            assert_eq!(decrypted.content_type, content_type);
+            assert_eq!(decrypted.plaintext, b"payload");
+        }
+
+        // An empty body still carries its type
+        let ciphertext = encrypt_typed_message(b"", ContentType::Typing, &mut bob).unwrap();
+        let decrypted = decrypt_typed_message(&ciphertext, &mut alice).unwrap();
+        assert_eq!(decrypted.content_type, ContentType::Typing);
+        assert!(decrypted.plaintext.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_content_type_falls_back() {
+        let root_key = [7u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // A type from a newer peer
+        let sticker = ContentType::from_byte(0x42);
+        assert!(matches!(sticker, ContentType::Unknown(unknown) if unknown.byte() == 0x42));
+        let ciphertext = encrypt_typed_message(b"sticker", sticker, &mut alice).unwrap();
+        let decrypted = decrypt_typed_message(&ciphertext, &mut bob).unwrap();
+        assert_eq!(decrypted.content_type, sticker);
+        assert_eq!(decrypted.plaintext, b"sticker");
+
+        // Every assigned byte decodes to its known type
+        for byte in 0..=5 {
+            assert!(!matches!(
+                ContentType::from_byte(byte),
+                ContentType::Unknown(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_typed_and_untyped_messages_are_not_confused() {
+        let root_key = [7u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        let untyped = encrypt_message(b"\x02not an image", &mut alice).unwrap();
+        assert!(matches!(
+            decrypt_typed_message(&untyped, &mut bob),
+            Err(ComLockError::ContentTypeMismatch)
+        ));
+        let typed = encrypt_typed_message(b"hi", ContentType::Text, &mut alice).unwrap();
+        assert!(matches!(
+            decrypt_message(&typed, &mut bob),
+            Err(ComLockError::ContentTypeMismatch)
+        ));
+
+        // Clearing the flag in transit fails authentication
+        let mut stripped = typed.clone();
+        stripped[2 + 32] &= !0x20;
+        assert!(matches!(
+            decrypt_message(&stripped, &mut bob),
+            Err(ComLockError::DecryptionFailed)
+        ));
+
+        // Neither rejection disturbed the session
+        assert_eq!(
+            decrypt_message(&untyped, &mut bob).unwrap(),
+            b"\x02not an image"
+        );
+        assert_eq!(
+            decrypt_typed_message(&typed, &mut bob).unwrap().plaintext,
+            b"hi"
+        );
+    }
+}
@@ -10,6 +10,15 @@
 //! the maximum Sphinx payload size, we split it into multiple fragments
 //! that can be sent via different mix routes and reassembled by the
 //! recipient.
+//!
+//! Large payloads (e.g. file attachments) beyond a single Sphinx payload
+//! are split the same way with [`fragment_payload`]. Payload fragments
+//! carry no checksum of their own: the payload is a message ciphertext, so
+//! its AEAD tag rejects a corrupted or mismatched fragment when the
+//! reassembled payload is decrypted.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 use crate::ComLockError;
 use crate::header::MessageHeader;
@@ -18,7 +27,7 @@ use crate::header::MessageHeader;
 pub const MAX_SINGLE_HEADER_SIZE: usize = 2048;
 
 /// Size of fragment metadata overhead.
-const FRAGMENT_OVERHEAD: usize = 12; // fragment_id(1) + total(1) + seq(4) + len(2) + reserved(4)
+const FRAGMENT_OVERHEAD: usize = 12; // fragment_id(8) + index(1) + total(1) + len(2)
 
 /// A fragmented piece of a message header.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -34,6 +43,12 @@ pub struct HeaderFragment {
 }
 
 impl HeaderFragment {
+    /// Whether `index` and `total` describe a fragment of a real group:
+    /// at least one fragment, and this one among them.
+    fn has_valid_position(&self) -> bool {
+        self.total != 0 && self.index < self.total
+    }
+
     /// Serialize the fragment to bytes.
     pub fn serialize(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(FRAGMENT_OVERHEAD + self.data.len());
@@ -47,6 +62,10 @@ impl HeaderFragment {
     }
 
     /// Deserialize a fragment from bytes.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` if the bytes are truncated, the
+    /// total is zero or the index is not below the total.
     pub fn deserialize(bytes: &[u8]) -> Result<Self, ComLockError> {
         if bytes.len() < FRAGMENT_OVERHEAD {
             return Err(ComLockError::InvalidHeader);
@@ -65,38 +84,51 @@ impl HeaderFragment {
 
         let data = bytes[FRAGMENT_OVERHEAD..FRAGMENT_OVERHEAD + len].to_vec();
 
-        Ok(Self {
+        let fragment = Self {
             fragment_id,
             index,
             total,
             data,
-        })
+        };
+        if !fragment.has_valid_position() {
+            return Err(ComLockError::InvalidHeader);
+        }
+        Ok(fragment)
     }
 }
 
 /// Fragment a message header into smaller pieces.
 ///
-/// Returns `None` if the header fits in a single packet (no fragmentation needed).
-/// Returns `Some(fragments)` if the header was split.
+/// Returns `Ok(None)` if the header fits in a single packet (no
+/// fragmentation needed) and `Ok(Some(fragments))` if the header was split.
+///
+/// # Errors
+/// - `FragmentSizeTooSmall` if `max_fragment_size` leaves no room for data
+///   after the fragment overhead
+/// - `TooManyFragments` if the header would need more than 255 fragments
 pub fn fragment_header(
     header: &MessageHeader,
     max_fragment_size: usize,
-) -> Option<Vec<HeaderFragment>> {
+) -> Result<Option<Vec<HeaderFragment>>, ComLockError> {
     let header_bytes = header.serialize();
 
     if header_bytes.len() <= MAX_SINGLE_HEADER_SIZE {
-        return None; // No fragmentation needed
+        return Ok(None); // No fragmentation needed
     }
 
     let data_per_fragment = max_fragment_size.saturating_sub(FRAGMENT_OVERHEAD);
     if data_per_fragment == 0 {
-        return None; // Invalid configuration
+        return Err(ComLockError::FragmentSizeTooSmall {
+            size: max_fragment_size,
+            min: FRAGMENT_OVERHEAD + 1,
+        });
     }
 
-    let total_fragments = (header_bytes.len() + data_per_fragment - 1) / data_per_fragment;
-    if total_fragments > 255 {
-        return None; // Too many fragments
-    }
+    let total_fragments = header_bytes.len().div_ceil(data_per_fragment);
+    let total = u8::try_from(total_fragments).map_err(|_| ComLockError::TooManyFragments {
+        needed: total_fragments,
+        max: u8::MAX as usize,
+    })?;
 
     // Generate a random fragment ID
     let mut fragment_id = [0u8; 8];
@@ -108,18 +140,22 @@ pub fn fragment_header(
         fragments.push(HeaderFragment {
             fragment_id,
             index: i as u8,
-            total: total_fragments as u8,
+            total,
             data: chunk.to_vec(),
         });
     }
 
-    Some(fragments)
+    Ok(Some(fragments))
 }
 
 /// Reassemble header fragments into a complete MessageHeader.
 ///
 /// Fragments must all have the same `fragment_id` and all indices
 /// from 0 to total-1 must be present.
+///
+/// # Errors
+/// Returns `ComLockError::InvalidHeader` for an empty or incomplete set,
+/// fragments from different groups, a zero total or an index out of range.
 pub fn reassemble_header(fragments: &[HeaderFragment]) -> Result<MessageHeader, ComLockError> {
     if fragments.is_empty() {
         return Err(ComLockError::InvalidHeader);
@@ -134,7 +170,10 @@ pub fn reassemble_header(fragments: &[HeaderFragment]) -> Result<MessageHeader,
     }
 
     for frag in fragments {
-        if frag.fragment_id != expected_id || frag.total != expected_total {
+        if frag.fragment_id != expected_id
+            || frag.total != expected_total
+            || !frag.has_valid_position()
+        {
             return Err(ComLockError::InvalidHeader);
         }
     }
@@ -185,8 +224,12 @@ impl FragmentBuffer {
     /// Add a fragment to the buffer.
     ///
     /// Returns `Some(header)` if all fragments are now received and
-    /// the header was successfully reassembled.
+    /// the header was successfully reassembled. A fragment with a zero
+    /// total or an index out of range is dropped.
     pub fn add_fragment(&mut self, fragment: HeaderFragment) -> Option<MessageHeader> {
+        if !fragment.has_valid_position() {
+            return None;
+        }
         let frag_id = fragment.fragment_id;
         let expected_total = fragment.total;
 
@@ -220,6 +263,266 @@ impl FragmentBuffer {
     }
 }
 
+/// Size of payload fragment metadata overhead.
+const PAYLOAD_FRAGMENT_OVERHEAD: usize = 16; // group_id(8) + index(2) + total(2) + len(4)
+
+/// Maximum number of incomplete payloads held by a [`PayloadFragmentBuffer`].
+pub const MAX_PENDING_PAYLOADS: usize = 32;
+
+/// Maximum bytes of fragment data held by a [`PayloadFragmentBuffer`] (16 MiB).
+pub const MAX_PENDING_PAYLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long a [`PayloadFragmentBuffer`] waits for the rest of a payload
+/// after its first fragment arrives.
+pub const PAYLOAD_FRAGMENT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A fragmented piece of a large message payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadFragment {
+    /// Unique identifier for this fragmented payload (random).
+    pub group_id: [u8; 8],
+    /// This fragment's index (0-indexed).
+    pub index: u16,
+    /// Total number of fragments.
+    pub total: u16,
+    /// The fragment data.
+    pub data: Vec<u8>,
+}
+
+impl PayloadFragment {
+    /// Serialize the fragment to bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PAYLOAD_FRAGMENT_OVERHEAD + self.data.len());
+        bytes.extend_from_slice(&self.group_id);
+        bytes.extend_from_slice(&self.index.to_le_bytes());
+        bytes.extend_from_slice(&self.total.to_le_bytes());
+        let len = self.data.len() as u32;
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Deserialize a fragment from bytes.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, ComLockError> {
+        if bytes.len() < PAYLOAD_FRAGMENT_OVERHEAD {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+
+        let group_id: [u8; 8] = bytes[0..8]
+            .try_into()
+            .map_err(|_| ComLockError::InvalidCiphertext)?;
+        let index = u16::from_le_bytes([bytes[8], bytes[9]]);
+        let total = u16::from_le_bytes([bytes[10], bytes[11]]);
+        let len = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize;
+
+        if bytes.len() - PAYLOAD_FRAGMENT_OVERHEAD < len {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+        if total == 0 || index >= total {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+
+        let data = bytes[PAYLOAD_FRAGMENT_OVERHEAD..PAYLOAD_FRAGMENT_OVERHEAD + len].to_vec();
+
+        Ok(Self {
+            group_id,
+            index,
+            total,
+            data,
+        })
+    }
+}
+
+/// Fragment a payload into pieces of at most `max_fragment_size` serialized bytes.
+///
+/// Returns an empty `Vec` if `max_fragment_size` leaves no room for data or
+/// the payload would need more than `u16::MAX` fragments.
+pub fn fragment_payload(data: &[u8], max_fragment_size: usize) -> Vec<PayloadFragment> {
+    let data_per_fragment = max_fragment_size.saturating_sub(PAYLOAD_FRAGMENT_OVERHEAD);
+    if data_per_fragment == 0 {
+        return Vec::new(); // Invalid configuration
+    }
+
+    let total_fragments = data.len().div_ceil(data_per_fragment).max(1);
+    let Ok(total) = u16::try_from(total_fragments) else {
+        return Vec::new(); // Too many fragments
+    };
+
+    // Generate a random group ID
+    let mut group_id = [0u8; 8];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut group_id);
+
+    if data.is_empty() {
+        return vec![PayloadFragment {
+            group_id,
+            index: 0,
+            total,
+            data: Vec::new(),
+        }];
+    }
+
+    data.chunks(data_per_fragment)
+        .enumerate()
+        .map(|(i, chunk)| PayloadFragment {
+            group_id,
+            index: i as u16,
+            total,
+            data: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Reassemble payload fragments.
+///
+/// Fragments must all belong to the same group and all indices from 0 to
+/// total-1 must be present. The result is not authenticated: it is only
+/// trustworthy once it decrypts.
+pub fn reassemble_payload(fragments: &[PayloadFragment]) -> Result<Vec<u8>, ComLockError> {
+    let first = fragments.first().ok_or(ComLockError::InvalidCiphertext)?;
+
+    if fragments.len() != first.total as usize {
+        return Err(ComLockError::InvalidCiphertext);
+    }
+
+    for frag in fragments {
+        if frag.group_id != first.group_id || frag.total != first.total {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+    }
+
+    // Sort by index
+    let mut sorted: Vec<&PayloadFragment> = fragments.iter().collect();
+    sorted.sort_by_key(|f| f.index);
+
+    // Verify we have all indices
+    for (i, frag) in sorted.iter().enumerate() {
+        if frag.index as usize != i {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+    }
+
+    // Concatenate data
+    let total_size: usize = sorted.iter().map(|f| f.data.len()).sum();
+    let mut reassembled = Vec::with_capacity(total_size);
+    for frag in sorted {
+        reassembled.extend_from_slice(&frag.data);
+    }
+    Ok(reassembled)
+}
+
+/// Fragment buffer for accumulating incoming payload fragments.
+///
+/// At most [`MAX_PENDING_PAYLOADS`] incomplete payloads and
+/// [`MAX_PENDING_PAYLOAD_BYTES`] of fragment data are held; going beyond
+/// either drops the oldest payloads. A payload still incomplete
+/// [`PAYLOAD_FRAGMENT_TIMEOUT`] after its first fragment arrived is
+/// dropped.
+#[derive(Debug, Default)]
+pub struct PayloadFragmentBuffer {
+    /// Pending fragments grouped by group_id.
+    pending: HashMap<[u8; 8], Vec<PayloadFragment>>,
+    /// Pending group IDs and when their first fragment arrived, oldest first.
+    order: VecDeque<([u8; 8], Instant)>,
+    /// Bytes of fragment data held.
+    pending_bytes: usize,
+}
+
+impl PayloadFragmentBuffer {
+    /// Create a new payload fragment buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a fragment to the buffer.
+    ///
+    /// Returns `Some(payload)` if all fragments are now received and the
+    /// payload was reassembled. The payload is unauthenticated until it
+    /// decrypts.
+    pub fn add_fragment(&mut self, fragment: PayloadFragment) -> Option<Vec<u8>> {
+        self.add_fragment_at(fragment, Instant::now())
+    }
+
+    /// [`PayloadFragmentBuffer::add_fragment`] with the fragment arriving
+    /// at `now`.
+    pub fn add_fragment_at(&mut self, fragment: PayloadFragment, now: Instant) -> Option<Vec<u8>> {
+        self.expire(now);
+        let group_id = fragment.group_id;
+        let expected_total = fragment.total;
+        let len = fragment.data.len();
+        if len > MAX_PENDING_PAYLOAD_BYTES {
+            return None;
+        }
+
+        // Check if we already have this index
+        if self
+            .pending
+            .get(&group_id)
+            .is_some_and(|entry| entry.iter().any(|f| f.index == fragment.index))
+        {
+            return None; // Duplicate
+        }
+
+        // Make room by dropping the oldest payloads
+        while (!self.pending.contains_key(&group_id) && self.order.len() >= MAX_PENDING_PAYLOADS)
+            || self.pending_bytes + len > MAX_PENDING_PAYLOAD_BYTES
+        {
+            let Some(&(oldest, _)) = self.order.front() else {
+                break;
+            };
+            self.remove_group(&oldest);
+        }
+
+        if !self.pending.contains_key(&group_id) {
+            self.order.push_back((group_id, now));
+        }
+        self.pending_bytes += len;
+        let entry = self.pending.entry(group_id).or_default();
+        entry.push(fragment);
+        if entry.len() != expected_total as usize {
+            return None;
+        }
+
+        let frags = self.remove_group(&group_id)?;
+        reassemble_payload(&frags).ok()
+    }
+
+    /// Drop every payload whose first fragment arrived more than
+    /// [`PAYLOAD_FRAGMENT_TIMEOUT`] before `now`.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(&(group_id, started)) = self.order.front() {
+            if now.saturating_duration_since(started) <= PAYLOAD_FRAGMENT_TIMEOUT {
+                break;
+            }
+            self.remove_group(&group_id);
+        }
+    }
+
+    /// Remove a pending payload and return its fragments.
+    fn remove_group(&mut self, group_id: &[u8; 8]) -> Option<Vec<PayloadFragment>> {
+        let frags = self.pending.remove(group_id)?;
+        self.order.retain(|(id, _)| id != group_id);
+        self.pending_bytes -= frags.iter().map(|f| f.data.len()).sum::<usize>();
+        Some(frags)
+    }
+
+    /// Clear old pending fragments.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.order.clear();
+        self.pending_bytes = 0;
+    }
+
+    /// Number of incomplete payloads.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Bytes of fragment data held for incomplete payloads.
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -230,8 +533,12 @@ mod tests {
             classical_pubkey: [0x42; 32],
             kem_ciphertext: Some(vec![0xAB; 1568]), // Kyber-1024 ciphertext
             kem_pubkey: Some(vec![0xCD; 1568]),     // Kyber-1024 public key
+            kem_pubkey_ref: None,
             message_number: 42,
             previous_chain_length: 10,
+            capabilities: None,
+            kem_ciphertext_ref: None,
+            typed_content: false,
         }
     }
 
@@ -240,15 +547,19 @@ mod tests {
             classical_pubkey: [0x42; 32],
             kem_ciphertext: None,
             kem_pubkey: None,
+            kem_pubkey_ref: None,
             message_number: 1,
             previous_chain_length: 0,
+            capabilities: None,
+            kem_ciphertext_ref: None,
+            typed_content: false,
         }
     }
 
     #[test]
     fn test_small_header_no_fragmentation() {
         let header = create_small_header();
-        let result = fragment_header(&header, 512);
+        let result = fragment_header(&header, 512).unwrap();
         assert!(result.is_none());
         assert!(!needs_fragmentation(&header));
     }
@@ -258,7 +569,7 @@ mod tests {
         let header = create_large_header();
         assert!(needs_fragmentation(&header));
 
-        let fragments = fragment_header(&header, 512).unwrap();
+        let fragments = fragment_header(&header, 512).unwrap().unwrap();
         assert!(fragments.len() > 1);
 
         // Verify all fragments have same ID and correct total
@@ -292,7 +603,7 @@ mod tests {
     #[test]
     fn test_reassembly() {
         let header = create_large_header();
-        let fragments = fragment_header(&header, 512).unwrap();
+        let fragments = fragment_header(&header, 512).unwrap().unwrap();
 
         // Reassemble in order
         let reassembled = reassemble_header(&fragments).unwrap();
@@ -304,7 +615,7 @@ mod tests {
     #[test]
     fn test_reassembly_out_of_order() {
         let header = create_large_header();
-        let mut fragments = fragment_header(&header, 512).unwrap();
+        let mut fragments = fragment_header(&header, 512).unwrap().unwrap();
 
         // Shuffle fragments
         fragments.reverse();
@@ -316,7 +627,7 @@ mod tests {
     #[test]
     fn test_fragment_buffer() {
         let header = create_large_header();
-        let fragments = fragment_header(&header, 512).unwrap();
+        let fragments = fragment_header(&header, 512).unwrap().unwrap();
 
         let mut buffer = FragmentBuffer::new();
 
@@ -341,7 +652,7 @@ mod tests {
     #[test]
     fn test_missing_fragment_fails() {
         let header = create_large_header();
-        let mut fragments = fragment_header(&header, 512).unwrap();
+        let mut fragments = fragment_header(&header, 512).unwrap().unwrap();
 
         // Remove one fragment
         fragments.remove(1);
@@ -349,4 +660,191 @@ mod tests {
         let result = reassemble_header(&fragments);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_zero_total_fragment_rejected() {
+        let frag = HeaderFragment {
+            fragment_id: [9; 8],
+            index: 0,
+            total: 0,
+            data: vec![0xAA; 4],
+        };
+        assert!(matches!(
+            HeaderFragment::deserialize(&frag.serialize()),
+            Err(ComLockError::InvalidHeader)
+        ));
+        assert!(reassemble_header(std::slice::from_ref(&frag)).is_err());
+
+        // The buffer does not start a group that can never complete
+        let mut buffer = FragmentBuffer::new();
+        assert!(buffer.add_fragment(frag).is_none());
+        assert_eq!(buffer.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_out_of_range_index_rejected() {
+        let header = create_large_header();
+        let mut fragments = fragment_header(&header, 512).unwrap().unwrap();
+        let total = fragments[0].total;
+
+        let stray = HeaderFragment {
+            index: total,
+            ..fragments[0].clone()
+        };
+        assert!(matches!(
+            HeaderFragment::deserialize(&stray.serialize()),
+            Err(ComLockError::InvalidHeader)
+        ));
+
+        let mut buffer = FragmentBuffer::new();
+        assert!(buffer.add_fragment(stray.clone()).is_none());
+        assert_eq!(buffer.pending_count(), 0);
+
+        // A full set with one index replaced by an out-of-range one
+        let last = fragments.len() - 1;
+        fragments[last] = stray;
+        assert!(reassemble_header(&fragments).is_err());
+    }
+
+    #[test]
+    fn test_fragment_size_at_overhead_rejected() {
+        let header = create_large_header();
+        for size in [FRAGMENT_OVERHEAD, FRAGMENT_OVERHEAD - 1, 0] {
+            assert!(matches!(
+                fragment_header(&header, size),
+                Err(ComLockError::FragmentSizeTooSmall { min, .. }) if min == FRAGMENT_OVERHEAD + 1
+            ));
+        }
+        // One data byte per fragment needs far more than 255 fragments
+        assert!(matches!(
+            fragment_header(&header, FRAGMENT_OVERHEAD + 1),
+            Err(ComLockError::TooManyFragments { max: 255, .. })
+        ));
+    }
+
+    fn large_payload() -> Vec<u8> {
+        (0..100 * 1024).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_payload_fragments_fit_size() {
+        let payload = large_payload();
+        let fragments = fragment_payload(&payload, 32 * 1024);
+
+        assert_eq!(fragments.len(), 4);
+        for (i, frag) in fragments.iter().enumerate() {
+            assert_eq!(frag.index as usize, i);
+            assert_eq!(frag.total, 4);
+            assert!(frag.serialize().len() <= 32 * 1024);
+        }
+
+        let parsed = PayloadFragment::deserialize(&fragments[1].serialize()).unwrap();
+        assert_eq!(parsed, fragments[1]);
+    }
+
+    #[test]
+    fn test_payload_buffer_out_of_order() {
+        let payload = large_payload();
+        let mut fragments = fragment_payload(&payload, 32 * 1024);
+        fragments.swap(0, 2);
+        fragments.reverse();
+
+        let mut buffer = PayloadFragmentBuffer::new();
+        let (last, rest) = fragments.split_last().unwrap();
+        for frag in rest {
+            assert!(buffer.add_fragment(frag.clone()).is_none());
+        }
+        assert_eq!(buffer.pending_count(), 1);
+
+        assert_eq!(buffer.add_fragment(last.clone()).unwrap(), payload);
+        assert_eq!(buffer.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_payload_missing_fragment_fails() {
+        let payload = large_payload();
+        let mut fragments = fragment_payload(&payload, 32 * 1024);
+        fragments.remove(2);
+
+        assert!(reassemble_payload(&fragments).is_err());
+
+        let mut buffer = PayloadFragmentBuffer::new();
+        for frag in fragments {
+            assert!(buffer.add_fragment(frag).is_none());
+        }
+        assert_eq!(buffer.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_corrupted_payload_fragment_fails_decryption() {
+        let shared_secret = [7u8; 32];
+        let mut alice = crate::RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = crate::RatchetState::new(shared_secret, false).unwrap();
+
+        let ciphertext = crate::encrypt_message(&large_payload(), &mut alice).unwrap();
+        let mut fragments = fragment_payload(&ciphertext, 32 * 1024);
+        fragments[1].data[0] ^= 1;
+
+        let reassembled = reassemble_payload(&fragments).unwrap();
+        assert!(matches!(
+            crate::decrypt_message(&reassembled, &mut bob),
+            Err(ComLockError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_payload_buffer_is_bounded() {
+        let mut buffer = PayloadFragmentBuffer::new();
+        for _ in 0..MAX_PENDING_PAYLOADS + 5 {
+            let fragments = fragment_payload(&large_payload(), 32 * 1024);
+            buffer.add_fragment(fragments[0].clone());
+        }
+        assert_eq!(buffer.pending_count(), MAX_PENDING_PAYLOADS);
+    }
+
+    #[test]
+    fn test_payload_buffer_bytes_are_bounded() {
+        let big = vec![0u8; 6 * 1024 * 1024];
+        let mut buffer = PayloadFragmentBuffer::new();
+        for _ in 0..4 {
+            let fragments = fragment_payload(&big, big.len());
+            assert_eq!(fragments.len(), 2);
+            buffer.add_fragment(fragments[0].clone());
+            assert!(buffer.pending_bytes() <= MAX_PENDING_PAYLOAD_BYTES);
+        }
+        assert_eq!(buffer.pending_count(), 2);
+
+        let oversized = PayloadFragment {
+            group_id: [9; 8],
+            index: 0,
+            total: 2,
+            data: vec![0u8; MAX_PENDING_PAYLOAD_BYTES + 1],
+        };
+        assert!(buffer.add_fragment(oversized).is_none());
+        assert_eq!(buffer.pending_count(), 2);
+    }
+
+    #[test]
+    fn test_payload_buffer_expires_stale_payloads() {
+        let payload = large_payload();
+        let stale = fragment_payload(&payload, 32 * 1024);
+        let fresh = fragment_payload(&payload, 32 * 1024);
+        let start = Instant::now();
+
+        let mut buffer = PayloadFragmentBuffer::new();
+        assert!(buffer.add_fragment_at(stale[0].clone(), start).is_none());
+        let later = start + PAYLOAD_FRAGMENT_TIMEOUT + Duration::from_secs(1);
+        assert!(buffer.add_fragment_at(fresh[0].clone(), later).is_none());
+        assert_eq!(buffer.pending_count(), 1);
+
+        // The rest of the stale payload no longer completes it
+        for frag in &stale[1..] {
+            assert!(buffer.add_fragment_at(frag.clone(), later).is_none());
+        }
+        assert_eq!(buffer.pending_count(), 2);
+
+        buffer.expire(later + PAYLOAD_FRAGMENT_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(buffer.pending_count(), 0);
+        assert_eq!(buffer.pending_bytes(), 0);
+    }
 }
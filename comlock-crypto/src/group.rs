@@ -0,0 +1,462 @@
+//! # Group Messaging
+//!
+//! Sender-keys fan-out for group conversations. The sender holds one
+//! symmetric sender key per group; each message body is encrypted once
+//! under a per-message key derived from it, and only that small key is
+//! wrapped for each member over their pairwise ratchet session.
+//!
+//! ## Wire Format (per member)
+//!
+//! ```text
+//! [wrap_len: u32 LE][wrap: pairwise ratchet ciphertext][nonce: 12 bytes][body + tag]
+//! ```
+//!
+//! The wrap decrypts to `[group header][message key: 32 bytes]`, and the
+//! body is authenticated together with the group header.
+
+use aes_gcm_siv::{
+    Aes256GcmSiv, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+use crate::{
+    ComLockError, NONCE_SIZE, RatchetState, Result, decrypt_message, encrypt_message_with_rng,
+};
+
+/// Size of the serialized group header.
+const GROUP_HEADER_SIZE: usize = 16 + 4 + 4;
+
+/// A group conversation from the sender's point of view.
+pub struct GroupSession {
+    /// Random group identifier
+    group_id: [u8; 16],
+    /// Our sender key for this group (rotated on membership changes)
+    sender_key: [u8; 32],
+    /// Incremented whenever the sender key rotates
+    epoch: u32,
+    /// Shared group message counter (for ordering)
+    counter: u32,
+    /// Session IDs of the members, in the order ratchets are supplied
+    members: Vec<String>,
+}
+
+/// A decrypted group message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMessage {
+    /// Group identifier
+    pub group_id: [u8; 16],
+    /// Sender key epoch the message was sent under
+    pub epoch: u32,
+    /// Group message counter
+    pub counter: u32,
+    /// Decrypted plaintext
+    pub plaintext: Vec<u8>,
+}
+
+impl GroupSession {
+    /// Create a new group with the given member session IDs.
+    ///
+    /// # Errors
+    /// - `RngFailure` if the group ID or sender key cannot be drawn
+    pub fn new(members: Vec<String>) -> Result<Self> {
+        Self::new_with_rng(members, &mut rand::thread_rng())
+    }
+
+    /// [`GroupSession::new`] drawing the group ID and sender key from `rng`.
+    pub fn new_with_rng<R: RngCore + CryptoRng>(members: Vec<String>, rng: &mut R) -> Result<Self> {
+        let mut group_id = [0u8; 16];
+        rng.try_fill_bytes(&mut group_id)
+            .map_err(|_| ComLockError::RngFailure)?;
+        let mut sender_key = [0u8; 32];
+        rng.try_fill_bytes(&mut sender_key)
+            .map_err(|_| ComLockError::RngFailure)?;
+
+        Ok(Self {
+            group_id,
+            sender_key,
+            epoch: 0,
+            counter: 0,
+            members,
+        })
+    }
+
+    /// The group identifier.
+    pub fn group_id(&self) -> &[u8; 16] {
+        &self.group_id
+    }
+
+    /// Current sender key epoch.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Member session IDs, in the order `encrypt_group` expects ratchets.
+    pub fn members(&self) -> &[String] {
+        &self.members
+    }
+
+    /// Add a member and rotate the sender key.
+    ///
+    /// # Errors
+    /// - `RngFailure` if the new sender key cannot be drawn; the group is
+    ///   left unchanged
+    pub fn add_member(&mut self, session_id: String) -> Result<()> {
+        self.rotate_sender_key()?;
+        self.members.push(session_id);
+        Ok(())
+    }
+
+    /// Remove a member and rotate the sender key.
+    ///
+    /// Returns `false` if the session ID is not a member.
+    ///
+    /// # Errors
+    /// - `RngFailure` if the new sender key cannot be drawn; the group is
+    ///   left unchanged
+    pub fn remove_member(&mut self, session_id: &str) -> Result<bool> {
+        if !self.members.iter().any(|member| member == session_id) {
+            return Ok(false);
+        }
+        self.rotate_sender_key()?;
+        self.members.retain(|member| member != session_id);
+        Ok(true)
+    }
+
+    /// Encrypt a message once and wrap its key for every member.
+    ///
+    /// `members` must hold the pairwise ratchet for each member, in the
+    /// same order as [`GroupSession::members`]. The message is wrapped on
+    /// copies of the ratchets, which replace them only once every member's
+    /// wrap succeeded, so a failure leaves the group and every pairwise
+    /// session as they were.
+    ///
+    /// # Returns
+    /// * One `(session_id, ciphertext)` pair per member
+    ///
+    /// # Errors
+    /// - `MemberCountMismatch` if the number of ratchets differs from the
+    ///   number of members
+    /// - Any error from wrapping the key for a member
+    pub fn encrypt_group(
+        &mut self,
+        msg: &[u8],
+        members: &mut [RatchetState],
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        self.encrypt_group_with_rng(msg, members, &mut rand::thread_rng())
+    }
+
+    /// [`GroupSession::encrypt_group`] drawing nonces and any new ratchet
+    /// keys from `rng`.
+    pub fn encrypt_group_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        msg: &[u8],
+        members: &mut [RatchetState],
+        rng: &mut R,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        if members.len() != self.members.len() {
+            return Err(ComLockError::MemberCountMismatch {
+                expected: self.members.len(),
+                actual: members.len(),
+            });
+        }
+
+        let counter = self.counter;
+        let group_header = self.group_header(counter);
+        let mut message_key = self.message_key(counter)?;
+
+        // Encrypt the body once
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        rng.try_fill_bytes(&mut nonce_bytes)
+            .map_err(|_| ComLockError::RngFailure)?;
+        let cipher = Aes256GcmSiv::new_from_slice(&message_key)
+            .map_err(|_| ComLockError::EncryptionFailed)?;
+        let body = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce_bytes),
+                Payload {
+                    msg,
+                    aad: &group_header,
+                },
+            )
+            .map_err(|_| ComLockError::EncryptionFailed)?;
+
+        // Wrap the message key for each member over their pairwise session
+        let mut key_blob = Vec::with_capacity(GROUP_HEADER_SIZE + 32);
+        key_blob.extend_from_slice(&group_header);
+        key_blob.extend_from_slice(&message_key);
+        message_key.zeroize();
+
+        let mut staged = members.to_vec();
+        let wraps: Result<Vec<Vec<u8>>> = staged
+            .iter_mut()
+            .map(|ratchet| encrypt_message_with_rng(&key_blob, ratchet, rng))
+            .collect();
+        key_blob.zeroize();
+        let wraps = wraps?;
+
+        // Every wrap succeeded: commit the advanced ratchets
+        for (ratchet, next) in members.iter_mut().zip(staged) {
+            *ratchet = next;
+        }
+        self.counter += 1;
+
+        Ok(self
+            .members
+            .iter()
+            .zip(wraps)
+            .map(|(session_id, wrap)| {
+                let mut output = Vec::with_capacity(4 + wrap.len() + NONCE_SIZE + body.len());
+                output.extend_from_slice(&(wrap.len() as u32).to_le_bytes());
+                output.extend_from_slice(&wrap);
+                output.extend_from_slice(&nonce_bytes);
+                output.extend_from_slice(&body);
+                (session_id.clone(), output)
+            })
+            .collect())
+    }
+
+    /// Replace the sender key, keeping the old one if no new key can be drawn
+    fn rotate_sender_key(&mut self) -> Result<()> {
+        let mut sender_key = [0u8; 32];
+        rand::thread_rng()
+            .try_fill_bytes(&mut sender_key)
+            .map_err(|_| ComLockError::RngFailure)?;
+        self.sender_key.zeroize();
+        self.sender_key = sender_key;
+        sender_key.zeroize();
+        self.epoch += 1;
+        Ok(())
+    }
+
+    fn group_header(&self, counter: u32) -> [u8; GROUP_HEADER_SIZE] {
+        let mut header = [0u8; GROUP_HEADER_SIZE];
+        header[..16].copy_from_slice(&self.group_id);
+        header[16..20].copy_from_slice(&self.epoch.to_le_bytes());
+        header[20..].copy_from_slice(&counter.to_le_bytes());
+        header
+    }
+
+    fn message_key(&self, counter: u32) -> Result<[u8; 32]> {
+        let hk = Hkdf::<Sha256>::new(Some(&self.group_id), &self.sender_key);
+        let mut info = Vec::with_capacity(13);
+        info.extend_from_slice(b"group_msg");
+        info.extend_from_slice(&counter.to_le_bytes());
+
+        let mut key = [0u8; 32];
+        hk.expand(&info, &mut key)
+            .map_err(|_| ComLockError::EncryptionFailed)?;
+        Ok(key)
+    }
+}
+
+impl Drop for GroupSession {
+    fn drop(&mut self) {
+        self.sender_key.zeroize();
+    }
+}
+
+/// Decrypt a group message with the pairwise ratchet shared with its sender.
+///
+/// # Errors
+/// - `MessageTooShort` if the framing is truncated
+/// - `InvalidHeader` if the wrapped key blob is malformed
+/// - `DecryptionFailed` if the wrap or body fails authentication
+pub fn decrypt_group(ciphertext: &[u8], state: &mut RatchetState) -> Result<GroupMessage> {
+    let wrap_len = ciphertext
+        .get(..4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or(ComLockError::MessageTooShort)?;
+    let nonce_start = 4usize
+        .checked_add(wrap_len)
+        .ok_or(ComLockError::MessageTooShort)?;
+    let body_start = nonce_start + NONCE_SIZE;
+    if ciphertext.len() < body_start + 16 {
+        return Err(ComLockError::MessageTooShort);
+    }
+
+    // Unwrap the message key
+    let mut key_blob = decrypt_message(&ciphertext[4..nonce_start], state)?;
+    if key_blob.len() != GROUP_HEADER_SIZE + 32 {
+        key_blob.zeroize();
+        return Err(ComLockError::InvalidHeader);
+    }
+    let group_header = &key_blob[..GROUP_HEADER_SIZE];
+
+    // Decrypt the shared body
+    let cipher = Aes256GcmSiv::new_from_slice(&key_blob[GROUP_HEADER_SIZE..])
+        .map_err(|_| ComLockError::DecryptionFailed)?;
+    let plaintext = cipher.decrypt(
+        Nonce::from_slice(&ciphertext[nonce_start..body_start]),
+        Payload {
+            msg: &ciphertext[body_start..],
+            aad: group_header,
+        },
+    );
+
+    let mut group_id = [0u8; 16];
+    group_id.copy_from_slice(&group_header[..16]);
+    let epoch = u32::from_le_bytes([
+        group_header[16],
+        group_header[17],
+        group_header[18],
+        group_header[19],
+    ]);
+    let counter = u32::from_le_bytes([
+        group_header[20],
+        group_header[21],
+        group_header[22],
+        group_header[23],
+    ]);
+    key_blob.zeroize();
+
+    Ok(GroupMessage {
+        group_id,
+        epoch,
+        counter,
+        plaintext: plaintext.map_err(|_| ComLockError::DecryptionFailed)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairwise_sessions(count: u8) -> (Vec<RatchetState>, Vec<RatchetState>) {
+        (0..count)
+            .map(|i| {
+                let secret = [i.wrapping_mul(31).wrapping_add(1); 32];
+                (
+                    RatchetState::new(secret, true).unwrap(),
+                    RatchetState::new(secret, false).unwrap(),
+                )
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn test_group_fan_out_to_three_members() {
+        let members = vec!["bob".to_string(), "carol".into(), "dave".into()];
+        let mut group = GroupSession::new(members.clone()).unwrap();
+        let (mut ours, mut theirs) = pairwise_sessions(3);
+
+        let outputs = group.encrypt_group(b"Hello group", &mut ours).unwrap();
+        assert_eq!(outputs.len(), 3);
+
+        for (i, (session_id, ciphertext)) in outputs.iter().enumerate() {
+            assert_eq!(session_id, &members[i]);
+            let message = decrypt_group(ciphertext, &mut theirs[i]).unwrap();
+            assert_eq!(message.plaintext, b"Hello group");
+            assert_eq!(&message.group_id, group.group_id());
+            assert_eq!(message.counter, 0);
+        }
+
+        // The counter orders subsequent messages
+        let outputs = group.encrypt_group(b"Second", &mut ours).unwrap();
+        let message = decrypt_group(&outputs[2].1, &mut theirs[2]).unwrap();
+        assert_eq!(message.counter, 1);
+
+        // A member's ciphertext does not open with another member's ratchet
+        let outputs = group.encrypt_group(b"Third", &mut ours).unwrap();
+        assert!(decrypt_group(&outputs[0].1, &mut theirs[1]).is_err());
+    }
+
+    #[test]
+    fn test_membership_changes_rotate_sender_key() {
+        let mut group = GroupSession::new(vec!["bob".into(), "carol".into()]).unwrap();
+        let (mut ours, mut theirs) = pairwise_sessions(2);
+
+        assert_eq!(group.epoch(), 0);
+        assert!(group.remove_member("carol").unwrap());
+        assert!(!group.remove_member("carol").unwrap());
+        assert_eq!(group.epoch(), 1);
+        assert_eq!(group.members(), ["bob".to_string()]);
+
+        // Ratchets must match the current membership
+        assert!(matches!(
+            group.encrypt_group(b"Hi", &mut ours),
+            Err(ComLockError::MemberCountMismatch {
+                expected: 1,
+                actual: 2
+            })
+        ));
+
+        let outputs = group.encrypt_group(b"Hi", &mut ours[..1]).unwrap();
+        let message = decrypt_group(&outputs[0].1, &mut theirs[0]).unwrap();
+        assert_eq!(message.epoch, 1);
+
+        group.add_member("erin".into()).unwrap();
+        assert_eq!(group.epoch(), 2);
+        assert_eq!(group.members().len(), 2);
+    }
+
+    /// An RNG that fails once it has handed out `budget` bytes.
+    struct ExhaustibleRng {
+        budget: usize,
+    }
+
+    impl RngCore for ExhaustibleRng {
+        fn next_u32(&mut self) -> u32 {
+            panic!("infallible call on an exhaustible RNG")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            panic!("infallible call on an exhaustible RNG")
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            panic!("infallible call on an exhaustible RNG")
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> core::result::Result<(), rand::Error> {
+            self.budget = self
+                .budget
+                .checked_sub(dest.len())
+                .ok_or_else(|| rand::Error::new(std::io::Error::other("RNG exhausted")))?;
+            dest.fill(0x5a);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for ExhaustibleRng {}
+
+    #[test]
+    fn test_failed_fan_out_leaves_sessions_untouched() {
+        let mut group = GroupSession::new(vec!["bob".into(), "carol".into()]).unwrap();
+        let (mut ours, mut theirs) = pairwise_sessions(2);
+
+        // Enough for the body nonce and the first wrap, not the second
+        let mut rng = ExhaustibleRng {
+            budget: 2 * NONCE_SIZE,
+        };
+        assert!(matches!(
+            group.encrypt_group_with_rng(b"Hi", &mut ours, &mut rng),
+            Err(ComLockError::RngFailure)
+        ));
+        assert_eq!(ours[0].status().messages_sent, 0);
+
+        // The retry reuses the counter and both members stay in step
+        let outputs = group.encrypt_group(b"Hi", &mut ours).unwrap();
+        for (i, (_, ciphertext)) in outputs.iter().enumerate() {
+            let message = decrypt_group(ciphertext, &mut theirs[i]).unwrap();
+            assert_eq!(message.counter, 0);
+        }
+    }
+
+    #[test]
+    fn test_group_creation_reports_rng_failure() {
+        // The group ID draws, the sender key does not
+        let mut rng = ExhaustibleRng { budget: 16 };
+        assert!(matches!(
+            GroupSession::new_with_rng(vec!["bob".into()], &mut rng),
+            Err(ComLockError::RngFailure)
+        ));
+
+        let mut rng = ExhaustibleRng { budget: 48 };
+        let group = GroupSession::new_with_rng(vec!["bob".into()], &mut rng).unwrap();
+        assert_eq!(group.group_id(), &[0x5a; 16]);
+    }
+}
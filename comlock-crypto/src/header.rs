@@ -3,18 +3,104 @@
 //! Defines the `MessageHeader` structure for efficient serialization
 //! of cryptographic metadata in ComLock messages.
 
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ciborium::value::Value;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+use crate::kem::{Kem, Kyber1024};
 use crate::ratchet::{KYBER_CIPHERTEXT_SIZE, KYBER_PUBKEY_SIZE};
 use crate::ComLockError;
 
+/// Flag bit set when the header carries a KEM ciphertext.
+const FLAG_KEM_CIPHERTEXT: u8 = 0x01;
+
+/// Flag bit set when the header carries a KEM public key.
+const FLAG_KEM_PUBKEY: u8 = 0x02;
+
+/// Flag bit set when the header carries a reference to a KEM public key.
+const FLAG_KEM_PUBKEY_REF: u8 = 0x04;
+
+/// Flag bit set when the header carries the sender's capabilities byte.
+const FLAG_CAPABILITIES: u8 = 0x08;
+
+/// Flag bit set when the header names the receiver KEM key its ciphertext
+/// was encapsulated to.
+const FLAG_KEM_CIPHERTEXT_REF: u8 = 0x10;
+
+/// Flag bit set when the plaintext starts with a content type byte.
+const FLAG_TYPED_CONTENT: u8 = 0x20;
+
+/// All defined flag bits.
+const FLAGS_MASK: u8 = FLAG_KEM_CIPHERTEXT
+    | FLAG_KEM_PUBKEY
+    | FLAG_KEM_PUBKEY_REF
+    | FLAG_CAPABILITIES
+    | FLAG_KEM_CIPHERTEXT_REF
+    | FLAG_TYPED_CONTENT;
+
+/// Capabilities bit set by a sender that runs the KEM ratchet; see
+/// [`ProtocolMode`](crate::ratchet::ProtocolMode).
+pub const CAPABILITY_KEM: u8 = 0x01;
+
+/// Capabilities bit set by a sender on protocol version 2: it derives keys
+/// under the current [`KDF_PROTOCOL_VERSION`](crate::ratchet::KDF_PROTOCOL_VERSION)
+/// and frames every plaintext with a length prefix (see
+/// [`padding`](crate::padding)). Older senders leave it clear, and no key
+/// or plaintext of theirs can be read as ours.
+pub const CAPABILITY_PROTOCOL_V2: u8 = 0x02;
+
+/// Length of a KEM public key reference, see [`kem_pubkey_reference`].
+pub const KEM_PUBKEY_REF_LEN: usize = 8;
+
+/// KEM public keys remembered by a [`KemKeyCache`] in each direction.
+const KEM_KEY_CACHE_SIZE: usize = 4;
+
+/// Version byte prefixed to a CBOR-encoded header.
+pub const HEADER_VERSION_CBOR: u8 = 1;
+
+/// Size of a binary header with no optional fields, the smallest header in
+/// either encoding.
+pub const MIN_HEADER_LEN: usize = 32 + 1 + 4 + 4;
+
+/// Upper bound on an encoded Kyber-1024 header in either encoding: every
+/// field at its largest, with room for the CBOR framing and version byte.
+pub const MAX_HEADER_LEN: usize =
+    MIN_HEADER_LEN + KYBER_CIPHERTEXT_SIZE + KYBER_PUBKEY_SIZE + KEM_PUBKEY_REF_LEN + 64;
+
+/// CBOR map keys of the header fields.
+const CBOR_KEY_CLASSICAL_PUBKEY: u8 = 0;
+const CBOR_KEY_MESSAGE_NUMBER: u8 = 1;
+const CBOR_KEY_PREVIOUS_CHAIN_LENGTH: u8 = 2;
+const CBOR_KEY_KEM_CIPHERTEXT: u8 = 3;
+const CBOR_KEY_KEM_PUBKEY: u8 = 4;
+const CBOR_KEY_KEM_PUBKEY_REF: u8 = 5;
+const CBOR_KEY_CAPABILITIES: u8 = 6;
+const CBOR_KEY_KEM_CIPHERTEXT_REF: u8 = 7;
+const CBOR_KEY_TYPED_CONTENT: u8 = 8;
+
+/// Wire encoding of a [`MessageHeader`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeaderEncoding {
+    /// Fixed binary layout, see [`MessageHeader::serialize`]
+    #[default]
+    Binary,
+    /// Self-describing CBOR map, see [`MessageHeader::serialize_cbor`]
+    Cbor,
+}
+
 /// Message header containing cryptographic metadata.
 ///
 /// This header accompanies every encrypted message and contains:
 /// - Classical X25519 ephemeral public key (always present, 32 bytes)
 /// - Optional Kyber-1024 ciphertext (when KEM ratchet advances, ~1568 bytes)
-/// - Optional Kyber-1024 public key (to enable the remote to encapsulate)
+/// - Optional Kyber-1024 public key (to enable the remote to encapsulate),
+///   or an 8-byte reference to one the remote already has
 /// - Message counters for ordering and replay detection
+/// - Optional capabilities byte, on the sender's first sending chain
 ///
 /// The header is designed for efficient serialization with optional
 /// fields to minimize bandwidth when KEM operations are not performed.
@@ -33,15 +119,39 @@ pub struct MessageHeader {
     #[serde(with = "optional_bytes")]
     pub kem_pubkey: Option<Vec<u8>>,
 
+    /// Reference to a Kyber-1024 public key the remote has already seen,
+    /// sent instead of `kem_pubkey` (see [`KemKeyCache`])
+    #[serde(default)]
+    pub kem_pubkey_ref: Option<[u8; KEM_PUBKEY_REF_LEN]>,
+
     /// Message number in the current sending chain (for ordering)
     pub message_number: u32,
 
-    /// Length of the previous receiving chain (for skipped message handling)
+    /// Message number at which the sender's previous sending chain ended
+    /// (for skipped message handling across chain rotations)
     pub previous_chain_length: u32,
+
+    /// Sender's capabilities (see [`CAPABILITY_KEM`]), carried by the
+    /// headers of its first sending chain
+    #[serde(default)]
+    pub capabilities: Option<u8>,
+
+    /// Reference to the receiver's Kyber-1024 public key that
+    /// `kem_ciphertext` was encapsulated to, so the receiver decapsulates
+    /// with that one keypair instead of trying each of its keys
+    #[serde(default)]
+    pub kem_ciphertext_ref: Option<[u8; KEM_PUBKEY_REF_LEN]>,
+
+    /// Whether the plaintext starts with a content type byte (see
+    /// [`content`](crate::content))
+    #[serde(default)]
+    pub typed_content: bool,
 }
 
 /// Custom serialization for optional byte vectors to handle compact encoding
 mod optional_bytes {
+    use alloc::vec::Vec;
+
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
     pub fn serialize<S>(value: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
@@ -67,7 +177,7 @@ impl MessageHeader {
     /// * `kem_ciphertext` - Optional Kyber ciphertext (when encapsulating)
     /// * `kem_pubkey` - Optional Kyber public key (to receive encapsulation)
     /// * `message_number` - Current message number in sending chain
-    /// * `previous_chain_length` - Length of previous receiving chain
+    /// * `previous_chain_length` - Message number where the previous sending chain ended
     pub fn new(
         classical_pubkey: [u8; 32],
         kem_ciphertext: Option<Vec<u8>>,
@@ -79,8 +189,12 @@ impl MessageHeader {
             classical_pubkey,
             kem_ciphertext,
             kem_pubkey: kem_pubkey.map(|pk| pk.to_vec()),
+            kem_pubkey_ref: None,
             message_number,
             previous_chain_length,
+            capabilities: None,
+            kem_ciphertext_ref: None,
+            typed_content: false,
         }
     }
 
@@ -88,32 +202,23 @@ impl MessageHeader {
     ///
     /// Format:
     /// - Bytes 0-31: Classical public key (fixed)
-    /// - Byte 32: Flags (bit 0: has_kem_ct, bit 1: has_kem_pk)
+    /// - Byte 32: Flags (bit 0: has_kem_ct, bit 1: has_kem_pk, bit 2: has_kem_pk_ref,
+    ///   bit 3: has_capabilities, bit 4: has_kem_ct_ref, bit 5: typed_content)
     /// - Bytes 33-36: Message number (u32 LE)
     /// - Bytes 37-40: Previous chain length (u32 LE)
-    /// - If has_kem_ct: Next KYBER_CIPHERTEXT_SIZE bytes
-    /// - If has_kem_pk: Next KYBER_PUBKEY_SIZE bytes
+    /// - If has_kem_ct: Next `K::CIPHERTEXT_SIZE` bytes
+    /// - If has_kem_pk: Next `K::PUBLIC_KEY_SIZE` bytes
+    /// - If has_kem_pk_ref: Next KEM_PUBKEY_REF_LEN bytes
+    /// - If has_capabilities: Next byte
+    /// - If has_kem_ct_ref: Next KEM_PUBKEY_REF_LEN bytes
     pub fn serialize(&self) -> Vec<u8> {
-        let has_kem_ct = self.kem_ciphertext.is_some();
-        let has_kem_pk = self.kem_pubkey.is_some();
-
-        // Calculate total size
-        let mut size = 32 + 1 + 4 + 4; // pubkey + flags + msg_num + prev_chain
-        if has_kem_ct {
-            size += KYBER_CIPHERTEXT_SIZE;
-        }
-        if has_kem_pk {
-            size += KYBER_PUBKEY_SIZE;
-        }
-
-        let mut buffer = Vec::with_capacity(size);
+        let mut buffer = Vec::with_capacity(self.serialized_size());
 
         // Classical public key (32 bytes)
         buffer.extend_from_slice(&self.classical_pubkey);
 
         // Flags byte
-        let flags: u8 = (has_kem_ct as u8) | ((has_kem_pk as u8) << 1);
-        buffer.push(flags);
+        buffer.push(self.flags());
 
         // Message counters
         buffer.extend_from_slice(&self.message_number.to_le_bytes());
@@ -129,97 +234,567 @@ impl MessageHeader {
             buffer.extend_from_slice(pk);
         }
 
+        // Optional KEM public key reference
+        if let Some(ref reference) = self.kem_pubkey_ref {
+            buffer.extend_from_slice(reference);
+        }
+
+        // Optional capabilities
+        if let Some(capabilities) = self.capabilities {
+            buffer.push(capabilities);
+        }
+
+        // Optional reference to the key the KEM ciphertext targets
+        if let Some(ref reference) = self.kem_ciphertext_ref {
+            buffer.extend_from_slice(reference);
+        }
+
         buffer
     }
 
-    /// Deserialize a header from binary format.
+    /// Deserialize a header from binary format, with Kyber-1024 KEM fields.
     ///
     /// # Errors
-    /// Returns `ComLockError::InvalidHeader` if the buffer is malformed.
+    /// As [`deserialize_for`](Self::deserialize_for).
     pub fn deserialize(bytes: &[u8]) -> Result<Self, ComLockError> {
-        const MIN_SIZE: usize = 32 + 1 + 4 + 4; // 41 bytes minimum
+        Self::deserialize_for::<Kyber1024>(bytes)
+    }
 
-        if bytes.len() < MIN_SIZE {
-            return Err(ComLockError::InvalidHeader);
-        }
+    /// Deserialize a header from binary format, with KEM fields sized for
+    /// the KEM `K`.
+    ///
+    /// Every field is read with checked slicing, so malformed input of any
+    /// length returns an error rather than panicking.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` if the buffer is truncated, has
+    /// unknown flag bits set, carries both a KEM public key and a reference,
+    /// names a ciphertext target without a ciphertext, or has trailing
+    /// bytes.
+    pub fn deserialize_for<K: Kem>(bytes: &[u8]) -> Result<Self, ComLockError> {
+        let mut offset: usize = 0;
+        let mut take = |len: usize| -> Result<&[u8], ComLockError> {
+            let field = offset
+                .checked_add(len)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(ComLockError::InvalidHeader)?;
+            offset += len;
+            Ok(field)
+        };
 
         // Parse classical public key
-        let classical_pubkey: [u8; 32] = bytes[0..32]
-            .try_into()
-            .map_err(|_| ComLockError::InvalidHeader)?;
-
-        // Parse flags
-        let flags = bytes[32];
-        let has_kem_ct = (flags & 0x01) != 0;
-        let has_kem_pk = (flags & 0x02) != 0;
+        let classical_pubkey = fixed_len(take(32)?)?;
 
-        // Parse message counters
-        let message_number = u32::from_le_bytes(
-            bytes[33..37]
-                .try_into()
-                .map_err(|_| ComLockError::InvalidHeader)?,
-        );
-        let previous_chain_length = u32::from_le_bytes(
-            bytes[37..41]
-                .try_into()
-                .map_err(|_| ComLockError::InvalidHeader)?,
-        );
-
-        // Calculate expected size and validate
-        let mut expected_size = MIN_SIZE;
-        if has_kem_ct {
-            expected_size += KYBER_CIPHERTEXT_SIZE;
-        }
-        if has_kem_pk {
-            expected_size += KYBER_PUBKEY_SIZE;
-        }
-
-        if bytes.len() < expected_size {
+        // Parse flags; bits beyond the defined fields are rejected
+        let flags = take(1)?[0];
+        if flags & !FLAGS_MASK != 0 {
             return Err(ComLockError::InvalidHeader);
         }
+        let has = |flag: u8| flags & flag != 0;
 
-        // Parse optional KEM ciphertext
-        let mut offset = MIN_SIZE;
-        let kem_ciphertext = if has_kem_ct {
-            let ct = bytes[offset..offset + KYBER_CIPHERTEXT_SIZE].to_vec();
-            offset += KYBER_CIPHERTEXT_SIZE;
-            Some(ct)
+        // Parse message counters
+        let mut read_u32 = || -> Result<u32, ComLockError> {
+            let field = take(4)?;
+            Ok(u32::from_le_bytes(
+                field.try_into().map_err(|_| ComLockError::InvalidHeader)?,
+            ))
+        };
+        let message_number = read_u32()?;
+        let previous_chain_length = read_u32()?;
+
+        // Parse optional KEM ciphertext and public key
+        let kem_ciphertext = if has(FLAG_KEM_CIPHERTEXT) {
+            Some(take(K::CIPHERTEXT_SIZE)?.to_vec())
         } else {
             None
         };
-
-        // Parse optional KEM public key
-        let kem_pubkey = if has_kem_pk {
-            let pk = bytes[offset..offset + KYBER_PUBKEY_SIZE].to_vec();
-            Some(pk)
+        let kem_pubkey = if has(FLAG_KEM_PUBKEY) {
+            Some(take(K::PUBLIC_KEY_SIZE)?.to_vec())
+        } else {
+            None
+        };
+        let kem_pubkey_ref = if has(FLAG_KEM_PUBKEY_REF) {
+            Some(fixed_len(take(KEM_PUBKEY_REF_LEN)?)?)
+        } else {
+            None
+        };
+        let capabilities = if has(FLAG_CAPABILITIES) {
+            Some(take(1)?[0])
+        } else {
+            None
+        };
+        let kem_ciphertext_ref = if has(FLAG_KEM_CIPHERTEXT_REF) {
+            Some(fixed_len(take(KEM_PUBKEY_REF_LEN)?)?)
         } else {
             None
         };
 
-        Ok(Self {
+        if offset != bytes.len() {
+            return Err(ComLockError::InvalidHeader);
+        }
+
+        Self {
             classical_pubkey,
             kem_ciphertext,
             kem_pubkey,
+            kem_pubkey_ref,
             message_number,
             previous_chain_length,
-        })
+            capabilities,
+            kem_ciphertext_ref,
+            typed_content: has(FLAG_TYPED_CONTENT),
+        }
+        .validated()
     }
 
-    /// Returns the total serialized size of this header.
-    pub fn serialized_size(&self) -> usize {
-        let mut size = 32 + 1 + 4 + 4; // Fixed overhead
+    /// Serialize the header as a CBOR map with small integer keys.
+    ///
+    /// Keys: `0` classical public key, `1` message number, `2` previous
+    /// chain length, `3` KEM ciphertext, `4` KEM public key, `5` KEM public
+    /// key reference, `6` capabilities, `7` KEM ciphertext target
+    /// reference, `8` typed content (`true` only). Byte fields are
+    /// CBOR byte strings and absent optional fields are omitted, so a minimal
+    /// header is about as small as the binary layout and a KEM-bearing one
+    /// only a few bytes larger.
+    pub fn serialize_cbor(&self) -> Vec<u8> {
+        let key = |k: u8| Value::Integer(k.into());
+        let mut map = vec![
+            (
+                key(CBOR_KEY_CLASSICAL_PUBKEY),
+                Value::Bytes(self.classical_pubkey.to_vec()),
+            ),
+            (
+                key(CBOR_KEY_MESSAGE_NUMBER),
+                Value::Integer(self.message_number.into()),
+            ),
+            (
+                key(CBOR_KEY_PREVIOUS_CHAIN_LENGTH),
+                Value::Integer(self.previous_chain_length.into()),
+            ),
+        ];
+        if let Some(ref ct) = self.kem_ciphertext {
+            map.push((key(CBOR_KEY_KEM_CIPHERTEXT), Value::Bytes(ct.clone())));
+        }
+        if let Some(ref pk) = self.kem_pubkey {
+            map.push((key(CBOR_KEY_KEM_PUBKEY), Value::Bytes(pk.clone())));
+        }
+        if let Some(ref reference) = self.kem_pubkey_ref {
+            map.push((
+                key(CBOR_KEY_KEM_PUBKEY_REF),
+                Value::Bytes(reference.to_vec()),
+            ));
+        }
+        if let Some(capabilities) = self.capabilities {
+            map.push((
+                key(CBOR_KEY_CAPABILITIES),
+                Value::Integer(capabilities.into()),
+            ));
+        }
+        if let Some(ref reference) = self.kem_ciphertext_ref {
+            map.push((
+                key(CBOR_KEY_KEM_CIPHERTEXT_REF),
+                Value::Bytes(reference.to_vec()),
+            ));
+        }
+        if self.typed_content {
+            map.push((key(CBOR_KEY_TYPED_CONTENT), Value::Bool(true)));
+        }
+
+        let mut buffer = Vec::with_capacity(self.serialized_size() + 16);
+        ciborium::into_writer(&Value::Map(map), &mut buffer)
+            .expect("writing CBOR to a Vec cannot fail");
+        buffer
+    }
+
+    /// Deserialize a header from the CBOR map written by
+    /// [`serialize_cbor`](Self::serialize_cbor), with Kyber-1024 KEM fields.
+    ///
+    /// # Errors
+    /// As [`deserialize_cbor_for`](Self::deserialize_cbor_for).
+    pub fn deserialize_cbor(bytes: &[u8]) -> Result<Self, ComLockError> {
+        Self::deserialize_cbor_for::<Kyber1024>(bytes)
+    }
+
+    /// Deserialize a header from the CBOR map written by
+    /// [`serialize_cbor`](Self::serialize_cbor), with KEM fields sized for
+    /// the KEM `K`.
+    ///
+    /// Unknown keys are skipped so later versions can add fields.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` if the input is not a single
+    /// CBOR map, a required field is missing or duplicated, a field has the
+    /// wrong type or length, both a KEM public key and a reference are
+    /// present, or a ciphertext target is named without a ciphertext.
+    pub fn deserialize_cbor_for<K: Kem>(bytes: &[u8]) -> Result<Self, ComLockError> {
+        let mut reader = bytes;
+        let value: Value =
+            ciborium::from_reader(&mut reader).map_err(|_| ComLockError::InvalidHeader)?;
+        if !reader.is_empty() {
+            return Err(ComLockError::InvalidHeader);
+        }
+        let Value::Map(entries) = value else {
+            return Err(ComLockError::InvalidHeader);
+        };
+
+        let mut classical_pubkey = None;
+        let mut message_number = None;
+        let mut previous_chain_length = None;
+        let mut kem_ciphertext = None;
+        let mut kem_pubkey = None;
+        let mut kem_pubkey_ref = None;
+        let mut capabilities = None;
+        let mut kem_ciphertext_ref = None;
+        let mut typed_content = None;
+
+        for (key, value) in entries {
+            let Value::Integer(key) = key else {
+                continue;
+            };
+            let Ok(key) = u8::try_from(key) else {
+                continue;
+            };
+            let slot_taken = match key {
+                CBOR_KEY_CLASSICAL_PUBKEY => classical_pubkey.replace(cbor_array(value)?).is_some(),
+                CBOR_KEY_MESSAGE_NUMBER => message_number.replace(cbor_u32(value)?).is_some(),
+                CBOR_KEY_PREVIOUS_CHAIN_LENGTH => {
+                    previous_chain_length.replace(cbor_u32(value)?).is_some()
+                }
+                CBOR_KEY_KEM_CIPHERTEXT => kem_ciphertext
+                    .replace(cbor_bytes(value, K::CIPHERTEXT_SIZE)?)
+                    .is_some(),
+                CBOR_KEY_KEM_PUBKEY => kem_pubkey
+                    .replace(cbor_bytes(value, K::PUBLIC_KEY_SIZE)?)
+                    .is_some(),
+                CBOR_KEY_KEM_PUBKEY_REF => kem_pubkey_ref.replace(cbor_array(value)?).is_some(),
+                CBOR_KEY_CAPABILITIES => capabilities.replace(cbor_u8(value)?).is_some(),
+                CBOR_KEY_KEM_CIPHERTEXT_REF => {
+                    kem_ciphertext_ref.replace(cbor_array(value)?).is_some()
+                }
+                CBOR_KEY_TYPED_CONTENT => typed_content.replace(cbor_bool(value)?).is_some(),
+                _ => false,
+            };
+            if slot_taken {
+                return Err(ComLockError::InvalidHeader);
+            }
+        }
+
+        Self {
+            classical_pubkey: classical_pubkey.ok_or(ComLockError::InvalidHeader)?,
+            kem_ciphertext,
+            kem_pubkey,
+            kem_pubkey_ref,
+            message_number: message_number.ok_or(ComLockError::InvalidHeader)?,
+            previous_chain_length: previous_chain_length.ok_or(ComLockError::InvalidHeader)?,
+            capabilities,
+            kem_ciphertext_ref,
+            typed_content: typed_content.unwrap_or(false),
+        }
+        .validated()
+    }
+
+    /// The binary flags byte: which optional fields are present.
+    fn flags(&self) -> u8 {
+        let mut flags = 0u8;
         if self.kem_ciphertext.is_some() {
-            size += KYBER_CIPHERTEXT_SIZE;
+            flags |= FLAG_KEM_CIPHERTEXT;
         }
         if self.kem_pubkey.is_some() {
-            size += KYBER_PUBKEY_SIZE;
+            flags |= FLAG_KEM_PUBKEY;
+        }
+        if self.kem_pubkey_ref.is_some() {
+            flags |= FLAG_KEM_PUBKEY_REF;
+        }
+        if self.capabilities.is_some() {
+            flags |= FLAG_CAPABILITIES;
+        }
+        if self.kem_ciphertext_ref.is_some() {
+            flags |= FLAG_KEM_CIPHERTEXT_REF;
+        }
+        if self.typed_content {
+            flags |= FLAG_TYPED_CONTENT;
+        }
+        flags
+    }
+
+    /// Reject a decoded header, in either encoding, that carries both a KEM
+    /// public key and a reference, or names a ciphertext target without a
+    /// ciphertext.
+    fn validated(self) -> Result<Self, ComLockError> {
+        if (self.kem_pubkey.is_some() && self.kem_pubkey_ref.is_some())
+            || (self.kem_ciphertext_ref.is_some() && self.kem_ciphertext.is_none())
+        {
+            return Err(ComLockError::InvalidHeader);
+        }
+        Ok(self)
+    }
+
+    /// Serialize the header with the given encoding.
+    ///
+    /// Binary headers are written as is. CBOR headers are prefixed with
+    /// [`HEADER_VERSION_CBOR`] so [`decode_versioned`](Self::decode_versioned)
+    /// can tell the encodings apart.
+    pub fn encode(&self, encoding: HeaderEncoding) -> Vec<u8> {
+        match encoding {
+            HeaderEncoding::Binary => self.serialize(),
+            HeaderEncoding::Cbor => {
+                let mut buffer = vec![HEADER_VERSION_CBOR];
+                buffer.extend_from_slice(&self.serialize_cbor());
+                buffer
+            }
+        }
+    }
+
+    /// Deserialize a header that starts with a version byte, with
+    /// Kyber-1024 KEM fields.
+    ///
+    /// # Errors
+    /// As [`decode_versioned_for`](Self::decode_versioned_for).
+    pub fn decode_versioned(bytes: &[u8]) -> Result<Self, ComLockError> {
+        Self::decode_versioned_for::<Kyber1024>(bytes)
+    }
+
+    /// Deserialize a header that starts with a version byte, with KEM
+    /// fields sized for the KEM `K`.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` for an empty buffer, an unknown
+    /// version or a malformed header.
+    pub fn decode_versioned_for<K: Kem>(bytes: &[u8]) -> Result<Self, ComLockError> {
+        match bytes.split_first() {
+            Some((&HEADER_VERSION_CBOR, rest)) => Self::deserialize_cbor_for::<K>(rest),
+            _ => Err(ComLockError::InvalidHeader),
+        }
+    }
+
+    /// Returns the total serialized size of this header.
+    pub fn serialized_size(&self) -> usize {
+        let mut size = MIN_HEADER_LEN; // Fixed overhead
+        if let Some(ref ct) = self.kem_ciphertext {
+            size += ct.len();
+        }
+        if let Some(ref pk) = self.kem_pubkey {
+            size += pk.len();
+        }
+        if self.kem_pubkey_ref.is_some() {
+            size += KEM_PUBKEY_REF_LEN;
+        }
+        if self.capabilities.is_some() {
+            size += 1;
+        }
+        if self.kem_ciphertext_ref.is_some() {
+            size += KEM_PUBKEY_REF_LEN;
         }
         size
     }
 
-    /// Check if this header includes KEM advancement (ciphertext or pubkey).
+    /// Check if this header includes KEM advancement (ciphertext, pubkey or
+    /// pubkey reference).
     pub fn has_kem_data(&self) -> bool {
-        self.kem_ciphertext.is_some() || self.kem_pubkey.is_some()
+        self.kem_ciphertext.is_some() || self.kem_pubkey.is_some() || self.kem_pubkey_ref.is_some()
+    }
+}
+
+/// Short reference to a KEM public key: the first
+/// [`KEM_PUBKEY_REF_LEN`] bytes of a domain-separated SHA-256 hash.
+pub fn kem_pubkey_reference(kem_pubkey: &[u8]) -> [u8; KEM_PUBKEY_REF_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"COMLOCK_KEM_PUBKEY_REF_V1");
+    hasher.update(kem_pubkey);
+    let hash = hasher.finalize();
+
+    let mut reference = [0u8; KEM_PUBKEY_REF_LEN];
+    reference.copy_from_slice(&hash[..KEM_PUBKEY_REF_LEN]);
+    reference
+}
+
+/// Recently exchanged KEM public keys, so a key can be re-advertised by
+/// reference instead of in full.
+///
+/// The sending side tracks which of our keys the remote has acknowledged
+/// seeing and swaps those for their [`kem_pubkey_reference`]; any other
+/// key still goes out in full. The receiving side remembers keys it got in
+/// full so it can resolve references. A reference that does not resolve is
+/// stale and should be answered by asking for the full key again.
+///
+/// `P` is the public key type of the ratchet's [`Kem`](crate::kem::Kem).
+#[derive(Debug, Clone)]
+pub struct KemKeyCache<P = [u8; KYBER_PUBKEY_SIZE]> {
+    /// Remote keys received in full, oldest first
+    received: VecDeque<([u8; KEM_PUBKEY_REF_LEN], P)>,
+    /// References to our keys the remote has acknowledged, oldest first
+    acknowledged: VecDeque<[u8; KEM_PUBKEY_REF_LEN]>,
+}
+
+impl<P> Default for KemKeyCache<P> {
+    fn default() -> Self {
+        Self {
+            received: VecDeque::new(),
+            acknowledged: VecDeque::new(),
+        }
+    }
+}
+
+impl KemKeyCache {
+    /// Create an empty cache for Kyber-1024 keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<P: AsRef<[u8]> + Copy> KemKeyCache<P> {
+    /// Record that the remote has seen our key with this reference.
+    pub fn acknowledge(&mut self, reference: [u8; KEM_PUBKEY_REF_LEN]) {
+        if self.acknowledged.contains(&reference) {
+            return;
+        }
+        if self.acknowledged.len() >= KEM_KEY_CACHE_SIZE {
+            self.acknowledged.pop_front();
+        }
+        self.acknowledged.push_back(reference);
+    }
+
+    /// Replace the header's KEM public key with its reference if the remote
+    /// has acknowledged it.
+    pub fn compress(&self, header: &mut MessageHeader) {
+        let Some(ref kem_pubkey) = header.kem_pubkey else {
+            return;
+        };
+        let reference = kem_pubkey_reference(kem_pubkey);
+        if self.acknowledged.contains(&reference) {
+            header.kem_pubkey = None;
+            header.kem_pubkey_ref = Some(reference);
+        }
+    }
+
+    /// Remember a remote key received in full.
+    pub fn remember(&mut self, kem_pubkey: &P) {
+        let reference = kem_pubkey_reference(kem_pubkey.as_ref());
+        if self.resolve(&reference).is_some() {
+            return;
+        }
+        if self.received.len() >= KEM_KEY_CACHE_SIZE {
+            self.received.pop_front();
+        }
+        self.received.push_back((reference, *kem_pubkey));
+    }
+
+    /// Look up a remote key by reference.
+    pub fn resolve(&self, reference: &[u8; KEM_PUBKEY_REF_LEN]) -> Option<P> {
+        self.received
+            .iter()
+            .find(|(known, _)| known == reference)
+            .map(|(_, kem_pubkey)| *kem_pubkey)
+    }
+}
+
+/// Read a CBOR byte string of exactly `len` bytes.
+fn cbor_bytes(value: Value, len: usize) -> Result<Vec<u8>, ComLockError> {
+    match value {
+        Value::Bytes(bytes) if bytes.len() == len => Ok(bytes),
+        _ => Err(ComLockError::InvalidHeader),
+    }
+}
+
+/// Read a CBOR byte string of exactly `N` bytes into an array.
+fn cbor_array<const N: usize>(value: Value) -> Result<[u8; N], ComLockError> {
+    match value {
+        Value::Bytes(bytes) => fixed_len(&bytes),
+        _ => Err(ComLockError::InvalidHeader),
+    }
+}
+
+/// Copy a field of exactly `N` bytes into an array.
+fn fixed_len<const N: usize>(field: &[u8]) -> Result<[u8; N], ComLockError> {
+    field.try_into().map_err(|_| ComLockError::InvalidHeader)
+}
+
+/// Read a CBOR integer that fits in a `u32`.
+fn cbor_u32(value: Value) -> Result<u32, ComLockError> {
+    match value {
+        Value::Integer(n) => u32::try_from(n).map_err(|_| ComLockError::InvalidHeader),
+        _ => Err(ComLockError::InvalidHeader),
+    }
+}
+
+/// Read a CBOR integer that fits in a `u8`.
+fn cbor_u8(value: Value) -> Result<u8, ComLockError> {
+    match value {
+        Value::Integer(n) => u8::try_from(n).map_err(|_| ComLockError::InvalidHeader),
+        _ => Err(ComLockError::InvalidHeader),
+    }
+}
+
+/// Read a CBOR boolean.
+fn cbor_bool(value: Value) -> Result<bool, ComLockError> {
+    match value {
+        Value::Bool(flag) => Ok(flag),
+        _ => Err(ComLockError::InvalidHeader),
+    }
+}
+
+/// Length of the authentication tag on a [`ResyncHeader`].
+pub const RESYNC_TAG_LEN: usize = 16;
+
+/// Size of a serialized [`ResyncHeader`] in bytes.
+pub const RESYNC_HEADER_SIZE: usize = 32 + 4 + RESYNC_TAG_LEN;
+
+/// Out-of-band resync marker carrying the sender's current counter.
+///
+/// A receiver that has lost track of a session applies it with
+/// [`RatchetState::apply_resync`](crate::RatchetState::apply_resync) to jump
+/// its receiving chain ahead to the sender's next message. It carries no
+/// secrets, and its tag, keyed by the session and bound to the sender's
+/// chain and direction, lets the receiver refuse one forged by anyone
+/// outside the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResyncHeader {
+    /// X25519 public key identifying the sender's current chain
+    pub classical_pubkey: [u8; 32],
+
+    /// Message number the sender will use next
+    pub message_number: u32,
+
+    /// Authentication tag over the chain and message number
+    pub tag: [u8; RESYNC_TAG_LEN],
+}
+
+impl ResyncHeader {
+    /// Serialize as
+    /// `classical_pubkey (32) || message_number (u32 LE) || tag (16)`.
+    pub fn serialize(&self) -> [u8; RESYNC_HEADER_SIZE] {
+        let mut buffer = [0u8; RESYNC_HEADER_SIZE];
+        buffer[..32].copy_from_slice(&self.classical_pubkey);
+        buffer[32..36].copy_from_slice(&self.message_number.to_le_bytes());
+        buffer[36..].copy_from_slice(&self.tag);
+        buffer
+    }
+
+    /// Deserialize a resync header.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` if `bytes` is not exactly
+    /// [`RESYNC_HEADER_SIZE`] long.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, ComLockError> {
+        if bytes.len() != RESYNC_HEADER_SIZE {
+            return Err(ComLockError::InvalidHeader);
+        }
+        let classical_pubkey: [u8; 32] = bytes[..32]
+            .try_into()
+            .map_err(|_| ComLockError::InvalidHeader)?;
+        let message_number = u32::from_le_bytes(
+            bytes[32..36]
+                .try_into()
+                .map_err(|_| ComLockError::InvalidHeader)?,
+        );
+        let tag = bytes[36..]
+            .try_into()
+            .map_err(|_| ComLockError::InvalidHeader)?;
+        Ok(Self {
+            classical_pubkey,
+            message_number,
+            tag,
+        })
     }
 }
 
@@ -278,6 +853,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_header_len_bounds_cover_both_encodings() {
+        let minimal = MessageHeader::new([0u8; 32], None, None, 0, 0);
+        let mut largest = MessageHeader::new(
+            [0xFFu8; 32],
+            Some(vec![0xFFu8; KYBER_CIPHERTEXT_SIZE]),
+            Some([0xFFu8; KYBER_PUBKEY_SIZE]),
+            u32::MAX,
+            u32::MAX,
+        );
+        largest.capabilities = Some(u8::MAX);
+        largest.kem_ciphertext_ref = Some([0xFFu8; KEM_PUBKEY_REF_LEN]);
+
+        for encoding in [HeaderEncoding::Binary, HeaderEncoding::Cbor] {
+            assert!(minimal.encode(encoding).len() >= MIN_HEADER_LEN);
+            assert!(largest.encode(encoding).len() <= MAX_HEADER_LEN);
+        }
+    }
+
     #[test]
     fn test_header_too_short() {
         let short_buffer = [0u8; 10];
@@ -325,4 +919,220 @@ mod tests {
         let header_pk = MessageHeader::new([0u8; 32], None, Some([0u8; KYBER_PUBKEY_SIZE]), 0, 0);
         assert!(header_pk.has_kem_data());
     }
+
+    #[test]
+    fn test_header_rejects_unknown_flags_and_trailing_bytes() {
+        let header = MessageHeader::new([1u8; 32], None, None, 0, 0);
+        let mut serialized = header.serialize();
+
+        let mut extra = serialized.clone();
+        extra.push(0);
+        assert!(MessageHeader::deserialize(&extra).is_err());
+
+        serialized[32] |= 0x80;
+        assert!(MessageHeader::deserialize(&serialized).is_err());
+    }
+
+    #[test]
+    fn test_random_header_bytes_never_panic() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x4ead);
+        let max_len = 41 + KYBER_CIPHERTEXT_SIZE + KYBER_PUBKEY_SIZE + 8;
+        for _ in 0..5_000 {
+            let len = rng.gen_range(0..max_len);
+            let mut bytes = vec![0u8; len];
+            rng.fill(&mut bytes[..]);
+            // Mostly valid flag values so the KEM branches are reached
+            if len > 32 && rng.gen_bool(0.8) {
+                bytes[32] &= FLAGS_MASK;
+            }
+
+            if let Ok(header) = MessageHeader::deserialize(&bytes) {
+                assert_eq!(header.serialize(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resync_header_roundtrip() {
+        let header = ResyncHeader {
+            classical_pubkey: [0x42; 32],
+            message_number: 1234,
+            tag: [0x17; RESYNC_TAG_LEN],
+        };
+        let bytes = header.serialize();
+        assert_eq!(ResyncHeader::deserialize(&bytes).unwrap(), header);
+        assert!(ResyncHeader::deserialize(&bytes[..RESYNC_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_header_cbor_minimal_roundtrip() {
+        let header = MessageHeader::new([42u8; 32], None, None, 5, 3);
+
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+        // Comparable to the 41-byte binary layout
+        assert!(cbor.len() <= header.serialized_size() + 4, "{}", cbor.len());
+
+        let versioned = header.encode(HeaderEncoding::Cbor);
+        assert_eq!(versioned[0], HEADER_VERSION_CBOR);
+        assert_eq!(MessageHeader::decode_versioned(&versioned).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_cbor_full_roundtrip() {
+        let kem_ct = vec![0xEFu8; KYBER_CIPHERTEXT_SIZE];
+        let kem_pk: [u8; KYBER_PUBKEY_SIZE] = [0x12u8; KYBER_PUBKEY_SIZE];
+        let header = MessageHeader::new([3u8; 32], Some(kem_ct), Some(kem_pk), 100_000, 99_999);
+
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+        assert!(
+            cbor.len() <= header.serialized_size() + 16,
+            "{}",
+            cbor.len()
+        );
+    }
+
+    #[test]
+    fn test_header_cbor_rejects_malformed_input() {
+        let header = MessageHeader::new([7u8; 32], None, None, 1, 0);
+        let cbor = header.serialize_cbor();
+
+        let mut trailing = cbor.clone();
+        trailing.push(0);
+        assert!(MessageHeader::deserialize_cbor(&trailing).is_err());
+        assert!(MessageHeader::deserialize_cbor(&cbor[..cbor.len() - 1]).is_err());
+        assert!(MessageHeader::decode_versioned(&[]).is_err());
+        assert!(MessageHeader::decode_versioned(&[0xFF]).is_err());
+
+        // A wrong-length KEM field is rejected; unknown keys are skipped
+        let with_entry = |key: u64, value: Value| {
+            let mut map = vec![
+                (Value::Integer(0.into()), Value::Bytes(vec![7u8; 32])),
+                (Value::Integer(1.into()), Value::Integer(1.into())),
+                (Value::Integer(2.into()), Value::Integer(0.into())),
+            ];
+            map.push((Value::Integer(key.into()), value));
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&Value::Map(map), &mut bytes).unwrap();
+            bytes
+        };
+        let bad_ct = with_entry(3, Value::Bytes(vec![0u8; 10]));
+        assert!(MessageHeader::deserialize_cbor(&bad_ct).is_err());
+        let duplicate = with_entry(1, Value::Integer(2.into()));
+        assert!(MessageHeader::deserialize_cbor(&duplicate).is_err());
+        let future = with_entry(9, Value::Text("future field".into()));
+        assert_eq!(MessageHeader::deserialize_cbor(&future).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_with_capabilities() {
+        let mut header = MessageHeader::new([5u8; 32], None, None, 0, 0);
+        header.capabilities = Some(CAPABILITY_KEM);
+        assert!(!header.has_kem_data());
+
+        let serialized = header.serialize();
+        assert_eq!(serialized.len(), header.serialized_size());
+        assert_eq!(serialized.len(), 42);
+        assert_eq!(MessageHeader::deserialize(&serialized).unwrap(), header);
+        assert!(MessageHeader::deserialize(&serialized[..41]).is_err());
+
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+
+        header.capabilities = Some(0);
+        let classical = header.serialize();
+        assert_eq!(
+            MessageHeader::deserialize(&classical).unwrap().capabilities,
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_header_typed_content_round_trips() {
+        let mut header = MessageHeader::new([5u8; 32], None, None, 3, 0);
+        header.typed_content = true;
+
+        let serialized = header.serialize();
+        assert_eq!(serialized.len(), MIN_HEADER_LEN);
+        assert_eq!(serialized[32], FLAG_TYPED_CONTENT);
+        assert_eq!(MessageHeader::deserialize(&serialized).unwrap(), header);
+
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+        header.typed_content = false;
+        assert!(
+            !MessageHeader::deserialize_cbor(&header.serialize_cbor())
+                .unwrap()
+                .typed_content
+        );
+    }
+
+    #[test]
+    fn test_header_with_kem_ciphertext_ref() {
+        let mut header = MessageHeader::new(
+            [4u8; 32],
+            Some(vec![0x56u8; KYBER_CIPHERTEXT_SIZE]),
+            None,
+            3,
+            0,
+        );
+        header.kem_ciphertext_ref = Some([0x78u8; KEM_PUBKEY_REF_LEN]);
+
+        let serialized = header.serialize();
+        assert_eq!(
+            serialized.len(),
+            41 + KYBER_CIPHERTEXT_SIZE + KEM_PUBKEY_REF_LEN
+        );
+        assert_eq!(serialized.len(), header.serialized_size());
+        assert_eq!(MessageHeader::deserialize(&serialized).unwrap(), header);
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+
+        // A target without a ciphertext is malformed
+        let mut orphan = serialized[..41].to_vec();
+        orphan[32] = FLAG_KEM_CIPHERTEXT_REF;
+        orphan.extend_from_slice(&[0x78u8; KEM_PUBKEY_REF_LEN]);
+        assert!(MessageHeader::deserialize(&orphan).is_err());
+        let mut orphan = MessageHeader::new([4u8; 32], None, None, 3, 0);
+        orphan.kem_ciphertext_ref = Some([0x78u8; KEM_PUBKEY_REF_LEN]);
+        assert!(MessageHeader::deserialize_cbor(&orphan.serialize_cbor()).is_err());
+    }
+
+    #[test]
+    fn test_header_with_kem_pubkey_ref() {
+        let kem_pk = [0x34u8; KYBER_PUBKEY_SIZE];
+        let mut header = MessageHeader::new([4u8; 32], None, Some(kem_pk), 2, 0);
+
+        // Unacknowledged keys go out in full
+        let mut cache = KemKeyCache::new();
+        cache.compress(&mut header);
+        assert_eq!(header.kem_pubkey.as_deref(), Some(&kem_pk[..]));
+
+        cache.acknowledge(kem_pubkey_reference(&kem_pk));
+        cache.compress(&mut header);
+        assert!(header.kem_pubkey.is_none());
+        assert!(header.has_kem_data());
+
+        let serialized = header.serialize();
+        assert_eq!(serialized.len(), 41 + KEM_PUBKEY_REF_LEN);
+        assert_eq!(MessageHeader::deserialize(&serialized).unwrap(), header);
+        let cbor = header.serialize_cbor();
+        assert_eq!(MessageHeader::deserialize_cbor(&cbor).unwrap(), header);
+
+        // A key and a reference together is malformed
+        let mut both = serialized[..41].to_vec();
+        both[32] = FLAG_KEM_PUBKEY | FLAG_KEM_PUBKEY_REF;
+        both.extend_from_slice(&kem_pk);
+        both.extend_from_slice(&[0u8; KEM_PUBKEY_REF_LEN]);
+        assert!(MessageHeader::deserialize(&both).is_err());
+
+        let mut receiver = KemKeyCache::new();
+        let reference = header.kem_pubkey_ref.unwrap();
+        assert!(receiver.resolve(&reference).is_none());
+        receiver.remember(&kem_pk);
+        assert_eq!(receiver.resolve(&reference), Some(kem_pk));
+    }
 }
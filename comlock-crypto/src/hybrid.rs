@@ -0,0 +1,70 @@
+//! # ComLock Crypto - Hybrid Combiner Module
+//!
+//! Combines a classical (X25519) and a post-quantum (ML-KEM) shared secret
+//! into one key, following the concatenate-then-KDF pattern of NIST SP
+//! 800-56C Rev. 2 (as permitted for hybrid schemes by SP 800-227):
+//!
+//! ```text
+//! K = HKDF-Extract(salt = transcript, IKM = classical_ss || pq_ss)
+//! ```
+//!
+//! HMAC is a dual PRF here: the output stays pseudorandom as long as
+//! *either* secret is, so the key holds up if one of the two primitives is
+//! broken. The transcript binds the key to the exchange that produced the
+//! secrets (public keys, ciphertexts, labels).
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+/// Combine a classical and a post-quantum shared secret under `transcript`.
+///
+/// The result is a uniformly random 32-byte key, suitable as an HKDF
+/// input key; expand it with a label to derive the keys actually used.
+pub fn hybrid_combine(classical_ss: &[u8; 32], pq_ss: &[u8; 32], transcript: &[u8]) -> [u8; 32] {
+    let mut ikm = [0u8; 64];
+    ikm[..32].copy_from_slice(classical_ss);
+    ikm[32..].copy_from_slice(pq_ss);
+
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(transcript), &ikm);
+    ikm.zeroize();
+    prk.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_either_input_changes_output() {
+        let base = hybrid_combine(&[1u8; 32], &[2u8; 32], b"transcript");
+        assert_eq!(base, hybrid_combine(&[1u8; 32], &[2u8; 32], b"transcript"));
+
+        let mut classical = [1u8; 32];
+        classical[31] ^= 1;
+        assert_ne!(base, hybrid_combine(&classical, &[2u8; 32], b"transcript"));
+
+        let mut pq = [2u8; 32];
+        pq[0] ^= 1;
+        assert_ne!(base, hybrid_combine(&[1u8; 32], &pq, b"transcript"));
+
+        assert_ne!(base, hybrid_combine(&[1u8; 32], &[2u8; 32], b"transcripT"));
+        // The secrets are not interchangeable
+        assert_ne!(base, hybrid_combine(&[2u8; 32], &[1u8; 32], b"transcript"));
+    }
+
+    #[test]
+    fn test_zero_pq_secret_keeps_classical_strength() {
+        // A broken KEM yielding all zeros still leaves a key that depends
+        // on (and only on) the classical secret
+        let zero_pq = [0u8; 32];
+        let a = hybrid_combine(&[7u8; 32], &zero_pq, b"transcript");
+        let b = hybrid_combine(&[8u8; 32], &zero_pq, b"transcript");
+
+        assert_ne!(a, b);
+        assert_ne!(a, [0u8; 32]);
+        assert_ne!(a, [7u8; 32]);
+        assert_ne!(a, hybrid_combine(&[0u8; 32], &zero_pq, b"transcript"));
+        assert_eq!(a, hybrid_combine(&[7u8; 32], &zero_pq, b"transcript"));
+    }
+}
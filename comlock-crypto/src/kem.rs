@@ -0,0 +1,133 @@
+//! # ComLock Crypto - KEM Module
+//!
+//! The key encapsulation mechanism used by the ratchet's post-quantum
+//! timeline, behind the [`Kem`] trait so the state machine is not tied to
+//! one algorithm. [`Kyber1024`] is the default; a smaller parameter set can
+//! be plugged in for constrained devices, or a future KEM without touching
+//! the ratchet.
+//!
+//! Headers carrying another KEM's fields are parsed with
+//! [`MessageHeader::deserialize_for`](crate::MessageHeader::deserialize_for)
+//! and its CBOR counterparts, which take the field sizes from the KEM.
+//! [`encrypt_message`](crate::encrypt_message) and
+//! [`decrypt_message`](crate::decrypt_message) work with the default only;
+//! other KEMs drive [`KemRatchetState`](crate::ratchet::KemRatchetState)
+//! through `step` and `receive_step` directly.
+
+use pqc_kyber::{decapsulate, encapsulate, keypair};
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::ComLockError;
+use crate::ratchet::{KYBER_CIPHERTEXT_SIZE, KYBER_PUBKEY_SIZE, KYBER_SECRETKEY_SIZE};
+
+/// A key encapsulation mechanism producing 32-byte shared secrets.
+pub trait Kem {
+    /// Encapsulation (public) key length in bytes
+    const PUBLIC_KEY_SIZE: usize;
+    /// Decapsulation (secret) key length in bytes
+    const SECRET_KEY_SIZE: usize;
+    /// Ciphertext length in bytes
+    const CIPHERTEXT_SIZE: usize;
+
+    /// Encapsulation key, as sent in message headers
+    type PublicKey: AsRef<[u8]> + for<'a> TryFrom<&'a [u8]> + Copy + PartialEq + core::fmt::Debug;
+    /// Decapsulation key
+    type SecretKey: AsRef<[u8]> + for<'a> TryFrom<&'a [u8]> + Clone + Zeroize;
+    /// Ciphertext, as sent in message headers
+    type Ciphertext: AsRef<[u8]> + for<'a> TryFrom<&'a [u8]>;
+
+    /// Generate a fresh keypair.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if the RNG fails.
+    fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), ComLockError>;
+
+    /// Encapsulate a fresh shared secret to `public_key`.
+    fn encapsulate<R: RngCore + CryptoRng>(
+        public_key: &Self::PublicKey,
+        rng: &mut R,
+    ) -> Result<(Self::Ciphertext, [u8; 32]), ComLockError>;
+
+    /// Recover the shared secret from `ciphertext`.
+    fn decapsulate(
+        ciphertext: &Self::Ciphertext,
+        secret_key: &Self::SecretKey,
+    ) -> Result<[u8; 32], ComLockError>;
+}
+
+/// Kyber-1024 (ML-KEM-1024), the default KEM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Kyber1024;
+
+impl Kem for Kyber1024 {
+    const PUBLIC_KEY_SIZE: usize = KYBER_PUBKEY_SIZE;
+    const SECRET_KEY_SIZE: usize = KYBER_SECRETKEY_SIZE;
+    const CIPHERTEXT_SIZE: usize = KYBER_CIPHERTEXT_SIZE;
+
+    type PublicKey = [u8; KYBER_PUBKEY_SIZE];
+    type SecretKey = [u8; KYBER_SECRETKEY_SIZE];
+    type Ciphertext = [u8; KYBER_CIPHERTEXT_SIZE];
+
+    fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), ComLockError> {
+        let keys = keypair(rng).map_err(|_| ComLockError::RngFailure)?;
+        Ok((keys.public, keys.secret))
+    }
+
+    fn encapsulate<R: RngCore + CryptoRng>(
+        public_key: &Self::PublicKey,
+        rng: &mut R,
+    ) -> Result<(Self::Ciphertext, [u8; 32]), ComLockError> {
+        encapsulate(public_key, rng).map_err(|_| ComLockError::EncapsulationFailed)
+    }
+
+    fn decapsulate(
+        ciphertext: &Self::Ciphertext,
+        secret_key: &Self::SecretKey,
+    ) -> Result<[u8; 32], ComLockError> {
+        decapsulate(ciphertext, secret_key).map_err(|_| ComLockError::DecapsulationFailed)
+    }
+}
+
+/// Check that a KEM field taken from a header is `expected` bytes long.
+///
+/// # Errors
+/// Returns `ComLockError::KemSizeMismatch` naming both lengths otherwise.
+pub(crate) fn check_kem_size(bytes: &[u8], expected: usize) -> Result<(), ComLockError> {
+    if bytes.len() != expected {
+        return Err(ComLockError::KemSizeMismatch {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+/// A keypair of the KEM `K`.
+pub(crate) struct KemKeypair<K: Kem> {
+    /// Encapsulation key
+    pub public: K::PublicKey,
+    /// Decapsulation key
+    pub secret: K::SecretKey,
+}
+
+impl<K: Kem> KemKeypair<K> {
+    /// Generate a fresh keypair.
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, ComLockError> {
+        let (public, secret) = K::generate(rng)?;
+        Ok(Self { public, secret })
+    }
+}
+
+impl<K: Kem> Clone for KemKeypair<K> {
+    fn clone(&self) -> Self {
+        Self {
+            public: self.public,
+            secret: self.secret.clone(),
+        }
+    }
+}
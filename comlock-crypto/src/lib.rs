@@ -13,15 +13,30 @@
 //!
 //! This crate forbids all unsafe code to maximize auditability and security.
 //!
+//! ## `no_std`
+//!
+//! The default `std` feature can be turned off to embed the ratchet in a
+//! device without an operating system; only `alloc` is needed then. The
+//! core ([`RatchetState`], [`encrypt_message_with_rng`],
+//! [`decrypt_message_with_rng`], headers, padding, content types and the
+//! PQXDH responder) stays available and takes its randomness from the
+//! caller. Functions using the thread RNG, session backups, group
+//! sessions, fragmentation and the self-test need `std`.
+//!
 //! ## Example
 //!
 //! ```rust,ignore
 //! use comlock_crypto::{RatchetState, encrypt_message, decrypt_message};
+//! use comlock_crypto::pqxdh::{pqxdh_initiator, pqxdh_responder};
+//!
+//! // Run the PQXDH handshake (Bob's keys come from his published bundle)
+//! let init = pqxdh_initiator(&alice_ik, &bob_ik_pub, &bob_spk_pub, &bob_kem_ek).unwrap();
+//! let bob_root =
+//!     pqxdh_responder(&bob_ik, &bob_spk, &bob_kem_dk, &alice_ik_pub, &init.message).unwrap();
 //!
-//! // Initialize from PQXDH handshake output
-//! let shared_secret = [0u8; 32]; // From handshake
-//! let mut alice_state = RatchetState::new(shared_secret, true);
-//! let mut bob_state = RatchetState::new(shared_secret, false);
+//! // Initialize the ratchets from the handshake output
+//! let mut alice_state = RatchetState::new(init.root_key, true).unwrap();
+//! let mut bob_state = RatchetState::new(bob_root, false).unwrap();
 //!
 //! // Alice sends a message
 //! let ciphertext = encrypt_message(b"Hello, Bob!", &mut alice_state).unwrap();
@@ -31,79 +46,263 @@
 //! assert_eq!(plaintext, b"Hello, Bob!");
 //! ```
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 #![deny(clippy::unwrap_used)]
 
+extern crate alloc;
+
+pub mod content;
+#[cfg(feature = "std")]
 pub mod fragment;
+#[cfg(feature = "std")]
+pub mod group;
 pub mod header;
+pub mod hybrid;
+pub mod kem;
+pub mod padding;
+pub mod pqxdh;
 pub mod ratchet;
-
+#[cfg(feature = "std")]
+pub mod self_test;
+#[cfg(test)]
+mod sync_model;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod test_vectors;
+pub mod util;
+
+pub use content::{
+    ContentType, DecryptedMessage, UnknownContentType, decrypt_typed_message_with_rng,
+    encrypt_typed_message_with_rng,
+};
+#[cfg(feature = "std")]
+pub use content::{decrypt_typed_message, encrypt_typed_message};
+#[cfg(feature = "std")]
 pub use fragment::{
-    FragmentBuffer, HeaderFragment, fragment_header, needs_fragmentation, reassemble_header,
+    FragmentBuffer, HeaderFragment, MAX_PENDING_PAYLOAD_BYTES, MAX_PENDING_PAYLOADS,
+    PAYLOAD_FRAGMENT_TIMEOUT, PayloadFragment, PayloadFragmentBuffer, fragment_header,
+    fragment_payload, needs_fragmentation, reassemble_header, reassemble_payload,
+};
+#[cfg(feature = "std")]
+pub use group::{GroupMessage, GroupSession, decrypt_group};
+pub use header::{
+    CAPABILITY_KEM, CAPABILITY_PROTOCOL_V2, HeaderEncoding, KEM_PUBKEY_REF_LEN, KemKeyCache,
+    MAX_HEADER_LEN, MIN_HEADER_LEN, MessageHeader, ResyncHeader, kem_pubkey_reference,
+};
+pub use hybrid::hybrid_combine;
+pub use kem::{Kem, Kyber1024};
+pub use padding::PaddingScheme;
+#[cfg(feature = "std")]
+pub use pqxdh::pqxdh_initiator;
+pub use pqxdh::{PqxdhInitMessage, PqxdhInitiatorOutput, pqxdh_responder};
+pub use ratchet::{
+    KemRatchetBuilder, KemRatchetState, ProtocolMode, RatchetBuilder, RatchetState, RatchetStatus,
 };
-pub use header::MessageHeader;
-pub use ratchet::RatchetState;
+#[cfg(feature = "std")]
+pub use self_test::self_test;
+pub use util::ct_eq;
+
+use alloc::vec::Vec;
+use core::fmt;
 
 use aes_gcm_siv::{
     Aes256GcmSiv, Nonce,
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
 };
-use rand::RngCore;
-use thiserror::Error;
+#[cfg(feature = "std")]
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "std")]
+use sha2::Sha256;
 
 /// Errors that can occur during ComLock cryptographic operations.
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum ComLockError {
     /// The message header is malformed or invalid.
-    #[error("Invalid message header")]
     InvalidHeader,
 
     /// The ciphertext is malformed or invalid.
-    #[error("Invalid ciphertext")]
     InvalidCiphertext,
 
     /// The public key is malformed or invalid.
-    #[error("Invalid public key")]
     InvalidPublicKey,
 
+    /// The shared secret is all zero or has too little entropy to be a
+    /// handshake output.
+    WeakSecret,
+
     /// KEM encapsulation failed.
-    #[error("KEM encapsulation failed")]
     EncapsulationFailed,
 
     /// KEM decapsulation failed (wrong key or tampered ciphertext).
-    #[error("KEM decapsulation failed")]
     DecapsulationFailed,
 
     /// Missing KEM keypair for decapsulation.
-    #[error("Missing KEM keypair")]
     MissingKemKeypair,
 
+    /// A KEM ciphertext arrived on a session negotiated as classical-only.
+    KemNotNegotiated,
+
+    /// A classical-only remote on a hybrid session that does not allow one.
+    ProtocolDowngrade,
+
     /// AEAD encryption failed.
-    #[error("Encryption failed")]
     EncryptionFailed,
 
     /// AEAD decryption failed (authentication error).
-    #[error("Decryption failed: authentication error")]
     DecryptionFailed,
 
+    /// The message number was already received or is too old to accept.
+    ReplayedMessage,
+
+    /// The message number is too far ahead of the receiving chain.
+    TooManySkippedMessages,
+
+    /// The decrypted plaintext's length prefix is missing or invalid.
+    InvalidPadding,
+
     /// Message is too short to be valid.
-    #[error("Message too short")]
     MessageTooShort,
+
+    /// The plaintext exceeds the maximum allowed size.
+    MessageTooLarge {
+        /// Size of the rejected plaintext.
+        size: usize,
+        /// Maximum allowed size.
+        max: usize,
+    },
+
+    /// A KEM ciphertext or public key in a header has the wrong length for
+    /// the session's KEM.
+    KemSizeMismatch {
+        /// Length the KEM expects.
+        expected: usize,
+        /// Length received.
+        actual: usize,
+    },
+
+    /// The number of ratchets supplied does not match the group membership.
+    MemberCountMismatch {
+        /// Number of group members.
+        expected: usize,
+        /// Number of ratchets supplied.
+        actual: usize,
+    },
+
+    /// The random number generator failed to produce key material.
+    RngFailure,
+
+    /// Session options that cannot be used together.
+    InvalidConfiguration(&'static str),
+
+    /// A startup self-test found a primitive producing wrong output.
+    SelfTestFailed(&'static str),
+
+    /// The remote runs a different protocol version (key derivation and
+    /// plaintext framing).
+    ProtocolVersionMismatch,
+
+    /// The fragment size leaves no room for data after the fragment
+    /// overhead.
+    FragmentSizeTooSmall {
+        /// Requested fragment size.
+        size: usize,
+        /// Smallest size that carries any data.
+        min: usize,
+    },
+
+    /// The data would need more fragments than the fragment format counts.
+    TooManyFragments {
+        /// Number of fragments needed.
+        needed: usize,
+        /// Most fragments the format allows.
+        max: usize,
+    },
+
+    /// A typed message was opened as untyped, or an untyped one as typed.
+    ContentTypeMismatch,
+}
+
+impl fmt::Display for ComLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComLockError::InvalidHeader => f.write_str("Invalid message header"),
+            ComLockError::InvalidCiphertext => f.write_str("Invalid ciphertext"),
+            ComLockError::InvalidPublicKey => f.write_str("Invalid public key"),
+            ComLockError::WeakSecret => f.write_str("Shared secret is too weak"),
+            ComLockError::EncapsulationFailed => f.write_str("KEM encapsulation failed"),
+            ComLockError::DecapsulationFailed => f.write_str("KEM decapsulation failed"),
+            ComLockError::MissingKemKeypair => f.write_str("Missing KEM keypair"),
+            ComLockError::KemNotNegotiated => {
+                f.write_str("KEM ciphertext on a classical-only session")
+            }
+            ComLockError::ProtocolDowngrade => {
+                f.write_str("Remote is classical-only and the session requires a KEM")
+            }
+            ComLockError::EncryptionFailed => f.write_str("Encryption failed"),
+            ComLockError::DecryptionFailed => {
+                f.write_str("Decryption failed: authentication error")
+            }
+            ComLockError::ReplayedMessage => f.write_str("Replayed message"),
+            ComLockError::TooManySkippedMessages => f.write_str("Too many skipped messages"),
+            ComLockError::InvalidPadding => f.write_str("Invalid plaintext padding"),
+            ComLockError::MessageTooShort => f.write_str("Message too short"),
+            ComLockError::MessageTooLarge { size, max } => {
+                write!(f, "Message too large: {size} bytes (max {max})")
+            }
+            ComLockError::KemSizeMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "KEM field size mismatch: expected {expected} bytes, got {actual}"
+                )
+            }
+            ComLockError::MemberCountMismatch { expected, actual } => {
+                write!(f, "Expected {expected} member sessions, got {actual}")
+            }
+            ComLockError::RngFailure => f.write_str("Random number generator failure"),
+            ComLockError::InvalidConfiguration(detail) => {
+                write!(f, "Invalid session configuration: {detail}")
+            }
+            ComLockError::SelfTestFailed(detail) => write!(f, "Self-test failed: {detail}"),
+            ComLockError::ProtocolVersionMismatch => {
+                f.write_str("Remote uses a different protocol version")
+            }
+            ComLockError::FragmentSizeTooSmall { size, min } => {
+                write!(f, "Fragment size too small: {size} bytes (min {min})")
+            }
+            ComLockError::TooManyFragments { needed, max } => {
+                write!(f, "Too many fragments: {needed} needed (max {max})")
+            }
+            ComLockError::ContentTypeMismatch => {
+                f.write_str("Typed and untyped messages cannot be read as each other")
+            }
+        }
+    }
 }
 
+impl core::error::Error for ComLockError {}
+
 /// Result type for ComLock operations.
-pub type Result<T> = std::result::Result<T, ComLockError>;
+pub type Result<T> = core::result::Result<T, ComLockError>;
 
 /// Size of the AES-GCM-SIV nonce in bytes.
 const NONCE_SIZE: usize = 12;
 
+/// Bit of the header length field marking a header that starts with a
+/// version byte (see [`MessageHeader::decode_versioned`]).
+const VERSIONED_HEADER_FLAG: u16 = 0x8000;
+
+/// Default maximum plaintext size accepted by the encrypt functions (1 MiB).
+pub const MAX_PLAINTEXT_SIZE: usize = 1024 * 1024;
+
 /// Encrypt a message using the current ratchet state.
 ///
 /// This function:
 /// 1. Advances the ratchet to derive a fresh message key
-/// 2. Encrypts the plaintext using AES-256-GCM-SIV
+/// 2. Encrypts the plaintext using AES-256-GCM-SIV, with the serialized
+///    header bound to the ciphertext as associated data
 /// 3. Serializes the header and ciphertext into a single blob
 ///
 /// # Arguments
@@ -117,25 +316,258 @@ const NONCE_SIZE: usize = 12;
 /// ```text
 /// [header_len: u16 LE][header bytes][nonce: 12 bytes][ciphertext + tag]
 /// ```
+///
+/// The top bit of `header_len` is clear for the binary header; see
+/// [`encrypt_message_with_encoding`] for versioned headers.
+///
+/// The encrypted plaintext is framed as `[len: u32 LE][msg]` with no extra
+/// padding; see [`encrypt_message_padded`] to hide the message length.
+#[cfg(feature = "std")]
 pub fn encrypt_message(msg: &[u8], state: &mut RatchetState) -> Result<Vec<u8>> {
+    encrypt_message_with_rng(msg, state, &mut rand::thread_rng())
+}
+
+/// Encrypt a message, serializing its header with `encoding`.
+///
+/// [`HeaderEncoding::Binary`] gives the same output as [`encrypt_message`].
+/// Other encodings set the top bit of `header_len` and prefix the header
+/// with a version byte; [`decrypt_message`] accepts either form.
+#[cfg(feature = "std")]
+pub fn encrypt_message_with_encoding(
+    msg: &[u8],
+    state: &mut RatchetState,
+    encoding: HeaderEncoding,
+) -> Result<Vec<u8>> {
+    encrypt_with_options(
+        msg,
+        state,
+        MAX_PLAINTEXT_SIZE,
+        PaddingScheme::None,
+        encoding,
+        None,
+        &mut rand::thread_rng(),
+    )
+}
+
+/// Encrypt a message, drawing the nonce and any new ratchet keys from `rng`.
+///
+/// Same as [`encrypt_message`]; a seeded RNG makes the output reproducible
+/// for tests and test vectors.
+pub fn encrypt_message_with_rng<R: RngCore + CryptoRng>(
+    msg: &[u8],
+    state: &mut RatchetState,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    encrypt_with_options(
+        msg,
+        state,
+        MAX_PLAINTEXT_SIZE,
+        PaddingScheme::None,
+        HeaderEncoding::Binary,
+        None,
+        rng,
+    )
+}
+
+/// Encrypt a message, padding the plaintext according to `scheme`.
+///
+/// Ciphertexts of messages that fall into the same padding bucket have the
+/// same length. [`decrypt_message`] strips the padding automatically.
+///
+/// # Arguments
+/// * `msg` - The plaintext message to encrypt
+/// * `state` - Mutable reference to the sender's ratchet state
+/// * `scheme` - How to pad the plaintext before encryption
+#[cfg(feature = "std")]
+pub fn encrypt_message_padded(
+    msg: &[u8],
+    state: &mut RatchetState,
+    scheme: PaddingScheme,
+) -> Result<Vec<u8>> {
+    encrypt_with_options(
+        msg,
+        state,
+        MAX_PLAINTEXT_SIZE,
+        scheme,
+        HeaderEncoding::Binary,
+        None,
+        &mut rand::thread_rng(),
+    )
+}
+
+/// Encrypt a message, rejecting plaintexts larger than `max_size`.
+///
+/// Same as [`encrypt_message`] with a caller-chosen size limit. The ratchet
+/// is not advanced when the message is rejected.
+///
+/// # Errors
+/// - `MessageTooLarge` if `msg` is longer than `max_size`
+#[cfg(feature = "std")]
+pub fn encrypt_message_with_limit(
+    msg: &[u8],
+    state: &mut RatchetState,
+    max_size: usize,
+) -> Result<Vec<u8>> {
+    encrypt_with_options(
+        msg,
+        state,
+        max_size,
+        PaddingScheme::None,
+        HeaderEncoding::Binary,
+        None,
+        &mut rand::thread_rng(),
+    )
+}
+
+/// Encrypt a message with a nonce derived from the message key.
+///
+/// Identical to [`encrypt_message_with_rng`] except that the nonce is
+/// derived via HKDF from the message key and the message number, so it is
+/// unique per key. `rng` is only drawn from when the ratchet step needs new
+/// keys, so the ciphertext depends only on the ratchet state and the state
+/// of `rng`, which gives reproducible test vectors. AES-GCM-SIV is
+/// nonce-misuse resistant, so this is safe. The output can be decrypted
+/// with [`decrypt_message`] as usual.
+///
+/// # Arguments
+/// * `msg` - The plaintext message to encrypt
+/// * `state` - Mutable reference to the sender's ratchet state
+/// * `rng` - Source of any new ratchet keys
+#[cfg(feature = "std")]
+pub fn encrypt_message_deterministic<R: RngCore + CryptoRng>(
+    msg: &[u8],
+    state: &mut RatchetState,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    check_plaintext_size(msg, MAX_PLAINTEXT_SIZE)?;
+
     // Advance the ratchet and get the message key
-    let ratchet_output = state.step(None)?;
+    let ratchet_output = state.step_with_rng(None, rng)?;
+
+    let nonce_bytes = derive_nonce(
+        &ratchet_output.message_key,
+        ratchet_output.header.message_number,
+    )?;
+
+    seal(
+        msg,
+        PaddingScheme::None,
+        &ratchet_output,
+        nonce_bytes,
+        HeaderEncoding::Binary,
+        state,
+    )
+}
 
-    // Serialize the header
-    let header_bytes = ratchet_output.header.serialize();
-    let header_len = header_bytes.len() as u16;
+/// Re-encrypt a received plaintext for another session.
+///
+/// The ciphertext is built entirely from `to_state`: the header (ratchet
+/// key, KEM data, counters) and message key belong to the new session, so
+/// nothing from the session the message arrived on is carried over.
+///
+/// With `strip_attribution` set the plaintext is also padded to the next
+/// power of two, so the forwarded ciphertext cannot be matched to the
+/// original by its exact length.
+///
+/// # Arguments
+/// * `plaintext` - The decrypted message to forward
+/// * `to_state` - Mutable reference to the ratchet state of the new recipient
+/// * `strip_attribution` - Hide the original message length as well
+#[cfg(feature = "std")]
+pub fn forward_message(
+    plaintext: &[u8],
+    to_state: &mut RatchetState,
+    strip_attribution: bool,
+) -> Result<Vec<u8>> {
+    let scheme = if strip_attribution {
+        PaddingScheme::PowerOfTwo
+    } else {
+        PaddingScheme::None
+    };
+    encrypt_message_padded(plaintext, to_state, scheme)
+}
+
+/// Encrypt a message with a random nonce, drawing the nonce and any new
+/// ratchet keys from `rng`. The ratchet is not advanced when `msg` is
+/// larger than `max_size`.
+fn encrypt_with_options<R: RngCore + CryptoRng>(
+    msg: &[u8],
+    state: &mut RatchetState,
+    max_size: usize,
+    scheme: PaddingScheme,
+    encoding: HeaderEncoding,
+    remote_kem_ct: Option<&[u8]>,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    check_plaintext_size(msg, max_size)?;
+
+    // Advance the ratchet and get the message key
+    let ratchet_output = state.step_with_rng(remote_kem_ct, rng)?;
 
-    // Generate a random nonce
     let mut nonce_bytes = [0u8; NONCE_SIZE];
-    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    rng.try_fill_bytes(&mut nonce_bytes)
+        .map_err(|_| ComLockError::RngFailure)?;
+
+    seal(msg, scheme, &ratchet_output, nonce_bytes, encoding, state)
+}
+
+/// Reject plaintexts larger than `max_size`.
+fn check_plaintext_size(msg: &[u8], max_size: usize) -> Result<()> {
+    if msg.len() > max_size {
+        return Err(ComLockError::MessageTooLarge {
+            size: msg.len(),
+            max: max_size,
+        });
+    }
+    Ok(())
+}
+
+/// Derive a per-message nonce from the message key and message number.
+#[cfg(feature = "std")]
+fn derive_nonce(message_key: &[u8; 32], message_number: u32) -> Result<[u8; NONCE_SIZE]> {
+    let hk = Hkdf::<Sha256>::new(None, message_key);
+    let mut info = Vec::with_capacity(20);
+    info.extend_from_slice(b"comlock_nonce");
+    info.extend_from_slice(&message_number.to_le_bytes());
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    hk.expand(&info, &mut nonce)
+        .map_err(|_| ComLockError::EncryptionFailed)?;
+    Ok(nonce)
+}
+
+/// Pad and encrypt with the ratchet output's key and build the wire-format
+/// blob, binding the ciphertext length into `state`'s transcript.
+fn seal(
+    msg: &[u8],
+    scheme: PaddingScheme,
+    ratchet_output: &ratchet::RatchetOutput,
+    nonce_bytes: [u8; NONCE_SIZE],
+    encoding: HeaderEncoding,
+    state: &mut RatchetState,
+) -> Result<Vec<u8>> {
+    // Serialize the header
+    let header_bytes = ratchet_output.header.encode(encoding);
+    let mut header_len = header_bytes.len() as u16;
+    if encoding != HeaderEncoding::Binary {
+        header_len |= VERSIONED_HEADER_FLAG;
+    }
     let nonce = Nonce::from_slice(&nonce_bytes);
+    let padded = padding::pad(msg, scheme)?;
 
-    // Encrypt the message using AES-256-GCM-SIV
+    // Encrypt the message using AES-256-GCM-SIV, authenticating the header as AAD
     let cipher =
         Aes256GcmSiv::new_from_slice(&ratchet_output.message_key).expect("Invalid key length");
     let ciphertext = cipher
-        .encrypt(nonce, msg)
+        .encrypt(
+            nonce,
+            Payload {
+                msg: &padded,
+                aad: &header_bytes,
+            },
+        )
         .map_err(|_| ComLockError::EncryptionFailed)?;
+    state.bind_sent_ciphertext_len(ciphertext.len());
 
     // Build the output: [header_len][header][nonce][ciphertext]
     let mut output = Vec::with_capacity(2 + header_bytes.len() + NONCE_SIZE + ciphertext.len());
@@ -152,7 +584,8 @@ pub fn encrypt_message(msg: &[u8], state: &mut RatchetState) -> Result<Vec<u8>>
 /// This function:
 /// 1. Parses the header from the ciphertext blob
 /// 2. Advances the receiving ratchet to derive the message key
-/// 3. Decrypts and authenticates the ciphertext
+/// 3. Decrypts and authenticates the ciphertext together with the header
+/// 4. Strips the plaintext's length prefix and padding
 ///
 /// # Arguments
 /// * `ciphertext` - The complete encrypted message blob
@@ -162,17 +595,57 @@ pub fn encrypt_message(msg: &[u8], state: &mut RatchetState) -> Result<Vec<u8>>
 /// * `Vec<u8>` containing the decrypted plaintext
 ///
 /// # Errors
-/// - `InvalidHeader` if the header cannot be parsed
-/// - `DecryptionFailed` if authentication fails (tampered or wrong key)
+/// - `MessageTooShort` if the blob cannot hold the header it claims, a
+///   nonce and a tag
+/// - `InvalidHeader` if the header length is out of range or the header
+///   cannot be parsed
+/// - `DecryptionFailed` if authentication fails (tampered header or
+///   ciphertext, or wrong key)
+/// - `InvalidPadding` if the plaintext's length prefix is invalid
+/// - `ContentTypeMismatch` for a typed message (see [`content`])
+#[cfg(feature = "std")]
 pub fn decrypt_message(ciphertext: &[u8], state: &mut RatchetState) -> Result<Vec<u8>> {
-    // Minimum size: 2 (len) + 41 (min header) + 12 (nonce) + 16 (tag)
-    const MIN_SIZE: usize = 2 + 41 + NONCE_SIZE + 16;
+    decrypt_message_with_rng(ciphertext, state, &mut rand::thread_rng())
+}
+
+/// Parse the header of an encrypted message without decrypting it.
+///
+/// Nothing is authenticated until the message decrypts, so the header is
+/// only good for scheduling, such as putting messages on one chain in
+/// order by their message number.
+///
+/// # Errors
+/// As [`decrypt_message`] for a blob too short or a header that cannot be
+/// parsed.
+pub fn peek_header(ciphertext: &[u8]) -> Result<MessageHeader> {
+    parse_message(ciphertext).map(|parsed| parsed.header)
+}
+
+/// The parts of an encrypted message blob.
+struct ParsedMessage<'a> {
+    header: MessageHeader,
+    /// The encoded header, authenticated as AAD
+    header_bytes: &'a [u8],
+    nonce: [u8; NONCE_SIZE],
+    /// AEAD ciphertext and tag
+    encrypted_data: &'a [u8],
+}
+
+/// Split a message blob into its header, nonce and AEAD ciphertext.
+fn parse_message(ciphertext: &[u8]) -> Result<ParsedMessage<'_>> {
+    // Minimum size: 2 (len) + smallest header + 12 (nonce) + 16 (tag)
+    const MIN_SIZE: usize = 2 + MIN_HEADER_LEN + NONCE_SIZE + 16;
     if ciphertext.len() < MIN_SIZE {
         return Err(ComLockError::MessageTooShort);
     }
 
-    // Parse header length
-    let header_len = u16::from_le_bytes([ciphertext[0], ciphertext[1]]) as usize;
+    // Parse header length and encoding
+    let header_len_field = u16::from_le_bytes([ciphertext[0], ciphertext[1]]);
+    let versioned = header_len_field & VERSIONED_HEADER_FLAG != 0;
+    let header_len = (header_len_field & !VERSIONED_HEADER_FLAG) as usize;
+    if !(MIN_HEADER_LEN..=MAX_HEADER_LEN).contains(&header_len) {
+        return Err(ComLockError::InvalidHeader);
+    }
 
     // Validate header length
     if ciphertext.len() < 2 + header_len + NONCE_SIZE + 16 {
@@ -181,28 +654,150 @@ pub fn decrypt_message(ciphertext: &[u8], state: &mut RatchetState) -> Result<Ve
 
     // Parse header
     let header_bytes = &ciphertext[2..2 + header_len];
-    let header = MessageHeader::deserialize(header_bytes)?;
+    let header = if versioned {
+        MessageHeader::decode_versioned(header_bytes)?
+    } else {
+        MessageHeader::deserialize(header_bytes)?
+    };
 
     // Extract nonce and ciphertext
     let nonce_start = 2 + header_len;
-    let nonce_bytes: [u8; NONCE_SIZE] = ciphertext[nonce_start..nonce_start + NONCE_SIZE]
+    let nonce: [u8; NONCE_SIZE] = ciphertext[nonce_start..nonce_start + NONCE_SIZE]
         .try_into()
         .map_err(|_| ComLockError::InvalidCiphertext)?;
-    let nonce = Nonce::from_slice(&nonce_bytes);
 
-    let encrypted_data = &ciphertext[nonce_start + NONCE_SIZE..];
+    Ok(ParsedMessage {
+        header,
+        header_bytes,
+        nonce,
+        encrypted_data: &ciphertext[nonce_start + NONCE_SIZE..],
+    })
+}
 
-    // Advance the receiving ratchet
-    let decrypt_ctx = state.receive_step(&header)?;
+/// Decrypt a message, drawing any new KEM keypair from `rng`.
+///
+/// Same as [`decrypt_message`]; pairs with [`encrypt_message_with_rng`].
+pub fn decrypt_message_with_rng<R: RngCore + CryptoRng>(
+    ciphertext: &[u8],
+    state: &mut RatchetState,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    open_message(ciphertext, state, false, rng)
+}
 
-    // Decrypt using AES-256-GCM-SIV
-    let cipher =
-        Aes256GcmSiv::new_from_slice(&decrypt_ctx.message_key).expect("Invalid key length");
-    let plaintext = cipher
-        .decrypt(nonce, encrypted_data)
-        .map_err(|_| ComLockError::DecryptionFailed)?;
+/// Decrypt a message whose header must be marked `typed_content` as given.
+fn open_message<R: RngCore + CryptoRng>(
+    ciphertext: &[u8],
+    state: &mut RatchetState,
+    typed_content: bool,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    let ParsedMessage {
+        header,
+        header_bytes,
+        nonce,
+        encrypted_data,
+    } = parse_message(ciphertext)?;
+    if header.typed_content != typed_content {
+        return Err(ComLockError::ContentTypeMismatch);
+    }
+    let nonce = Nonce::from_slice(&nonce);
+
+    // Advance a copy of the receiving ratchet; it is only committed once the
+    // message authenticates, so forged headers cannot corrupt the session.
+    // The remote may have used keys of ours we have since replaced, so a
+    // few combinations are tried, most likely first.
+    let mut first_error = None;
+    for (i, attempt) in state.receive_attempts(&header).into_iter().enumerate() {
+        let mut next_state = state.clone();
+        let opened = match next_state.receive_step_attempt(&header, attempt, rng) {
+            Ok(decrypt_ctx) => open(
+                &decrypt_ctx.message_key,
+                nonce,
+                encrypted_data,
+                header_bytes,
+            ),
+            // Replays and out-of-range counters fail the same way every time
+            Err(e) if i == 0 && !is_kem_error(&e) => return Err(e),
+            Err(e) => Err(e),
+        };
+
+        match opened {
+            Ok(padded) => {
+                let plaintext = padding::unpad(padded)?;
+                next_state.bind_received_ciphertext_len(encrypted_data.len());
+                *state = next_state;
+                return Ok(plaintext);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    // A KEM ciphertext we could not use: carry on with the classical chain,
+    // and ask the remote for a fresh encapsulation once a later message
+    // shows this one was genuine
+    if header.kem_ciphertext.is_some() {
+        state.note_kem_failure(&header);
+    }
+    Err(first_error.unwrap_or(ComLockError::DecryptionFailed))
+}
+
+/// Errors from a KEM ciphertext that another receive attempt may avoid.
+fn is_kem_error(error: &ComLockError) -> bool {
+    matches!(
+        error,
+        ComLockError::MissingKemKeypair
+            | ComLockError::DecapsulationFailed
+            | ComLockError::InvalidCiphertext
+            | ComLockError::KemSizeMismatch { .. }
+    )
+}
+
+/// Decrypt and authenticate a payload with AES-256-GCM-SIV; the header
+/// bytes must match exactly.
+fn open(
+    message_key: &[u8; 32],
+    nonce: &Nonce,
+    encrypted_data: &[u8],
+    header_bytes: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new_from_slice(message_key).expect("Invalid key length");
+    cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: encrypted_data,
+                aad: header_bytes,
+            },
+        )
+        .map_err(|_| ComLockError::DecryptionFailed)
+}
 
-    Ok(plaintext)
+/// Decrypt a batch of messages, reporting a result for each one.
+///
+/// Each ciphertext is passed to [`decrypt_message`] in turn. A message that
+/// fails leaves the ratchet untouched, so later messages still decrypt;
+/// the failed message's key is cached as skipped if a later message from
+/// the same chain succeeds.
+///
+/// Ordering still matters: the receiving chain advances as messages are
+/// processed, so ciphertexts should be supplied in the order they were
+/// received (ideally the order they were sent).
+///
+/// # Arguments
+/// * `ciphertexts` - The encrypted message blobs
+/// * `state` - Mutable reference to the receiver's ratchet state
+///
+/// # Returns
+/// * One result per ciphertext, in the same order
+#[cfg(feature = "std")]
+pub fn decrypt_batch(ciphertexts: &[Vec<u8>], state: &mut RatchetState) -> Vec<Result<Vec<u8>>> {
+    ciphertexts
+        .iter()
+        .map(|ciphertext| decrypt_message(ciphertext, state))
+        .collect()
 }
 
 /// Encrypt a message with explicit KEM ciphertext from the remote party.
@@ -214,38 +809,21 @@ pub fn decrypt_message(ciphertext: &[u8], state: &mut RatchetState) -> Result<Ve
 /// * `msg` - The plaintext message to encrypt
 /// * `state` - Mutable reference to the sender's ratchet state
 /// * `remote_kem_ct` - KEM ciphertext received from the remote party
+#[cfg(feature = "std")]
 pub fn encrypt_message_with_kem(
     msg: &[u8],
     state: &mut RatchetState,
     remote_kem_ct: Option<&[u8]>,
 ) -> Result<Vec<u8>> {
-    // Advance the ratchet with the remote KEM ciphertext
-    let ratchet_output = state.step(remote_kem_ct)?;
-
-    // Serialize the header
-    let header_bytes = ratchet_output.header.serialize();
-    let header_len = header_bytes.len() as u16;
-
-    // Generate a random nonce
-    let mut nonce_bytes = [0u8; NONCE_SIZE];
-    rand::thread_rng().fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    // Encrypt the message
-    let cipher =
-        Aes256GcmSiv::new_from_slice(&ratchet_output.message_key).expect("Invalid key length");
-    let ciphertext = cipher
-        .encrypt(nonce, msg)
-        .map_err(|_| ComLockError::EncryptionFailed)?;
-
-    // Build the output
-    let mut output = Vec::with_capacity(2 + header_bytes.len() + NONCE_SIZE + ciphertext.len());
-    output.extend_from_slice(&header_len.to_le_bytes());
-    output.extend_from_slice(&header_bytes);
-    output.extend_from_slice(&nonce_bytes);
-    output.extend_from_slice(&ciphertext);
-
-    Ok(output)
+    encrypt_with_options(
+        msg,
+        state,
+        MAX_PLAINTEXT_SIZE,
+        PaddingScheme::None,
+        HeaderEncoding::Binary,
+        remote_kem_ct,
+        &mut rand::thread_rng(),
+    )
 }
 
 #[cfg(test)]
@@ -265,8 +843,8 @@ mod tests {
     #[test]
     fn test_basic_encryption_decryption() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // Synchronize: Bob needs Alice's public key
         // First message from Alice establishes the link
@@ -280,8 +858,8 @@ mod tests {
     #[test]
     fn test_alice_sends_three_messages() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // Alice sends 3 messages
         let messages = [
@@ -306,8 +884,8 @@ mod tests {
     #[test]
     fn test_alice_sends_three_bob_replies_one() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // Alice sends 3 messages
         let alice_msgs = [
@@ -323,7 +901,7 @@ mod tests {
         }
 
         // Bob triggers KEM ratchet advancement and replies
-        bob.trigger_kem_advancement();
+        bob.trigger_kem_advancement().unwrap();
         let bob_msg = b"Bob's reply with KEM advancement!";
         let bob_ct = encrypt_message(bob_msg, &mut bob).expect("Bob encryption failed");
 
@@ -343,8 +921,8 @@ mod tests {
     #[test]
     fn test_tampered_ciphertext_fails() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         let msg = b"Secret message";
         let mut ciphertext = encrypt_message(msg, &mut alice).expect("Encryption failed");
@@ -365,8 +943,8 @@ mod tests {
     #[test]
     fn test_tampered_header_fails() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         let msg = b"Secret message";
         let mut ciphertext = encrypt_message(msg, &mut alice).expect("Encryption failed");
@@ -385,6 +963,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tampered_header_pubkey_fails_authentication() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let msg = b"Secret message";
+        let mut ciphertext = encrypt_message(msg, &mut alice).expect("Encryption failed");
+
+        // Flip a byte inside the classical public key (header bytes 0-31),
+        // which does not feed into the message key derivation
+        ciphertext[2 + 5] ^= 0xFF;
+
+        let result = decrypt_message(&ciphertext, &mut bob);
+        assert!(matches!(result, Err(ComLockError::DecryptionFailed)));
+    }
+
     #[test]
     fn test_wrong_recipient_fails() {
         let shared_secret_alice_bob = mock_handshake_secret();
@@ -392,8 +987,8 @@ mod tests {
         shared_secret_alice_eve.copy_from_slice(&shared_secret_alice_bob);
         shared_secret_alice_eve[0] ^= 0x01; // Different secret for Eve
 
-        let mut alice = RatchetState::new(shared_secret_alice_bob, true);
-        let mut eve = RatchetState::new(shared_secret_alice_eve, false);
+        let mut alice = RatchetState::new(shared_secret_alice_bob, true).unwrap();
+        let mut eve = RatchetState::new(shared_secret_alice_eve, false).unwrap();
 
         let msg = b"For Bob's eyes only";
         let ciphertext = encrypt_message(msg, &mut alice).expect("Encryption failed");
@@ -406,8 +1001,8 @@ mod tests {
     #[test]
     fn test_message_ordering_matters() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // Alice sends 2 messages
         let msg1 = b"First message";
@@ -424,11 +1019,462 @@ mod tests {
         assert_eq!(pt2, msg2);
     }
 
+    #[test]
+    fn test_old_chain_messages_arrive_after_chain_rotation() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // Alice sends 3 messages; only the first reaches Bob for now
+        let ct1 = encrypt_message(b"Alice 1", &mut alice).expect("Encryption 1 failed");
+        let ct2 = encrypt_message(b"Alice 2", &mut alice).expect("Encryption 2 failed");
+        let ct3 = encrypt_message(b"Alice 3", &mut alice).expect("Encryption 3 failed");
+        assert_eq!(decrypt_message(&ct1, &mut bob).unwrap(), b"Alice 1");
+
+        // Bob replies, so Alice's next message starts a new sending chain
+        let reply = encrypt_message(b"Bob reply", &mut bob).expect("Bob encryption failed");
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"Bob reply");
+        let ct4 = encrypt_message(b"Alice 4", &mut alice).expect("Encryption 4 failed");
+
+        // The new chain arrives first; messages 2 and 3 come in afterwards
+        assert_eq!(decrypt_message(&ct4, &mut bob).unwrap(), b"Alice 4");
+        assert_eq!(bob.skipped_key_count(), 2);
+        assert_eq!(decrypt_message(&ct2, &mut bob).unwrap(), b"Alice 2");
+        assert_eq!(decrypt_message(&ct3, &mut bob).unwrap(), b"Alice 3");
+        assert_eq!(bob.skipped_key_count(), 0);
+
+        // A replay of an already consumed skipped message is rejected
+        assert!(decrypt_message(&ct2, &mut bob).is_err());
+    }
+
+    #[test]
+    fn test_replayed_message_rejected() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let ct1 = encrypt_message(b"First", &mut alice).expect("Encryption 1 failed");
+        let ct2 = encrypt_message(b"Second", &mut alice).expect("Encryption 2 failed");
+        let ct3 = encrypt_message(b"Third", &mut alice).expect("Encryption 3 failed");
+
+        assert_eq!(decrypt_message(&ct1, &mut bob).unwrap(), b"First");
+        assert!(matches!(
+            decrypt_message(&ct1, &mut bob),
+            Err(ComLockError::ReplayedMessage)
+        ));
+
+        // Out-of-order delivery inside the window still works, once
+        assert_eq!(decrypt_message(&ct3, &mut bob).unwrap(), b"Third");
+        assert_eq!(decrypt_message(&ct2, &mut bob).unwrap(), b"Second");
+        assert!(matches!(
+            decrypt_message(&ct2, &mut bob),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(matches!(
+            decrypt_message(&ct3, &mut bob),
+            Err(ComLockError::ReplayedMessage)
+        ));
+    }
+
+    #[test]
+    fn test_message_older_than_replay_window_rejected() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let ciphertexts: Vec<Vec<u8>> = (0..=ratchet::REPLAY_WINDOW_SIZE + 1)
+            .map(|i| encrypt_message(&i.to_le_bytes(), &mut alice).expect("Encryption failed"))
+            .collect();
+
+        // Message 0 is delayed; everything after it arrives
+        for ct in &ciphertexts[1..] {
+            assert!(decrypt_message(ct, &mut bob).is_ok());
+        }
+
+        // By now message 0 has fallen out of the window and its key is gone
+        assert_eq!(bob.skipped_key_count(), 0);
+        assert!(matches!(
+            decrypt_message(&ciphertexts[0], &mut bob),
+            Err(ComLockError::ReplayedMessage)
+        ));
+    }
+
+    #[test]
+    fn test_lagging_receiver_fast_forwards() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let first = encrypt_message(b"first", &mut alice).unwrap();
+        decrypt_message(&first, &mut bob).unwrap();
+
+        // 50 messages are lost on the way to Bob
+        for _ in 0..50 {
+            encrypt_message(b"lost", &mut alice).unwrap();
+        }
+
+        let resync = ResyncHeader::deserialize(&alice.resync_header().serialize()).unwrap();
+        bob.apply_resync(&resync).unwrap();
+        assert_eq!(bob.skipped_key_count(), 0);
+
+        let live = encrypt_message(b"live", &mut alice).unwrap();
+        assert_eq!(decrypt_message(&live, &mut bob).unwrap(), b"live");
+
+        let reply = encrypt_message(b"reply", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn test_cbor_headers_interoperate_with_binary() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // The first message carries Alice's KEM public key
+        let first = encrypt_message_with_encoding(b"cbor", &mut alice, HeaderEncoding::Cbor)
+            .expect("Encryption failed");
+        let header_len_field = u16::from_le_bytes([first[0], first[1]]);
+        assert_ne!(header_len_field & VERSIONED_HEADER_FLAG, 0);
+        assert_eq!(first[2], header::HEADER_VERSION_CBOR);
+        assert_eq!(decrypt_message(&first, &mut bob).unwrap(), b"cbor");
+
+        let second = encrypt_message(b"binary", &mut alice).unwrap();
+        assert_eq!(decrypt_message(&second, &mut bob).unwrap(), b"binary");
+
+        let reply =
+            encrypt_message_with_encoding(b"reply", &mut bob, HeaderEncoding::Cbor).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"reply");
+
+        // The version byte is authenticated along with the header
+        let mut tampered =
+            encrypt_message_with_encoding(b"x", &mut alice, HeaderEncoding::Cbor).unwrap();
+        tampered[2] ^= 0xFF;
+        assert!(decrypt_message(&tampered, &mut bob).is_err());
+    }
+
+    #[test]
+    fn test_seeded_rng_gives_reproducible_sessions() {
+        use rand_chacha::ChaCha20Rng;
+        use rand_chacha::rand_core::SeedableRng;
+
+        // Run a conversation with every random choice drawn from one seed
+        let run = |seed: u64| {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let shared_secret = mock_handshake_secret();
+            let mut alice = RatchetState::new_with_rng(shared_secret, true, &mut rng).unwrap();
+            let mut bob = RatchetState::new_with_rng(shared_secret, false, &mut rng).unwrap();
+
+            let mut transcript = vec![
+                alice.our_public_key().to_bytes().to_vec(),
+                bob.our_public_key().to_bytes().to_vec(),
+            ];
+            for round in 0..4u8 {
+                let ct = encrypt_message_with_rng(&[round], &mut alice, &mut rng).unwrap();
+                assert_eq!(
+                    decrypt_message_with_rng(&ct, &mut bob, &mut rng).unwrap(),
+                    [round]
+                );
+                let reply = encrypt_message_with_rng(&[round], &mut bob, &mut rng).unwrap();
+                decrypt_message_with_rng(&reply, &mut alice, &mut rng).unwrap();
+                transcript.push(ct);
+                transcript.push(reply);
+            }
+            assert!(alice.status().pq_active);
+            transcript
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_failed_decryption_does_not_advance_state() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let mut forged = encrypt_message(b"Original", &mut alice).expect("Encryption failed");
+        let genuine = forged.clone();
+
+        // Claim a far-future message number; AEAD rejects it
+        forged[2 + 33] = 0x40;
+        assert!(decrypt_message(&forged, &mut bob).is_err());
+
+        // Bob's state is untouched, so the genuine message still decrypts
+        assert_eq!(decrypt_message(&genuine, &mut bob).unwrap(), b"Original");
+    }
+
+    #[test]
+    fn test_batch_decrypt_skips_corrupted_message() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let mut batch: Vec<Vec<u8>> = [b"First".as_slice(), b"Second", b"Third"]
+            .iter()
+            .map(|msg| encrypt_message(msg, &mut alice).expect("Encryption failed"))
+            .collect();
+
+        // Corrupt the middle ciphertext
+        let last = batch[1].len() - 1;
+        batch[1][last] ^= 0xFF;
+
+        let results = decrypt_batch(&batch, &mut bob);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), b"First");
+        assert!(matches!(results[1], Err(ComLockError::DecryptionFailed)));
+        assert_eq!(results[2].as_ref().unwrap(), b"Third");
+    }
+
+    #[test]
+    fn test_deterministic_encryption_is_reproducible() {
+        use rand::SeedableRng;
+
+        let shared_secret = mock_handshake_secret();
+        let alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // Same ratchet position, same message: identical ciphertext
+        let mut alice_a = alice.clone();
+        let mut alice_b = alice.clone();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let ct_a = encrypt_message_deterministic(b"Vector", &mut alice_a, &mut rng).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let ct_b = encrypt_message_deterministic(b"Vector", &mut alice_b, &mut rng).unwrap();
+        assert_eq!(ct_a, ct_b);
+
+        // Random nonces still differ at the same position
+        let random = encrypt_message(b"Vector", &mut alice.clone()).unwrap();
+        assert_ne!(random, ct_a);
+
+        // Both paths decrypt with the normal API
+        assert_eq!(decrypt_message(&ct_a, &mut bob).unwrap(), b"Vector");
+        let next = encrypt_message(b"Random", &mut alice_a).unwrap();
+        assert_eq!(decrypt_message(&next, &mut bob).unwrap(), b"Random");
+
+        // A step that rotates the sending chain draws its keys from `rng`
+        let reply = encrypt_message(b"Reply", &mut bob).unwrap();
+        decrypt_message(&reply, &mut alice_a).unwrap();
+        let mut alice_b = alice_a.clone();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let rotated_a = encrypt_message_deterministic(b"Again", &mut alice_a, &mut rng).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let rotated_b = encrypt_message_deterministic(b"Again", &mut alice_b, &mut rng).unwrap();
+        assert_eq!(rotated_a, rotated_b);
+        assert_eq!(decrypt_message(&rotated_a, &mut bob).unwrap(), b"Again");
+    }
+
+    #[test]
+    fn test_rekey_resynchronizes_sessions() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let ct = encrypt_message(b"Before", &mut alice).unwrap();
+        assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"Before");
+        let reply = encrypt_message(b"Reply", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"Reply");
+
+        let mut compromised_bob = bob.clone();
+
+        // Both sides rekey with the same out-of-band secret
+        let new_root = [0x5Au8; 32];
+        alice.rekey(new_root).unwrap();
+        bob.rekey(new_root).unwrap();
+
+        let ct = encrypt_message(b"After", &mut alice).unwrap();
+        assert!(decrypt_message(&ct, &mut compromised_bob).is_err());
+        assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"After");
+
+        let reply = encrypt_message(b"After reply", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"After reply");
+    }
+
+    #[test]
+    fn test_session_backup_roundtrip() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        for _ in 0..2 {
+            let ct = encrypt_message(b"ping", &mut alice).unwrap();
+            decrypt_message(&ct, &mut bob).unwrap();
+            let reply = encrypt_message(b"pong", &mut bob).unwrap();
+            decrypt_message(&reply, &mut alice).unwrap();
+        }
+        // A message Bob sent before the backup is still in flight
+        let in_flight = encrypt_message(b"in flight", &mut bob).unwrap();
+
+        let backup_key = [0x42u8; 32];
+        let backup = alice.export_backup(&backup_key).unwrap();
+        let mut restored = RatchetState::import_backup(&backup, &backup_key).unwrap();
+        assert_eq!(
+            restored.status().messages_sent,
+            alice.status().messages_sent
+        );
+        assert!(restored.status().pq_active);
+        // The KEM key is fresh rather than restored
+        assert_ne!(restored.our_kem_public_key(), alice.our_kem_public_key());
+
+        assert_eq!(
+            decrypt_message(&in_flight, &mut restored).unwrap(),
+            b"in flight"
+        );
+        for _ in 0..3 {
+            let ct = encrypt_message(b"after restore", &mut restored).unwrap();
+            assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"after restore");
+            let reply = encrypt_message(b"reply", &mut bob).unwrap();
+            assert_eq!(decrypt_message(&reply, &mut restored).unwrap(), b"reply");
+        }
+        assert_eq!(restored.transcript_hash(), bob.transcript_hash());
+    }
+
+    #[test]
+    fn test_restored_session_starts_fresh_epoch() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let ct = encrypt_message(b"ping", &mut alice).unwrap();
+        decrypt_message(&ct, &mut bob).unwrap();
+        let reply = encrypt_message(b"pong", &mut bob).unwrap();
+        decrypt_message(&reply, &mut alice).unwrap();
+        let bob_chain = header_of(&reply).classical_pubkey;
+        let ct = encrypt_message(b"ping again", &mut alice).unwrap();
+        decrypt_message(&ct, &mut bob).unwrap();
+
+        let backup_key = [0x42u8; 32];
+        let backup = alice.export_backup(&backup_key).unwrap();
+        let mut restored = RatchetState::import_backup(&backup, &backup_key).unwrap();
+
+        // The original and the restored copy no longer share a chain
+        let original = header_of(&encrypt_message(b"original", &mut alice.clone()).unwrap());
+        let ct = encrypt_message(b"restored", &mut restored).unwrap();
+        let header = header_of(&ct);
+        assert_ne!(header.classical_pubkey, original.classical_pubkey);
+        assert!(header.kem_pubkey.is_some());
+        assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"restored");
+
+        // Bob answers on a new DH chain with a KEM ciphertext to the fresh key
+        let answer = encrypt_message(b"answer", &mut bob).unwrap();
+        let header = header_of(&answer);
+        assert_ne!(header.classical_pubkey, bob_chain);
+        assert!(header.kem_ciphertext.is_some());
+        assert_eq!(decrypt_message(&answer, &mut restored).unwrap(), b"answer");
+    }
+
+    #[test]
+    fn test_transcript_binds_ciphertext_length() {
+        use rand::SeedableRng;
+
+        let shared_secret = [7u8; 32];
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+        let mut twin = alice.clone();
+
+        // The same header carrying payloads of different lengths
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let short = encrypt_message_with_rng(b"ping", &mut alice, &mut rng).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let long = encrypt_message_with_rng(b"ping pong", &mut twin, &mut rng).unwrap();
+        assert_eq!(
+            peek_header(&short).unwrap().serialize(),
+            peek_header(&long).unwrap().serialize()
+        );
+        assert_ne!(alice.transcript_hash(), twin.transcript_hash());
+
+        decrypt_message(&long, &mut bob).unwrap();
+        assert_eq!(twin.transcript_hash(), bob.transcript_hash());
+        assert_ne!(alice.transcript_hash(), bob.transcript_hash());
+    }
+
+    #[test]
+    fn test_session_backup_rejects_wrong_key() {
+        let alice = RatchetState::new(mock_handshake_secret(), true).unwrap();
+        let backup = alice.export_backup(&[1u8; 32]).unwrap();
+
+        assert!(matches!(
+            RatchetState::import_backup(&backup, &[2u8; 32]),
+            Err(ComLockError::DecryptionFailed)
+        ));
+
+        let mut tampered = backup.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(RatchetState::import_backup(&tampered, &[1u8; 32]).is_err());
+        assert!(matches!(
+            RatchetState::import_backup(&backup[..8], &[1u8; 32]),
+            Err(ComLockError::InvalidCiphertext)
+        ));
+
+        // A backup from before the KDF version bump
+        let mut old = backup.clone();
+        old[4] -= 1;
+        assert!(matches!(
+            RatchetState::import_backup(&old, &[1u8; 32]),
+            Err(ComLockError::ProtocolVersionMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_peer_on_old_protocol_version_rejected() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // A first-chain header from a peer that predates protocol version 2
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        let mut header = header_of(&hello);
+        header.capabilities = Some(CAPABILITY_KEM);
+        let header_bytes = header.serialize();
+        let header_len = u16::from_le_bytes([hello[0], hello[1]]) as usize;
+        let mut old = (header_bytes.len() as u16).to_le_bytes().to_vec();
+        old.extend_from_slice(&header_bytes);
+        old.extend_from_slice(&hello[2 + header_len..]);
+
+        assert!(matches!(
+            decrypt_message(&old, &mut bob),
+            Err(ComLockError::ProtocolVersionMismatch)
+        ));
+        assert_eq!(decrypt_message(&hello, &mut bob).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_plaintext_size_limit() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let over = vec![0u8; 1025];
+        assert!(matches!(
+            encrypt_message_with_limit(&over, &mut alice, 1024),
+            Err(ComLockError::MessageTooLarge {
+                size: 1025,
+                max: 1024
+            })
+        ));
+
+        // The rejected message did not advance the ratchet
+        let under = vec![0u8; 1023];
+        let ct = encrypt_message_with_limit(&under, &mut alice, 1024).unwrap();
+        assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), under);
+
+        // Default limit
+        let too_large = vec![0u8; MAX_PLAINTEXT_SIZE + 1];
+        assert!(matches!(
+            encrypt_message(&too_large, &mut alice),
+            Err(ComLockError::MessageTooLarge { .. })
+        ));
+        let largest = vec![0u8; MAX_PLAINTEXT_SIZE - 1];
+        let ct = encrypt_message(&largest, &mut alice).unwrap();
+        assert_eq!(decrypt_message(&ct, &mut bob).unwrap().len(), largest.len());
+    }
+
     #[test]
     fn test_empty_message() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         let msg: &[u8] = b"";
         let ciphertext = encrypt_message(msg, &mut alice).expect("Encryption failed");
@@ -437,11 +1483,60 @@ mod tests {
         assert_eq!(plaintext, msg);
     }
 
+    #[test]
+    fn test_minimal_message_decrypts() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice =
+            RatchetState::new_with_mode(shared_secret, true, ProtocolMode::Classical).unwrap();
+        let mut bob =
+            RatchetState::new_with_mode(shared_secret, false, ProtocolMode::Classical).unwrap();
+
+        let ciphertext = encrypt_message(b"", &mut alice).unwrap();
+        let header_len = u16::from_le_bytes([ciphertext[0], ciphertext[1]]) as usize;
+        // Only the capabilities byte on top of the fixed fields
+        assert_eq!(header_len, MIN_HEADER_LEN + 1);
+        // The plaintext is just its 4-byte length prefix
+        let body_start = 2 + header_len + NONCE_SIZE;
+        assert_eq!(ciphertext.len(), body_start + 4 + 16);
+
+        assert!(matches!(
+            decrypt_message(&ciphertext[..body_start + 15], &mut bob.clone()),
+            Err(ComLockError::MessageTooShort)
+        ));
+        assert_eq!(decrypt_message(&ciphertext, &mut bob).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_out_of_range_header_len_rejected_early() {
+        let mut bob = RatchetState::new(mock_handshake_secret(), false).unwrap();
+
+        // Near u16::MAX, in either encoding, whether or not the blob is
+        // long enough to hold it
+        for field in [0x7fffu16, 0xffff, (MAX_HEADER_LEN + 1) as u16] {
+            for len in [2 + MIN_HEADER_LEN + NONCE_SIZE + 16, 0x8000 + 64] {
+                let mut blob = vec![0u8; len];
+                blob[..2].copy_from_slice(&field.to_le_bytes());
+                assert!(matches!(
+                    decrypt_message(&blob, &mut bob),
+                    Err(ComLockError::InvalidHeader)
+                ));
+            }
+        }
+
+        // Too small to be any header
+        let mut blob = vec![0u8; 2 + MIN_HEADER_LEN + NONCE_SIZE + 16];
+        blob[..2].copy_from_slice(&((MIN_HEADER_LEN - 1) as u16).to_le_bytes());
+        assert!(matches!(
+            decrypt_message(&blob, &mut bob),
+            Err(ComLockError::InvalidHeader)
+        ));
+    }
+
     #[test]
     fn test_large_message() {
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // 64KB message
         let msg: Vec<u8> = (0..65536).map(|i| (i & 0xFF) as u8).collect();
@@ -451,13 +1546,465 @@ mod tests {
         assert_eq!(plaintext, msg);
     }
 
+    #[test]
+    fn test_fixed_bucket_padding_hides_length() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+        let scheme = PaddingScheme::FixedBucket(256);
+
+        // The first message carries Alice's KEM public key in its header
+        let first = encrypt_message(b"hi", &mut alice).unwrap();
+        decrypt_message(&first, &mut bob).unwrap();
+
+        let short = b"short".to_vec();
+        let long = vec![0x5a; 200];
+        let short_ct = encrypt_message_padded(&short, &mut alice, scheme).unwrap();
+        let long_ct = encrypt_message_padded(&long, &mut alice, scheme).unwrap();
+
+        assert_eq!(short_ct.len(), long_ct.len());
+        assert_eq!(decrypt_message(&short_ct, &mut bob).unwrap(), short);
+        assert_eq!(decrypt_message(&long_ct, &mut bob).unwrap(), long);
+    }
+
+    #[test]
+    fn test_forward_across_sessions() {
+        let mut alice = RatchetState::new([0x11; 32], true).unwrap();
+        let mut bob = RatchetState::new([0x11; 32], false).unwrap();
+        let mut bob_to_carol = RatchetState::new([0x22; 32], true).unwrap();
+        let mut carol = RatchetState::new([0x22; 32], false).unwrap();
+
+        let original = encrypt_message(b"meet at noon", &mut alice).unwrap();
+        let plaintext = decrypt_message(&original, &mut bob).unwrap();
+
+        for strip in [false, true] {
+            let forwarded = forward_message(&plaintext, &mut bob_to_carol, strip).unwrap();
+            assert_eq!(decrypt_message(&forwarded, &mut carol).unwrap(), plaintext);
+
+            // The forwarded header carries none of the original session's keys
+            let header_len = u16::from_le_bytes([forwarded[0], forwarded[1]]) as usize;
+            let header = MessageHeader::deserialize(&forwarded[2..2 + header_len]).unwrap();
+            assert_ne!(header.classical_pubkey, alice.our_public_key().to_bytes());
+            assert_eq!(
+                header.classical_pubkey,
+                bob_to_carol.our_public_key().to_bytes()
+            );
+        }
+
+        // The original session is unaffected
+        let reply = encrypt_message(b"ok", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"ok");
+    }
+
+    #[test]
+    fn test_primed_sessions_encapsulate_on_first_message() {
+        let mut rng = rand::thread_rng();
+        let alice_kem = pqc_kyber::keypair(&mut rng).unwrap();
+        let bob_kem = pqc_kyber::keypair(&mut rng).unwrap();
+        let primed = |is_initiator: bool| {
+            let (ours, theirs) = if is_initiator {
+                (&alice_kem, &bob_kem)
+            } else {
+                (&bob_kem, &alice_kem)
+            };
+            RatchetState::new_primed(
+                mock_handshake_secret(),
+                is_initiator,
+                &theirs.public,
+                &ours.public,
+                &ours.secret,
+            )
+            .unwrap()
+        };
+
+        // Either side may speak first; both sides at once works too
+        for (alice_first, bob_first) in [(true, false), (false, true), (true, true)] {
+            let mut alice = primed(true);
+            let mut bob = primed(false);
+
+            let from_alice = alice_first.then(|| encrypt_message(b"hi bob", &mut alice).unwrap());
+            let from_bob = bob_first.then(|| encrypt_message(b"hi alice", &mut bob).unwrap());
+            if let Some(ct) = from_alice {
+                assert!(header_of(&ct).kem_ciphertext.is_some());
+                assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"hi bob");
+                assert!(bob.status().pq_active);
+            }
+            if let Some(ct) = from_bob {
+                assert!(header_of(&ct).kem_ciphertext.is_some());
+                assert_eq!(decrypt_message(&ct, &mut alice).unwrap(), b"hi alice");
+                assert!(alice.status().pq_active);
+            }
+
+            // The conversation carries on normally
+            for _ in 0..2 {
+                let ct = encrypt_message(b"ping", &mut alice).unwrap();
+                assert_eq!(decrypt_message(&ct, &mut bob).unwrap(), b"ping");
+                let ct = encrypt_message(b"pong", &mut bob).unwrap();
+                assert_eq!(decrypt_message(&ct, &mut alice).unwrap(), b"pong");
+            }
+        }
+
+        assert!(matches!(
+            RatchetState::new_primed(
+                [0u8; 32],
+                true,
+                &[0u8; 10],
+                &alice_kem.public,
+                &alice_kem.secret
+            ),
+            Err(ComLockError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_status_tracks_counters_and_kem() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+        assert!(!alice.status().pq_active);
+
+        // Alice's first messages carry her KEM public key but no KEM secret yet
+        for _ in 0..2 {
+            let ct = encrypt_message(b"ping", &mut alice).unwrap();
+            decrypt_message(&ct, &mut bob).unwrap();
+        }
+        let status = alice.status();
+        assert_eq!(status.messages_sent, 2);
+        assert_eq!(status.messages_since_kem, 2);
+        assert!(!status.pq_active);
+        assert_eq!(bob.status().messages_received, 2);
+        assert!(!bob.status().pq_active);
+
+        // Bob encapsulates to Alice's key on his reply, completing a KEM round
+        let reply = encrypt_message(b"pong", &mut bob).unwrap();
+        assert!(bob.status().pq_active);
+        decrypt_message(&reply, &mut alice).unwrap();
+
+        let status = alice.status();
+        assert!(status.pq_active);
+        assert_eq!(status.messages_received, 1);
+        assert_eq!(status.messages_since_kem, 0);
+
+        encrypt_message(b"after", &mut alice).unwrap();
+        assert_eq!(alice.status().messages_sent, 3);
+        assert_eq!(alice.status().messages_since_kem, 1);
+    }
+
+    #[test]
+    fn test_responder_may_send_first() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let first = encrypt_message(b"Bob speaks first", &mut bob).unwrap();
+        assert_eq!(
+            decrypt_message(&first, &mut alice).unwrap(),
+            b"Bob speaks first"
+        );
+
+        // The conversation continues normally in both directions
+        let reply = encrypt_message(b"Hi Bob", &mut alice).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut bob).unwrap(), b"Hi Bob");
+        let next = encrypt_message(b"Hi again", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&next, &mut alice).unwrap(), b"Hi again");
+        let last = encrypt_message(b"Bye", &mut alice).unwrap();
+        assert_eq!(decrypt_message(&last, &mut bob).unwrap(), b"Bye");
+    }
+
+    #[test]
+    fn test_simultaneous_first_messages() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // Both parties send before either has received anything
+        let from_alice = encrypt_message(b"from Alice", &mut alice).unwrap();
+        let from_bob = encrypt_message(b"from Bob", &mut bob).unwrap();
+
+        assert_eq!(decrypt_message(&from_bob, &mut alice).unwrap(), b"from Bob");
+        assert_eq!(
+            decrypt_message(&from_alice, &mut bob).unwrap(),
+            b"from Alice"
+        );
+
+        // Both rotate their chains at once, each against the other's old key
+        let from_alice = encrypt_message(b"rotated Alice", &mut alice).unwrap();
+        let reply = encrypt_message(b"again", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"again");
+        assert_eq!(
+            decrypt_message(&from_alice, &mut bob).unwrap(),
+            b"rotated Alice"
+        );
+        assert!(alice.status().pq_active);
+
+        let reply = encrypt_message(b"settled", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"settled");
+    }
+
+    /// Parse the header of an encrypted message.
+    fn header_of(ciphertext: &[u8]) -> MessageHeader {
+        let header_len = u16::from_le_bytes([ciphertext[0], ciphertext[1]]) as usize;
+        MessageHeader::deserialize(&ciphertext[2..2 + header_len]).unwrap()
+    }
+
+    #[test]
+    fn test_lost_kem_message_does_not_break_session() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        // Alice advertises her KEM key; Bob's reply encapsulates to it
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        decrypt_message(&hello, &mut bob).unwrap();
+        let kem_message = encrypt_message(b"lost", &mut bob).unwrap();
+        assert!(header_of(&kem_message).kem_ciphertext.is_some());
+
+        // The KEM message never arrives; the classical chain carries on
+        let after = encrypt_message(b"after", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&after, &mut alice).unwrap(), b"after");
+        for _ in 0..3 {
+            let reply = encrypt_message(b"reply", &mut alice).unwrap();
+            assert_eq!(decrypt_message(&reply, &mut bob).unwrap(), b"reply");
+            let back = encrypt_message(b"back", &mut bob).unwrap();
+            assert_eq!(decrypt_message(&back, &mut alice).unwrap(), b"back");
+        }
+    }
+
+    #[test]
+    fn test_undecapsulable_kem_ciphertext_triggers_resync() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        decrypt_message(&hello, &mut bob).unwrap();
+        let kem_message = encrypt_message(b"unreadable", &mut bob).unwrap();
+
+        // Alice has replaced and advertised so many KEM keys since the one
+        // Bob encapsulated to that it has left her keypair history
+        for _ in 0..=ratchet::MAX_PREVIOUS_KEM_KEYPAIRS {
+            alice.trigger_kem_advancement().unwrap();
+            encrypt_message(b"advertises a new key", &mut alice).unwrap();
+        }
+        assert!(decrypt_message(&kem_message, &mut alice).is_err());
+        assert!(!alice.status().kem_resync_pending);
+
+        // Later messages still decrypt on the classical chain, and confirm
+        // the failed message was genuine
+        let after = encrypt_message(b"after", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&after, &mut alice).unwrap(), b"after");
+        assert!(alice.status().kem_resync_pending);
+
+        // Alice's reply advertises a fresh KEM key and Bob encapsulates again
+        let reply = encrypt_message(b"reply", &mut alice).unwrap();
+        assert!(header_of(&reply).kem_pubkey.is_some());
+        assert!(!alice.status().kem_resync_pending);
+        decrypt_message(&reply, &mut bob).unwrap();
+
+        let kem_again = encrypt_message(b"kem again", &mut bob).unwrap();
+        assert!(header_of(&kem_again).kem_ciphertext.is_some());
+        assert_eq!(
+            decrypt_message(&kem_again, &mut alice).unwrap(),
+            b"kem again"
+        );
+
+        // Both sides adopt the new secret and keep talking
+        for _ in 0..3 {
+            let reply = encrypt_message(b"reply", &mut alice).unwrap();
+            assert_eq!(decrypt_message(&reply, &mut bob).unwrap(), b"reply");
+            let back = encrypt_message(b"back", &mut bob).unwrap();
+            assert_eq!(decrypt_message(&back, &mut alice).unwrap(), b"back");
+        }
+        assert!(alice.status().pq_active);
+    }
+
+    #[test]
+    fn test_peek_header_reads_position() {
+        let mut alice = RatchetState::new(mock_handshake_secret(), true).unwrap();
+        let first = encrypt_message(b"one", &mut alice).unwrap();
+        let second = encrypt_message(b"two", &mut alice).unwrap();
+
+        let (first, second) = (peek_header(&first).unwrap(), peek_header(&second).unwrap());
+        assert_eq!(first.classical_pubkey, second.classical_pubkey);
+        assert_eq!(second.message_number, first.message_number + 1);
+        assert!(matches!(
+            peek_header(&[0u8; 8]),
+            Err(ComLockError::MessageTooShort)
+        ));
+    }
+
+    #[test]
+    fn test_forged_kem_ciphertext_does_not_trigger_resync() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        decrypt_message(&hello, &mut bob).unwrap();
+        let kem_message = encrypt_message(b"kem", &mut bob).unwrap();
+
+        // A forged message claiming a new chain, with a garbled ciphertext
+        let mut header = header_of(&kem_message);
+        header.classical_pubkey = [0x42; 32];
+        header.kem_ciphertext.as_mut().unwrap()[0] ^= 0xFF;
+        let header_bytes = header.serialize();
+        let mut forged = (header_bytes.len() as u16).to_le_bytes().to_vec();
+        forged.extend_from_slice(&header_bytes);
+        forged.extend_from_slice(&[0u8; NONCE_SIZE + 32]);
+        assert!(decrypt_message(&forged, &mut alice).is_err());
+
+        // Genuine traffic carries on without rotating Alice's KEM keys
+        assert_eq!(decrypt_message(&kem_message, &mut alice).unwrap(), b"kem");
+        let after = encrypt_message(b"after", &mut bob).unwrap();
+        assert_eq!(decrypt_message(&after, &mut alice).unwrap(), b"after");
+        assert!(!alice.status().kem_resync_pending);
+    }
+
+    #[test]
+    fn test_kem_ciphertext_for_previous_keypair_decrypts() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        decrypt_message(&hello, &mut bob).unwrap();
+        let kem_message = encrypt_message(b"in flight", &mut bob).unwrap();
+
+        // Alice rotated her KEM key while Bob's ciphertext was in flight
+        alice.trigger_kem_advancement().unwrap();
+        assert_eq!(
+            decrypt_message(&kem_message, &mut alice).unwrap(),
+            b"in flight"
+        );
+        assert!(!alice.status().kem_resync_pending);
+    }
+
+    #[test]
+    fn test_hybrid_peers_negotiate_kem() {
+        let shared_secret = mock_handshake_secret();
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
+
+        let hello = encrypt_message(b"hello", &mut alice).unwrap();
+        assert_eq!(
+            header_of(&hello).capabilities,
+            Some(CAPABILITY_PROTOCOL_V2 | CAPABILITY_KEM)
+        );
+        decrypt_message(&hello, &mut bob).unwrap();
+        assert_eq!(bob.negotiated_mode(), ProtocolMode::Hybrid);
+
+        let reply = encrypt_message(b"reply", &mut bob).unwrap();
+        assert!(header_of(&reply).kem_ciphertext.is_some());
+        assert_eq!(decrypt_message(&reply, &mut alice).unwrap(), b"reply");
+        assert_eq!(alice.negotiated_mode(), ProtocolMode::Hybrid);
+        assert!(alice.status().pq_active);
+
+        // Once Alice's first chain ends she stops sending her capabilities
+        let next = encrypt_message(b"next", &mut alice).unwrap();
+        assert!(header_of(&next).capabilities.is_none());
+        assert_eq!(decrypt_message(&next, &mut bob).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_classical_peer_interoperates_with_hybrid() {
+        let shared_secret = mock_handshake_secret();
+        for classical_initiator in [false, true] {
+            let mut hybrid = RatchetState::new(shared_secret, !classical_initiator).unwrap();
+            hybrid.set_allow_classical_peer(true);
+            let mut classical = RatchetState::new_with_mode(
+                shared_secret,
+                classical_initiator,
+                ProtocolMode::Classical,
+            )
+            .unwrap();
+            assert!(classical.our_kem_public_key().is_none());
+
+            for round in 0..4 {
+                hybrid.trigger_kem_advancement().unwrap();
+                let from_hybrid = encrypt_message(b"from hybrid", &mut hybrid).unwrap();
+                assert_eq!(
+                    decrypt_message(&from_hybrid, &mut classical).unwrap(),
+                    b"from hybrid"
+                );
+
+                let from_classical = encrypt_message(b"from classical", &mut classical).unwrap();
+                let header = header_of(&from_classical);
+                assert!(!header.has_kem_data());
+                if round == 0 {
+                    assert_eq!(header.capabilities, Some(CAPABILITY_PROTOCOL_V2));
+                }
+                assert_eq!(
+                    decrypt_message(&from_classical, &mut hybrid).unwrap(),
+                    b"from classical"
+                );
+                assert_eq!(hybrid.negotiated_mode(), ProtocolMode::Classical);
+            }
+            assert!(!hybrid.status().pq_active);
+            assert!(!classical.status().pq_active);
+            assert!(hybrid.our_kem_public_key().is_none());
+
+            // A KEM ciphertext claiming to come from the classical peer is
+            // rejected outright
+            let mut forged = header_of(&encrypt_message(b"x", &mut classical).unwrap());
+            forged.kem_ciphertext = Some(vec![0u8; ratchet::KYBER_CIPHERTEXT_SIZE]);
+            assert!(matches!(
+                hybrid.receive_step(&forged),
+                Err(ComLockError::KemNotNegotiated)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_classical_peer_rejected_without_policy() {
+        let shared_secret = mock_handshake_secret();
+        let mut hybrid = RatchetState::new(shared_secret, true).unwrap();
+        let mut classical =
+            RatchetState::new_with_mode(shared_secret, false, ProtocolMode::Classical).unwrap();
+
+        let from_classical = encrypt_message(b"downgrade", &mut classical).unwrap();
+        assert!(matches!(
+            decrypt_message(&from_classical, &mut hybrid),
+            Err(ComLockError::ProtocolDowngrade)
+        ));
+        assert!(hybrid.our_kem_public_key().is_some());
+        assert_eq!(hybrid.negotiated_mode(), ProtocolMode::Hybrid);
+
+        // The same message is accepted once local policy allows it
+        hybrid.set_allow_classical_peer(true);
+        assert_eq!(
+            decrypt_message(&from_classical, &mut hybrid).unwrap(),
+            b"downgrade"
+        );
+        assert_eq!(hybrid.negotiated_mode(), ProtocolMode::Classical);
+    }
+
+    #[test]
+    fn test_random_ciphertexts_never_panic() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0xdec0);
+        let mut bob = RatchetState::new(mock_handshake_secret(), false).unwrap();
+        for _ in 0..2_000 {
+            let len = rng.gen_range(0..256);
+            let mut blob = vec![0u8; len];
+            rng.fill(&mut blob[..]);
+            // Plausible header lengths reach the header and AEAD parsing
+            if len >= 2 && rng.gen_bool(0.5) {
+                let header_len = rng.gen_range(0..len as u16);
+                blob[..2].copy_from_slice(&header_len.to_le_bytes());
+            }
+
+            assert!(decrypt_message(&blob, &mut bob).is_err());
+        }
+    }
+
     #[test]
     fn test_kem_tampering_detection() {
         // This test verifies that tampering with encrypted data
         // causes AEAD authentication to fail
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // Exchange initial messages to establish the ratchet
         let msg1 = b"Setup message";
@@ -465,7 +2012,7 @@ mod tests {
         let _ = decrypt_message(&ct1, &mut bob).expect("Decryption failed");
 
         // Bob triggers KEM and sends
-        bob.trigger_kem_advancement();
+        bob.trigger_kem_advancement().unwrap();
         let bob_msg = b"Bob's KEM message";
         let mut bob_ct = encrypt_message(bob_msg, &mut bob).expect("Bob encryption failed");
 
@@ -493,8 +2040,8 @@ mod tests {
         // 3. Verify tampered KEM causes failure
 
         let shared_secret = mock_handshake_secret();
-        let mut alice = RatchetState::new(shared_secret, true);
-        let mut bob = RatchetState::new(shared_secret, false);
+        let mut alice = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob = RatchetState::new(shared_secret, false).unwrap();
 
         // === Part 1: Alice sends 3 messages ===
         println!("=== Alice sends 3 messages ===");
@@ -518,7 +2065,7 @@ mod tests {
 
         // === Part 2: Bob replies, advancing KEM ratchet ===
         println!("=== Bob replies with KEM advancement ===");
-        bob.trigger_kem_advancement();
+        bob.trigger_kem_advancement().unwrap();
 
         let bob_reply = b"Bob: Great news! Let's celebrate!";
         let bob_ct = encrypt_message(bob_reply, &mut bob).expect("Bob encryption failed");
@@ -530,8 +2077,8 @@ mod tests {
         println!("=== Verifying tamper detection ===");
 
         // Create a fresh pair for the tamper test
-        let mut alice2 = RatchetState::new(shared_secret, true);
-        let mut bob2 = RatchetState::new(shared_secret, false);
+        let mut alice2 = RatchetState::new(shared_secret, true).unwrap();
+        let mut bob2 = RatchetState::new(shared_secret, false).unwrap();
 
         // Initial exchange
         let init_msg = b"Initial sync";
@@ -539,7 +2086,7 @@ mod tests {
         decrypt_message(&init_ct, &mut bob2).expect("Decryption failed");
 
         // Bob sends with KEM
-        bob2.trigger_kem_advancement();
+        bob2.trigger_kem_advancement().unwrap();
         let kem_msg = b"Message with KEM data";
         let mut kem_ct = encrypt_message(kem_msg, &mut bob2).expect("Encryption failed");
 
@@ -0,0 +1,128 @@
+//! # ComLock Crypto - Padding Module
+//!
+//! Length-hiding padding applied to plaintexts before AEAD encryption.
+//!
+//! Every plaintext is framed as
+//!
+//! ```text
+//! [length: u32 LE][plaintext][zero padding]
+//! ```
+//!
+//! so the receiver can strip the padding regardless of the scheme the
+//! sender chose. The scheme only decides how much zero padding follows.
+//!
+//! The framing is part of protocol version 2. Peers advertise it with
+//! [`CAPABILITY_PROTOCOL_V2`](crate::header::CAPABILITY_PROTOCOL_V2), and
+//! the ratchet refuses a peer whose capabilities byte lacks it before any
+//! of its plaintexts are unpadded, so a plaintext from an older peer is
+//! never misread as a length prefix.
+
+use alloc::vec::Vec;
+
+use crate::{ComLockError, Result};
+
+/// Size of the length prefix in bytes.
+pub(crate) const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// How plaintexts are padded before encryption.
+///
+/// Padding hides the exact plaintext length: ciphertexts only reveal
+/// which size bucket the message fell into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaddingScheme {
+    /// Length prefix only; ciphertext length tracks plaintext length.
+    #[default]
+    None,
+    /// Pad the framed plaintext up to the next power of two.
+    PowerOfTwo,
+    /// Pad the framed plaintext up to a multiple of the bucket size.
+    FixedBucket(usize),
+}
+
+impl PaddingScheme {
+    /// Total framed size for a plaintext of `len` bytes.
+    fn padded_len(&self, len: usize) -> usize {
+        let framed = LENGTH_PREFIX_SIZE + len;
+        match *self {
+            PaddingScheme::None => framed,
+            PaddingScheme::PowerOfTwo => framed.next_power_of_two(),
+            PaddingScheme::FixedBucket(bucket) => framed.next_multiple_of(bucket.max(1)),
+        }
+    }
+}
+
+/// Frame and pad a plaintext according to `scheme`.
+///
+/// # Errors
+/// * `MessageTooLarge` if the plaintext length does not fit the prefix
+pub(crate) fn pad(msg: &[u8], scheme: PaddingScheme) -> Result<Vec<u8>> {
+    let len = u32::try_from(msg.len()).map_err(|_| ComLockError::MessageTooLarge {
+        size: msg.len(),
+        max: u32::MAX as usize,
+    })?;
+
+    let mut padded = Vec::with_capacity(scheme.padded_len(msg.len()));
+    padded.extend_from_slice(&len.to_le_bytes());
+    padded.extend_from_slice(msg);
+    padded.resize(scheme.padded_len(msg.len()), 0);
+    Ok(padded)
+}
+
+/// Strip the framing from a decrypted plaintext.
+///
+/// # Errors
+/// * `InvalidPadding` if the prefix is missing or exceeds the data
+pub(crate) fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>> {
+    let prefix: [u8; LENGTH_PREFIX_SIZE] = padded
+        .get(..LENGTH_PREFIX_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ComLockError::InvalidPadding)?;
+    let len = u32::from_le_bytes(prefix) as usize;
+
+    if len > padded.len() - LENGTH_PREFIX_SIZE {
+        return Err(ComLockError::InvalidPadding);
+    }
+
+    padded.truncate(LENGTH_PREFIX_SIZE + len);
+    padded.drain(..LENGTH_PREFIX_SIZE);
+    Ok(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_lengths() {
+        assert_eq!(PaddingScheme::None.padded_len(5), 9);
+        assert_eq!(PaddingScheme::PowerOfTwo.padded_len(5), 16);
+        assert_eq!(PaddingScheme::PowerOfTwo.padded_len(60), 64);
+        assert_eq!(PaddingScheme::FixedBucket(256).padded_len(5), 256);
+        assert_eq!(PaddingScheme::FixedBucket(256).padded_len(253), 512);
+        assert_eq!(PaddingScheme::FixedBucket(0).padded_len(5), 9);
+    }
+
+    #[test]
+    fn test_pad_roundtrip() {
+        for scheme in [
+            PaddingScheme::None,
+            PaddingScheme::PowerOfTwo,
+            PaddingScheme::FixedBucket(100),
+        ] {
+            let padded = pad(b"hello", scheme).unwrap();
+            assert_eq!(unpad(padded).unwrap(), b"hello");
+        }
+    }
+
+    #[test]
+    fn test_unpad_rejects_bad_length() {
+        assert!(matches!(
+            unpad(vec![1, 0]),
+            Err(ComLockError::InvalidPadding)
+        ));
+        assert!(matches!(
+            unpad(vec![9, 0, 0, 0, b'a']),
+            Err(ComLockError::InvalidPadding)
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+//! # ComLock Crypto - PQXDH Module
+//!
+//! Post-quantum extended Diffie-Hellman handshake producing the root key
+//! that seeds a [`RatchetState`](crate::RatchetState).
+//!
+//! The initiator combines three X25519 agreements with an ML-KEM
+//! encapsulation to the responder's KEM key:
+//!
+//! ```text
+//! DH1 = DH(IK_A, SPK_B)
+//! DH2 = DH(EK_A, IK_B)
+//! DH3 = DH(EK_A, SPK_B)
+//! SS  = ML-KEM-Encaps(KEM_B)
+//! CS  = HKDF-Extract(0x00 * 32, 0xFF * 32 || DH1 || DH2 || DH3)
+//! TH  = version || 0x00 || "pqxdh" || IK_A || IK_B || SPK_B || EK_A || CT
+//! SK  = HKDF-Expand(hybrid_combine(CS, SS, TH), version || 0x00 || "pqxdh_root")
+//! ```
+//!
+//! The responder recomputes the same agreements from the initiator's
+//! ephemeral key and KEM ciphertext, so both sides end with the same root
+//! key only if the classical and post-quantum exchanges both succeeded.
+//! [`hybrid_combine`] keeps the root key secret as long as either exchange
+//! is, and binds it to the public keys and ciphertext of this handshake.
+
+use alloc::vec::Vec;
+
+use hkdf::Hkdf;
+use pqc_kyber::decapsulate;
+#[cfg(feature = "std")]
+use pqc_kyber::encapsulate;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::hybrid::hybrid_combine;
+#[cfg(feature = "std")]
+use crate::ratchet::KYBER_PUBKEY_SIZE;
+use crate::ratchet::{KDF_PROTOCOL_VERSION, KYBER_CIPHERTEXT_SIZE};
+use crate::{ComLockError, Result};
+
+/// HKDF label for the handshake root key, versioned with the way the root
+/// key is derived: version 2 joins the secrets with the hybrid combiner
+const LABEL_PQXDH_ROOT: &[u8] = b"pqxdh_root_v2";
+
+/// Label starting the handshake transcript
+const LABEL_PQXDH_TRANSCRIPT: &[u8] = b"pqxdh";
+
+/// Domain-separation prefix prepended to the key material (as in X3DH)
+const PQXDH_PREFIX: [u8; 32] = [0xFF; 32];
+
+/// Message the initiator sends so the responder can complete the handshake.
+#[derive(Clone, Debug)]
+pub struct PqxdhInitMessage {
+    /// The initiator's one-time X25519 ephemeral public key.
+    pub ephemeral_key: [u8; 32],
+    /// ML-KEM ciphertext encapsulated to the responder's KEM key.
+    pub kem_ciphertext: Vec<u8>,
+}
+
+/// Result of the initiator side of the handshake.
+pub struct PqxdhInitiatorOutput {
+    /// Root key for `RatchetState::new(root_key, true)`.
+    pub root_key: [u8; 32],
+    /// Message to deliver to the responder.
+    pub message: PqxdhInitMessage,
+}
+
+/// Run the initiator side of PQXDH.
+///
+/// # Arguments
+/// * `our_ik` - Our long-term X25519 identity key
+/// * `their_ik` - The responder's X25519 identity public key
+/// * `their_spk` - The responder's X25519 signed prekey
+/// * `their_kem_ek` - The responder's ML-KEM encapsulation key
+///
+/// # Errors
+/// * `InvalidPublicKey` if `their_kem_ek` has the wrong length
+/// * `EncapsulationFailed` if ML-KEM encapsulation fails
+#[cfg(feature = "std")]
+pub fn pqxdh_initiator(
+    our_ik: &StaticSecret,
+    their_ik: &[u8; 32],
+    their_spk: &[u8; 32],
+    their_kem_ek: &[u8],
+) -> Result<PqxdhInitiatorOutput> {
+    let kem_ek: [u8; KYBER_PUBKEY_SIZE] = their_kem_ek
+        .try_into()
+        .map_err(|_| ComLockError::InvalidPublicKey)?;
+
+    let mut rng = rand::thread_rng();
+    let ephemeral = StaticSecret::random_from_rng(&mut rng);
+    let their_ik = X25519PublicKey::from(*their_ik);
+    let their_spk = X25519PublicKey::from(*their_spk);
+
+    let dh1 = our_ik.diffie_hellman(&their_spk);
+    let dh2 = ephemeral.diffie_hellman(&their_ik);
+    let dh3 = ephemeral.diffie_hellman(&their_spk);
+
+    let (kem_ciphertext, kem_secret) =
+        encapsulate(&kem_ek, &mut rng).map_err(|_| ComLockError::EncapsulationFailed)?;
+
+    let message = PqxdhInitMessage {
+        ephemeral_key: X25519PublicKey::from(&ephemeral).to_bytes(),
+        kem_ciphertext: kem_ciphertext.to_vec(),
+    };
+    let transcript = transcript(
+        X25519PublicKey::from(our_ik).as_bytes(),
+        their_ik.as_bytes(),
+        their_spk.as_bytes(),
+        &message,
+    );
+    let root_key = derive_root_key(
+        dh1.as_bytes(),
+        dh2.as_bytes(),
+        dh3.as_bytes(),
+        &kem_secret,
+        &transcript,
+    );
+
+    Ok(PqxdhInitiatorOutput { root_key, message })
+}
+
+/// Run the responder side of PQXDH, returning the root key for
+/// `RatchetState::new(root_key, false)`.
+///
+/// # Arguments
+/// * `our_ik` - Our long-term X25519 identity key
+/// * `our_spk` - Our X25519 signed prekey
+/// * `our_kem_dk` - Our ML-KEM decapsulation key
+/// * `their_ik` - The initiator's X25519 identity public key
+/// * `message` - The initiator's handshake message
+///
+/// # Errors
+/// * `InvalidCiphertext` if the KEM ciphertext has the wrong length
+/// * `DecapsulationFailed` if ML-KEM decapsulation fails
+pub fn pqxdh_responder(
+    our_ik: &StaticSecret,
+    our_spk: &StaticSecret,
+    our_kem_dk: &[u8],
+    their_ik: &[u8; 32],
+    message: &PqxdhInitMessage,
+) -> Result<[u8; 32]> {
+    let ciphertext: [u8; KYBER_CIPHERTEXT_SIZE] = message
+        .kem_ciphertext
+        .as_slice()
+        .try_into()
+        .map_err(|_| ComLockError::InvalidCiphertext)?;
+
+    let their_ik = X25519PublicKey::from(*their_ik);
+    let their_ephemeral = X25519PublicKey::from(message.ephemeral_key);
+
+    let dh1 = our_spk.diffie_hellman(&their_ik);
+    let dh2 = our_ik.diffie_hellman(&their_ephemeral);
+    let dh3 = our_spk.diffie_hellman(&their_ephemeral);
+
+    let kem_secret =
+        decapsulate(&ciphertext, our_kem_dk).map_err(|_| ComLockError::DecapsulationFailed)?;
+
+    let transcript = transcript(
+        their_ik.as_bytes(),
+        X25519PublicKey::from(our_ik).as_bytes(),
+        X25519PublicKey::from(our_spk).as_bytes(),
+        message,
+    );
+    Ok(derive_root_key(
+        dh1.as_bytes(),
+        dh2.as_bytes(),
+        dh3.as_bytes(),
+        &kem_secret,
+        &transcript,
+    ))
+}
+
+/// The public values of the handshake, in a fixed order for both sides.
+fn transcript(
+    initiator_ik: &[u8; 32],
+    responder_ik: &[u8; 32],
+    responder_spk: &[u8; 32],
+    message: &PqxdhInitMessage,
+) -> Vec<u8> {
+    let mut transcript = Vec::with_capacity(
+        KDF_PROTOCOL_VERSION.len()
+            + 1
+            + LABEL_PQXDH_TRANSCRIPT.len()
+            + 4 * 32
+            + message.kem_ciphertext.len(),
+    );
+    transcript.extend_from_slice(KDF_PROTOCOL_VERSION);
+    transcript.push(0x00);
+    transcript.extend_from_slice(LABEL_PQXDH_TRANSCRIPT);
+    transcript.extend_from_slice(initiator_ik);
+    transcript.extend_from_slice(responder_ik);
+    transcript.extend_from_slice(responder_spk);
+    transcript.extend_from_slice(&message.ephemeral_key);
+    transcript.extend_from_slice(&message.kem_ciphertext);
+    transcript
+}
+
+/// Combine the classical agreements and the KEM secret into the root key.
+fn derive_root_key(
+    dh1: &[u8],
+    dh2: &[u8],
+    dh3: &[u8],
+    kem_secret: &[u8; 32],
+    transcript: &[u8],
+) -> [u8; 32] {
+    let mut ikm = Vec::with_capacity(PQXDH_PREFIX.len() + 96);
+    ikm.extend_from_slice(&PQXDH_PREFIX);
+    ikm.extend_from_slice(dh1);
+    ikm.extend_from_slice(dh2);
+    ikm.extend_from_slice(dh3);
+    let (classical_secret, _) = Hkdf::<Sha256>::extract(Some(&[0u8; 32]), &ikm);
+    ikm.zeroize();
+
+    let mut classical_secret: [u8; 32] = classical_secret.into();
+    let mut combined = hybrid_combine(&classical_secret, kem_secret, transcript);
+    classical_secret.zeroize();
+
+    let mut info = Vec::with_capacity(KDF_PROTOCOL_VERSION.len() + 1 + LABEL_PQXDH_ROOT.len());
+    info.extend_from_slice(KDF_PROTOCOL_VERSION);
+    info.push(0x00);
+    info.extend_from_slice(LABEL_PQXDH_ROOT);
+
+    let hk = Hkdf::<Sha256>::from_prk(&combined).expect("32 bytes is a valid PRK length");
+    let mut root_key = [0u8; 32];
+    hk.expand(&info, &mut root_key)
+        .expect("HKDF expansion failed");
+
+    combined.zeroize();
+    root_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RatchetState, decrypt_message, encrypt_message};
+    use pqc_kyber::keypair;
+
+    struct Responder {
+        ik: StaticSecret,
+        spk: StaticSecret,
+        kem: pqc_kyber::Keypair,
+    }
+
+    impl Responder {
+        fn new() -> Self {
+            let mut rng = rand::thread_rng();
+            Self {
+                ik: StaticSecret::random_from_rng(&mut rng),
+                spk: StaticSecret::random_from_rng(&mut rng),
+                kem: keypair(&mut rng).unwrap(),
+            }
+        }
+
+        fn ik_public(&self) -> [u8; 32] {
+            X25519PublicKey::from(&self.ik).to_bytes()
+        }
+
+        fn spk_public(&self) -> [u8; 32] {
+            X25519PublicKey::from(&self.spk).to_bytes()
+        }
+    }
+
+    #[test]
+    fn test_initiator_and_responder_agree() {
+        let alice_ik = StaticSecret::random_from_rng(rand::thread_rng());
+        let alice_ik_public = X25519PublicKey::from(&alice_ik).to_bytes();
+        let bob = Responder::new();
+
+        let init = pqxdh_initiator(
+            &alice_ik,
+            &bob.ik_public(),
+            &bob.spk_public(),
+            &bob.kem.public,
+        )
+        .unwrap();
+        let bob_root = pqxdh_responder(
+            &bob.ik,
+            &bob.spk,
+            &bob.kem.secret,
+            &alice_ik_public,
+            &init.message,
+        )
+        .unwrap();
+
+        assert_eq!(init.root_key, bob_root);
+
+        // The shared root key seeds working ratchets
+        let mut alice = RatchetState::new(init.root_key, true).unwrap();
+        let mut bob_state = RatchetState::new(bob_root, false).unwrap();
+        let ciphertext = encrypt_message(b"hello", &mut alice).unwrap();
+        assert_eq!(
+            decrypt_message(&ciphertext, &mut bob_state).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_wrong_identity_yields_different_root() {
+        let alice_ik = StaticSecret::random_from_rng(rand::thread_rng());
+        let impostor_ik = StaticSecret::random_from_rng(rand::thread_rng());
+        let bob = Responder::new();
+
+        let init = pqxdh_initiator(
+            &alice_ik,
+            &bob.ik_public(),
+            &bob.spk_public(),
+            &bob.kem.public,
+        )
+        .unwrap();
+        let bob_root = pqxdh_responder(
+            &bob.ik,
+            &bob.spk,
+            &bob.kem.secret,
+            &X25519PublicKey::from(&impostor_ik).to_bytes(),
+            &init.message,
+        )
+        .unwrap();
+
+        assert_ne!(init.root_key, bob_root);
+    }
+
+    #[test]
+    fn test_malformed_kem_inputs_rejected() {
+        let alice_ik = StaticSecret::random_from_rng(rand::thread_rng());
+        let bob = Responder::new();
+
+        assert!(matches!(
+            pqxdh_initiator(&alice_ik, &bob.ik_public(), &bob.spk_public(), &[0u8; 16]),
+            Err(ComLockError::InvalidPublicKey)
+        ));
+
+        let message = PqxdhInitMessage {
+            ephemeral_key: [1u8; 32],
+            kem_ciphertext: vec![0u8; 16],
+        };
+        assert!(matches!(
+            pqxdh_responder(&bob.ik, &bob.spk, &bob.kem.secret, &[2u8; 32], &message),
+            Err(ComLockError::InvalidCiphertext)
+        ));
+    }
+}
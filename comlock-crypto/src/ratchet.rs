@@ -3,14 +3,131 @@
 //! Implements the KEM Braid ratchet state machine for hybrid post-quantum
 //! key agreement. Combines X25519 (classical ECDH) with Kyber-1024 (ML-KEM)
 //! for quantum-resistant forward secrecy.
-
+//!
+//! ## Sending chains
+//!
+//! Each party sends on a chain identified by its current X25519 ephemeral
+//! public key. A party starts a new sending chain (rotating its ephemeral)
+//! on its first send after receiving a new chain from the remote. Message
+//! numbers keep counting across chains; the header's `previous_chain_length`
+//! records where the sender's previous chain ended so the receiver can cache
+//! keys for messages from the old chain that are still in flight.
+//!
+//! The ephemeral is rotated once per sending chain, not once per message.
+//! Within a chain, forward secrecy comes from the symmetric chain: each
+//! message key is derived from the chain key, which is then replaced, so a
+//! stolen state does not yield the keys of messages already sent. A new
+//! ephemeral only adds secrecy when the remote answers it with a DH step,
+//! which happens once per round trip; a fresh key on every message would
+//! make every message its own chain without the remote ever using it, and
+//! would break the skipped-key cache that `previous_chain_length` relies
+//! on. (Earlier versions drew a new ephemeral per message, but no DH was
+//! ever done with it.)
+//!
+//! ## DH ratchet
+//!
+//! Every new chain after the first is keyed by a Diffie-Hellman step:
+//!
+//! ```text
+//! chain' = kdf_derive(root_key, "dh_ratchet", DH(new_ephemeral, remote) || chain)
+//! ```
+//!
+//! The sender uses the remote key it last received; the receiver uses its
+//! current ephemeral secret, or its previous one when both sides rotated
+//! at the same time. A compromised chain key therefore stops yielding
+//! message keys once the next direction change completes.
+//!
+//! ## KEM confirmation
+//!
+//! A fresh KEM shared secret first keys only the message that carries its
+//! ciphertext; that message's AEAD tag is what tells the receiver its
+//! decapsulation was correct. The receiver then mixes the secret into its
+//! sending chain, and the encapsulating side adopts it for its own chain
+//! once a message keyed with it arrives. Until then both chains keep the
+//! last confirmed secret, so a lost or undecapsulable KEM message costs
+//! only that message: the classical chain carries on, and the receiver
+//! advertises a fresh KEM key to resynchronize.
+//!
+//! ## Protocol negotiation
+//!
+//! A session runs in [`ProtocolMode::Hybrid`] unless created with
+//! [`ProtocolMode::Classical`] for a peer without ML-KEM. Each side puts
+//! its capabilities byte on every header of its first sending chain; once
+//! the remote's byte arrives, KEM data is only exchanged if both sides run
+//! the KEM ratchet, and a KEM ciphertext on a classical-only session is
+//! rejected. A hybrid session refuses a classical-only remote with
+//! [`ComLockError::ProtocolDowngrade`] unless local policy allows it (see
+//! [`RatchetState::set_allow_classical_peer`]). A capabilities byte without [`CAPABILITY_PROTOCOL_V2`] comes
+//! from a peer on an older key schedule and plaintext framing, and is
+//! refused outright.
+//!
+//! ## Backups
+//!
+//! [`RatchetState::export_backup`] lets a session be stored somewhere
+//! untrusted, such as cloud storage. The backup is encrypted with
+//! AES-256-GCM under a 32-byte backup key the user holds separately (for
+//! example written down or kept in a password manager); it is never derived
+//! from the app PIN, so whoever can read the storage and guess the PIN still
+//! learns nothing. Anyone with the backup key can read the chain secrets and
+//! continue the conversation, so it must be guarded like the device itself.
+//!
+//! Our KEM decapsulation keys are left out: a restored session generates
+//! and advertises a fresh KEM key, and any KEM ciphertext still in flight
+//! to an old key triggers the usual resync.
+//!
+//! A backup is a copy of the session, and two copies that both send fork
+//! it: they derive the same message keys for the same message numbers.
+//! A restored session therefore starts a new epoch on its first send, a
+//! new sending chain on a fresh X25519 key together with the fresh KEM
+//! key, which makes the peer answer with a new DH chain and a new KEM
+//! exchange. That only separates the copies once the peer has sent at
+//! least once; a backup must still only be restored where the original
+//! session is gone.
+//!
+//! ## Replay protection
+//!
+//! The receiving chain keeps a sliding window over the last
+//! [`REPLAY_WINDOW_SIZE`] message numbers. A message number already seen in
+//! the window, or older than the window, is rejected as a replay. The window
+//! starts over whenever the remote starts a new sending chain.
+//!
+//! ## Ephemeral key reuse
+//!
+//! Every header on one sending chain carries the same X25519 ephemeral
+//! public key, so seeing a key again with a new message number is normal.
+//! A key from a chain the remote has already moved on from is not: an
+//! honest sender draws a fresh ephemeral for every chain, and late messages
+//! from an old chain are answered from the skipped-key cache before chain
+//! handling. The last [`MAX_RETIRED_REMOTE_KEYS`] retired remote keys are
+//! remembered, and in strict mode (see [`RatchetState::set_strict_mode`]) a
+//! header that brings one back as a "new" chain is rejected with
+//! `InvalidHeader` as a replay or a broken peer. Outside strict mode such a
+//! header is handled as a new chain, as before.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "std")]
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+#[cfg(feature = "std")]
+use aes_gcm::{Aes256Gcm, Nonce};
 use hkdf::Hkdf;
-use pqc_kyber::*;
-use sha2::Sha256;
+use pqc_kyber::{KYBER_CIPHERTEXTBYTES, KYBER_PUBLICKEYBYTES, KYBER_SECRETKEYBYTES};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroize;
 
 use crate::ComLockError;
-use crate::header::MessageHeader;
+use crate::header::{
+    CAPABILITY_KEM, CAPABILITY_PROTOCOL_V2, KEM_PUBKEY_REF_LEN, KemKeyCache, MessageHeader,
+    RESYNC_TAG_LEN, ResyncHeader, kem_pubkey_reference,
+};
+use crate::hybrid::hybrid_combine;
+use crate::kem::{Kem, KemKeypair, Kyber1024, check_kem_size};
 
 /// Size of Kyber-1024 public key in bytes
 pub const KYBER_PUBKEY_SIZE: usize = KYBER_PUBLICKEYBYTES;
@@ -21,17 +138,155 @@ pub const KYBER_CIPHERTEXT_SIZE: usize = KYBER_CIPHERTEXTBYTES;
 /// Size of Kyber-1024 secret key in bytes
 pub const KYBER_SECRETKEY_SIZE: usize = KYBER_SECRETKEYBYTES;
 
-/// The ratchet state machine managing the KEM Braid.
+/// Protocol version mixed into every HKDF `info` for domain separation.
+///
+/// Version 2 split message and chain key derivation and changed the hybrid
+/// combiner. Peers advertise it with [`CAPABILITY_PROTOCOL_V2`], and sessions
+/// from before it are rejected rather than left to fail decryption.
+pub const KDF_PROTOCOL_VERSION: &[u8] = b"ComLock-KDF-v2";
+
+/// Message-key label for the initiator-to-responder chain
+pub(crate) const LABEL_MSG_INITIATOR: &[u8] = b"msg_send";
+
+/// Message-key label for the responder-to-initiator chain
+pub(crate) const LABEL_MSG_RESPONDER: &[u8] = b"msg_recv";
+
+/// Label (and combiner transcript) for mixing a KEM secret into a message key
+const LABEL_KEM_MESSAGE: &[u8] = b"kem_message";
+
+/// Label for the authentication tag of a [`ResyncHeader`]
+const LABEL_RESYNC: &[u8] = b"resync";
+
+/// Purpose of the message key derived from a chain key
+pub(crate) const PURPOSE_MESSAGE_KEY: &[u8] = b"mk";
+
+/// Purpose of the next chain key derived from a chain key
+pub(crate) const PURPOSE_CHAIN_KEY: &[u8] = b"ck";
+
+/// Number of message numbers tracked by the replay window.
+pub const REPLAY_WINDOW_SIZE: u32 = 64;
+
+/// Maximum number of message keys derived to skip ahead on a receiving chain.
+///
+/// Bounds the work (and skipped-key cache growth) a peer can force with a
+/// header claiming a message number far beyond what we have received.
+pub const MAX_SKIP: u32 = 2000;
+
+/// Maximum number of messages [`RatchetState::fast_forward_recv`] may skip.
+pub const MAX_FAST_FORWARD: u32 = 100_000;
+
+/// Maximum number of unconfirmed KEM secrets kept for the receiving chain.
+const MAX_KEM_CANDIDATES: usize = 8;
+
+/// Maximum number of replaced KEM keypairs kept for late ciphertexts.
+///
+/// A keypair is dropped as soon as the remote encapsulates to it or to a
+/// newer one, so only keys the remote may still be about to use are kept.
+/// A ciphertext for a key rotated out further back than this cannot be
+/// decapsulated, and triggers a KEM resync.
+pub const MAX_PREVIOUS_KEM_KEYPAIRS: usize = 8;
+
+/// Number of retired remote ephemeral keys remembered to detect reuse.
+pub const MAX_RETIRED_REMOTE_KEYS: usize = 16;
+
+/// Fewest distinct byte values [`RatchetState::new_checked`] accepts in a
+/// root key.
+pub const MIN_DISTINCT_ROOT_KEY_BYTES: usize = 8;
+
+/// Domain separation label for the transcript hash chains.
+const TRANSCRIPT_LABEL: &[u8] = b"ComLock_Transcript_v2";
+
+/// Magic prefix of a session backup, see [`RatchetState::export_backup`].
+#[cfg(feature = "std")]
+const BACKUP_MAGIC: &[u8; 4] = b"CLRB";
+
+/// Current session backup format version.
+///
+/// Version 2 added the transcript hash chains. Version 3 marks sessions
+/// keyed under KDF protocol version 2; older backups are refused.
+#[cfg(feature = "std")]
+const BACKUP_VERSION: u8 = 3;
+
+/// Length of the AES-256-GCM nonce in a session backup.
+#[cfg(feature = "std")]
+const BACKUP_NONCE_LEN: usize = 12;
+
+/// Sliding-window bitmap of message numbers seen on the receiving chain.
+///
+/// Bit `i` of `seen` is set if `highest - i` has been received.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct ReplayWindow {
+    /// Highest message number seen on this chain
+    highest: Option<u32>,
+    /// Bitmap of seen message numbers, relative to `highest`
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Reject a message number that was already seen or is below the window.
+    fn check(&self, message_number: u32) -> Result<(), ComLockError> {
+        let Some(highest) = self.highest else {
+            return Ok(());
+        };
+        if message_number > highest {
+            return Ok(());
+        }
+
+        let offset = highest - message_number;
+        if offset >= REPLAY_WINDOW_SIZE || self.seen & (1u64 << offset) != 0 {
+            return Err(ComLockError::ReplayedMessage);
+        }
+        Ok(())
+    }
+
+    /// Record a message number as seen, sliding the window forward if needed.
+    fn mark(&mut self, message_number: u32) {
+        match self.highest {
+            Some(highest) if message_number <= highest => {
+                let offset = highest - message_number;
+                if offset < REPLAY_WINDOW_SIZE {
+                    self.seen |= 1u64 << offset;
+                }
+            }
+            Some(highest) => {
+                let shift = message_number - highest;
+                self.seen = if shift >= REPLAY_WINDOW_SIZE {
+                    0
+                } else {
+                    self.seen << shift
+                };
+                self.seen |= 1;
+                self.highest = Some(message_number);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(message_number);
+            }
+        }
+    }
+
+    /// Whether a message number has fallen out of the window.
+    fn is_below(&self, message_number: u32) -> bool {
+        self.highest
+            .is_some_and(|highest| highest.saturating_sub(message_number) >= REPLAY_WINDOW_SIZE)
+    }
+}
+
+/// The ratchet state machine with the default KEM, Kyber-1024.
+pub type RatchetState = KemRatchetState<Kyber1024>;
+
+/// The ratchet state machine managing the KEM Braid, over the KEM `K`.
 ///
 /// This struct maintains two parallel key evolution timelines:
 /// - **Classical (fast)**: X25519 ECDH updates with every message
-/// - **Post-Quantum (heavy)**: Kyber-1024 KEM updates opportunistically
+/// - **Post-Quantum (heavy)**: KEM updates opportunistically
 ///
 /// The "braid" design allows sparse PQ ratcheting to minimize bandwidth
 /// while maintaining post-compromise security against quantum adversaries.
+/// Most code uses [`RatchetState`]; see [`crate::kem`] for other KEMs.
 #[derive(Clone)]
 #[allow(dead_code)] // Some fields reserved for future ECDH integration
-pub struct RatchetState {
+pub struct KemRatchetState<K: Kem> {
     /// The root key derived from the initial PQXDH handshake.
     root_key: [u8; 32],
 
@@ -44,32 +299,114 @@ pub struct RatchetState {
     /// Our current X25519 ephemeral keypair for sending
     our_ephemeral_secret: StaticSecret,
 
+    /// Our ephemeral secret before the last rotation, for a remote that
+    /// rotated before seeing our new key
+    previous_ephemeral_secret: Option<StaticSecret>,
+
     /// Counter for messages sent
     send_count: u32,
 
     /// Counter for messages received
     recv_count: u32,
 
-    /// The remote party's X25519 public key (last received)
+    /// Message number at which our current sending chain started, i.e. where
+    /// the previous one ended; sent as the header's `previous_chain_length`
+    send_chain_start: u32,
+
+    /// Whether our next send should start a new sending chain
+    rotate_send_chain: bool,
+
+    /// Whether that new chain starts even if nothing was sent on the
+    /// current one, as after [`RatchetState::import_backup`]
+    force_send_chain_rotation: bool,
+
+    /// The remote party's X25519 public key (identifies the receiving chain)
     remote_pubkey: Option<X25519PublicKey>,
 
+    /// Message keys for messages not yet received, by (chain pubkey, number)
+    skipped_keys: BTreeMap<([u8; 32], u32), [u8; 32]>,
+
+    /// Message numbers recently received on the current receiving chain
+    replay_window: ReplayWindow,
+
     /// Our pending Kyber keypair for KEM exchange
-    our_kem_keypair: Option<Keypair>,
+    our_kem_keypair: Option<KemKeypair<K>>,
+
+    /// Our advertised Kyber keypairs from before the last rotations, oldest
+    /// first, for ciphertexts the remote encapsulated before seeing our
+    /// newer keys
+    previous_kem_keypairs: Vec<KemKeypair<K>>,
+
+    /// KEM secrets the remote may switch its chain to, oldest first,
+    /// awaiting confirmation
+    recv_kem_candidates: Vec<[u8; 32]>,
+
+    /// Whether a KEM ciphertext failed and a fresh KEM key should be sent
+    kem_resync_needed: bool,
+
+    /// Chain key and number of a message whose KEM ciphertext could not be
+    /// used, until a later message on that chain authenticates
+    suspected_kem_failure: Option<([u8; 32], u32)>,
+
+    /// Whether we encapsulated a KEM secret the remote has not yet confirmed
+    kem_confirmation_pending: bool,
 
     /// The remote party's Kyber public key (if they sent one)
-    pending_kem_pubkey: Option<[u8; KYBER_PUBKEY_SIZE]>,
+    pending_kem_pubkey: Option<K::PublicKey>,
 
-    /// The shared secret from the last successful KEM operation
+    /// The shared secret of the last KEM exchange, whichever side
+    /// encapsulated it
     last_kem_secret: [u8; 32],
 
+    /// KEM secret mixed into our sending chain's message keys
+    send_kem_secret: [u8; 32],
+
+    /// KEM secret mixed into the receiving chain's message keys
+    recv_kem_secret: [u8; 32],
+
     /// Flag indicating if we should include our KEM pubkey in next message
     should_send_kem_pubkey: bool,
 
+    /// Whether our current KEM pubkey has gone out in a header (or the
+    /// remote had it before the session started)
+    kem_pubkey_advertised: bool,
+
+    /// KEM public keys both sides have seen, for sending them by reference
+    kem_key_cache: KemKeyCache<K::PublicKey>,
+
     /// Message number of last KEM ratchet advancement
     last_kem_message_number: u32,
 
+    /// Whether a KEM shared secret has been mixed into either chain
+    pq_active: bool,
+
+    /// Unix time (seconds) a KEM shared secret was last mixed in, 0 if never
+    last_kem_advance_at: i64,
+
     /// Whether this party is the initiator (affects initial state)
     is_initiator: bool,
+
+    /// Protocol features we support
+    mode: ProtocolMode,
+
+    /// Protocol features the remote advertised, once its capabilities arrive
+    peer_mode: Option<ProtocolMode>,
+
+    /// Whether a hybrid session accepts a classical-only remote
+    allow_classical_peer: bool,
+
+    /// Remote ephemeral keys of chains the remote has moved on from, oldest
+    /// first
+    retired_remote_pubkeys: VecDeque<[u8; 32]>,
+
+    /// Whether a retired remote ephemeral key coming back is rejected
+    strict_mode: bool,
+
+    /// Hash chain over the headers we sent, in order
+    sent_transcript: [u8; 32],
+
+    /// Hash chain over the headers we received, in the order processed
+    received_transcript: [u8; 32],
 }
 
 /// Output from a ratchet step: the message key and header to send
@@ -80,22 +417,207 @@ pub struct RatchetOutput {
     pub header: MessageHeader,
 }
 
+/// Snapshot of a session's progress for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RatchetStatus {
+    /// Messages sent on this session
+    pub messages_sent: u32,
+    /// Highest received message number plus one
+    pub messages_received: u32,
+    /// Messages sent since the KEM ratchet last advanced
+    pub messages_since_kem: u32,
+    /// Whether a post-quantum shared secret has been mixed in
+    pub pq_active: bool,
+    /// Unix time (seconds) post-quantum protection was last refreshed, 0 if never
+    pub last_kem_advance_at: i64,
+    /// Whether a KEM exchange failed and a fresh KEM key will be sent
+    pub kem_resync_pending: bool,
+}
+
+/// Protocol features a party supports, advertised in the capabilities
+/// byte of its first headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolMode {
+    /// X25519 only, for peers without ML-KEM
+    Classical,
+    /// X25519 with the Kyber-1024 KEM ratchet
+    #[default]
+    Hybrid,
+}
+
+impl ProtocolMode {
+    /// The capabilities byte advertising this mode.
+    pub fn capabilities(self) -> u8 {
+        match self {
+            Self::Classical => CAPABILITY_PROTOCOL_V2,
+            Self::Hybrid => CAPABILITY_PROTOCOL_V2 | CAPABILITY_KEM,
+        }
+    }
+
+    /// The mode advertised by a capabilities byte; unknown bits are ignored.
+    pub fn from_capabilities(capabilities: u8) -> Self {
+        if capabilities & CAPABILITY_KEM != 0 {
+            Self::Hybrid
+        } else {
+            Self::Classical
+        }
+    }
+}
+
+/// Which of our keys a receive step should try.
+///
+/// The remote may have used a key of ours that we have since replaced, or
+/// may or may not have switched to a pending KEM secret yet. Callers try
+/// the attempts from [`RatchetState::receive_attempts`] until one yields a
+/// message key that authenticates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ReceiveAttempt {
+    /// Derive a new remote chain with our previous X25519 ephemeral
+    pub previous_ephemeral: bool,
+    /// Decapsulate with this previous Kyber keypair (index, oldest first)
+    pub previous_kem_keypair: Option<usize>,
+    /// Use this pending KEM secret (index into the candidates) for the
+    /// remote's chain
+    pub kem_candidate: Option<usize>,
+}
+
 /// Output from receiving a message
 pub struct DecryptionContext {
     /// The symmetric key for decrypting the message payload
     pub message_key: [u8; 32],
 }
 
-impl RatchetState {
+/// Plaintext of a session backup: everything needed to carry on the
+/// conversation except our KEM decapsulation keys.
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize)]
+struct RatchetBackup {
+    root_key: [u8; 32],
+    send_chain_key: [u8; 32],
+    recv_chain_key: [u8; 32],
+    our_ephemeral_secret: [u8; 32],
+    previous_ephemeral_secret: Option<[u8; 32]>,
+    send_count: u32,
+    recv_count: u32,
+    send_chain_start: u32,
+    rotate_send_chain: bool,
+    remote_pubkey: Option<[u8; 32]>,
+    skipped_keys: Vec<([u8; 32], u32, [u8; 32])>,
+    replay_window: ReplayWindow,
+    recv_kem_candidates: Vec<[u8; 32]>,
+    kem_confirmation_pending: bool,
+    last_kem_secret: [u8; 32],
+    send_kem_secret: [u8; 32],
+    recv_kem_secret: [u8; 32],
+    last_kem_message_number: u32,
+    pq_active: bool,
+    last_kem_advance_at: i64,
+    is_initiator: bool,
+    mode: ProtocolMode,
+    peer_mode: Option<ProtocolMode>,
+    sent_transcript: [u8; 32],
+    received_transcript: [u8; 32],
+}
+
+#[cfg(feature = "std")]
+impl Drop for RatchetBackup {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        self.send_chain_key.zeroize();
+        self.recv_chain_key.zeroize();
+        self.our_ephemeral_secret.zeroize();
+        self.previous_ephemeral_secret.zeroize();
+        for (_, _, key) in self.skipped_keys.iter_mut() {
+            key.zeroize();
+        }
+        for candidate in self.recv_kem_candidates.iter_mut() {
+            candidate.zeroize();
+        }
+        self.last_kem_secret.zeroize();
+        self.send_kem_secret.zeroize();
+        self.recv_kem_secret.zeroize();
+    }
+}
+
+impl<K: Kem> KemRatchetState<K> {
     /// Create a new RatchetState from the output of a PQXDH handshake.
     ///
     /// Both parties must use the same `root_key` from the handshake.
     /// The `is_initiator` flag determines asymmetric initial state.
-    pub fn new(root_key: [u8; 32], is_initiator: bool) -> Self {
-        let mut rng = rand::thread_rng();
+    ///
+    /// The roles only pick which half of the initial chain keys each side
+    /// sends on, so either party may send the first message, and both may
+    /// send before hearing from the other. The initiator starts with a KEM
+    /// keypair and advertises it on its first message; the responder
+    /// generates one when it first sees the initiator's KEM public key, so
+    /// post-quantum protection begins once the initiator's first message
+    /// has been received.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if the thread RNG fails.
+    #[cfg(feature = "std")]
+    pub fn new(root_key: [u8; 32], is_initiator: bool) -> Result<Self, ComLockError> {
+        Self::new_with_mode(root_key, is_initiator, ProtocolMode::Hybrid)
+    }
+
+    /// [`RatchetState::new`], rejecting a `root_key` that cannot be the
+    /// output of a successful handshake.
+    ///
+    /// An all-zero key, or one with fewer than [`MIN_DISTINCT_ROOT_KEY_BYTES`]
+    /// distinct byte values, is refused with [`ComLockError::WeakSecret`]; a
+    /// uniformly random key falls below that bound with negligible
+    /// probability. An RNG failure is returned as
+    /// [`ComLockError::RngFailure`].
+    #[cfg(feature = "std")]
+    pub fn new_checked(root_key: [u8; 32], is_initiator: bool) -> Result<Self, ComLockError> {
+        Self::builder(root_key, is_initiator)
+            .check_root_key(true)
+            .build()
+    }
+
+    /// [`RatchetState::new`] drawing its keys from `rng`.
+    ///
+    /// Lets tests and test vectors supply a seeded RNG; use `new` otherwise.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if `rng` fails.
+    pub fn new_with_rng<R: RngCore + CryptoRng>(
+        root_key: [u8; 32],
+        is_initiator: bool,
+        rng: &mut R,
+    ) -> Result<Self, ComLockError> {
+        Self::new_with_mode_and_rng(root_key, is_initiator, ProtocolMode::Hybrid, rng)
+    }
 
+    /// [`RatchetState::new`] supporting only the features of `mode`.
+    ///
+    /// A [`ProtocolMode::Classical`] session never generates KEM keys and
+    /// interoperates with hybrid peers over X25519 alone.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if the thread RNG fails.
+    #[cfg(feature = "std")]
+    pub fn new_with_mode(
+        root_key: [u8; 32],
+        is_initiator: bool,
+        mode: ProtocolMode,
+    ) -> Result<Self, ComLockError> {
+        Self::new_with_mode_and_rng(root_key, is_initiator, mode, &mut rand::thread_rng())
+    }
+
+    /// [`RatchetState::new_with_mode`] drawing its keys from `rng`.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if `rng` fails.
+    pub fn new_with_mode_and_rng<R: RngCore + CryptoRng>(
+        root_key: [u8; 32],
+        is_initiator: bool,
+        mode: ProtocolMode,
+        rng: &mut R,
+    ) -> Result<Self, ComLockError> {
+        let hybrid_initiator = is_initiator && mode == ProtocolMode::Hybrid;
         // Generate initial X25519 keypair
-        let our_ephemeral_secret = StaticSecret::random_from_rng(&mut rng);
+        let our_ephemeral_secret = random_static_secret(rng)?;
 
         // Derive initial chain keys from root - asymmetric for sender/receiver roles
         let (send_chain, recv_chain) = if is_initiator {
@@ -108,81 +630,286 @@ impl RatchetState {
         };
 
         // Generate initial Kyber keypair for the initiator
-        let our_kem_keypair = if is_initiator {
-            Some(keypair(&mut rng).expect("Kyber keypair generation failed"))
+        let our_kem_keypair = if hybrid_initiator {
+            Some(KemKeypair::generate(rng)?)
         } else {
             None
         };
 
-        Self {
+        Ok(Self {
             root_key,
             send_chain_key: send_chain,
             recv_chain_key: recv_chain,
             our_ephemeral_secret,
+            previous_ephemeral_secret: None,
             send_count: 0,
             recv_count: 0,
+            send_chain_start: 0,
+            rotate_send_chain: false,
+            force_send_chain_rotation: false,
             remote_pubkey: None,
+            skipped_keys: BTreeMap::new(),
+            replay_window: ReplayWindow::default(),
             our_kem_keypair,
+            previous_kem_keypairs: Vec::new(),
+            recv_kem_candidates: Vec::new(),
+            kem_resync_needed: false,
+            suspected_kem_failure: None,
+            kem_confirmation_pending: false,
             pending_kem_pubkey: None,
             last_kem_secret: [0u8; 32],
-            should_send_kem_pubkey: is_initiator,
+            send_kem_secret: [0u8; 32],
+            recv_kem_secret: [0u8; 32],
+            should_send_kem_pubkey: hybrid_initiator,
+            kem_pubkey_advertised: false,
+            kem_key_cache: KemKeyCache::default(),
             last_kem_message_number: 0,
+            pq_active: false,
+            last_kem_advance_at: 0,
             is_initiator,
+            mode,
+            peer_mode: None,
+            allow_classical_peer: false,
+            retired_remote_pubkeys: VecDeque::new(),
+            strict_mode: false,
+            sent_transcript: [0u8; 32],
+            received_transcript: [0u8; 32],
+        })
+    }
+
+    /// Create a session primed with both parties' long-term KEM keys.
+    ///
+    /// With [`RatchetState::new`] no post-quantum secret can flow until the
+    /// initiator's KEM public key has arrived, and none at all if the
+    /// responder speaks first. Here each side already knows the other's
+    /// long-term encapsulation key (from the contact or invite), so the
+    /// first message in either direction carries a KEM ciphertext. Our own
+    /// long-term keypair decapsulates the ciphertext the peer sends first;
+    /// the ratchet moves on to fresh KEM keys from then on.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidPublicKey` if a key has the wrong length.
+    #[cfg(feature = "std")]
+    pub fn new_primed(
+        root_key: [u8; 32],
+        is_initiator: bool,
+        peer_kem_ek: &[u8],
+        our_kem_ek: &[u8],
+        our_kem_dk: &[u8],
+    ) -> Result<Self, ComLockError> {
+        Self::builder(root_key, is_initiator)
+            .kem_keypair(our_kem_ek, our_kem_dk)
+            .peer_kem_key(peer_kem_ek)
+            .primed(true)
+            .build()
+    }
+
+    /// Start a [`KemRatchetBuilder`] for a session on `root_key`.
+    ///
+    /// The builder covers every option of the `new_*` constructors and
+    /// checks that they fit together before creating the session.
+    pub fn builder(root_key: [u8; 32], is_initiator: bool) -> KemRatchetBuilder<K> {
+        KemRatchetBuilder::new(root_key, is_initiator)
+    }
+
+    /// Reset the session onto a fresh root key for post-compromise recovery.
+    ///
+    /// Re-derives the send/receive chains from `new_root_key` (respecting
+    /// `is_initiator`), resets all counters and skipped keys, and rotates the
+    /// X25519 ephemeral and KEM keypairs. The old root, chain and KEM
+    /// secrets are zeroized.
+    ///
+    /// Both parties must call this with the same secret, agreed out of band
+    /// (e.g. a fresh handshake), before exchanging further messages.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if the thread RNG fails; the session
+    /// is then left as it was.
+    #[cfg(feature = "std")]
+    pub fn rekey(&mut self, new_root_key: [u8; 32]) -> Result<(), ComLockError> {
+        let mut rekeyed = Self::new_with_mode(new_root_key, self.is_initiator, self.mode)?;
+        rekeyed.strict_mode = self.strict_mode;
+        rekeyed.allow_classical_peer = self.allow_classical_peer;
+        self.zeroize_secrets();
+        *self = rekeyed;
+        Ok(())
+    }
+
+    /// Reject headers that reuse a retired remote ephemeral key (see the
+    /// module docs). Off by default.
+    ///
+    /// Not part of session backups: set it again after
+    /// [`RatchetState::import_backup`].
+    pub fn set_strict_mode(&mut self, strict: bool) {
+        self.strict_mode = strict;
+    }
+
+    /// Whether strict ephemeral-reuse checking is on.
+    pub fn strict_mode(&self) -> bool {
+        self.strict_mode
+    }
+
+    /// Let a hybrid session fall back to X25519 alone when the remote
+    /// advertises classical-only capabilities. Off by default, so such a
+    /// remote is refused with `ComLockError::ProtocolDowngrade`.
+    ///
+    /// Not part of session backups: set it again after
+    /// [`RatchetState::import_backup`].
+    pub fn set_allow_classical_peer(&mut self, allow: bool) {
+        self.allow_classical_peer = allow;
+    }
+
+    /// Whether a classical-only remote is accepted.
+    pub fn allow_classical_peer(&self) -> bool {
+        self.allow_classical_peer
+    }
+
+    /// Wipe all symmetric secrets held by this state.
+    fn zeroize_secrets(&mut self) {
+        self.root_key.zeroize();
+        self.send_chain_key.zeroize();
+        self.recv_chain_key.zeroize();
+        self.last_kem_secret.zeroize();
+        self.send_kem_secret.zeroize();
+        self.recv_kem_secret.zeroize();
+        for key in self.skipped_keys.values_mut() {
+            key.zeroize();
+        }
+        self.skipped_keys.clear();
+        for candidate in self.recv_kem_candidates.iter_mut() {
+            candidate.zeroize();
+        }
+        self.recv_kem_candidates.clear();
+        for keypair in self
+            .our_kem_keypair
+            .iter_mut()
+            .chain(self.previous_kem_keypairs.iter_mut())
+        {
+            keypair.secret.zeroize();
         }
     }
 
     /// Perform a sending ratchet step - derive message key and produce header.
     ///
     /// This implements the "KEM Braid" design with sparse PQ ratcheting.
+    #[cfg(feature = "std")]
     pub fn step(
+        &mut self,
+        remote_kem_ciphertext: Option<&[u8]>,
+    ) -> Result<RatchetOutput, ComLockError> {
+        self.step_with_rng(remote_kem_ciphertext, &mut rand::thread_rng())
+    }
+
+    /// [`RatchetState::step`] drawing fresh keys from `rng`.
+    pub fn step_with_rng<R: RngCore + CryptoRng>(
         &mut self,
         _remote_kem_ciphertext: Option<&[u8]>,
+        rng: &mut R,
     ) -> Result<RatchetOutput, ComLockError> {
-        let mut rng = rand::thread_rng();
+        self.step_inner(false, rng)
+    }
+
+    /// [`RatchetState::step_with_rng`] for a message whose plaintext starts
+    /// with a content type byte, marked in the header.
+    pub(crate) fn step_typed_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<RatchetOutput, ComLockError> {
+        self.step_inner(true, rng)
+    }
+
+    /// A sending ratchet step producing a header with `typed_content`.
+    fn step_inner<R: RngCore + CryptoRng>(
+        &mut self,
+        typed_content: bool,
+        rng: &mut R,
+    ) -> Result<RatchetOutput, ComLockError> {
+        // === Chain Rotation ===
+        // Start a new sending chain if the remote has moved to a new chain
+        // since we last sent on ours
+        if self.rotate_send_chain {
+            let chain_used =
+                self.send_count > self.send_chain_start || self.force_send_chain_rotation;
+            if let (Some(remote), true) = (self.remote_pubkey, chain_used) {
+                let new_secret = random_static_secret(rng)?;
+                let shared = new_secret.diffie_hellman(&remote);
+                self.send_chain_key =
+                    Self::dh_ratchet_chain(&self.root_key, shared.as_bytes(), &self.send_chain_key);
+                self.previous_ephemeral_secret = Some(core::mem::replace(
+                    &mut self.our_ephemeral_secret,
+                    new_secret,
+                ));
+                self.send_chain_start = self.send_count;
+            }
+            self.rotate_send_chain = false;
+            self.force_send_chain_rotation = false;
+        }
 
         // Get our current public key for the header
         let our_public = X25519PublicKey::from(&self.our_ephemeral_secret);
 
         // === KEM Operations ===
-        let (kem_shared_secret, kem_ciphertext) = self.try_kem_encapsulate(&mut rng)?;
-
-        // Update last_kem_secret if we got a new one
-        if let Some(ref ss) = kem_shared_secret {
-            self.last_kem_secret = *ss;
-            self.last_kem_message_number = self.send_count;
+        if self.kem_resync_needed {
+            if self.kem_enabled() {
+                self.rotate_kem_keypair(rng)?;
+            }
+            self.kem_resync_needed = false;
         }
+        let encapsulated = self.try_kem_encapsulate(rng)?;
 
         // === Key Derivation ===
         // Mix the send chain key with counter to derive message key
-        let kem_input = kem_shared_secret.unwrap_or(self.last_kem_secret);
-        let mut ikm = Vec::with_capacity(36);
-        ikm.extend_from_slice(&self.send_count.to_le_bytes());
-        ikm.extend_from_slice(&kem_input);
+        let (mut message_key, new_send_chain) = Self::message_kdf(
+            &self.send_chain_key,
+            self.send_label(),
+            self.send_count,
+            &self.send_kem_secret,
+        );
 
-        let (message_key, new_send_chain) =
-            Self::kdf_derive(&self.send_chain_key, b"msg_send", &ikm);
+        // A new KEM secret keys only this message until the remote confirms
+        if let Some((ref ss, ref ciphertext, ref remote_pubkey)) = encapsulated {
+            message_key =
+                Self::kem_message_key(&message_key, ss, ciphertext, remote_pubkey.as_ref());
+            self.push_kem_candidate(*ss);
+            self.last_kem_secret = *ss;
+            self.kem_confirmation_pending = true;
+            self.last_kem_message_number = self.send_count;
+            self.mark_kem_advance();
+        }
 
         // Update state
         self.send_chain_key = new_send_chain;
 
-        // Rotate ephemeral key for forward secrecy
-        self.our_ephemeral_secret = StaticSecret::random_from_rng(&mut rng);
-
         // Build header
         let kem_pubkey = if self.should_send_kem_pubkey {
             self.should_send_kem_pubkey = false;
+            self.kem_pubkey_advertised = true;
             self.our_kem_keypair.as_ref().map(|kp| kp.public)
         } else {
             None
         };
 
-        let header = MessageHeader::new(
+        let (kem_ciphertext, kem_ciphertext_ref) = encapsulated
+            .map(|(_, ciphertext, remote_pubkey)| {
+                (ciphertext, kem_pubkey_reference(remote_pubkey.as_ref()))
+            })
+            .unzip();
+        let mut header = MessageHeader::new(
             our_public.to_bytes(),
             kem_ciphertext,
-            kem_pubkey,
+            None,
             self.send_count,
-            self.recv_count,
+            self.send_chain_start,
         );
+        header.kem_ciphertext_ref = kem_ciphertext_ref;
+        header.kem_pubkey = kem_pubkey.map(|pk| pk.as_ref().to_vec());
+        header.typed_content = typed_content;
+        self.kem_key_cache.compress(&mut header);
+        // Until our first chain ends the remote may not have our capabilities
+        if self.send_chain_start == 0 {
+            header.capabilities = Some(self.mode.capabilities());
+        }
+        Self::extend_transcript(&mut self.sent_transcript, &header);
 
         self.send_count += 1;
 
@@ -193,103 +920,680 @@ impl RatchetState {
     }
 
     /// Process an incoming message header and derive the decryption key.
+    ///
+    /// Messages from a previous receiving chain that were skipped over are
+    /// served from the skipped-key cache. When the header carries a new
+    /// remote chain key, the old chain is advanced to the header's
+    /// `previous_chain_length` (caching any skipped keys) and the new chain
+    /// is derived by a DH step with our current ephemeral secret.
+    #[cfg(feature = "std")]
     pub fn receive_step(
         &mut self,
         header: &MessageHeader,
     ) -> Result<DecryptionContext, ComLockError> {
-        let mut rng = rand::thread_rng();
+        self.receive_step_with_rng(header, &mut rand::thread_rng())
+    }
 
-        // Update remote public key
-        let remote_pub = X25519PublicKey::from(header.classical_pubkey);
-        self.remote_pubkey = Some(remote_pub);
+    /// [`RatchetState::receive_step`] drawing any new KEM keypair from `rng`.
+    pub fn receive_step_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        header: &MessageHeader,
+        rng: &mut R,
+    ) -> Result<DecryptionContext, ComLockError> {
+        self.receive_step_attempt(header, ReceiveAttempt::default(), rng)
+    }
 
-        // === KEM Decapsulation ===
-        let kem_shared_secret = if let Some(ref ct_bytes) = header.kem_ciphertext {
-            if let Some(ref our_keypair) = self.our_kem_keypair {
-                let ct: [u8; KYBER_CIPHERTEXT_SIZE] = ct_bytes
-                    .as_slice()
-                    .try_into()
-                    .map_err(|_| ComLockError::InvalidCiphertext)?;
+    /// Receive attempts worth trying for `header`, most likely first.
+    ///
+    /// The first attempt is always the plain [`RatchetState::receive_step`].
+    pub(crate) fn receive_attempts(&self, header: &MessageHeader) -> Vec<ReceiveAttempt> {
+        let new_chain = self
+            .remote_pubkey
+            .is_some_and(|current| current.to_bytes() != header.classical_pubkey);
+        let ephemerals: &[bool] = if new_chain && self.previous_ephemeral_secret.is_some() {
+            &[false, true]
+        } else {
+            &[false]
+        };
+        // The header names the keypair its ciphertext was encapsulated to,
+        // so exactly one is tried; without a name, the current one
+        let previous_kem_keypair = header.kem_ciphertext_ref.and_then(|reference| {
+            self.previous_kem_keypairs
+                .iter()
+                .position(|keypair| kem_pubkey_reference(keypair.public.as_ref()) == reference)
+        });
+        // Newest candidate first: the remote adopts secrets in the order
+        // they reach it
+        let candidates: Vec<Option<usize>> = core::iter::once(None)
+            .chain((0..self.recv_kem_candidates.len()).rev().map(Some))
+            .collect();
+
+        let mut attempts = Vec::new();
+        for &kem_candidate in &candidates {
+            for &previous_ephemeral in ephemerals {
+                attempts.push(ReceiveAttempt {
+                    previous_ephemeral,
+                    previous_kem_keypair,
+                    kem_candidate,
+                });
+            }
+        }
+        attempts
+    }
 
-                let shared_secret = decapsulate(&ct, &our_keypair.secret)
-                    .map_err(|_| ComLockError::DecapsulationFailed)?;
+    /// Note that no attempt could open `header`, which carries a KEM
+    /// ciphertext.
+    ///
+    /// Nothing authenticates a message we could not open, so this only
+    /// raises a suspicion. It becomes a KEM resync (our next message
+    /// advertises a fresh KEM key) once a later message on the same chain
+    /// authenticates while this one's key is still unused; a forged header
+    /// never gets that far, so it cannot force our KEM keys to rotate.
+    pub(crate) fn note_kem_failure(&mut self, header: &MessageHeader) {
+        self.suspected_kem_failure = Some((header.classical_pubkey, header.message_number));
+    }
 
-                // Generate new KEM keypair for next exchange
-                self.our_kem_keypair =
-                    Some(keypair(&mut rng).expect("Kyber keypair generation failed"));
-                self.should_send_kem_pubkey = true;
+    /// Settle a suspected KEM failure against an authenticated `header`.
+    fn settle_kem_suspicion(&mut self, header: &MessageHeader) {
+        let Some((chain, number)) = self.suspected_kem_failure else {
+            return;
+        };
+        if header.classical_pubkey != chain || header.message_number < number {
+            return;
+        }
+        self.suspected_kem_failure = None;
+        if header.message_number > number && self.skipped_keys.contains_key(&(chain, number)) {
+            self.kem_resync_needed = true;
+        }
+    }
 
-                Some(shared_secret)
-            } else {
-                return Err(ComLockError::MissingKemKeypair);
+    /// [`RatchetState::receive_step`] with a choice of our keys.
+    pub(crate) fn receive_step_attempt<R: RngCore + CryptoRng>(
+        &mut self,
+        header: &MessageHeader,
+        attempt: ReceiveAttempt,
+        rng: &mut R,
+    ) -> Result<DecryptionContext, ComLockError> {
+        // The remote's capabilities are fixed for the session
+        if let Some(capabilities) = header.capabilities {
+            if capabilities & CAPABILITY_PROTOCOL_V2 == 0 {
+                return Err(ComLockError::ProtocolVersionMismatch);
             }
-        } else {
-            None
-        };
+            let advertised = ProtocolMode::from_capabilities(capabilities);
+            if self.peer_mode.is_some_and(|mode| mode != advertised) {
+                return Err(ComLockError::InvalidHeader);
+            }
+            if self.peer_mode.is_none() && advertised == ProtocolMode::Classical {
+                if self.mode == ProtocolMode::Hybrid && !self.allow_classical_peer {
+                    return Err(ComLockError::ProtocolDowngrade);
+                }
+                self.disable_kem();
+            }
+            self.peer_mode = Some(advertised);
+        }
+        if header.kem_ciphertext.is_some() && !self.kem_enabled() {
+            return Err(ComLockError::KemNotNegotiated);
+        }
+
+        let message_number = header.message_number;
+        let on_current_chain = self
+            .remote_pubkey
+            .is_some_and(|current| current.to_bytes() == header.classical_pubkey);
+
+        // Replays on the current chain, including ones old enough to have
+        // left the window
+        if on_current_chain {
+            self.replay_window.check(message_number)?;
+        }
+
+        // A message we skipped over earlier
+        if let Some(message_key) = self
+            .skipped_keys
+            .remove(&(header.classical_pubkey, message_number))
+        {
+            if on_current_chain {
+                self.replay_window.mark(message_number);
+            }
+            let message_key = self.apply_kem_ciphertext(header, message_key, attempt, rng)?;
+            self.settle_kem_suspicion(header);
+            Self::extend_transcript(&mut self.received_transcript, header);
+            return Ok(DecryptionContext { message_key });
+        }
+
+        // === Chain Handling ===
+        let remote_pub = X25519PublicKey::from(header.classical_pubkey);
+        match self.remote_pubkey {
+            None => {
+                self.remote_pubkey = Some(remote_pub);
+                self.rotate_send_chain = true;
+            }
+            Some(current) if current != remote_pub => {
+                // A retired chain coming back is a replay or a broken peer
+                if self.strict_mode
+                    && self
+                        .retired_remote_pubkeys
+                        .contains(&header.classical_pubkey)
+                {
+                    return Err(ComLockError::InvalidHeader);
+                }
+
+                // The remote started a new sending chain: finish the old one
+                if header.previous_chain_length < self.recv_count {
+                    return Err(ComLockError::InvalidHeader);
+                }
+                self.check_skip(header.previous_chain_length)?;
+                self.check_skip(message_number)?;
+                self.skip_recv_keys(header.previous_chain_length);
+
+                let our_secret = if attempt.previous_ephemeral {
+                    self.previous_ephemeral_secret
+                        .as_ref()
+                        .ok_or(ComLockError::InvalidHeader)?
+                } else {
+                    &self.our_ephemeral_secret
+                };
+                let shared = our_secret.diffie_hellman(&remote_pub);
+                self.recv_chain_key =
+                    Self::dh_ratchet_chain(&self.root_key, shared.as_bytes(), &self.recv_chain_key);
+                if self.retired_remote_pubkeys.len() >= MAX_RETIRED_REMOTE_KEYS {
+                    self.retired_remote_pubkeys.pop_front();
+                }
+                self.retired_remote_pubkeys.push_back(current.to_bytes());
+                self.remote_pubkey = Some(remote_pub);
+                self.rotate_send_chain = true;
+                self.replay_window = ReplayWindow::default();
+            }
+            Some(_) => {}
+        }
+
+        // Already received (and not cached as skipped)
+        if message_number < self.recv_count {
+            return Err(ComLockError::InvalidHeader);
+        }
+        self.check_skip(message_number)?;
+        self.skip_recv_keys(message_number);
+
+        // The remote has switched its chain to a pending KEM secret; older
+        // candidates will not be used any more
+        if let Some(index) = attempt.kem_candidate {
+            let candidate = *self
+                .recv_kem_candidates
+                .get(index)
+                .ok_or(ComLockError::InvalidHeader)?;
+            for mut superseded in self.recv_kem_candidates.drain(..=index) {
+                superseded.zeroize();
+            }
+            self.confirm_kem_secret(candidate);
+        }
 
-        // Store remote's KEM pubkey if they sent one
-        if let Some(ref pubkey_bytes) = header.kem_pubkey {
-            let pubkey: [u8; KYBER_PUBKEY_SIZE] = pubkey_bytes
-                .as_slice()
-                .try_into()
-                .map_err(|_| ComLockError::InvalidPublicKey)?;
+        // Store remote's KEM pubkey if they sent one, in full or by
+        // reference; a stale reference gets a fresh KEM exchange instead
+        let remote_kem_pubkey = match (&header.kem_pubkey, &header.kem_pubkey_ref) {
+            (Some(pubkey_bytes), _) => {
+                check_kem_size(pubkey_bytes, K::PUBLIC_KEY_SIZE)?;
+                let pubkey = K::PublicKey::try_from(pubkey_bytes.as_slice())
+                    .map_err(|_| ComLockError::InvalidPublicKey)?;
+                self.kem_key_cache.remember(&pubkey);
+                Some(pubkey)
+            }
+            (None, Some(reference)) => {
+                let resolved = self.kem_key_cache.resolve(reference);
+                if resolved.is_none() {
+                    self.kem_resync_needed = true;
+                }
+                resolved
+            }
+            (None, None) => None,
+        };
+        if let Some(pubkey) = remote_kem_pubkey.filter(|_| self.kem_enabled()) {
             self.pending_kem_pubkey = Some(pubkey);
 
             // If we don't have a KEM keypair, generate one to respond
             if self.our_kem_keypair.is_none() {
-                self.our_kem_keypair =
-                    Some(keypair(&mut rng).expect("Kyber keypair generation failed"));
-                self.should_send_kem_pubkey = true;
+                self.rotate_kem_keypair(rng)?;
             }
         }
 
-        // Update last_kem_secret if we got a new one
-        if let Some(ref ss) = kem_shared_secret {
-            self.last_kem_secret = *ss;
-        }
-
         // === Key Derivation ===
-        let kem_input = kem_shared_secret.unwrap_or(self.last_kem_secret);
-        let mut ikm = Vec::with_capacity(36);
-        ikm.extend_from_slice(&header.message_number.to_le_bytes());
-        ikm.extend_from_slice(&kem_input);
+        let (message_key, new_recv_chain) = Self::message_kdf(
+            &self.recv_chain_key,
+            self.recv_label(),
+            message_number,
+            &self.recv_kem_secret,
+        );
 
-        let (message_key, new_recv_chain) =
-            Self::kdf_derive(&self.recv_chain_key, b"msg_send", &ikm);
+        let message_key = self.apply_kem_ciphertext(header, message_key, attempt, rng)?;
 
         // Update state
         self.recv_chain_key = new_recv_chain;
-        self.recv_count = header.message_number + 1;
+        self.recv_count = message_number + 1;
+        self.replay_window.mark(message_number);
+        self.drop_keys_below_window();
+        self.settle_kem_suspicion(header);
+        Self::extend_transcript(&mut self.received_transcript, header);
 
         Ok(DecryptionContext { message_key })
     }
 
-    /// Try to encapsulate to the remote's KEM public key if available.
-    #[allow(clippy::type_complexity)]
-    fn try_kem_encapsulate<R: rand::RngCore + rand::CryptoRng>(
-        &mut self,
-        rng: &mut R,
-    ) -> Result<(Option<[u8; 32]>, Option<Vec<u8>>), ComLockError> {
-        if let Some(remote_pubkey) = self.pending_kem_pubkey.take() {
-            let (ciphertext, shared_secret) =
-                encapsulate(&remote_pubkey, rng).map_err(|_| ComLockError::EncapsulationFailed)?;
-
-            // Generate new keypair for receiving their response
-            self.our_kem_keypair = Some(keypair(rng).expect("Kyber keypair generation failed"));
-            self.should_send_kem_pubkey = true;
-
-            Ok((Some(shared_secret), Some(ciphertext.to_vec())))
-        } else {
-            Ok((None, None))
+    /// Reject skipping ahead to `until` if it would derive more than
+    /// [`MAX_SKIP`] message keys.
+    fn check_skip(&self, until: u32) -> Result<(), ComLockError> {
+        if until.saturating_sub(self.recv_count) > MAX_SKIP {
+            return Err(ComLockError::TooManySkippedMessages);
         }
+        Ok(())
     }
 
-    /// HKDF-SHA256 based key derivation.
-    fn kdf_derive(input_key: &[u8; 32], info: &[u8], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+    /// Advance the receiving chain so the next expected message is
+    /// `to_message_number`, discarding the keys of the messages in between.
+    ///
+    /// Use this to recover a session whose receiving side fell too far
+    /// behind for skipped keys to help, e.g. with the counter from a
+    /// [`ResyncHeader`]. Does nothing if we are already at or past that
+    /// point; fails without changing anything beyond [`MAX_FAST_FORWARD`].
+    pub fn fast_forward_recv(&mut self, to_message_number: u32) -> Result<(), ComLockError> {
+        if to_message_number.saturating_sub(self.recv_count) > MAX_FAST_FORWARD {
+            return Err(ComLockError::TooManySkippedMessages);
+        }
+
+        while self.recv_count < to_message_number {
+            let (mut message_key, next_chain) = Self::message_kdf(
+                &self.recv_chain_key,
+                self.recv_label(),
+                self.recv_count,
+                &self.recv_kem_secret,
+            );
+            message_key.zeroize();
+            self.recv_chain_key = next_chain;
+            self.recv_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Resync marker describing where our sending chain currently stands.
+    pub fn resync_header(&self) -> ResyncHeader {
+        let classical_pubkey = self.our_public_key().to_bytes();
+        ResyncHeader {
+            classical_pubkey,
+            message_number: self.send_count,
+            tag: self.resync_tag(self.send_label(), &classical_pubkey, self.send_count),
+        }
+    }
+
+    /// Fast-forward the receiving chain to the point in a peer's
+    /// [`ResyncHeader`].
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidHeader` if the header names a chain
+    /// other than the one we are receiving on,
+    /// `ComLockError::DecryptionFailed` if its tag does not verify, and
+    /// `ComLockError::TooManySkippedMessages` if it is more than
+    /// [`MAX_SKIP`] messages ahead.
+    pub fn apply_resync(&mut self, header: &ResyncHeader) -> Result<(), ComLockError> {
+        if self
+            .remote_pubkey
+            .is_some_and(|current| current.to_bytes() != header.classical_pubkey)
+        {
+            return Err(ComLockError::InvalidHeader);
+        }
+        let expected = self.resync_tag(
+            self.recv_label(),
+            &header.classical_pubkey,
+            header.message_number,
+        );
+        if !crate::ct_eq(&expected, &header.tag) {
+            return Err(ComLockError::DecryptionFailed);
+        }
+        if header.message_number.saturating_sub(self.recv_count) > MAX_SKIP {
+            return Err(ComLockError::TooManySkippedMessages);
+        }
+        self.fast_forward_recv(header.message_number)
+    }
+
+    /// Tag authenticating a resync marker for the chain `classical_pubkey`
+    /// in the direction of `label`, keyed by the root key.
+    fn resync_tag(
+        &self,
+        label: &[u8],
+        classical_pubkey: &[u8; 32],
+        message_number: u32,
+    ) -> [u8; RESYNC_TAG_LEN] {
+        let mut data = [0u8; 36];
+        data[..32].copy_from_slice(classical_pubkey);
+        data[32..].copy_from_slice(&message_number.to_le_bytes());
+        let mut key = Self::kdf_expand(&self.root_key, LABEL_RESYNC, label, &data);
+
+        let mut tag = [0u8; RESYNC_TAG_LEN];
+        tag.copy_from_slice(&key[..RESYNC_TAG_LEN]);
+        key.zeroize();
+        tag
+    }
+
+    /// Advance the receiving chain up to (but not including) `until`,
+    /// caching the message keys of the messages skipped over.
+    fn skip_recv_keys(&mut self, until: u32) {
+        let Some(remote) = self.remote_pubkey else {
+            return;
+        };
+        let chain_id = remote.to_bytes();
+
+        while self.recv_count < until {
+            let (message_key, next_chain) = Self::message_kdf(
+                &self.recv_chain_key,
+                self.recv_label(),
+                self.recv_count,
+                &self.recv_kem_secret,
+            );
+            self.skipped_keys
+                .insert((chain_id, self.recv_count), message_key);
+            self.recv_chain_key = next_chain;
+            self.recv_count += 1;
+        }
+    }
+
+    /// Discard skipped keys on the current chain that can no longer be used
+    /// because they have fallen out of the replay window.
+    fn drop_keys_below_window(&mut self) {
+        let Some(remote) = self.remote_pubkey else {
+            return;
+        };
+        let chain_id = remote.to_bytes();
+        let window = self.replay_window;
+
+        self.skipped_keys.retain(|(chain, number), key| {
+            let expired = *chain == chain_id && window.is_below(*number);
+            if expired {
+                key.zeroize();
+            }
+            !expired
+        });
+    }
+
+    /// Message-key label for the chain we send on.
+    ///
+    /// Labels follow the chain's direction rather than our role in it, so
+    /// the receiver of a chain uses the same label as its sender while our
+    /// own send and receive derivations are always separated.
+    fn send_label(&self) -> &'static [u8] {
+        if self.is_initiator {
+            LABEL_MSG_INITIATOR
+        } else {
+            LABEL_MSG_RESPONDER
+        }
+    }
+
+    /// Message-key label for the chain we receive on.
+    fn recv_label(&self) -> &'static [u8] {
+        if self.is_initiator {
+            LABEL_MSG_RESPONDER
+        } else {
+            LABEL_MSG_INITIATOR
+        }
+    }
+
+    /// Derive a message key and the next chain key from a chain key.
+    ///
+    /// The two come from separate HKDF expansions, so a leaked message key
+    /// reveals nothing about the chain key that follows it.
+    pub(crate) fn message_kdf(
+        chain_key: &[u8; 32],
+        label: &[u8],
+        message_number: u32,
+        kem_secret: &[u8; 32],
+    ) -> ([u8; 32], [u8; 32]) {
+        let mut ikm = Vec::with_capacity(36);
+        ikm.extend_from_slice(&message_number.to_le_bytes());
+        ikm.extend_from_slice(kem_secret);
+
+        let message_key = Self::kdf_expand(chain_key, label, PURPOSE_MESSAGE_KEY, &ikm);
+        let next_chain_key = Self::kdf_expand(chain_key, label, PURPOSE_CHAIN_KEY, &ikm);
+        (message_key, next_chain_key)
+    }
+
+    /// Mix a freshly encapsulated KEM secret into one message key.
+    ///
+    /// The message key carries the classical chain (and with it every DH
+    /// ratchet step), so the two are joined with [`hybrid_combine`]. The
+    /// HKDF `info` also names the ciphertext and the public key it was
+    /// encapsulated to, as `H(ciphertext) || H(recipient_pubkey)`.
+    fn kem_message_key(
+        message_key: &[u8; 32],
+        kem_secret: &[u8; 32],
+        ciphertext: &[u8],
+        recipient_pubkey: &[u8],
+    ) -> [u8; 32] {
+        let mut binding = [0u8; 64];
+        binding[..32].copy_from_slice(&Sha256::digest(ciphertext));
+        binding[32..].copy_from_slice(&Sha256::digest(recipient_pubkey));
+
+        let mut combined = hybrid_combine(message_key, kem_secret, LABEL_KEM_MESSAGE);
+        let key = Self::kdf_expand(&combined, LABEL_KEM_MESSAGE, &binding, &[]);
+        combined.zeroize();
+        key
+    }
+
+    /// Decapsulate the header's KEM ciphertext, if any, and mix the secret
+    /// into `message_key`.
+    ///
+    /// The secret is adopted for our sending chain right away: the state is
+    /// only kept if the message authenticates, which proves the remote used
+    /// the same secret.
+    fn apply_kem_ciphertext<R: RngCore + CryptoRng>(
+        &mut self,
+        header: &MessageHeader,
+        message_key: [u8; 32],
+        attempt: ReceiveAttempt,
+        rng: &mut R,
+    ) -> Result<[u8; 32], ComLockError> {
+        let Some(ref ct_bytes) = header.kem_ciphertext else {
+            return Ok(message_key);
+        };
+        check_kem_size(ct_bytes, K::CIPHERTEXT_SIZE)?;
+        let ct = K::Ciphertext::try_from(ct_bytes.as_slice())
+            .map_err(|_| ComLockError::InvalidCiphertext)?;
+        let our_keypair = match attempt.previous_kem_keypair {
+            Some(index) => self.previous_kem_keypairs.get(index),
+            None => self.our_kem_keypair.as_ref(),
+        }
+        .ok_or(ComLockError::MissingKemKeypair)?;
+        if header
+            .kem_ciphertext_ref
+            .is_some_and(|reference| reference != kem_pubkey_reference(our_keypair.public.as_ref()))
+        {
+            return Err(ComLockError::MissingKemKeypair);
+        }
+
+        let shared_secret = K::decapsulate(&ct, &our_keypair.secret)?;
+        let recipient_pubkey = our_keypair.public;
+        // The remote encapsulated to this key, so it has seen it
+        self.kem_key_cache
+            .acknowledge(kem_pubkey_reference(recipient_pubkey.as_ref()));
+
+        // Both sides encapsulated before seeing each other's ciphertext
+        // (only possible in primed sessions): the initiator's secret wins,
+        // so the initiator uses this one for the message at hand only
+        if self.is_initiator
+            && self.kem_confirmation_pending
+            && attempt.previous_kem_keypair.is_some()
+        {
+            self.rotate_kem_keypair(rng)?;
+            return Ok(Self::kem_message_key(
+                &message_key,
+                &shared_secret,
+                ct_bytes,
+                recipient_pubkey.as_ref(),
+            ));
+        }
+
+        self.send_kem_secret = shared_secret;
+        self.kem_confirmation_pending = false;
+        self.push_kem_candidate(shared_secret);
+        self.last_kem_secret = shared_secret;
+        self.last_kem_message_number = self.send_count;
+        self.kem_resync_needed = false;
+        self.mark_kem_advance();
+
+        // The remote has used this key and will not use it or any older one
+        // again, so their secrets go now
+        let superseded = attempt
+            .previous_kem_keypair
+            .map_or(self.previous_kem_keypairs.len(), |index| index + 1);
+        for mut old in self.previous_kem_keypairs.drain(..superseded) {
+            old.secret.zeroize();
+        }
+        if attempt.previous_kem_keypair.is_none() {
+            // Not kept by the rotation below
+            self.kem_pubkey_advertised = false;
+        }
+
+        // Generate new KEM keypair for next exchange
+        self.rotate_kem_keypair(rng)?;
+
+        Ok(Self::kem_message_key(
+            &message_key,
+            &shared_secret,
+            ct_bytes,
+            recipient_pubkey.as_ref(),
+        ))
+    }
+
+    /// Protocol features used on this session: ours, limited to what the
+    /// remote advertised once its capabilities have arrived.
+    pub fn negotiated_mode(&self) -> ProtocolMode {
+        match self.peer_mode {
+            Some(ProtocolMode::Classical) => ProtocolMode::Classical,
+            _ => self.mode,
+        }
+    }
+
+    /// Whether KEM data is exchanged on this session.
+    fn kem_enabled(&self) -> bool {
+        self.negotiated_mode() == ProtocolMode::Hybrid
+    }
+
+    /// Stop the KEM ratchet for a classical-only remote, wiping our KEM keys.
+    fn disable_kem(&mut self) {
+        for mut keypair in self
+            .our_kem_keypair
+            .take()
+            .into_iter()
+            .chain(self.previous_kem_keypairs.drain(..))
+        {
+            keypair.secret.zeroize();
+        }
+        self.pending_kem_pubkey = None;
+        self.should_send_kem_pubkey = false;
+        self.kem_resync_needed = false;
+    }
+
+    /// Record that a KEM shared secret was just mixed in.
+    fn mark_kem_advance(&mut self) {
+        self.pq_active = true;
+        self.last_kem_advance_at = unix_now();
+    }
+
+    /// Remember a KEM secret the remote may switch its chain to.
+    fn push_kem_candidate(&mut self, kem_secret: [u8; 32]) {
+        if self.recv_kem_candidates.len() >= MAX_KEM_CANDIDATES {
+            self.recv_kem_candidates.remove(0).zeroize();
+        }
+        self.recv_kem_candidates.push(kem_secret);
+    }
+
+    /// Switch both chains to a KEM secret the remote has been seen using.
+    fn confirm_kem_secret(&mut self, kem_secret: [u8; 32]) {
+        self.kem_confirmation_pending = false;
+        self.recv_kem_secret = kem_secret;
+        self.send_kem_secret = kem_secret;
+        self.last_kem_secret = kem_secret;
+    }
+
+    /// Replace our KEM keypair, keeping the old one for ciphertexts
+    /// already in flight, and advertise the new public key.
+    ///
+    /// A keypair that was never advertised cannot have been used by the
+    /// remote, so it is dropped rather than kept. A pending advertisement
+    /// alone does not mean that: the initiator's first key and a key
+    /// queued by [`Self::readvertise_kem_pubkey`] both wait to be
+    /// sent, but only the latter may already be in the remote's hands.
+    /// Nothing changes if the RNG fails.
+    fn rotate_kem_keypair<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(), ComLockError> {
+        let new_keypair = KemKeypair::generate(rng)?;
+        if let Some(mut old) = self.our_kem_keypair.replace(new_keypair) {
+            if !self.kem_pubkey_advertised {
+                old.secret.zeroize();
+            } else {
+                if self.previous_kem_keypairs.len() >= MAX_PREVIOUS_KEM_KEYPAIRS {
+                    self.previous_kem_keypairs.remove(0).secret.zeroize();
+                }
+                self.previous_kem_keypairs.push(old);
+            }
+        }
+        self.should_send_kem_pubkey = true;
+        self.kem_pubkey_advertised = false;
+        Ok(())
+    }
+
+    /// Derive the first chain key of a new chain from a DH output and the
+    /// chain it replaces.
+    fn dh_ratchet_chain(
+        root_key: &[u8; 32],
+        dh_output: &[u8; 32],
+        chain_key: &[u8; 32],
+    ) -> [u8; 32] {
+        let mut ikm = [0u8; 64];
+        ikm[..32].copy_from_slice(dh_output);
+        ikm[32..].copy_from_slice(chain_key);
+
+        let (new_chain, _) = Self::kdf_derive(root_key, b"dh_ratchet", &ikm);
+        ikm.zeroize();
+        new_chain
+    }
+
+    /// Try to encapsulate to the remote's KEM public key if available.
+    ///
+    /// Returns the shared secret, the ciphertext and the remote key it was
+    /// encapsulated to.
+    #[allow(clippy::type_complexity)]
+    fn try_kem_encapsulate<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Option<([u8; 32], Vec<u8>, K::PublicKey)>, ComLockError> {
+        let Some(remote_pubkey) = self.pending_kem_pubkey.take() else {
+            return Ok(None);
+        };
+        let (ciphertext, shared_secret) = K::encapsulate(&remote_pubkey, rng)?;
+
+        // Generate new keypair for receiving their response
+        self.rotate_kem_keypair(rng)?;
+
+        Ok(Some((
+            shared_secret,
+            ciphertext.as_ref().to_vec(),
+            remote_pubkey,
+        )))
+    }
+
+    /// HKDF-SHA256 based key derivation.
+    ///
+    /// The `info` passed to HKDF is `KDF_PROTOCOL_VERSION || 0x00 || label`.
+    pub(crate) fn kdf_derive(
+        input_key: &[u8; 32],
+        label: &[u8],
+        ikm: &[u8],
+    ) -> ([u8; 32], [u8; 32]) {
         let hk = Hkdf::<Sha256>::new(Some(input_key), ikm);
 
+        let mut info = Vec::with_capacity(KDF_PROTOCOL_VERSION.len() + 1 + label.len());
+        info.extend_from_slice(KDF_PROTOCOL_VERSION);
+        info.push(0x00);
+        info.extend_from_slice(label);
+
         let mut okm = [0u8; 64];
-        hk.expand(info, &mut okm).expect("HKDF expansion failed");
+        hk.expand(&info, &mut okm).expect("HKDF expansion failed");
 
         let mut key1 = [0u8; 32];
         let mut key2 = [0u8; 32];
@@ -299,26 +1603,593 @@ impl RatchetState {
         (key1, key2)
     }
 
+    /// HKDF-SHA256 derivation of a single key for one `purpose` under
+    /// `label`.
+    ///
+    /// The `info` passed to HKDF is
+    /// `KDF_PROTOCOL_VERSION || 0x00 || label || 0x00 || purpose`.
+    pub(crate) fn kdf_expand(
+        input_key: &[u8; 32],
+        label: &[u8],
+        purpose: &[u8],
+        ikm: &[u8],
+    ) -> [u8; 32] {
+        let hk = Hkdf::<Sha256>::new(Some(input_key), ikm);
+
+        let mut info =
+            Vec::with_capacity(KDF_PROTOCOL_VERSION.len() + label.len() + purpose.len() + 2);
+        info.extend_from_slice(KDF_PROTOCOL_VERSION);
+        info.push(0x00);
+        info.extend_from_slice(label);
+        info.push(0x00);
+        info.extend_from_slice(purpose);
+
+        let mut key = [0u8; 32];
+        hk.expand(&info, &mut key).expect("HKDF expansion failed");
+        key
+    }
+
     /// Get our current X25519 public key.
     pub fn our_public_key(&self) -> X25519PublicKey {
         X25519PublicKey::from(&self.our_ephemeral_secret)
     }
 
-    /// Get our current Kyber public key if available.
-    pub fn our_kem_public_key(&self) -> Option<[u8; KYBER_PUBKEY_SIZE]> {
+    /// Get our current KEM public key if available.
+    pub fn our_kem_public_key(&self) -> Option<K::PublicKey> {
         self.our_kem_keypair.as_ref().map(|kp| kp.public)
     }
 
+    /// Number of cached message keys for skipped messages.
+    pub fn skipped_key_count(&self) -> usize {
+        self.skipped_keys.len()
+    }
+
+    /// Hash of the conversation so far, for checking that both parties saw
+    /// the same messages without revealing their content.
+    ///
+    /// Combines a hash chain over the messages each side sent, initiator
+    /// first, so both parties compute the same value once every message has
+    /// arrived, even if messages crossed in flight. A dropped or forged
+    /// message makes the values differ. Each link is a header followed, for
+    /// messages sealed by this crate, by the length of its AEAD ciphertext;
+    /// message content never enters the transcript.
+    ///
+    /// The chains assume each side receives the other's messages in the
+    /// order they were sent: a message delivered after a later one from the
+    /// same sender, even one recovered from the skipped keys, also makes the
+    /// values differ.
+    pub fn transcript_hash(&self) -> [u8; 32] {
+        let (initiator, responder) = if self.is_initiator {
+            (&self.sent_transcript, &self.received_transcript)
+        } else {
+            (&self.received_transcript, &self.sent_transcript)
+        };
+        Sha256::new()
+            .chain_update(TRANSCRIPT_LABEL)
+            .chain_update(initiator)
+            .chain_update(responder)
+            .finalize()
+            .into()
+    }
+
+    /// Append the AEAD ciphertext length of the message just sent to the
+    /// sent transcript chain, after its header.
+    pub(crate) fn bind_sent_ciphertext_len(&mut self, len: usize) {
+        Self::extend_transcript_len(&mut self.sent_transcript, len);
+    }
+
+    /// Append the AEAD ciphertext length of the message just received to
+    /// the received transcript chain, after its header.
+    pub(crate) fn bind_received_ciphertext_len(&mut self, len: usize) {
+        Self::extend_transcript_len(&mut self.received_transcript, len);
+    }
+
+    /// Append a ciphertext length to one of the transcript hash chains.
+    fn extend_transcript_len(chain: &mut [u8; 32], len: usize) {
+        *chain = Sha256::new()
+            .chain_update(TRANSCRIPT_LABEL)
+            .chain_update(*chain)
+            .chain_update((len as u64).to_le_bytes())
+            .finalize()
+            .into();
+    }
+
+    /// Append a header to one of the transcript hash chains.
+    fn extend_transcript(chain: &mut [u8; 32], header: &MessageHeader) {
+        let header_bytes = header.serialize();
+        *chain = Sha256::new()
+            .chain_update(TRANSCRIPT_LABEL)
+            .chain_update(*chain)
+            .chain_update((header_bytes.len() as u32).to_le_bytes())
+            .chain_update(&header_bytes)
+            .finalize()
+            .into();
+    }
+
+    /// Counters and post-quantum status of this session.
+    pub fn status(&self) -> RatchetStatus {
+        RatchetStatus {
+            messages_sent: self.send_count,
+            messages_received: self.recv_count,
+            messages_since_kem: self.send_count.saturating_sub(self.last_kem_message_number),
+            pq_active: self.pq_active,
+            last_kem_advance_at: self.last_kem_advance_at,
+            kem_resync_pending: self.kem_resync_needed,
+        }
+    }
+
     /// Check if we should advance the KEM ratchet based on policy.
     pub fn should_advance_kem(&self, policy_message_threshold: u32) -> bool {
         self.send_count.saturating_sub(self.last_kem_message_number) >= policy_message_threshold
     }
 
     /// Manually trigger KEM ratchet advancement.
-    pub fn trigger_kem_advancement(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.our_kem_keypair = Some(keypair(&mut rng).expect("Kyber keypair generation failed"));
-        self.should_send_kem_pubkey = true;
+    ///
+    /// Does nothing on a session that does not run the KEM ratchet.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::RngFailure` if no new keypair could be
+    /// generated; the current one stays in use.
+    #[cfg(feature = "std")]
+    pub fn trigger_kem_advancement(&mut self) -> Result<(), ComLockError> {
+        self.trigger_kem_advancement_with_rng(&mut rand::thread_rng())
+    }
+
+    /// [`RatchetState::trigger_kem_advancement`] drawing the new keypair
+    /// from `rng`.
+    pub fn trigger_kem_advancement_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(), ComLockError> {
+        if self.kem_enabled() {
+            self.rotate_kem_keypair(rng)?;
+        }
+        Ok(())
+    }
+
+    /// Advertise our current KEM public key again on the next message,
+    /// e.g. when the message that carried it may have been lost.
+    ///
+    /// Sent as a short reference if the remote has acknowledged the key.
+    pub fn readvertise_kem_pubkey(&mut self) {
+        self.should_send_kem_pubkey = self.our_kem_keypair.is_some();
+    }
+
+    /// Record that the remote has received our KEM public key with this
+    /// [`kem_pubkey_reference`], e.g. from a delivery receipt for the
+    /// message that carried it.
+    pub fn acknowledge_kem_pubkey(&mut self, reference: [u8; KEM_PUBKEY_REF_LEN]) {
+        self.kem_key_cache.acknowledge(reference);
+    }
+
+    /// Encrypt the session for storage on an untrusted backup medium.
+    ///
+    /// Format: `"CLRB" || version || nonce (12) || AES-256-GCM ciphertext`,
+    /// with the magic and version bound as associated data. Our KEM
+    /// decapsulation keys are not included; see the module docs for the trust model.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::EncryptionFailed` if serialization or
+    /// encryption fails.
+    #[cfg(feature = "std")]
+    pub fn export_backup(&self, backup_key: &[u8; 32]) -> Result<Vec<u8>, ComLockError> {
+        let backup = RatchetBackup {
+            root_key: self.root_key,
+            send_chain_key: self.send_chain_key,
+            recv_chain_key: self.recv_chain_key,
+            our_ephemeral_secret: self.our_ephemeral_secret.to_bytes(),
+            previous_ephemeral_secret: self
+                .previous_ephemeral_secret
+                .as_ref()
+                .map(StaticSecret::to_bytes),
+            send_count: self.send_count,
+            recv_count: self.recv_count,
+            send_chain_start: self.send_chain_start,
+            rotate_send_chain: self.rotate_send_chain,
+            remote_pubkey: self.remote_pubkey.map(|key| key.to_bytes()),
+            skipped_keys: self
+                .skipped_keys
+                .iter()
+                .map(|(&(chain, number), &key)| (chain, number, key))
+                .collect(),
+            replay_window: self.replay_window,
+            recv_kem_candidates: self.recv_kem_candidates.clone(),
+            kem_confirmation_pending: self.kem_confirmation_pending,
+            last_kem_secret: self.last_kem_secret,
+            send_kem_secret: self.send_kem_secret,
+            recv_kem_secret: self.recv_kem_secret,
+            last_kem_message_number: self.last_kem_message_number,
+            pq_active: self.pq_active,
+            last_kem_advance_at: self.last_kem_advance_at,
+            is_initiator: self.is_initiator,
+            mode: self.mode,
+            peer_mode: self.peer_mode,
+            sent_transcript: self.sent_transcript,
+            received_transcript: self.received_transcript,
+        };
+        let mut plaintext =
+            bincode::serialize(&backup).map_err(|_| ComLockError::EncryptionFailed)?;
+
+        let mut nonce = [0u8; BACKUP_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut aad = BACKUP_MAGIC.to_vec();
+        aad.push(BACKUP_VERSION);
+
+        let mut key = Self::backup_cipher_key(backup_key);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| ComLockError::EncryptionFailed);
+        key.zeroize();
+        let sealed = cipher?.encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        );
+        plaintext.zeroize();
+
+        let mut output = aad;
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&sealed.map_err(|_| ComLockError::EncryptionFailed)?);
+        Ok(output)
+    }
+
+    /// Restore a session written by [`RatchetState::export_backup`].
+    ///
+    /// The restored session starts a new sending chain on a fresh X25519
+    /// key and advertises a fresh KEM key on its next message, so the peer
+    /// answers with a fresh DH and KEM exchange.
+    ///
+    /// Only restore a backup where the session it was taken from is gone.
+    /// If the original keeps sending, both copies use the same message
+    /// keys until the peer replies (the fresh chain needs the peer's
+    /// X25519 key, so a session that never received cannot diverge at
+    /// all), and the peer may refuse whichever copy falls behind in
+    /// message numbers. Restoring the same backup twice forks it the same
+    /// way.
+    ///
+    /// # Errors
+    /// Returns `ComLockError::InvalidCiphertext` for input that is not a
+    /// session backup, `ComLockError::ProtocolVersionMismatch` for a
+    /// backup of a session keyed under an older KDF protocol version, and
+    /// `ComLockError::DecryptionFailed` if the backup key is wrong or the
+    /// backup was modified.
+    #[cfg(feature = "std")]
+    pub fn import_backup(backup: &[u8], backup_key: &[u8; 32]) -> Result<Self, ComLockError> {
+        let header_len = BACKUP_MAGIC.len() + 1;
+        let (aad, rest) = backup
+            .split_at_checked(header_len)
+            .ok_or(ComLockError::InvalidCiphertext)?;
+        if aad[..BACKUP_MAGIC.len()] != BACKUP_MAGIC[..] {
+            return Err(ComLockError::InvalidCiphertext);
+        }
+        match aad[BACKUP_MAGIC.len()].cmp(&BACKUP_VERSION) {
+            core::cmp::Ordering::Less => return Err(ComLockError::ProtocolVersionMismatch),
+            core::cmp::Ordering::Greater => return Err(ComLockError::InvalidCiphertext),
+            core::cmp::Ordering::Equal => {}
+        }
+        let (nonce, sealed) = rest
+            .split_at_checked(BACKUP_NONCE_LEN)
+            .ok_or(ComLockError::InvalidCiphertext)?;
+
+        let mut key = Self::backup_cipher_key(backup_key);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| ComLockError::DecryptionFailed);
+        key.zeroize();
+        let mut plaintext = cipher?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+            .map_err(|_| ComLockError::DecryptionFailed)?;
+        let decoded = bincode::deserialize::<RatchetBackup>(&plaintext);
+        plaintext.zeroize();
+        let backup = decoded.map_err(|_| ComLockError::InvalidCiphertext)?;
+
+        let mut state = Self::new_with_mode(backup.root_key, backup.is_initiator, backup.mode)?;
+        state.send_chain_key = backup.send_chain_key;
+        state.recv_chain_key = backup.recv_chain_key;
+        state.our_ephemeral_secret = StaticSecret::from(backup.our_ephemeral_secret);
+        state.previous_ephemeral_secret = backup.previous_ephemeral_secret.map(StaticSecret::from);
+        state.send_count = backup.send_count;
+        state.recv_count = backup.recv_count;
+        state.send_chain_start = backup.send_chain_start;
+        state.rotate_send_chain = true;
+        state.force_send_chain_rotation = true;
+        state.remote_pubkey = backup.remote_pubkey.map(X25519PublicKey::from);
+        state.skipped_keys = backup
+            .skipped_keys
+            .iter()
+            .map(|&(chain, number, key)| ((chain, number), key))
+            .collect();
+        state.replay_window = backup.replay_window;
+        state.recv_kem_candidates = backup.recv_kem_candidates.clone();
+        state.kem_confirmation_pending = backup.kem_confirmation_pending;
+        state.last_kem_secret = backup.last_kem_secret;
+        state.send_kem_secret = backup.send_kem_secret;
+        state.recv_kem_secret = backup.recv_kem_secret;
+        state.last_kem_message_number = backup.last_kem_message_number;
+        state.pq_active = backup.pq_active;
+        state.last_kem_advance_at = backup.last_kem_advance_at;
+        state.peer_mode = backup.peer_mode;
+        state.sent_transcript = backup.sent_transcript;
+        state.received_transcript = backup.received_transcript;
+
+        // Our KEM secret keys were not backed up: start over with a new one
+        if let Some(mut unused) = state.our_kem_keypair.take() {
+            unused.secret.zeroize();
+        }
+        state.should_send_kem_pubkey = false;
+        if state.kem_enabled() {
+            state.rotate_kem_keypair(&mut rand::thread_rng())?;
+        }
+        Ok(state)
+    }
+
+    /// AES-256-GCM key for session backups, derived from the backup key.
+    #[cfg(feature = "std")]
+    fn backup_cipher_key(backup_key: &[u8; 32]) -> [u8; 32] {
+        let (key, mut unused) = Self::kdf_derive(backup_key, b"session_backup", &[]);
+        unused.zeroize();
+        key
+    }
+}
+
+/// Current Unix time in seconds (0 if the clock is before the epoch).
+#[cfg(feature = "std")]
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Without `std` there is no clock, so KEM advances are not timestamped.
+#[cfg(not(feature = "std"))]
+fn unix_now() -> i64 {
+    0
+}
+
+/// A fresh X25519 secret, failing cleanly where `random_from_rng` would
+/// panic on an RNG error.
+fn random_static_secret<R: RngCore + CryptoRng>(rng: &mut R) -> Result<StaticSecret, ComLockError> {
+    let mut bytes = [0u8; 32];
+    rng.try_fill_bytes(&mut bytes)
+        .map_err(|_| ComLockError::RngFailure)?;
+    let secret = StaticSecret::from(bytes);
+    bytes.zeroize();
+    Ok(secret)
+}
+
+/// Whether `root_key` is all zero or too repetitive to be a handshake output.
+fn is_weak_root_key(root_key: &[u8; 32]) -> bool {
+    let mut seen = [false; 256];
+    for &byte in root_key {
+        seen[byte as usize] = true;
+    }
+    seen.iter().filter(|&&present| present).count() < MIN_DISTINCT_ROOT_KEY_BYTES
+}
+
+/// Wipes every secret held by the session, leaving it unusable.
+///
+/// Use this before dropping a session that must not survive in memory;
+/// [`RatchetState::rekey`] is the way to keep talking afterwards.
+impl<K: Kem> Zeroize for KemRatchetState<K> {
+    fn zeroize(&mut self) {
+        self.zeroize_secrets();
+        self.our_ephemeral_secret.zeroize();
+        if let Some(secret) = self.previous_ephemeral_secret.as_mut() {
+            secret.zeroize();
+        }
+        self.previous_ephemeral_secret = None;
+        self.our_kem_keypair = None;
+        self.previous_kem_keypairs.clear();
+        self.pending_kem_pubkey = None;
+        self.remote_pubkey = None;
+    }
+}
+
+/// [`KemRatchetBuilder`] with the default KEM, Kyber-1024.
+pub type RatchetBuilder = KemRatchetBuilder<Kyber1024>;
+
+/// Step-by-step construction of a [`KemRatchetState`].
+///
+/// Every option starts at what [`RatchetState::new`] does: a hybrid
+/// session, a fresh KEM keypair for the initiator, strict mode off, no
+/// classical-only remotes and no root key check. [`KemRatchetBuilder::build`] refuses combinations that
+/// cannot work rather than quietly ignoring part of them:
+///
+/// - a primed session needs both the peer's KEM key and our own keypair
+/// - a peer KEM key is only used by a primed session
+/// - a [`ProtocolMode::Classical`] session takes no KEM keys at all
+///
+/// ```
+/// use comlock_crypto::{ProtocolMode, RatchetBuilder};
+///
+/// let state = RatchetBuilder::new([0x42; 32], true)
+///     .mode(ProtocolMode::Hybrid)
+///     .strict_mode(true)
+///     .build()
+///     .expect("valid configuration");
+/// assert!(state.strict_mode());
+/// ```
+pub struct KemRatchetBuilder<K: Kem> {
+    root_key: [u8; 32],
+    is_initiator: bool,
+    mode: ProtocolMode,
+    strict_mode: bool,
+    allow_classical_peer: bool,
+    check_root_key: bool,
+    primed: bool,
+    /// Our KEM keypair as encoded public and secret key bytes
+    kem_keypair: Option<(Vec<u8>, Vec<u8>)>,
+    peer_kem_key: Option<Vec<u8>>,
+    _kem: core::marker::PhantomData<K>,
+}
+
+impl<K: Kem> KemRatchetBuilder<K> {
+    /// Start building a session on the handshake output `root_key`.
+    pub fn new(root_key: [u8; 32], is_initiator: bool) -> Self {
+        Self {
+            root_key,
+            is_initiator,
+            mode: ProtocolMode::default(),
+            strict_mode: false,
+            allow_classical_peer: false,
+            check_root_key: false,
+            primed: false,
+            kem_keypair: None,
+            peer_kem_key: None,
+            _kem: core::marker::PhantomData,
+        }
+    }
+
+    /// Support only the features of `mode` (see
+    /// [`RatchetState::new_with_mode`]).
+    pub fn mode(mut self, mode: ProtocolMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Reject retired remote ephemeral keys (see
+    /// [`RatchetState::set_strict_mode`]).
+    pub fn strict_mode(mut self, strict: bool) -> Self {
+        self.strict_mode = strict;
+        self
+    }
+
+    /// Accept a classical-only remote (see
+    /// [`RatchetState::set_allow_classical_peer`]).
+    pub fn allow_classical_peer(mut self, allow: bool) -> Self {
+        self.allow_classical_peer = allow;
+        self
+    }
+
+    /// Refuse a weak root key, as [`RatchetState::new_checked`] does.
+    pub fn check_root_key(mut self, check: bool) -> Self {
+        self.check_root_key = check;
+        self
+    }
+
+    /// Use this KEM keypair instead of generating one.
+    ///
+    /// Unless the session is primed, the public key is advertised on our
+    /// first message, whichever role we have.
+    pub fn kem_keypair(mut self, public_key: &[u8], secret_key: &[u8]) -> Self {
+        if let Some((_, mut unused)) = self.kem_keypair.take() {
+            unused.zeroize();
+        }
+        self.kem_keypair = Some((public_key.to_vec(), secret_key.to_vec()));
+        self
+    }
+
+    /// The peer's long-term KEM public key, for a primed session.
+    pub fn peer_kem_key(mut self, public_key: &[u8]) -> Self {
+        self.peer_kem_key = Some(public_key.to_vec());
+        self
+    }
+
+    /// Start with both parties' long-term KEM keys known (see
+    /// [`RatchetState::new_primed`]).
+    pub fn primed(mut self, primed: bool) -> Self {
+        self.primed = primed;
+        self
+    }
+
+    /// Create the session.
+    ///
+    /// # Errors
+    /// - `ComLockError::InvalidConfiguration` if the options conflict
+    /// - `ComLockError::WeakSecret` if the root key is checked and weak
+    /// - `ComLockError::InvalidPublicKey` if a KEM key has the wrong length
+    #[cfg(feature = "std")]
+    pub fn build(self) -> Result<KemRatchetState<K>, ComLockError> {
+        self.build_with_rng(&mut rand::thread_rng())
+    }
+
+    /// [`KemRatchetBuilder::build`] drawing the session's keys from `rng`.
+    pub fn build_with_rng<R: RngCore + CryptoRng>(
+        self,
+        rng: &mut R,
+    ) -> Result<KemRatchetState<K>, ComLockError> {
+        self.validate()?;
+
+        let our_keypair = match &self.kem_keypair {
+            Some((public, secret)) => Some(KemKeypair::<K> {
+                public: K::PublicKey::try_from(public)
+                    .map_err(|_| ComLockError::InvalidPublicKey)?,
+                secret: K::SecretKey::try_from(secret)
+                    .map_err(|_| ComLockError::InvalidPublicKey)?,
+            }),
+            None => None,
+        };
+        let peer_kem_key = match &self.peer_kem_key {
+            Some(public) => {
+                Some(K::PublicKey::try_from(public).map_err(|_| ComLockError::InvalidPublicKey)?)
+            }
+            None => None,
+        };
+
+        let mut state = KemRatchetState::new_with_mode_and_rng(
+            self.root_key,
+            self.is_initiator,
+            self.mode,
+            rng,
+        )?;
+        state.strict_mode = self.strict_mode;
+        state.allow_classical_peer = self.allow_classical_peer;
+
+        if let Some(our_keypair) = our_keypair {
+            if self.primed {
+                state
+                    .kem_key_cache
+                    .acknowledge(kem_pubkey_reference(our_keypair.public.as_ref()));
+            }
+            if let Some(mut unused) = state.our_kem_keypair.replace(our_keypair) {
+                unused.secret.zeroize();
+            }
+            // The peer of a primed session already has our long-term key
+            state.should_send_kem_pubkey = !self.primed;
+            state.kem_pubkey_advertised = self.primed;
+        }
+        if let Some(peer_kem_key) = peer_kem_key {
+            state.kem_key_cache.remember(&peer_kem_key);
+            state.pending_kem_pubkey = Some(peer_kem_key);
+        }
+        Ok(state)
+    }
+
+    /// Check that the options fit together.
+    fn validate(&self) -> Result<(), ComLockError> {
+        let has_kem_keys = self.kem_keypair.is_some() || self.peer_kem_key.is_some();
+        if self.mode == ProtocolMode::Classical && (self.primed || has_kem_keys) {
+            return Err(ComLockError::InvalidConfiguration(
+                "a classical session takes no KEM keys",
+            ));
+        }
+        if self.primed && self.peer_kem_key.is_none() {
+            return Err(ComLockError::InvalidConfiguration(
+                "a primed session needs the peer's KEM key",
+            ));
+        }
+        if self.primed && self.kem_keypair.is_none() {
+            return Err(ComLockError::InvalidConfiguration(
+                "a primed session needs our KEM keypair",
+            ));
+        }
+        if !self.primed && self.peer_kem_key.is_some() {
+            return Err(ComLockError::InvalidConfiguration(
+                "the peer's KEM key is only used by a primed session",
+            ));
+        }
+        if self.check_root_key && is_weak_root_key(&self.root_key) {
+            return Err(ComLockError::WeakSecret);
+        }
+        Ok(())
+    }
+}
+
+impl<K: Kem> Drop for KemRatchetBuilder<K> {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        if let Some((_, secret)) = self.kem_keypair.as_mut() {
+            secret.zeroize();
+        }
     }
 }
 
@@ -326,10 +2197,30 @@ impl RatchetState {
 mod tests {
     use super::*;
 
+    /// Receive like `decrypt_message` does, with a known message key
+    /// standing in for the AEAD check. Returns whether any attempt matched.
+    fn receive_matching<K: Kem + Clone>(
+        state: &mut KemRatchetState<K>,
+        header: &MessageHeader,
+        message_key: &[u8; 32],
+    ) -> bool {
+        for attempt in state.receive_attempts(header) {
+            let mut next_state = state.clone();
+            if next_state
+                .receive_step_attempt(header, attempt, &mut rand::thread_rng())
+                .is_ok_and(|received| received.message_key == *message_key)
+            {
+                *state = next_state;
+                return true;
+            }
+        }
+        false
+    }
+
     #[test]
     fn test_ratchet_initialization() {
         let root_key = [42u8; 32];
-        let state = RatchetState::new(root_key, true);
+        let state = RatchetState::new(root_key, true).unwrap();
 
         assert_eq!(state.send_count, 0);
         assert_eq!(state.recv_count, 0);
@@ -339,7 +2230,7 @@ mod tests {
     #[test]
     fn test_responder_initialization() {
         let root_key = [42u8; 32];
-        let state = RatchetState::new(root_key, false);
+        let state = RatchetState::new(root_key, false).unwrap();
 
         assert!(state.our_kem_keypair.is_none());
     }
@@ -347,8 +2238,8 @@ mod tests {
     #[test]
     fn test_chain_key_asymmetry() {
         let root_key = [42u8; 32];
-        let alice = RatchetState::new(root_key, true);
-        let bob = RatchetState::new(root_key, false);
+        let alice = RatchetState::new(root_key, true).unwrap();
+        let bob = RatchetState::new(root_key, false).unwrap();
 
         // Alice's send chain should equal Bob's recv chain
         assert_eq!(alice.send_chain_key, bob.recv_chain_key);
@@ -356,6 +2247,941 @@ mod tests {
         assert_eq!(alice.recv_chain_key, bob.send_chain_key);
     }
 
+    #[test]
+    fn test_last_kem_advance_updates_only_on_kem_rounds() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // Alice's first message only carries her KEM public key
+        let first = alice.step(None).unwrap().header;
+        bob.receive_step(&first).unwrap();
+        assert_eq!(alice.status().last_kem_advance_at, 0);
+        assert_eq!(bob.status().last_kem_advance_at, 0);
+
+        // Bob encapsulates on his reply and Alice decapsulates it
+        let reply = bob.step(None).unwrap().header;
+        assert!(reply.kem_ciphertext.is_some());
+        alice.receive_step(&reply).unwrap();
+        assert!(bob.status().last_kem_advance_at > 0);
+        assert!(alice.status().last_kem_advance_at > 0);
+
+        // Plain classical messages leave the timestamp alone
+        alice.last_kem_advance_at = 1;
+        bob.last_kem_advance_at = 1;
+        let plain = bob.step(None).unwrap().header;
+        assert!(plain.kem_ciphertext.is_none());
+        alice.receive_step(&plain).unwrap();
+        assert_eq!(alice.status().last_kem_advance_at, 1);
+        assert_eq!(bob.status().last_kem_advance_at, 1);
+    }
+
+    #[test]
+    fn test_acknowledged_kem_pubkey_sent_by_reference() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // The first advertisement carries the full key
+        let first = alice.step(None).unwrap();
+        let full_key = first.header.kem_pubkey.clone().unwrap();
+        assert!(first.header.kem_pubkey_ref.is_none());
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+
+        // Once a receipt confirms it arrived, re-advertising costs 8 bytes
+        alice.acknowledge_kem_pubkey(kem_pubkey_reference(&full_key));
+        alice.readvertise_kem_pubkey();
+        let second = alice.step(None).unwrap();
+        assert!(second.header.kem_pubkey.is_none());
+        assert_eq!(
+            second.header.kem_pubkey_ref,
+            Some(kem_pubkey_reference(&full_key))
+        );
+        // (plus the capabilities byte of Alice's first chain)
+        assert_eq!(second.header.serialized_size(), 41 + KEM_PUBKEY_REF_LEN + 1);
+
+        bob.pending_kem_pubkey = None;
+        assert!(receive_matching(
+            &mut bob,
+            &second.header,
+            &second.message_key
+        ));
+        assert_eq!(bob.pending_kem_pubkey, alice.our_kem_public_key());
+
+        // Bob encapsulates to the referenced key and Alice opens it
+        let reply = bob.step(None).unwrap();
+        assert!(reply.header.kem_ciphertext.is_some());
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        assert!(alice.status().pq_active);
+    }
+
+    #[test]
+    fn test_stale_kem_pubkey_reference_falls_back_to_full_exchange() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // Alice wrongly believes Bob already has her key
+        let key = alice.our_kem_public_key().unwrap();
+        alice.acknowledge_kem_pubkey(kem_pubkey_reference(&key));
+        let first = alice.step(None).unwrap();
+        assert!(first.header.kem_pubkey.is_none());
+
+        // Bob cannot resolve it, but the message still opens
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+        assert!(bob.pending_kem_pubkey.is_none());
+        assert!(bob.status().kem_resync_pending);
+
+        // Bob sends his own key in full, and the KEM exchange recovers
+        let reply = bob.step(None).unwrap();
+        assert!(reply.header.kem_pubkey.is_some());
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        let next = alice.step(None).unwrap();
+        assert!(next.header.kem_ciphertext.is_some());
+        assert!(receive_matching(&mut bob, &next.header, &next.message_key));
+        assert!(bob.status().pq_active);
+    }
+
+    /// Insecure stand-in KEM with small keys: the secret key is the public
+    /// key, and the shared secret a hash of key and ciphertext.
+    #[derive(Clone)]
+    struct MockKem;
+
+    impl Kem for MockKem {
+        const PUBLIC_KEY_SIZE: usize = 16;
+        const SECRET_KEY_SIZE: usize = 16;
+        const CIPHERTEXT_SIZE: usize = 24;
+
+        type PublicKey = [u8; 16];
+        type SecretKey = [u8; 16];
+        type Ciphertext = [u8; 24];
+
+        fn generate<R: RngCore + CryptoRng>(
+            rng: &mut R,
+        ) -> Result<([u8; 16], [u8; 16]), ComLockError> {
+            let mut key = [0u8; 16];
+            rng.fill_bytes(&mut key);
+            Ok((key, key))
+        }
+
+        fn encapsulate<R: RngCore + CryptoRng>(
+            public_key: &[u8; 16],
+            rng: &mut R,
+        ) -> Result<([u8; 24], [u8; 32]), ComLockError> {
+            let mut ciphertext = [0u8; 24];
+            rng.fill_bytes(&mut ciphertext);
+            Ok((ciphertext, Self::decapsulate(&ciphertext, public_key)?))
+        }
+
+        fn decapsulate(
+            ciphertext: &[u8; 24],
+            secret_key: &[u8; 16],
+        ) -> Result<[u8; 32], ComLockError> {
+            use sha2::Digest;
+            Ok(Sha256::new()
+                .chain_update(secret_key)
+                .chain_update(ciphertext)
+                .finalize()
+                .into())
+        }
+    }
+
+    #[test]
+    fn test_weak_root_key_rejected() {
+        let mut single_bit = [0u8; 32];
+        single_bit[31] = 1;
+
+        for weak in [[0u8; 32], single_bit, [0xff; 32]] {
+            assert!(matches!(
+                RatchetState::new_checked(weak, true),
+                Err(ComLockError::WeakSecret)
+            ));
+        }
+
+        let mut strong = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut strong);
+        assert!(RatchetState::new_checked(strong, true).is_ok());
+    }
+
+    #[test]
+    fn test_mock_kem_headers_parse_with_its_sizes() {
+        let root_key = [42u8; 32];
+        let mut alice = KemRatchetState::<MockKem>::new(root_key, true).unwrap();
+        let mut bob = KemRatchetState::<MockKem>::new(root_key, false).unwrap();
+
+        let first = alice.step(None).unwrap();
+        let wire = first.header.serialize();
+        assert!(MessageHeader::deserialize(&wire).is_err());
+        let parsed = MessageHeader::deserialize_for::<MockKem>(&wire).unwrap();
+        assert_eq!(parsed, first.header);
+        assert!(receive_matching(&mut bob, &parsed, &first.message_key));
+
+        let reply = bob.step(None).unwrap();
+        assert!(reply.header.kem_ciphertext.is_some());
+        let wire = reply.header.encode(crate::HeaderEncoding::Cbor);
+        assert!(MessageHeader::decode_versioned(&wire).is_err());
+        let parsed = MessageHeader::decode_versioned_for::<MockKem>(&wire).unwrap();
+        assert_eq!(parsed, reply.header);
+        assert!(receive_matching(&mut alice, &parsed, &reply.message_key));
+        assert!(alice.status().pq_active);
+    }
+
+    #[test]
+    fn test_ratchet_over_mock_kem() {
+        let root_key = [42u8; 32];
+        let mut alice = KemRatchetState::<MockKem>::new(root_key, true).unwrap();
+        let mut bob = KemRatchetState::<MockKem>::new(root_key, false).unwrap();
+
+        let first = alice.step(None).unwrap();
+        assert_eq!(
+            first.header.kem_pubkey.as_deref(),
+            alice.our_kem_public_key().as_ref().map(|pk| &pk[..])
+        );
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+
+        let reply = bob.step(None).unwrap();
+        let ciphertext = reply.header.kem_ciphertext.as_ref().unwrap();
+        assert_eq!(ciphertext.len(), MockKem::CIPHERTEXT_SIZE);
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        assert!(alice.status().pq_active);
+        assert!(bob.status().pq_active);
+
+        for round in 0..6 {
+            if round % 2 == 0 {
+                alice.trigger_kem_advancement().unwrap();
+            }
+            let out = alice.step(None).unwrap();
+            assert!(receive_matching(&mut bob, &out.header, &out.message_key));
+            let back = bob.step(None).unwrap();
+            assert!(receive_matching(
+                &mut alice,
+                &back.header,
+                &back.message_key
+            ));
+        }
+        assert!(!alice.status().kem_resync_pending);
+        assert!(!bob.status().kem_resync_pending);
+
+        // Keys of the wrong size for the KEM are rejected
+        let mut wrong_size = alice.step(None).unwrap().header;
+        wrong_size.kem_pubkey = Some(vec![0u8; MockKem::PUBLIC_KEY_SIZE + 1]);
+        assert!(matches!(
+            bob.receive_step(&wrong_size),
+            Err(ComLockError::KemSizeMismatch {
+                expected: MockKem::PUBLIC_KEY_SIZE,
+                actual,
+            }) if actual == MockKem::PUBLIC_KEY_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn test_kem_ciphertext_size_mismatch_reported() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+        let reply = bob.step(None).unwrap().header;
+        let ciphertext = reply.kem_ciphertext.clone().unwrap();
+
+        for actual in [KYBER_CIPHERTEXT_SIZE - 1, KYBER_CIPHERTEXT_SIZE + 1] {
+            let mut resized = ciphertext.clone();
+            resized.resize(actual, 0);
+            let mut forged = reply.clone();
+            forged.kem_ciphertext = Some(resized);
+            let mut receiver = alice.clone();
+            assert!(matches!(
+                receiver.receive_step(&forged),
+                Err(ComLockError::KemSizeMismatch { expected: KYBER_CIPHERTEXT_SIZE, actual: got })
+                    if got == actual
+            ));
+        }
+        alice.receive_step(&reply).unwrap();
+    }
+
+    #[test]
+    fn test_kem_pubkey_size_mismatch_reported() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let bob = RatchetState::new(root_key, false).unwrap();
+        let first = alice.step(None).unwrap().header;
+        let pubkey = first.kem_pubkey.clone().unwrap();
+
+        for actual in [KYBER_PUBKEY_SIZE - 1, KYBER_PUBKEY_SIZE + 1] {
+            let mut resized = pubkey.clone();
+            resized.resize(actual, 0);
+            let mut forged = first.clone();
+            forged.kem_pubkey = Some(resized);
+            let mut receiver = bob.clone();
+            assert!(matches!(
+                receiver.receive_step(&forged),
+                Err(ComLockError::KemSizeMismatch { expected: KYBER_PUBKEY_SIZE, actual: got })
+                    if got == actual
+            ));
+        }
+    }
+
+    #[test]
+    fn test_transcript_hash_agrees_and_detects_dropped_message() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        assert_eq!(alice.transcript_hash(), bob.transcript_hash());
+
+        for _ in 0..3 {
+            bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+            alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+        }
+        // Messages crossing in flight are ordered by sender, not arrival
+        let from_alice = alice.step(None).unwrap().header;
+        let from_bob = bob.step(None).unwrap().header;
+        bob.receive_step(&from_alice).unwrap();
+        alice.receive_step(&from_bob).unwrap();
+        assert_eq!(alice.transcript_hash(), bob.transcript_hash());
+
+        // Bob never sees the first of these
+        let _dropped = alice.step(None).unwrap();
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+        assert_ne!(alice.transcript_hash(), bob.transcript_hash());
+    }
+
+    #[test]
+    fn test_sending_chain_rotates_after_receiving() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        let first = alice.step(None).unwrap().header;
+        let second = alice.step(None).unwrap().header;
+        assert_eq!(first.classical_pubkey, second.classical_pubkey);
+        assert_eq!(second.previous_chain_length, 0);
+
+        bob.receive_step(&first).unwrap();
+        bob.receive_step(&second).unwrap();
+        let reply = bob.step(None).unwrap().header;
+        alice.receive_step(&reply).unwrap();
+
+        // Alice's next send starts a new chain ending the old one at 2
+        let third = alice.step(None).unwrap().header;
+        assert_ne!(third.classical_pubkey, first.classical_pubkey);
+        assert_eq!(third.message_number, 2);
+        assert_eq!(third.previous_chain_length, 2);
+    }
+
+    #[test]
+    fn test_previous_chain_length_tracks_sends_not_receives() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+        for _ in 0..3 {
+            alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+        }
+
+        // Alice has received three messages but sent only one before rotating
+        let rotated = alice.step(None).unwrap().header;
+        assert_eq!(rotated.previous_chain_length, 1);
+        let same_chain = alice.step(None).unwrap().header;
+        assert_eq!(same_chain.previous_chain_length, 1);
+        bob.receive_step(&rotated).unwrap();
+        bob.receive_step(&same_chain).unwrap();
+
+        // The next rotation records where the second chain ended
+        alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+        let rotated_again = alice.step(None).unwrap().header;
+        assert_eq!(rotated_again.message_number, 3);
+        assert_eq!(rotated_again.previous_chain_length, 3);
+        assert_ne!(rotated_again.classical_pubkey, rotated.classical_pubkey);
+        bob.receive_step(&rotated_again).unwrap();
+    }
+
+    #[test]
+    fn test_same_chain_key_reuse_accepted_in_strict_mode() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        bob.set_strict_mode(true);
+
+        let headers: Vec<_> = (0..3).map(|_| alice.step(None).unwrap().header).collect();
+        assert!(
+            headers
+                .iter()
+                .all(|h| h.classical_pubkey == headers[0].classical_pubkey)
+        );
+        // Out of order on the same chain is fine too
+        for index in [0, 2, 1] {
+            bob.receive_step(&headers[index]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cross_chain_key_reuse_rejected_in_strict_mode() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        let old_chain = alice.step(None).unwrap().header;
+        bob.receive_step(&old_chain).unwrap();
+        alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+        let new_chain = alice.step(None).unwrap().header;
+        assert_ne!(new_chain.classical_pubkey, old_chain.classical_pubkey);
+        bob.receive_step(&new_chain).unwrap();
+
+        // A header bringing back the retired key as if it were a new chain
+        let mut reused = old_chain.clone();
+        reused.message_number = 5;
+        reused.previous_chain_length = 2;
+
+        let mut lenient = bob.clone();
+        assert!(!lenient.strict_mode());
+        assert!(lenient.receive_step(&reused).is_ok());
+
+        bob.set_strict_mode(true);
+        assert!(matches!(
+            bob.receive_step(&reused),
+            Err(ComLockError::InvalidHeader)
+        ));
+
+        // The current chain carries on
+        let next = alice.step(None).unwrap().header;
+        bob.receive_step(&next).unwrap();
+
+        // Strict mode survives a rekey
+        bob.rekey([43u8; 32]).unwrap();
+        assert!(bob.strict_mode());
+    }
+
+    #[test]
+    fn test_builder_default_matches_new() {
+        let root_key = [42u8; 32];
+        let built = RatchetBuilder::new(root_key, true).build().unwrap();
+        let plain = RatchetState::new(root_key, true).unwrap();
+
+        assert_eq!(built.mode, plain.mode);
+        assert_eq!(built.strict_mode(), plain.strict_mode());
+        assert_eq!(
+            built.our_kem_keypair.is_some(),
+            plain.our_kem_keypair.is_some()
+        );
+        assert_eq!(built.should_send_kem_pubkey, plain.should_send_kem_pubkey);
+        assert!(built.pending_kem_pubkey.is_none());
+
+        // Same chains, so the two can talk to a responder either way
+        let mut alice = built;
+        let mut bob = RatchetState::builder(root_key, false).build().unwrap();
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+    }
+
+    #[test]
+    fn test_builder_fully_configured() {
+        let root_key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let mut rng = rand::thread_rng();
+        let (alice_ek, alice_dk) = Kyber1024::generate(&mut rng).unwrap();
+        let (bob_ek, bob_dk) = Kyber1024::generate(&mut rng).unwrap();
+
+        let alice = RatchetBuilder::new(root_key, true)
+            .mode(ProtocolMode::Hybrid)
+            .check_root_key(true)
+            .strict_mode(true)
+            .kem_keypair(&alice_ek, &alice_dk)
+            .peer_kem_key(&bob_ek)
+            .primed(true)
+            .build_with_rng(&mut rng)
+            .unwrap();
+        assert!(alice.strict_mode());
+        assert_eq!(alice.our_kem_public_key(), Some(alice_ek));
+        assert_eq!(alice.pending_kem_pubkey, Some(bob_ek));
+        assert!(!alice.should_send_kem_pubkey);
+
+        // An unprimed responder advertises the keypair it was given
+        let bob = RatchetBuilder::new(root_key, false)
+            .kem_keypair(&bob_ek, &bob_dk)
+            .build()
+            .unwrap();
+        assert_eq!(bob.our_kem_public_key(), Some(bob_ek));
+        assert!(bob.should_send_kem_pubkey);
+        assert!(bob.pending_kem_pubkey.is_none());
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_combinations() {
+        let root_key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let (ek, dk) = Kyber1024::generate(&mut rand::thread_rng()).unwrap();
+        let builder = || RatchetBuilder::new(root_key, true);
+        let invalid = |result: Result<RatchetState, ComLockError>| {
+            matches!(result, Err(ComLockError::InvalidConfiguration(_)))
+        };
+
+        assert!(invalid(
+            builder().kem_keypair(&ek, &dk).primed(true).build()
+        ));
+        assert!(invalid(builder().peer_kem_key(&ek).primed(true).build()));
+        assert!(invalid(builder().peer_kem_key(&ek).build()));
+        assert!(invalid(
+            builder()
+                .mode(ProtocolMode::Classical)
+                .kem_keypair(&ek, &dk)
+                .build()
+        ));
+        assert!(invalid(
+            builder()
+                .mode(ProtocolMode::Classical)
+                .kem_keypair(&ek, &dk)
+                .peer_kem_key(&ek)
+                .primed(true)
+                .build()
+        ));
+
+        assert!(matches!(
+            RatchetBuilder::new([0u8; 32], true)
+                .check_root_key(true)
+                .build(),
+            Err(ComLockError::WeakSecret)
+        ));
+        assert!(RatchetBuilder::new([0u8; 32], true).build().is_ok());
+        assert!(matches!(
+            builder().kem_keypair(&ek[..10], &dk).build(),
+            Err(ComLockError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_dh_ratchet_on_each_direction_change() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        let mut seen_keys = Vec::new();
+
+        for round in 0..4 {
+            let (sender, receiver) = if round % 2 == 0 {
+                (&mut alice, &mut bob)
+            } else {
+                (&mut bob, &mut alice)
+            };
+
+            // A copy of the receiver that knows its chain keys but not its
+            // ephemeral secrets, as after a chain key compromise
+            let mut attacker = receiver.clone();
+            attacker.our_ephemeral_secret = StaticSecret::random_from_rng(rand::thread_rng());
+            attacker.previous_ephemeral_secret = None;
+
+            for _ in 0..2 {
+                let output = sender.step(None).unwrap();
+                assert!(receive_matching(
+                    receiver,
+                    &output.header,
+                    &output.message_key
+                ));
+                assert!(!seen_keys.contains(&output.message_key));
+                seen_keys.push(output.message_key);
+
+                // Once both initial chains are used, every new chain is
+                // keyed by a DH step, so the stolen chain keys are useless
+                if round > 1 {
+                    assert!(!receive_matching(
+                        &mut attacker,
+                        &output.header,
+                        &output.message_key
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_rotation_uses_previous_key() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // Both sides send, then receive, then rotate at the same time
+        let a0 = alice.step(None).unwrap();
+        let b0 = bob.step(None).unwrap();
+        alice.receive_step(&b0.header).unwrap();
+        bob.receive_step(&a0.header).unwrap();
+        let a1 = alice.step(None).unwrap();
+        let b1 = bob.step(None).unwrap();
+        let b2 = bob.step(None).unwrap();
+
+        // Each new chain was keyed against the other side's old key
+        let previous = ReceiveAttempt {
+            previous_ephemeral: true,
+            ..Default::default()
+        };
+        assert!(alice.receive_attempts(&b1.header).contains(&previous));
+        let mut wrong = alice.clone();
+        assert_ne!(
+            wrong.receive_step(&b1.header).unwrap().message_key,
+            b1.message_key
+        );
+        let received = alice
+            .receive_step_attempt(&b1.header, previous, &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(received.message_key, b1.message_key);
+        assert_eq!(
+            alice.receive_step(&b2.header).unwrap().message_key,
+            b2.message_key
+        );
+        let received = bob
+            .receive_step_attempt(&a1.header, previous, &mut rand::thread_rng())
+            .unwrap();
+        assert_eq!(received.message_key, a1.message_key);
+
+        // Bob's next chain is keyed against Alice's current key again
+        let b3 = bob.step(None).unwrap();
+        assert_ne!(b3.header.classical_pubkey, b1.header.classical_pubkey);
+        assert_eq!(
+            alice.receive_step(&b3.header).unwrap().message_key,
+            b3.message_key
+        );
+    }
+
+    #[test]
+    fn test_initiator_kem_pubkey_answered_by_responder() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // A trigger before Alice's first message replaces the key she was
+        // about to send; the old one never left, so it is not kept
+        alice.trigger_kem_advancement().unwrap();
+        assert!(alice.previous_kem_keypairs.is_empty());
+        let first = alice.step(None).unwrap();
+        assert_eq!(
+            first.header.kem_pubkey.as_deref(),
+            alice.our_kem_public_key().as_ref().map(|pk| pk.as_slice())
+        );
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+
+        // Bob encapsulates to Alice's key on his reply, consuming it
+        let reply = bob.step(None).unwrap();
+        assert!(reply.header.kem_ciphertext.is_some());
+        assert!(bob.pending_kem_pubkey.is_none());
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        assert_ne!(alice.last_kem_secret, [0u8; 32]);
+        assert_eq!(alice.last_kem_secret, bob.last_kem_secret);
+
+        // Alice's next chain is keyed with the secret, and Bob follows
+        let next = alice.step(None).unwrap();
+        assert!(receive_matching(&mut bob, &next.header, &next.message_key));
+        assert_eq!(alice.last_kem_secret, bob.last_kem_secret);
+    }
+
+    #[test]
+    fn test_readvertised_kem_pubkey_survives_trigger() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        let first = alice.step(None).unwrap();
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+
+        // Alice queues her key again, then rotates before Bob's reply: the
+        // key Bob already has must stay around for his ciphertext
+        alice.readvertise_kem_pubkey();
+        alice.trigger_kem_advancement().unwrap();
+        assert_eq!(alice.previous_kem_keypairs.len(), 1);
+
+        let reply = bob.step(None).unwrap();
+        assert!(reply.header.kem_ciphertext.is_some());
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        assert!(!alice.status().kem_resync_pending);
+        assert_eq!(alice.last_kem_secret, bob.last_kem_secret);
+    }
+
+    #[test]
+    fn test_kem_ciphertext_names_one_keypair() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        // Bob holds Alice's first key; she has rotated twice since
+        let first = alice.step(None).unwrap();
+        let first_key = alice.our_kem_public_key().unwrap();
+        assert!(receive_matching(
+            &mut bob,
+            &first.header,
+            &first.message_key
+        ));
+        alice.readvertise_kem_pubkey();
+        alice.trigger_kem_advancement().unwrap();
+        alice.step(None).unwrap();
+        alice.trigger_kem_advancement().unwrap();
+        assert_eq!(alice.previous_kem_keypairs.len(), 2);
+
+        // The reply names the key it used, and only that keypair is tried
+        let reply = bob.step(None).unwrap();
+        assert_eq!(
+            reply.header.kem_ciphertext_ref,
+            Some(kem_pubkey_reference(&first_key))
+        );
+        let attempts = alice.receive_attempts(&reply.header);
+        assert!(
+            attempts
+                .iter()
+                .all(|attempt| attempt.previous_kem_keypair == Some(0))
+        );
+        assert!(attempts.len() <= 2 * (MAX_KEM_CANDIDATES + 1));
+
+        // Once used, that key and any older one are wiped; the newer one the
+        // remote may still use is kept
+        assert!(receive_matching(
+            &mut alice,
+            &reply.header,
+            &reply.message_key
+        ));
+        assert_eq!(alice.previous_kem_keypairs.len(), 1);
+        assert_ne!(
+            alice.previous_kem_keypairs[0].public.as_ref(),
+            first_key.as_slice()
+        );
+    }
+
+    #[test]
+    fn test_far_future_message_number_rejected() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        let mut header = alice.step(None).unwrap().header;
+        header.message_number = u32::MAX;
+        assert!(matches!(
+            bob.receive_step(&header),
+            Err(ComLockError::TooManySkippedMessages)
+        ));
+        assert_eq!(bob.recv_count, 0);
+        assert_eq!(bob.skipped_key_count(), 0);
+
+        // A gap of exactly MAX_SKIP is still accepted
+        header.message_number = MAX_SKIP;
+        assert!(bob.receive_step(&header).is_ok());
+        assert_eq!(bob.recv_count, MAX_SKIP + 1);
+    }
+
+    #[test]
+    fn test_far_future_previous_chain_length_rejected() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+        alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+
+        // Alice's next message starts a new chain
+        let mut header = alice.step(None).unwrap().header;
+        header.previous_chain_length = u32::MAX;
+        assert!(matches!(
+            bob.receive_step(&header),
+            Err(ComLockError::TooManySkippedMessages)
+        ));
+        assert_eq!(bob.skipped_key_count(), 0);
+    }
+
+    #[test]
+    fn test_fast_forward_is_capped() {
+        let mut bob = RatchetState::new([42u8; 32], false).unwrap();
+        assert!(matches!(
+            bob.fast_forward_recv(MAX_FAST_FORWARD + 1),
+            Err(ComLockError::TooManySkippedMessages)
+        ));
+        assert_eq!(bob.recv_count, 0);
+
+        bob.fast_forward_recv(10).unwrap();
+        bob.fast_forward_recv(5).unwrap();
+        assert_eq!(bob.recv_count, 10);
+        assert_eq!(bob.skipped_key_count(), 0);
+    }
+
+    #[test]
+    fn test_resync_header_for_other_chain_rejected() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+
+        let mut resync = alice.resync_header();
+        resync.classical_pubkey = [0x11; 32];
+        assert!(matches!(
+            bob.apply_resync(&resync),
+            Err(ComLockError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn test_forged_resync_header_rejected() {
+        let root_key = [42u8; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        bob.receive_step(&alice.step(None).unwrap().header).unwrap();
+
+        // A counter changed in transit
+        let mut resync = alice.resync_header();
+        resync.message_number += 500;
+        assert!(matches!(
+            bob.apply_resync(&resync),
+            Err(ComLockError::DecryptionFailed)
+        ));
+
+        // A genuine marker more than MAX_SKIP ahead is refused
+        for _ in 0..MAX_SKIP + 1 {
+            alice.step(None).unwrap();
+        }
+        assert!(matches!(
+            bob.apply_resync(&alice.resync_header()),
+            Err(ComLockError::TooManySkippedMessages)
+        ));
+        assert_eq!(bob.recv_count, 1);
+    }
+
+    #[test]
+    fn test_replay_window_tracks_seen_numbers() {
+        let mut window = ReplayWindow::default();
+        assert!(window.check(5).is_ok());
+
+        window.mark(5);
+        window.mark(3);
+        assert!(matches!(
+            window.check(5),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(matches!(
+            window.check(3),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(window.check(4).is_ok());
+        assert!(window.check(6).is_ok());
+
+        // Sliding forward keeps earlier marks
+        window.mark(70);
+        assert!(matches!(
+            window.check(70),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(window.check(7).is_ok());
+        assert!(matches!(
+            window.check(6),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(matches!(
+            window.check(5),
+            Err(ComLockError::ReplayedMessage)
+        ));
+        assert!(window.is_below(6));
+        assert!(!window.is_below(7));
+    }
+
+    /// An RNG that always fails, like an unavailable OS RNG.
+    struct FailingRng;
+
+    impl RngCore for FailingRng {
+        fn next_u32(&mut self) -> u32 {
+            panic!("infallible call on a failing RNG")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            panic!("infallible call on a failing RNG")
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            panic!("infallible call on a failing RNG")
+        }
+
+        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand::Error> {
+            Err(rand::Error::new(std::io::Error::other("RNG unavailable")))
+        }
+    }
+
+    impl CryptoRng for FailingRng {}
+
+    #[test]
+    fn test_rng_failure_is_an_error() {
+        let root_key = [42u8; 32];
+        assert!(matches!(
+            RatchetState::new_with_rng(root_key, true, &mut FailingRng),
+            Err(ComLockError::RngFailure)
+        ));
+        assert!(matches!(
+            RatchetBuilder::new(root_key, false).build_with_rng(&mut FailingRng),
+            Err(ComLockError::RngFailure)
+        ));
+
+        // Alice must start a new sending chain, which needs a new key
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+        let first = alice.step(None).unwrap().header;
+        bob.receive_step(&first).unwrap();
+        alice.receive_step(&bob.step(None).unwrap().header).unwrap();
+
+        let kem_key = alice.our_kem_public_key();
+        assert!(matches!(
+            alice.step_with_rng(None, &mut FailingRng),
+            Err(ComLockError::RngFailure)
+        ));
+        assert!(matches!(
+            alice.rotate_kem_keypair(&mut FailingRng),
+            Err(ComLockError::RngFailure)
+        ));
+        assert_eq!(alice.our_kem_public_key(), kem_key);
+
+        // Nothing was lost: the session carries on once the RNG is back
+        let header = alice.step(None).unwrap().header;
+        assert_ne!(header.classical_pubkey, first.classical_pubkey);
+        bob.receive_step(&header).unwrap();
+    }
+
+    #[test]
+    fn test_zeroize_wipes_secrets() {
+        let mut state = RatchetState::new([42u8; 32], true).unwrap();
+        state.zeroize();
+
+        assert_eq!(state.root_key, [0u8; 32]);
+        assert_eq!(state.send_chain_key, [0u8; 32]);
+        assert_eq!(state.recv_chain_key, [0u8; 32]);
+        assert_eq!(state.our_ephemeral_secret.to_bytes(), [0u8; 32]);
+        assert!(state.previous_ephemeral_secret.is_none());
+        assert!(state.our_kem_keypair.is_none());
+    }
+
     #[test]
     fn test_kdf_determinism() {
         let key = [1u8; 32];
@@ -366,6 +3192,74 @@ mod tests {
         assert_eq!(k2a, k2b);
     }
 
+    #[test]
+    fn test_send_and_receive_labels_are_separated() {
+        let chain_key = [7u8; 32];
+        let kem_secret = [0u8; 32];
+
+        for is_initiator in [true, false] {
+            let state = RatchetState::new([42u8; 32], is_initiator).unwrap();
+            let (send_key, send_next) =
+                RatchetState::message_kdf(&chain_key, state.send_label(), 0, &kem_secret);
+            let (recv_key, recv_next) =
+                RatchetState::message_kdf(&chain_key, state.recv_label(), 0, &kem_secret);
+
+            assert_ne!(send_key, recv_key);
+            assert_ne!(send_next, recv_next);
+        }
+
+        // The two sides agree on each chain's label
+        let alice = RatchetState::new([42u8; 32], true).unwrap();
+        let bob = RatchetState::new([42u8; 32], false).unwrap();
+        assert_eq!(alice.send_label(), bob.recv_label());
+        assert_eq!(alice.recv_label(), bob.send_label());
+    }
+
+    #[test]
+    fn test_kdf_info_includes_protocol_version() {
+        let key = [1u8; 32];
+        let (versioned, _) = RatchetState::kdf_derive(&key, b"test", &[0u8; 32]);
+
+        let hk = Hkdf::<Sha256>::new(Some(&key), &[0u8; 32]);
+        let mut unversioned = [0u8; 64];
+        hk.expand(b"test", &mut unversioned).unwrap();
+
+        assert_ne!(versioned[..], unversioned[..32]);
+    }
+
+    #[test]
+    fn test_chain_key_independent_of_message_key() {
+        let chain_key = [7u8; 32];
+        let kem_secret = [0u8; 32];
+        let (message_key, next_chain_key) =
+            RatchetState::message_kdf(&chain_key, LABEL_MSG_INITIATOR, 3, &kem_secret);
+
+        assert_ne!(message_key, next_chain_key);
+
+        // Running the derivation from the message key, as an attacker
+        // holding only that key could, yields neither the next chain key
+        // nor the message key that follows it
+        let (next_message_key, _) =
+            RatchetState::message_kdf(&next_chain_key, LABEL_MSG_INITIATOR, 4, &kem_secret);
+        for n in [3, 4] {
+            let (guess_key, guess_chain) =
+                RatchetState::message_kdf(&message_key, LABEL_MSG_INITIATOR, n, &kem_secret);
+            assert_ne!(guess_chain, next_chain_key);
+            assert_ne!(guess_key, next_message_key);
+        }
+
+        // Each key is a separate expansion under its own purpose
+        let ikm = [&3u32.to_le_bytes()[..], &kem_secret].concat();
+        assert_eq!(
+            RatchetState::kdf_expand(&chain_key, LABEL_MSG_INITIATOR, PURPOSE_MESSAGE_KEY, &ikm),
+            message_key
+        );
+        assert_eq!(
+            RatchetState::kdf_expand(&chain_key, LABEL_MSG_INITIATOR, PURPOSE_CHAIN_KEY, &ikm),
+            next_chain_key
+        );
+    }
+
     #[test]
     fn test_kdf_different_inputs() {
         let key = [1u8; 32];
@@ -374,4 +3268,23 @@ mod tests {
 
         assert_ne!(k1a, k1b);
     }
+
+    #[test]
+    fn test_kem_message_key_binds_ciphertext_and_pubkey() {
+        let (message_key, secret) = ([1u8; 32], [2u8; 32]);
+        let key = RatchetState::kem_message_key(&message_key, &secret, b"ct", b"pk");
+
+        assert_eq!(
+            key,
+            RatchetState::kem_message_key(&message_key, &secret, b"ct", b"pk")
+        );
+        assert_ne!(
+            key,
+            RatchetState::kem_message_key(&message_key, &secret, b"cu", b"pk")
+        );
+        assert_ne!(
+            key,
+            RatchetState::kem_message_key(&message_key, &secret, b"ct", b"pl")
+        );
+    }
 }
@@ -0,0 +1,235 @@
+//! # ComLock Crypto - Self-Test Module
+//!
+//! A startup health check of the primitives this crate builds on, so a
+//! client can refuse to run on a broken build or platform instead of
+//! trusting it with messages. [`self_test`] runs, in order:
+//!
+//! - an AES-256-GCM-SIV known-answer test (RFC 8452, Appendix C.2)
+//! - an HKDF-SHA256 known-answer test (RFC 5869, test case 1)
+//! - a Kyber-1024 encapsulate/decapsulate round trip
+//! - a [`MessageHeader`] serialize/deserialize round trip
+//! - an [`encrypt_message`]/[`decrypt_message`] round trip between two
+//!   fresh ratchets
+//!
+//! and stops at the first failure with [`ComLockError::SelfTestFailed`].
+//!
+//! With the `fault-injection` feature (and in this crate's own tests),
+//! [`inject_fault`] corrupts one check's output on the current thread, to
+//! show the check actually catches a broken primitive.
+
+use aes_gcm_siv::{
+    Aes256GcmSiv, Nonce,
+    aead::{Aead, KeyInit},
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::header::MessageHeader;
+use crate::kem::{Kem, Kyber1024};
+use crate::ratchet::RatchetState;
+use crate::{ComLockError, Result, decrypt_message, encrypt_message};
+
+/// RFC 8452 C.2: key `01 00..00`, nonce `03 00..00`, plaintext `01 00..00`
+/// (8 bytes), no associated data.
+const AEAD_KAT_CIPHERTEXT: [u8; 24] = [
+    0xc2, 0xef, 0x32, 0x8e, 0x5c, 0x71, 0xc8, 0x3b, 0x84, 0x31, 0x22, 0x13, 0x0f, 0x73, 0x64, 0xb7,
+    0x61, 0xe0, 0xb9, 0x74, 0x27, 0xe3, 0xdf, 0x28,
+];
+
+/// RFC 5869 test case 1 output keying material (42 bytes).
+const HKDF_KAT_OKM: [u8; 42] = [
+    0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36, 0x2f, 0x2a,
+    0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56, 0xec, 0xc4, 0xc5, 0xbf,
+    0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+];
+
+/// A primitive check that [`inject_fault`] can break.
+#[cfg(any(test, feature = "fault-injection"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Corrupt the AEAD known-answer ciphertext
+    Aead,
+    /// Corrupt the HKDF known-answer output
+    Hkdf,
+    /// Corrupt the decapsulated KEM secret
+    Kem,
+    /// Corrupt the serialized header
+    Header,
+    /// Corrupt the encrypted message
+    Message,
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+thread_local! {
+    static FAULT: std::cell::Cell<Option<Fault>> = const { std::cell::Cell::new(None) };
+}
+
+/// Make [`self_test`] see a broken primitive on this thread, or clear the
+/// fault with `None`.
+#[cfg(any(test, feature = "fault-injection"))]
+pub fn inject_fault(fault: Option<Fault>) {
+    FAULT.with(|active| active.set(fault));
+}
+
+/// Flip a bit of `bytes` if `fault` is the injected one.
+#[cfg(any(test, feature = "fault-injection"))]
+fn apply_fault(fault: Fault, bytes: &mut [u8]) {
+    if FAULT.with(|active| active.get()) == Some(fault)
+        && let Some(byte) = bytes.first_mut()
+    {
+        *byte ^= 1;
+    }
+}
+
+/// Without fault injection, the checks see the primitives' real output.
+#[cfg(not(any(test, feature = "fault-injection")))]
+#[derive(Clone, Copy)]
+enum Fault {
+    Aead,
+    Hkdf,
+    Kem,
+    Header,
+    Message,
+}
+
+#[cfg(not(any(test, feature = "fault-injection")))]
+#[inline(always)]
+fn apply_fault(_fault: Fault, _bytes: &mut [u8]) {}
+
+/// Run every check, returning the first failure.
+///
+/// # Errors
+/// [`ComLockError::SelfTestFailed`] naming the check that failed.
+pub fn self_test() -> Result<()> {
+    check_aead()?;
+    check_hkdf()?;
+    check_kem()?;
+    check_header()?;
+    check_message_round_trip()
+}
+
+/// AES-256-GCM-SIV known-answer test, in both directions.
+fn check_aead() -> Result<()> {
+    const FAILED: ComLockError = ComLockError::SelfTestFailed("AES-256-GCM-SIV known answer");
+
+    let mut key = [0u8; 32];
+    key[0] = 1;
+    let mut nonce = [0u8; 12];
+    nonce[0] = 3;
+    let mut plaintext = [0u8; 8];
+    plaintext[0] = 1;
+
+    let cipher = Aes256GcmSiv::new_from_slice(&key).map_err(|_| FAILED)?;
+    let mut ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), &plaintext[..])
+        .map_err(|_| FAILED)?;
+    apply_fault(Fault::Aead, &mut ciphertext);
+
+    if ciphertext != AEAD_KAT_CIPHERTEXT {
+        return Err(FAILED);
+    }
+    match cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice()) {
+        Ok(decrypted) if decrypted == plaintext => Ok(()),
+        _ => Err(FAILED),
+    }
+}
+
+/// HKDF-SHA256 known-answer test.
+fn check_hkdf() -> Result<()> {
+    const FAILED: ComLockError = ComLockError::SelfTestFailed("HKDF-SHA256 known answer");
+
+    let salt: Vec<u8> = (0x00..=0x0c).collect();
+    let info: Vec<u8> = (0xf0..=0xf9).collect();
+    let mut okm = [0u8; 42];
+    Hkdf::<Sha256>::new(Some(&salt), &[0x0b; 22])
+        .expand(&info, &mut okm)
+        .map_err(|_| FAILED)?;
+    apply_fault(Fault::Hkdf, &mut okm);
+
+    if okm == HKDF_KAT_OKM {
+        Ok(())
+    } else {
+        Err(FAILED)
+    }
+}
+
+/// Kyber-1024 round trip: both sides must agree on the shared secret.
+fn check_kem() -> Result<()> {
+    const FAILED: ComLockError = ComLockError::SelfTestFailed("Kyber-1024 round trip");
+
+    let mut rng = rand::thread_rng();
+    let (public_key, secret_key) = Kyber1024::generate(&mut rng).map_err(|_| FAILED)?;
+    let (ciphertext, sent) = Kyber1024::encapsulate(&public_key, &mut rng).map_err(|_| FAILED)?;
+    let mut received = Kyber1024::decapsulate(&ciphertext, &secret_key).map_err(|_| FAILED)?;
+    apply_fault(Fault::Kem, &mut received);
+
+    if sent == received && sent != [0u8; 32] {
+        Ok(())
+    } else {
+        Err(FAILED)
+    }
+}
+
+/// Header round trip through the binary encoding.
+fn check_header() -> Result<()> {
+    const FAILED: ComLockError = ComLockError::SelfTestFailed("message header round trip");
+
+    let header = MessageHeader::new([0x42; 32], None, None, 7, 3);
+    let mut bytes = header.serialize();
+    apply_fault(Fault::Header, &mut bytes);
+
+    match MessageHeader::deserialize(&bytes) {
+        Ok(decoded) if decoded == header => Ok(()),
+        _ => Err(FAILED),
+    }
+}
+
+/// Message round trip between two fresh ratchets.
+fn check_message_round_trip() -> Result<()> {
+    const FAILED: ComLockError = ComLockError::SelfTestFailed("message round trip");
+    const MESSAGE: &[u8] = b"ComLock self-test";
+
+    let root_key: [u8; 32] = std::array::from_fn(|i| i as u8);
+    let mut sender = RatchetState::new(root_key, true)?;
+    let mut receiver = RatchetState::new(root_key, false)?;
+
+    let mut ciphertext = encrypt_message(MESSAGE, &mut sender).map_err(|_| FAILED)?;
+    let last = ciphertext.len() - 1;
+    apply_fault(Fault::Message, &mut ciphertext[last..]);
+
+    match decrypt_message(&ciphertext, &mut receiver) {
+        Ok(plaintext) if plaintext == MESSAGE => Ok(()),
+        _ => Err(FAILED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        inject_fault(None);
+        assert!(self_test().is_ok());
+    }
+
+    #[test]
+    fn test_self_test_detects_each_broken_primitive() {
+        for fault in [
+            Fault::Aead,
+            Fault::Hkdf,
+            Fault::Kem,
+            Fault::Header,
+            Fault::Message,
+        ] {
+            inject_fault(Some(fault));
+            assert!(
+                matches!(self_test(), Err(ComLockError::SelfTestFailed(_))),
+                "{:?} not detected",
+                fault
+            );
+        }
+        inject_fault(None);
+        assert!(self_test().is_ok());
+    }
+}
@@ -0,0 +1,202 @@
+//! Property tests: Alice and Bob stay in sync under any interleaving of
+//! sends, in-order deliveries and KEM triggers.
+//!
+//! [`verify_conversation`] replays a sequence of [`Op`]s against two
+//! ratchets and checks every message decrypts to what was sent. Messages
+//! are queued per direction and delivered in order; anything still queued
+//! at the end is delivered last. Each message carries its own index, so a
+//! failure names the exact message.
+//!
+//! Sequences are kept short enough that neither side gets more than
+//! [`MAX_PREVIOUS_KEM_KEYPAIRS`](crate::ratchet::MAX_PREVIOUS_KEM_KEYPAIRS)
+//! KEM exchanges ahead of the other; beyond that a message may be lost and
+//! the ratchet resyncs its KEM keys by design.
+
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+use crate::{RatchetState, decrypt_message_with_rng, encrypt_message_with_rng};
+
+/// One step of a conversation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// Alice encrypts a message to Bob
+    AliceSend,
+    /// Bob encrypts a message to Alice
+    BobSend,
+    /// Alice decrypts the oldest message from Bob, if any
+    AliceReceive,
+    /// Bob decrypts the oldest message from Alice, if any
+    BobReceive,
+    /// Alice rotates her KEM keypair
+    AliceTriggerKem,
+    /// Bob rotates his KEM keypair
+    BobTriggerKem,
+}
+
+fn op_strategy() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => Just(Op::AliceSend),
+        3 => Just(Op::BobSend),
+        3 => Just(Op::AliceReceive),
+        3 => Just(Op::BobReceive),
+        1 => Just(Op::AliceTriggerKem),
+        1 => Just(Op::BobTriggerKem),
+    ]
+}
+
+/// One side of the conversation and the messages in flight to it.
+struct Party {
+    state: RatchetState,
+    inbox: VecDeque<(Vec<u8>, Vec<u8>)>,
+    name: &'static str,
+}
+
+impl Party {
+    fn receive(&mut self, rng: &mut ChaCha20Rng) -> Result<(), String> {
+        let Some((expected, ciphertext)) = self.inbox.pop_front() else {
+            return Ok(());
+        };
+        let plaintext =
+            decrypt_message_with_rng(&ciphertext, &mut self.state, rng).map_err(|e| {
+                format!(
+                    "{} failed to decrypt {:?}: {e}",
+                    self.name,
+                    String::from_utf8_lossy(&expected)
+                )
+            })?;
+        if plaintext != expected {
+            return Err(format!("{} decrypted the wrong plaintext", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// Run `ops` between a fresh Alice and Bob, returning the first message
+/// that fails to decrypt to what was sent.
+fn verify_conversation(seed: u64, ops: &[Op]) -> Result<(), String> {
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+    let root_key = [7u8; 32];
+    let mut alice = Party {
+        state: RatchetState::new_with_rng(root_key, true, &mut rng)
+            .map_err(|e| format!("Alice failed to start: {e}"))?,
+        inbox: VecDeque::new(),
+        name: "Alice",
+    };
+    let mut bob = Party {
+        state: RatchetState::new_with_rng(root_key, false, &mut rng)
+            .map_err(|e| format!("Bob failed to start: {e}"))?,
+        inbox: VecDeque::new(),
+        name: "Bob",
+    };
+
+    for (index, op) in ops.iter().enumerate() {
+        match op {
+            Op::AliceSend | Op::BobSend => {
+                let (sender, receiver) = if *op == Op::AliceSend {
+                    (&mut alice, &mut bob)
+                } else {
+                    (&mut bob, &mut alice)
+                };
+                let plaintext = format!("{} #{index}", sender.name).into_bytes();
+                let ciphertext = encrypt_message_with_rng(&plaintext, &mut sender.state, &mut rng)
+                    .map_err(|e| format!("{} failed to encrypt: {e}", sender.name))?;
+                receiver.inbox.push_back((plaintext, ciphertext));
+            }
+            Op::AliceReceive => alice.receive(&mut rng)?,
+            Op::BobReceive => bob.receive(&mut rng)?,
+            Op::AliceTriggerKem | Op::BobTriggerKem => {
+                let party = if *op == Op::AliceTriggerKem {
+                    &mut alice
+                } else {
+                    &mut bob
+                };
+                party
+                    .state
+                    .trigger_kem_advancement()
+                    .map_err(|e| format!("{} failed to advance the KEM: {e}", party.name))?;
+            }
+        }
+    }
+
+    while !alice.inbox.is_empty() || !bob.inbox.is_empty() {
+        alice.receive(&mut rng)?;
+        bob.receive(&mut rng)?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn prop_parties_stay_in_sync(
+        seed in any::<u64>(),
+        ops in prop::collection::vec(op_strategy(), 0..60),
+    ) {
+        if let Err(failure) = verify_conversation(seed, &ops) {
+            return Err(TestCaseError::fail(failure));
+        }
+    }
+}
+
+// Sequences the property test found, shrunk
+
+#[test]
+fn test_unadvertised_kem_key_does_not_evict_advertised_one() {
+    use Op::*;
+
+    let ops = [
+        AliceSend,
+        AliceTriggerKem,
+        AliceTriggerKem,
+        BobReceive,
+        BobSend,
+    ];
+    verify_conversation(0, &ops).unwrap();
+}
+
+#[test]
+fn test_second_encapsulation_keeps_first_candidate() {
+    use Op::*;
+
+    let ops = [
+        AliceSend,
+        AliceSend,
+        AliceSend,
+        BobReceive,
+        AliceTriggerKem,
+        AliceSend,
+        BobReceive,
+        BobReceive,
+        AliceSend,
+        BobSend,
+        BobReceive,
+        BobSend,
+        AliceReceive,
+        AliceSend,
+    ];
+    verify_conversation(0, &ops).unwrap();
+}
+
+#[test]
+fn test_ciphertext_for_older_advertised_kem_key() {
+    use Op::*;
+
+    let ops = [
+        AliceSend,
+        AliceTriggerKem,
+        AliceSend,
+        AliceSend,
+        AliceSend,
+        AliceSend,
+        AliceSend,
+        BobReceive,
+        AliceTriggerKem,
+        BobSend,
+    ];
+    verify_conversation(0, &ops).unwrap();
+}
@@ -0,0 +1,147 @@
+//! # ComLock Crypto - Test Vector Module
+//!
+//! Deterministic key-schedule vectors so other ComLock clients can check
+//! byte-for-byte agreement with this implementation.
+//!
+//! Each vector starts from a fixed root key and walks the initiator's
+//! sending chain to a given message number:
+//!
+//! ```text
+//! (send_ck, recv_ck) = kdf_derive(root_key, "init_chains", "")
+//! mk_i               = kdf_expand(ck_i, "msg_send", "mk", LE32(i) || 0x00 * 32)
+//! ck_{i+1}           = kdf_expand(ck_i, "msg_send", "ck", LE32(i) || 0x00 * 32)
+//! ```
+//!
+//! The header is serialized with the public key of the fixed X25519
+//! secret [`HEADER_SECRET`] and no KEM data. All values are lowercase hex.
+//!
+//! The published vectors live in `test-vectors/kdf_v2.json`. Regenerate
+//! them with `cargo run --example dump_vectors --features vector-dump`
+//! only when the key schedule is changed on purpose.
+//!
+//! Outside this crate's own tests the module needs the `test-vectors`
+//! feature.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+use crate::header::MessageHeader;
+use crate::ratchet::{LABEL_MSG_INITIATOR, RatchetState};
+
+/// X25519 secret whose public key goes into every vector header.
+pub const HEADER_SECRET: [u8; 32] = [0x42; 32];
+
+/// Message numbers covered by [`standard_vectors`].
+const STANDARD_COUNTERS: [u32; 4] = [0, 1, 7, 64];
+
+/// One key-schedule vector, with every byte string hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfVector {
+    /// Root key from the handshake
+    pub root_key: String,
+    /// Message number on the initiator's sending chain
+    pub message_number: u32,
+    /// Initial sending chain key of the initiator
+    pub send_chain_key: String,
+    /// Initial receiving chain key of the initiator
+    pub recv_chain_key: String,
+    /// Message key for `message_number`
+    pub message_key: String,
+    /// Chain key after deriving `message_key`
+    pub next_chain_key: String,
+    /// Serialized header for `message_number`
+    pub header: String,
+}
+
+/// Initial `(send, recv)` chain keys of the initiator for a root key.
+pub fn init_chain_keys(root_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    RatchetState::kdf_derive(root_key, b"init_chains", &[])
+}
+
+/// Message key and next chain key at `message_number` on the initiator's
+/// sending chain, with no KEM secret mixed in.
+pub fn message_key_at(root_key: &[u8; 32], message_number: u32) -> ([u8; 32], [u8; 32]) {
+    let (mut chain_key, _) = init_chain_keys(root_key);
+    let mut message_key = [0u8; 32];
+    for n in 0..=message_number {
+        (message_key, chain_key) =
+            RatchetState::message_kdf(&chain_key, LABEL_MSG_INITIATOR, n, &[0u8; 32]);
+    }
+    (message_key, chain_key)
+}
+
+/// Serialized header for `message_number` on the first sending chain.
+pub fn header_at(message_number: u32) -> Vec<u8> {
+    let public = X25519PublicKey::from(&StaticSecret::from(HEADER_SECRET));
+    MessageHeader::new(public.to_bytes(), None, None, message_number, 0).serialize()
+}
+
+/// Build the vector for a root key and message number.
+pub fn vector(root_key: &[u8; 32], message_number: u32) -> KdfVector {
+    let (send_chain_key, recv_chain_key) = init_chain_keys(root_key);
+    let (message_key, next_chain_key) = message_key_at(root_key, message_number);
+
+    KdfVector {
+        root_key: hex::encode(root_key),
+        message_number,
+        send_chain_key: hex::encode(send_chain_key),
+        recv_chain_key: hex::encode(recv_chain_key),
+        message_key: hex::encode(message_key),
+        next_chain_key: hex::encode(next_chain_key),
+        header: hex::encode(header_at(message_number)),
+    }
+}
+
+/// The published vector set: three root keys at each standard counter.
+pub fn standard_vectors() -> Vec<KdfVector> {
+    let mut sequential = [0u8; 32];
+    for (i, byte) in sequential.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let roots = [[0x00; 32], [0xFF; 32], sequential];
+
+    roots
+        .iter()
+        .flat_map(|root| STANDARD_COUNTERS.iter().map(move |&n| vector(root, n)))
+        .collect()
+}
+
+/// Pretty-printed JSON of [`standard_vectors`].
+#[cfg(feature = "vector-dump")]
+pub fn dump_json() -> String {
+    serde_json::to_string_pretty(&standard_vectors()).expect("vectors serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RatchetState, decrypt_message, encrypt_message};
+
+    const PUBLISHED: &str = include_str!("../test-vectors/kdf_v2.json");
+
+    #[test]
+    fn test_published_vectors_match() {
+        let published: Vec<KdfVector> = serde_json::from_str(PUBLISHED).unwrap();
+        assert_eq!(published, standard_vectors());
+    }
+
+    #[test]
+    fn test_vectors_match_live_ratchet() {
+        let root_key = [0x07; 32];
+        let mut alice = RatchetState::new(root_key, true).unwrap();
+        let mut bob = RatchetState::new(root_key, false).unwrap();
+
+        for n in 0..3u32 {
+            let ciphertext = encrypt_message(b"vector", &mut alice).unwrap();
+            assert_eq!(decrypt_message(&ciphertext, &mut bob).unwrap(), b"vector");
+
+            // The live chain uses the same keys the vectors publish
+            let output = alice.step(None).unwrap();
+            let (expected, _) = message_key_at(&root_key, n * 2 + 1);
+            assert_eq!(output.message_key, expected);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! # ComLock Crypto - Utilities
+//!
+//! Small helpers shared by the ComLock crates.
+
+use subtle::ConstantTimeEq;
+
+/// Compare two byte slices without leaking where they differ.
+///
+/// The running time depends only on the lengths, which are treated as
+/// public: slices of different lengths compare unequal straight away.
+/// Use this for MACs, hashes, commitments and other secret-derived values.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq_same_length() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(&[7u8; 32], &[7u8; 32]));
+
+        let mut other = [7u8; 32];
+        other[31] ^= 1;
+        assert!(!ct_eq(&[7u8; 32], &other));
+        other[31] ^= 1;
+        other[0] ^= 0x80;
+        assert!(!ct_eq(&[7u8; 32], &other));
+    }
+
+    #[test]
+    fn test_ct_eq_different_lengths() {
+        assert!(!ct_eq(&[7u8; 16], &[7u8; 32]));
+        assert!(!ct_eq(&[7u8; 32], &[7u8; 16]));
+        assert!(!ct_eq(b"", &[0u8]));
+    }
+}
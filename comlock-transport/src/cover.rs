@@ -3,19 +3,86 @@
 //! Implements Poisson-distributed cover (dummy) traffic to prevent
 //! traffic analysis attacks. Maintains constant traffic patterns
 //! regardless of actual user activity.
+//!
+//! The spacing between packets is controlled by [`TrafficShape`]: pure
+//! Poisson, constant bitrate, or Poisson clamped to a jitter window.
+//!
+//! Each cover packet is a loop routed back to our own loop mailbox and
+//! carries a random nonce, encrypted under a key only this client holds so
+//! that loop payloads look like any other ciphertext. A [`LoopTracker`]
+//! remembers the nonces in flight; a loop only counts as completed when its
+//! packet comes back through the mailbox before
+//! [`CoverConfig::loop_timeout`] expires.
+//!
+//! With [`CoverConfig::adaptive`] set, an [`AdaptiveBudget`] lowers the
+//! rate while loops keep failing (poor connectivity) and restores it as
+//! they recover, never going above the chosen [`AnonymityBudget`].
+//!
+//! The rate ramps up over [`CoverConfig::warmup`] after each start, so
+//! going online does not show as a burst of packets.
+//! [`CoverTrafficGenerator::stop_and_drain`] shuts down cleanly: the
+//! scheduling task is awaited and loops still out get a last chance to
+//! return before the final statistics are taken.
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-use rand::rngs::StdRng;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::rngs::{OsRng, StdRng};
 use rand::{Rng, SeedableRng};
 use rand_distr::{Distribution, Exp};
-use tokio::sync::mpsc;
-use tokio::time::Duration;
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
 
+use crate::envelope::Envelope;
 use crate::sphinx::SphinxPacket;
 use crate::{MixNode, Result, Route, TransportError};
 
+/// Source of the device battery level (0-100).
+///
+/// The generator polls this periodically so battery saver mode engages on
+/// its own; platform layers provide an implementation backed by the OS.
+pub trait BatterySource: Send + Sync {
+    /// Current battery level as a percentage (0-100).
+    fn level(&self) -> u8;
+}
+
+/// Battery source whose level is set by hand (tests, or platforms without
+/// a battery API).
+#[derive(Debug)]
+pub struct ManualBatterySource {
+    level: AtomicU8,
+}
+
+impl ManualBatterySource {
+    /// Create a source reporting `level`.
+    pub fn new(level: u8) -> Self {
+        Self {
+            level: AtomicU8::new(level),
+        }
+    }
+
+    /// Change the reported level.
+    pub fn set(&self, level: u8) {
+        self.level.store(level, Ordering::SeqCst);
+    }
+}
+
+impl Default for ManualBatterySource {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl BatterySource for ManualBatterySource {
+    fn level(&self) -> u8 {
+        self.level.load(Ordering::SeqCst)
+    }
+}
+
 /// Anonymity budget determining cover traffic intensity.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AnonymityBudget {
@@ -50,6 +117,134 @@ impl AnonymityBudget {
     }
 }
 
+/// Loop completion rate below which the adaptive rate is lowered.
+const ADAPTIVE_LOW_COMPLETION: f64 = 0.5;
+
+/// Loop completion rate at or above which the adaptive rate recovers.
+const ADAPTIVE_GOOD_COMPLETION: f64 = 0.8;
+
+/// Resolved loops (returned or lost) needed before the rate is adjusted.
+const ADAPTIVE_MIN_SAMPLES: u64 = 8;
+
+/// Lowest fraction of the budget's rate the adaptive mode drops to.
+const ADAPTIVE_MIN_SCALE: f64 = 0.25;
+
+/// How much of the budget's rate is restored per good window.
+const ADAPTIVE_RECOVERY_STEP: f64 = 0.25;
+
+/// Cover rate that follows loop completion, capped by an anonymity budget.
+///
+/// Fed the tracker's cumulative loop counts; once enough loops have
+/// resolved since the last adjustment, a poor completion rate halves the
+/// rate (down to a quarter of the budget) and a good one restores it in
+/// steps. The rate never exceeds the budget, so neither does data usage.
+#[derive(Debug, Clone)]
+pub struct AdaptiveBudget {
+    /// The user's chosen budget, the ceiling for the rate.
+    budget: AnonymityBudget,
+    /// Fraction of the budget's rate currently in use.
+    scale: f64,
+    /// Completed count at the last adjustment.
+    last_completed: u64,
+    /// Timed-out count at the last adjustment.
+    last_timed_out: u64,
+}
+
+impl AdaptiveBudget {
+    /// Start at the full rate of `budget`.
+    pub fn new(budget: AnonymityBudget) -> Self {
+        Self {
+            budget,
+            scale: 1.0,
+            last_completed: 0,
+            last_timed_out: 0,
+        }
+    }
+
+    /// Change the ceiling, starting again at its full rate.
+    pub fn set_budget(&mut self, budget: AnonymityBudget) {
+        self.budget = budget;
+        self.scale = 1.0;
+    }
+
+    /// Adjust to the tracker's cumulative `completed` and `timed_out`
+    /// counts and return the effective rate.
+    pub fn update(&mut self, completed: u64, timed_out: u64) -> f64 {
+        let returned = completed.saturating_sub(self.last_completed);
+        let lost = timed_out.saturating_sub(self.last_timed_out);
+        let resolved = returned + lost;
+        if resolved < ADAPTIVE_MIN_SAMPLES {
+            return self.packets_per_second();
+        }
+        self.last_completed = completed;
+        self.last_timed_out = timed_out;
+
+        let completion = returned as f64 / resolved as f64;
+        let scale = if completion < ADAPTIVE_LOW_COMPLETION {
+            (self.scale * 0.5).max(ADAPTIVE_MIN_SCALE)
+        } else if completion >= ADAPTIVE_GOOD_COMPLETION {
+            (self.scale + ADAPTIVE_RECOVERY_STEP).min(1.0)
+        } else {
+            self.scale
+        };
+        if scale != self.scale {
+            tracing::info!(completion, scale, "Adaptive cover rate changed");
+            self.scale = scale;
+        }
+        self.packets_per_second()
+    }
+
+    /// Effective packets per second.
+    pub fn packets_per_second(&self) -> f64 {
+        self.budget.packets_per_second() * self.scale
+    }
+}
+
+/// Fraction of the rate a generator starts at during warmup.
+const WARMUP_MIN_SCALE: f64 = 0.1;
+
+/// How often loops in flight are checked while draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How inter-arrival times between cover packets are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TrafficShape {
+    /// Exponentially distributed gaps (Poisson process).
+    #[default]
+    Poisson,
+    /// Fixed gaps of `1 / rate` (constant bitrate).
+    ConstantRate,
+    /// Exponential gaps clamped to `[min, max]`.
+    PoissonWithJitter {
+        /// Shortest allowed gap.
+        min: Duration,
+        /// Longest allowed gap.
+        max: Duration,
+    },
+}
+
+impl TrafficShape {
+    /// Sample the delay before the next packet at `rate` packets per second.
+    pub fn next_delay<R: Rng + ?Sized>(&self, rate: f64, rng: &mut R) -> Duration {
+        match self {
+            Self::Poisson => Duration::from_secs_f64(Self::sample_exp(rate, rng)),
+            Self::ConstantRate => {
+                let rate = if rate > 0.0 { rate } else { 0.1 };
+                Duration::from_secs_f64(1.0 / rate)
+            }
+            Self::PoissonWithJitter { min, max } => {
+                let delay = Duration::from_secs_f64(Self::sample_exp(rate, rng));
+                delay.clamp(*min, (*max).max(*min))
+            }
+        }
+    }
+
+    fn sample_exp<R: Rng + ?Sized>(rate: f64, rng: &mut R) -> f64 {
+        let exp = Exp::new(rate).unwrap_or_else(|_| Exp::new(0.1).unwrap());
+        exp.sample(rng)
+    }
+}
+
 /// Configuration for cover traffic generation.
 #[derive(Debug, Clone)]
 pub struct CoverConfig {
@@ -61,6 +256,32 @@ pub struct CoverConfig {
     pub battery_threshold: u8,
     /// Whether cover traffic is enabled.
     pub enabled: bool,
+    /// How often the battery source is polled while running.
+    pub battery_poll_interval: Duration,
+    /// Distribution of gaps between cover packets.
+    pub shape: TrafficShape,
+    /// How long a loop may take to return before it counts as lost.
+    pub loop_timeout: Duration,
+    /// Lower the rate while loops fail, up to the budget's rate.
+    pub adaptive: bool,
+    /// How long after starting the rate takes to ramp up to full.
+    pub warmup: Duration,
+    /// How long [`CoverTrafficGenerator::stop_and_drain`] waits for loops
+    /// still in flight.
+    pub drain_timeout: Duration,
+}
+
+impl CoverConfig {
+    /// Fraction of the full rate to use `elapsed` after starting.
+    ///
+    /// Rises linearly from [`WARMUP_MIN_SCALE`] to 1 over the warmup.
+    pub fn warmup_scale(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.warmup {
+            return 1.0;
+        }
+        let progress = elapsed.as_secs_f64() / self.warmup.as_secs_f64();
+        WARMUP_MIN_SCALE + (1.0 - WARMUP_MIN_SCALE) * progress
+    }
 }
 
 impl Default for CoverConfig {
@@ -70,6 +291,12 @@ impl Default for CoverConfig {
             battery_saver: true,
             battery_threshold: 20,
             enabled: true,
+            battery_poll_interval: Duration::from_secs(30),
+            shape: TrafficShape::default(),
+            loop_timeout: Duration::from_secs(120),
+            adaptive: false,
+            warmup: Duration::from_secs(60),
+            drain_timeout: Duration::from_secs(5),
         }
     }
 }
@@ -81,12 +308,195 @@ pub struct CoverStats {
     pub packets_sent: u64,
     /// Total loops completed (round-trip dummies).
     pub loops_completed: u64,
-    /// Current packets per second rate.
+    /// Loops that did not return within the timeout.
+    pub loops_timed_out: u64,
+    /// Loops sent and not yet returned or timed out.
+    pub loops_in_flight: u64,
+    /// Current packets per second rate, after adaptive and battery
+    /// adjustments.
     pub current_rate: f64,
     /// Whether in degraded mode (battery saver active).
     pub degraded: bool,
 }
 
+/// Prefix marking the plaintext of a cover loop packet.
+const LOOP_MAGIC: &[u8; 8] = b"CLLOOP01";
+
+/// Length of the per-loop nonce.
+const LOOP_NONCE_LEN: usize = 16;
+
+/// Length of the AES-GCM nonce sealing a loop.
+const LOOP_AEAD_NONCE_LEN: usize = 12;
+
+/// Length of the AES-GCM tag sealing a loop.
+const LOOP_AEAD_TAG_LEN: usize = 16;
+
+/// Size of a loop payload: AES-GCM nonce, then magic, loop nonce and
+/// random padding sealed under the tracker's key.
+const LOOP_PAYLOAD_SIZE: usize = 256;
+
+/// Size of the sealed plaintext within a loop payload.
+const LOOP_PLAINTEXT_SIZE: usize = LOOP_PAYLOAD_SIZE - LOOP_AEAD_NONCE_LEN - LOOP_AEAD_TAG_LEN;
+
+/// Maximum number of loops tracked at once; the oldest is dropped beyond it.
+pub const MAX_LOOPS_IN_FLIGHT: usize = 4096;
+
+/// Tracks cover loops in flight and counts the ones that come back.
+///
+/// Cheap to clone; clones share state. Hand one to
+/// [`MixClient::set_loop_tracker`](crate::MixClient::set_loop_tracker) so
+/// returned loops are counted and filtered out of the mailbox.
+///
+/// Loops are sealed under a random key drawn by [`LoopTracker::new`] that
+/// never leaves this client, so mixes and the mailbox provider cannot tell
+/// them from real messages.
+#[derive(Clone)]
+pub struct LoopTracker {
+    /// Seals and opens loop payloads.
+    cipher: Aes256Gcm,
+    /// Send time of each loop in flight, by nonce.
+    in_flight: Arc<Mutex<HashMap<[u8; LOOP_NONCE_LEN], Instant>>>,
+    /// Loops that came back in time.
+    completed: Arc<AtomicU64>,
+    /// Loops given up on.
+    timed_out: Arc<AtomicU64>,
+    /// How long a loop may take to return.
+    timeout: Duration,
+}
+
+impl LoopTracker {
+    /// Create a tracker giving up on loops after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill(&mut key[..]);
+        let cipher = Aes256Gcm::new_from_slice(&key).expect("AES-256 key is 32 bytes");
+        key.fill(0);
+
+        Self {
+            cipher,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            completed: Arc::new(AtomicU64::new(0)),
+            timed_out: Arc::new(AtomicU64::new(0)),
+            timeout,
+        }
+    }
+
+    /// Start a new loop and return its envelope bytes.
+    pub fn start_loop<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<u8> {
+        let mut nonce = [0u8; LOOP_NONCE_LEN];
+        rng.fill(&mut nonce[..]);
+
+        let mut plaintext = vec![0u8; LOOP_PLAINTEXT_SIZE];
+        plaintext[..LOOP_MAGIC.len()].copy_from_slice(LOOP_MAGIC);
+        plaintext[LOOP_MAGIC.len()..LOOP_MAGIC.len() + LOOP_NONCE_LEN].copy_from_slice(&nonce);
+        rng.fill(&mut plaintext[LOOP_MAGIC.len() + LOOP_NONCE_LEN..]);
+
+        let mut aead_nonce = [0u8; LOOP_AEAD_NONCE_LEN];
+        rng.fill(&mut aead_nonce[..]);
+        let mut payload = aead_nonce.to_vec();
+        payload.extend(
+            self.cipher
+                .encrypt(Nonce::from_slice(&aead_nonce), plaintext.as_slice())
+                .expect("AES-GCM encryption failed"),
+        );
+
+        let now = Instant::now();
+        let mut in_flight = self.lock();
+        self.expire(&mut in_flight, now);
+        if in_flight.len() >= MAX_LOOPS_IN_FLIGHT {
+            let oldest = in_flight
+                .iter()
+                .min_by_key(|(_, sent)| **sent)
+                .map(|(nonce, _)| *nonce);
+            if let Some(oldest) = oldest {
+                in_flight.remove(&oldest);
+                self.timed_out.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        in_flight.insert(nonce, now);
+
+        Envelope::new(payload).serialize()
+    }
+
+    /// Check a payload received from the mailbox.
+    ///
+    /// Returns `true` if it is a cover loop packet sealed under our key,
+    /// which the caller should drop. Only a loop still in flight counts as
+    /// completed; replayed or late loops are dropped uncounted. Anything
+    /// that does not open under our key is left to the caller.
+    pub fn observe(&self, payload: &[u8]) -> bool {
+        if payload.len() != LOOP_PAYLOAD_SIZE {
+            return false;
+        }
+        let (aead_nonce, sealed) = payload.split_at(LOOP_AEAD_NONCE_LEN);
+        let Ok(plaintext) = self.cipher.decrypt(Nonce::from_slice(aead_nonce), sealed) else {
+            return false;
+        };
+        let Some(nonce) = plaintext
+            .strip_prefix(LOOP_MAGIC.as_slice())
+            .and_then(|rest| rest.get(..LOOP_NONCE_LEN))
+        else {
+            return false;
+        };
+
+        let now = Instant::now();
+        let mut in_flight = self.lock();
+        self.expire(&mut in_flight, now);
+        if in_flight.remove(nonce).is_some() {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+            tracing::trace!("Cover loop returned");
+        }
+        true
+    }
+
+    /// Loops that came back in time.
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::SeqCst)
+    }
+
+    /// Loops that did not come back in time.
+    pub fn timed_out(&self) -> u64 {
+        let now = Instant::now();
+        self.expire(&mut self.lock(), now);
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Loops still awaiting their return.
+    pub fn in_flight(&self) -> usize {
+        let now = Instant::now();
+        let mut in_flight = self.lock();
+        self.expire(&mut in_flight, now);
+        in_flight.len()
+    }
+
+    /// Give up on every loop in flight, counting them as timed out.
+    ///
+    /// Returns how many were abandoned.
+    pub fn abandon_all(&self) -> u64 {
+        let mut in_flight = self.lock();
+        let abandoned = in_flight.len() as u64;
+        in_flight.clear();
+        self.timed_out.fetch_add(abandoned, Ordering::SeqCst);
+        abandoned
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<[u8; LOOP_NONCE_LEN], Instant>> {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Drop loops older than the timeout, counting them as lost.
+    fn expire(&self, in_flight: &mut HashMap<[u8; LOOP_NONCE_LEN], Instant>, now: Instant) {
+        let before = in_flight.len();
+        in_flight.retain(|_, sent| now.duration_since(*sent) < self.timeout);
+        let expired = before - in_flight.len();
+        if expired > 0 {
+            self.timed_out.fetch_add(expired as u64, Ordering::SeqCst);
+        }
+    }
+}
+
 /// Cover traffic generator using Poisson-distributed timing.
 pub struct CoverTrafficGenerator {
     /// Configuration.
@@ -95,27 +505,66 @@ pub struct CoverTrafficGenerator {
     running: Arc<AtomicBool>,
     /// Packet counter.
     packets_sent: Arc<AtomicU64>,
-    /// Loops counter.
-    loops_completed: Arc<AtomicU64>,
+    /// Loops in flight and completed.
+    loops: LoopTracker,
+    /// Mailbox our loop packets are addressed to.
+    loop_mailbox: [u8; 32],
+    /// Rate following loop completion, when adaptive mode is on.
+    adaptive: Arc<Mutex<AdaptiveBudget>>,
     /// Channel for sending generated packets.
     packet_tx: mpsc::Sender<SphinxPacket>,
-    /// Current battery level (0-100, simulated).
+    /// Last known battery level (0-100).
     battery_level: Arc<AtomicU64>,
+    /// Where battery readings come from.
+    battery_source: Arc<dyn BatterySource>,
+    /// Default source, driven by `update_battery`.
+    manual_battery: Arc<ManualBatterySource>,
+    /// Wakes the scheduling task when the generator is stopped.
+    shutdown: Arc<Notify>,
+    /// The scheduling task, while one is running.
+    task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl CoverTrafficGenerator {
     /// Create a new cover traffic generator.
     pub fn new(config: CoverConfig, packet_tx: mpsc::Sender<SphinxPacket>) -> Self {
+        let manual_battery = Arc::new(ManualBatterySource::default());
+
         Self {
-            config,
             running: Arc::new(AtomicBool::new(false)),
             packets_sent: Arc::new(AtomicU64::new(0)),
-            loops_completed: Arc::new(AtomicU64::new(0)),
+            loops: LoopTracker::new(config.loop_timeout),
+            loop_mailbox: rand::random(),
+            adaptive: Arc::new(Mutex::new(AdaptiveBudget::new(config.budget))),
+            config,
             packet_tx,
-            battery_level: Arc::new(AtomicU64::new(100)),
+            battery_level: Arc::new(AtomicU64::new(manual_battery.level() as u64)),
+            battery_source: manual_battery.clone(),
+            manual_battery,
+            shutdown: Arc::new(Notify::new()),
+            task: Mutex::new(None),
         }
     }
 
+    /// Use a platform battery source instead of manual updates.
+    ///
+    /// Takes effect the next time the generator is started.
+    pub fn set_battery_source(&mut self, source: Arc<dyn BatterySource>) {
+        self.battery_level
+            .store(source.level() as u64, Ordering::SeqCst);
+        self.battery_source = source;
+    }
+
+    /// Tracker for this generator's loops, to be fed returned packets.
+    pub fn loop_tracker(&self) -> LoopTracker {
+        self.loops.clone()
+    }
+
+    /// Mailbox our loop packets return to; poll it alongside the others.
+    pub fn loop_mailbox(&self) -> [u8; 32] {
+        self.loop_mailbox
+    }
+
     /// Start the cover traffic generator.
     pub async fn start(&self, gateway: MixNode, topology: Vec<MixNode>) -> Result<()> {
         if !self.config.enabled {
@@ -123,20 +572,34 @@ impl CoverTrafficGenerator {
         }
 
         self.running.store(true, Ordering::SeqCst);
+        tracing::debug!(
+            budget = ?self.config.budget,
+            shape = ?self.config.shape,
+            gateway = %gateway.id,
+            "Starting cover traffic"
+        );
 
         let running = self.running.clone();
         let packets_sent = self.packets_sent.clone();
-        let loops_completed = self.loops_completed.clone();
+        let loops = self.loops.clone();
+        let loop_mailbox = self.loop_mailbox;
+        let adaptive = self.adaptive.clone();
         let battery_level = self.battery_level.clone();
+        let battery_source = self.battery_source.clone();
+        let shutdown = self.shutdown.clone();
         let config = self.config.clone();
         let packet_tx = self.packet_tx.clone();
 
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             Self::traffic_loop(
                 running,
                 packets_sent,
-                loops_completed,
+                loops,
+                loop_mailbox,
+                adaptive,
                 battery_level,
+                battery_source,
+                shutdown,
                 config,
                 packet_tx,
                 gateway,
@@ -144,17 +607,56 @@ impl CoverTrafficGenerator {
             )
             .await
         });
+        *self.lock_task() = Some(task);
 
         Ok(())
     }
 
     /// Stop the cover traffic generator.
+    ///
+    /// No new packets are scheduled; see [`Self::stop_and_drain`] to also
+    /// wait for the task and the loops in flight.
     pub fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
+        self.shutdown.notify_one();
+    }
+
+    /// Stop the generator, wait for it to wind down and return the final
+    /// statistics.
+    ///
+    /// Waits for the scheduling task, then for loops in flight to return,
+    /// for up to [`CoverConfig::drain_timeout`] in total. Loops still out
+    /// after that count as timed out, so in the returned statistics every
+    /// loop sent has either completed or timed out.
+    pub async fn stop_and_drain(&self) -> CoverStats {
+        self.stop();
+        let deadline = Instant::now() + self.config.drain_timeout;
+
+        let task = self.lock_task().take();
+        if let Some(task) = task {
+            if tokio::time::timeout_at(deadline, task).await.is_err() {
+                tracing::warn!("Cover traffic task did not stop within the drain window");
+            }
+        }
+
+        while self.loops.in_flight() > 0 && Instant::now() < deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(left)).await;
+        }
+        let abandoned = self.loops.abandon_all();
+        if abandoned > 0 {
+            tracing::debug!(abandoned, "Cover loops still in flight after drain");
+        }
+
+        self.stats()
     }
 
     /// Update the battery level (for battery saver mode).
+    ///
+    /// Drives the default manual source; when a platform source is set,
+    /// its next reading takes precedence.
     pub fn update_battery(&self, level: u8) {
+        self.manual_battery.set(level);
         self.battery_level.store(level as u64, Ordering::SeqCst);
     }
 
@@ -163,14 +665,14 @@ impl CoverTrafficGenerator {
         let battery = self.battery_level.load(Ordering::SeqCst) as u8;
         let degraded = self.config.battery_saver && battery < self.config.battery_threshold;
 
+        let rate = Self::base_rate(&self.config, &self.adaptive, &self.loops);
+
         CoverStats {
             packets_sent: self.packets_sent.load(Ordering::SeqCst),
-            loops_completed: self.loops_completed.load(Ordering::SeqCst),
-            current_rate: if degraded {
-                self.config.budget.packets_per_second() * 0.25
-            } else {
-                self.config.budget.packets_per_second()
-            },
+            loops_completed: self.loops.completed(),
+            loops_timed_out: self.loops.timed_out(),
+            loops_in_flight: self.loops.in_flight() as u64,
+            current_rate: if degraded { rate * 0.25 } else { rate },
             degraded,
         }
     }
@@ -178,6 +680,7 @@ impl CoverTrafficGenerator {
     /// Update configuration.
     pub fn set_budget(&mut self, budget: AnonymityBudget) {
         self.config.budget = budget;
+        Self::lock_adaptive(&self.adaptive).set_budget(budget);
     }
 
     /// Check if running.
@@ -187,60 +690,117 @@ impl CoverTrafficGenerator {
 
     // === Private methods ===
 
+    /// Rate before battery saver: the budget's, or the adaptive one.
+    fn base_rate(
+        config: &CoverConfig,
+        adaptive: &Mutex<AdaptiveBudget>,
+        loops: &LoopTracker,
+    ) -> f64 {
+        if config.adaptive {
+            Self::lock_adaptive(adaptive).update(loops.completed(), loops.timed_out())
+        } else {
+            config.budget.packets_per_second()
+        }
+    }
+
+    fn lock_task(&self) -> std::sync::MutexGuard<'_, Option<JoinHandle<()>>> {
+        self.task
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_adaptive(
+        adaptive: &Mutex<AdaptiveBudget>,
+    ) -> std::sync::MutexGuard<'_, AdaptiveBudget> {
+        adaptive
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn traffic_loop(
         running: Arc<AtomicBool>,
         packets_sent: Arc<AtomicU64>,
-        loops_completed: Arc<AtomicU64>,
+        loops: LoopTracker,
+        loop_mailbox: [u8; 32],
+        adaptive: Arc<Mutex<AdaptiveBudget>>,
         battery_level: Arc<AtomicU64>,
+        battery_source: Arc<dyn BatterySource>,
+        shutdown: Arc<Notify>,
         config: CoverConfig,
         packet_tx: mpsc::Sender<SphinxPacket>,
         gateway: MixNode,
         topology: Vec<MixNode>,
     ) {
         let mut rng = StdRng::from_entropy();
+        let poll_battery = || {
+            battery_level.store(battery_source.level() as u64, Ordering::SeqCst);
+        };
+
+        let mut degraded = false;
+        let started = Instant::now();
 
         while running.load(Ordering::SeqCst) {
             // Check battery level
+            poll_battery();
             let battery = battery_level.load(Ordering::SeqCst) as u8;
-            let rate_multiplier = if config.battery_saver && battery < config.battery_threshold {
+            let low_battery = config.battery_saver && battery < config.battery_threshold;
+            if low_battery != degraded {
+                degraded = low_battery;
+                tracing::info!(battery, degraded, "Cover traffic battery saver changed");
+            }
+            let rate_multiplier = if low_battery {
                 0.25 // Reduce to 25% when battery is low
             } else {
                 1.0
             };
 
-            let lambda = config.budget.lambda() * rate_multiplier;
+            let lambda = Self::base_rate(&config, &adaptive, &loops)
+                * rate_multiplier
+                * config.warmup_scale(started.elapsed());
 
-            // Sample inter-arrival time from exponential distribution
-            let exp = Exp::new(lambda).unwrap_or_else(|_| Exp::new(0.1).unwrap());
-            let delay_secs = exp.sample(&mut rng);
-            let delay = Duration::from_secs_f64(delay_secs);
+            // Sample inter-arrival time according to the configured shape
+            let mut remaining = config.shape.next_delay(lambda, &mut rng);
 
-            tokio::time::sleep(delay).await;
+            // Sleep in slices so the battery keeps being polled
+            while !remaining.is_zero() && running.load(Ordering::SeqCst) {
+                let slice = remaining.min(config.battery_poll_interval);
+                tokio::select! {
+                    _ = tokio::time::sleep(slice) => remaining -= slice,
+                    // Woken by `stop`: the loop condition decides
+                    _ = shutdown.notified() => continue,
+                }
+                poll_battery();
+            }
 
             if !running.load(Ordering::SeqCst) {
                 break;
             }
 
             // Generate a dummy packet (loop traffic)
-            match Self::generate_loop_packet(&gateway, &topology) {
+            match Self::generate_loop_packet(&gateway, &topology, &loops, loop_mailbox, &mut rng) {
                 Ok(packet) => {
                     if packet_tx.send(packet).await.is_ok() {
                         packets_sent.fetch_add(1, Ordering::SeqCst);
-                        // Loops complete when we receive them back (simulated here)
-                        if rng.gen_bool(0.9) {
-                            // 90% success rate
-                            loops_completed.fetch_add(1, Ordering::SeqCst);
-                        }
+                        tracing::trace!(gateway = %gateway.id, "Sent cover loop packet");
+                    } else {
+                        tracing::warn!("Cover packet channel closed");
                     }
                 }
-                Err(_) => {
-                    // Log error in production
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to generate cover loop packet");
                 }
             }
         }
     }
 
-    fn generate_loop_packet(gateway: &MixNode, topology: &[MixNode]) -> Result<SphinxPacket> {
+    fn generate_loop_packet<R: Rng + ?Sized>(
+        gateway: &MixNode,
+        topology: &[MixNode],
+        loops: &LoopTracker,
+        loop_mailbox: [u8; 32],
+        rng: &mut R,
+    ) -> Result<SphinxPacket> {
         // Create a loop: L1 -> L2 -> L1 (returns to us via gateway)
         let mix_nodes: Vec<&MixNode> = topology.iter().filter(|n| n.layer == 2).collect();
 
@@ -249,26 +809,23 @@ impl CoverTrafficGenerator {
         }
 
         // Build a simple loop route
-        let route = Route::new(vec![
+        let route = Route::new_loop(vec![
             gateway.clone(),
             mix_nodes[0].clone(),
             gateway.clone(), // Return to our gateway
         ])?;
 
-        // Dummy payload (random bytes)
-        let mut payload = vec![0u8; 256];
-        rand::thread_rng().fill(&mut payload[..]);
-
-        // Our mailbox ID (for loop return)
-        let mailbox_id = [0x10; 32]; // Loop pattern marker
+        // Tagged payload, tracked until it comes back to our loop mailbox
+        let payload = loops.start_loop(rng);
 
-        SphinxPacket::create(&payload, &route, mailbox_id)
+        SphinxPacket::create(&payload, &route, loop_mailbox)
     }
 }
 
 /// Builder for cover traffic generator.
 pub struct CoverTrafficBuilder {
     config: CoverConfig,
+    battery_source: Option<Arc<dyn BatterySource>>,
 }
 
 impl CoverTrafficBuilder {
@@ -276,6 +833,7 @@ impl CoverTrafficBuilder {
     pub fn new() -> Self {
         Self {
             config: CoverConfig::default(),
+            battery_source: None,
         }
     }
 
@@ -303,9 +861,55 @@ impl CoverTrafficBuilder {
         self
     }
 
+    /// Set how often the battery source is polled.
+    pub fn battery_poll_interval(mut self, interval: Duration) -> Self {
+        self.config.battery_poll_interval = interval;
+        self
+    }
+
+    /// Set the inter-arrival distribution.
+    pub fn traffic_shape(mut self, shape: TrafficShape) -> Self {
+        self.config.shape = shape;
+        self
+    }
+
+    /// Set how long a loop may take to return.
+    pub fn loop_timeout(mut self, timeout: Duration) -> Self {
+        self.config.loop_timeout = timeout;
+        self
+    }
+
+    /// Set how long the rate takes to ramp up after starting.
+    pub fn warmup(mut self, warmup: Duration) -> Self {
+        self.config.warmup = warmup;
+        self
+    }
+
+    /// Set how long stopping waits for loops in flight.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.config.drain_timeout = timeout;
+        self
+    }
+
+    /// Enable/disable adapting the rate to loop completion.
+    pub fn adaptive(mut self, enabled: bool) -> Self {
+        self.config.adaptive = enabled;
+        self
+    }
+
+    /// Use a platform battery source.
+    pub fn battery_source(mut self, source: Arc<dyn BatterySource>) -> Self {
+        self.battery_source = Some(source);
+        self
+    }
+
     /// Build the generator.
     pub fn build(self, packet_tx: mpsc::Sender<SphinxPacket>) -> CoverTrafficGenerator {
-        CoverTrafficGenerator::new(self.config, packet_tx)
+        let mut generator = CoverTrafficGenerator::new(self.config, packet_tx);
+        if let Some(source) = self.battery_source {
+            generator.set_battery_source(source);
+        }
+        generator
     }
 }
 
@@ -374,4 +978,212 @@ mod tests {
         assert!(stats.degraded);
         assert!(stats.current_rate < AnonymityBudget::Max.packets_per_second());
     }
+
+    #[test]
+    fn test_constant_rate_gaps_are_uniform() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let rate = AnonymityBudget::Max.packets_per_second();
+        let expected = Duration::from_secs_f64(1.0 / rate);
+
+        for _ in 0..100 {
+            let gap = TrafficShape::ConstantRate.next_delay(rate, &mut rng);
+            let diff = gap.abs_diff(expected);
+            assert!(diff < Duration::from_micros(1));
+        }
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let min = Duration::from_millis(200);
+        let max = Duration::from_millis(800);
+        let shape = TrafficShape::PoissonWithJitter { min, max };
+
+        let gaps: Vec<Duration> = (0..1000).map(|_| shape.next_delay(2.0, &mut rng)).collect();
+        assert!(gaps.iter().all(|gap| *gap >= min && *gap <= max));
+        // Both ends of the window are actually hit at this rate
+        assert!(gaps.contains(&min));
+        assert!(gaps.contains(&max));
+    }
+
+    #[tokio::test]
+    async fn test_late_loop_is_not_counted() {
+        let tracker = LoopTracker::new(Duration::from_millis(20));
+        let mut rng = StdRng::seed_from_u64(3);
+        let payload = Envelope::deserialize(&tracker.start_loop(&mut rng))
+            .unwrap()
+            .ciphertext;
+        assert_eq!(tracker.in_flight(), 1);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(tracker.observe(&payload));
+        assert_eq!(tracker.completed(), 0);
+        assert_eq!(tracker.timed_out(), 1);
+        assert_eq!(tracker.in_flight(), 0);
+
+        // Ordinary messages are left alone
+        assert!(!tracker.observe(b"not a loop"));
+    }
+
+    #[test]
+    fn test_loop_payload_is_opaque() {
+        let tracker = LoopTracker::new(Duration::from_secs(60));
+        let mut rng = StdRng::seed_from_u64(4);
+        let open = |bytes: Vec<u8>| Envelope::deserialize(&bytes).unwrap().ciphertext;
+        let first = open(tracker.start_loop(&mut rng));
+        let second = open(tracker.start_loop(&mut rng));
+
+        // No cleartext marker, and no shared prefix between loops
+        assert_eq!(first.len(), LOOP_PAYLOAD_SIZE);
+        assert!(!first.windows(LOOP_MAGIC.len()).any(|w| w == LOOP_MAGIC));
+        assert_ne!(first[..LOOP_AEAD_NONCE_LEN], second[..LOOP_AEAD_NONCE_LEN]);
+
+        // Another client's tracker cannot recognise our loops
+        assert!(!LoopTracker::new(Duration::from_secs(60)).observe(&first));
+        assert!(tracker.observe(&first));
+        assert_eq!(tracker.completed(), 1);
+    }
+
+    #[test]
+    fn test_adaptive_rate_follows_loop_completion() {
+        let ceiling = AnonymityBudget::Max.packets_per_second();
+        let mut adaptive = AdaptiveBudget::new(AnonymityBudget::Max);
+        assert_eq!(adaptive.packets_per_second(), ceiling);
+
+        // Sustained loss: 2 of every 10 loops return
+        let (mut completed, mut timed_out) = (0, 0);
+        for _ in 0..4 {
+            completed += 2;
+            timed_out += 8;
+            adaptive.update(completed, timed_out);
+        }
+        let lowered = adaptive.packets_per_second();
+        assert_eq!(lowered, ceiling * ADAPTIVE_MIN_SCALE);
+
+        // Too few new samples: no change
+        adaptive.update(completed + 3, timed_out);
+        assert_eq!(adaptive.packets_per_second(), lowered);
+
+        // Recovery raises it back, but never above the budget
+        for _ in 0..10 {
+            completed += 10;
+            adaptive.update(completed, timed_out);
+            assert!(adaptive.packets_per_second() <= ceiling);
+        }
+        assert_eq!(adaptive.packets_per_second(), ceiling);
+    }
+
+    #[test]
+    fn test_builder_sets_shape() {
+        let (tx, _rx) = mpsc::channel(10);
+        let generator = CoverTrafficBuilder::new()
+            .budget(AnonymityBudget::Max)
+            .traffic_shape(TrafficShape::ConstantRate)
+            .build(tx);
+
+        assert_eq!(generator.config.shape, TrafficShape::ConstantRate);
+    }
+
+    #[test]
+    fn test_warmup_ramps_up_to_full_rate() {
+        let config = CoverConfig {
+            warmup: Duration::from_secs(10),
+            ..CoverConfig::default()
+        };
+        assert_eq!(config.warmup_scale(Duration::ZERO), WARMUP_MIN_SCALE);
+        let halfway = config.warmup_scale(Duration::from_secs(5));
+        assert!(halfway > WARMUP_MIN_SCALE && halfway < 1.0);
+        assert_eq!(config.warmup_scale(Duration::from_secs(10)), 1.0);
+        assert_eq!(config.warmup_scale(Duration::from_secs(60)), 1.0);
+
+        let no_warmup = CoverConfig {
+            warmup: Duration::ZERO,
+            ..CoverConfig::default()
+        };
+        assert_eq!(no_warmup.warmup_scale(Duration::ZERO), 1.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_and_drain_waits_for_loops() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let drain_timeout = Duration::from_millis(100);
+        let generator = CoverTrafficBuilder::new()
+            .budget(AnonymityBudget::Max)
+            .traffic_shape(TrafficShape::ConstantRate)
+            .warmup(Duration::ZERO)
+            .drain_timeout(drain_timeout)
+            .build(tx);
+
+        let node = |id: u8, layer: u8| MixNode {
+            id: crate::NodeId::new([id; 32]),
+            public_key: [id; 32],
+            address: format!("127.0.0.1:900{id}"),
+            layer,
+            bandwidth_weight: 1,
+        };
+        generator
+            .start(node(1, 1), vec![node(1, 1), node(2, 2)])
+            .await
+            .unwrap();
+
+        // Two packets at 2 per second; the loops never come back
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert!(generator.stats().loops_in_flight > 0);
+
+        let started = Instant::now();
+        let stats = generator.stop_and_drain().await;
+        assert!(started.elapsed() >= drain_timeout);
+        assert!(!generator.is_running());
+        assert!(stats.packets_sent >= 2);
+        assert_eq!(stats.loops_in_flight, 0);
+        assert_eq!(
+            stats.loops_completed + stats.loops_timed_out,
+            stats.packets_sent
+        );
+
+        // Nothing more is scheduled
+        let mut received = 0;
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, stats.packets_sent);
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(generator.stats().packets_sent, stats.packets_sent);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_battery_source_polled_while_running() {
+        let (tx, _rx) = mpsc::channel(100);
+        let battery = Arc::new(ManualBatterySource::new(100));
+        let generator = CoverTrafficBuilder::new()
+            .budget(AnonymityBudget::Max)
+            .battery_saver(true)
+            .battery_threshold(20)
+            .battery_poll_interval(Duration::from_millis(10))
+            .battery_source(battery.clone())
+            .build(tx);
+
+        let gateway = MixNode {
+            id: crate::NodeId::new([1u8; 32]),
+            public_key: [1u8; 32],
+            address: "127.0.0.1:9001".into(),
+            layer: 1,
+            bandwidth_weight: 1,
+        };
+        generator.start(gateway, Vec::new()).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let full = generator.stats();
+        assert!(!full.degraded);
+
+        // Battery drops mid-run; the generator notices on its own
+        battery.set(10);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let low = generator.stats();
+        assert!(low.degraded);
+        assert!(low.current_rate < full.current_rate);
+
+        generator.stop();
+    }
 }
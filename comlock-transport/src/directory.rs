@@ -0,0 +1,346 @@
+//! # Directory Authority Client
+//!
+//! Fetches the mixnet topology from a directory authority and feeds it into
+//! the [`MixClient`]. A document is only trusted if it carries a valid
+//! Ed25519 signature from the pinned authority key and has not expired.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::mixnet::MixClient;
+use crate::{MixNode, NodeId, Result, TransportError};
+
+/// Largest directory response accepted, in bytes.
+pub const MAX_DIRECTORY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Configuration for the directory client.
+#[derive(Debug, Clone)]
+pub struct DirectoryConfig {
+    /// URL of the directory authority document (http:// or https://).
+    pub authority_url: String,
+    /// Pinned Ed25519 public key of the directory authority.
+    pub authority_key: [u8; 32],
+    /// Timeout for fetching the document.
+    pub timeout: Duration,
+}
+
+/// A node entry as published by the directory authority.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryNode {
+    /// Node identifier (hex, 32 bytes).
+    pub id: String,
+    /// X25519 public key (hex, 32 bytes).
+    pub public_key: String,
+    /// Network address (e.g., "192.168.1.1:9000").
+    pub address: String,
+    /// Layer in the stratified topology (1=Gateway, 2=Mix, 3=Exit).
+    pub layer: u8,
+    /// Relative capacity used to weight route selection.
+    #[serde(default = "crate::default_bandwidth_weight")]
+    pub bandwidth_weight: u32,
+}
+
+/// The signed body of a directory document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryDocument {
+    /// All nodes currently in the network.
+    pub nodes: Vec<DirectoryNode>,
+    /// Unix timestamp (seconds) after which the document is stale.
+    pub expires_at: i64,
+}
+
+/// A directory document as served by the authority.
+///
+/// `document` holds the JSON-encoded [`DirectoryDocument`]; the signature
+/// covers exactly these bytes, so no JSON canonicalization is required.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedDirectory {
+    /// JSON-encoded [`DirectoryDocument`].
+    pub document: String,
+    /// Hex-encoded Ed25519 signature over `document`.
+    pub signature: String,
+}
+
+impl SignedDirectory {
+    /// Sign a document with the authority's signing key.
+    pub fn sign(document: &DirectoryDocument, signing_key: &SigningKey) -> Result<Self> {
+        let document = serde_json::to_string(document)
+            .map_err(|e| TransportError::DirectoryError(e.to_string()))?;
+        let signature = signing_key.sign(document.as_bytes());
+
+        Ok(Self {
+            document,
+            signature: hex::encode(signature.to_bytes()),
+        })
+    }
+
+    /// Verify the signature and expiry, returning the listed nodes.
+    ///
+    /// `now` is the current Unix time in seconds.
+    pub fn verify(&self, authority_key: &[u8; 32], now: i64) -> Result<Vec<MixNode>> {
+        let verifying_key = VerifyingKey::from_bytes(authority_key)
+            .map_err(|_| TransportError::DirectoryError("Invalid authority key".into()))?;
+
+        let signature_bytes = hex::decode(&self.signature)
+            .map_err(|_| TransportError::DirectoryError("Malformed signature".into()))?;
+        let signature = Signature::from_slice(&signature_bytes)
+            .map_err(|_| TransportError::DirectoryError("Malformed signature".into()))?;
+
+        verifying_key
+            .verify(self.document.as_bytes(), &signature)
+            .map_err(|_| TransportError::DirectoryError("Signature verification failed".into()))?;
+
+        let document: DirectoryDocument = serde_json::from_str(&self.document)
+            .map_err(|e| TransportError::DirectoryError(e.to_string()))?;
+
+        if now >= document.expires_at {
+            return Err(TransportError::DirectoryError(
+                "Directory document has expired".into(),
+            ));
+        }
+
+        document.nodes.iter().map(Self::parse_node).collect()
+    }
+
+    fn parse_node(node: &DirectoryNode) -> Result<MixNode> {
+        Ok(MixNode {
+            id: NodeId::new(decode_key(&node.id)?),
+            public_key: decode_key(&node.public_key)?,
+            address: node.address.clone(),
+            layer: node.layer,
+            bandwidth_weight: node.bandwidth_weight,
+        })
+    }
+}
+
+/// Client for fetching the topology from a directory authority.
+pub struct DirectoryClient {
+    config: DirectoryConfig,
+    http: reqwest::Client,
+}
+
+impl DirectoryClient {
+    /// Create a new directory client.
+    pub fn new(config: DirectoryConfig) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| TransportError::NetworkError(e.to_string()))?;
+
+        Ok(Self { config, http })
+    }
+
+    /// Fetch and verify the current directory document.
+    ///
+    /// Responses larger than [`MAX_DIRECTORY_BYTES`] are refused, whether
+    /// or not they announce their length.
+    pub async fn fetch(&self) -> Result<Vec<MixNode>> {
+        let mut response = self
+            .http
+            .get(&self.config.authority_url)
+            .send()
+            .await
+            .map_err(|e| TransportError::NetworkError(e.to_string()))?
+            .error_for_status()
+            .map_err(|e| TransportError::NetworkError(e.to_string()))?;
+
+        let too_large = || {
+            TransportError::DirectoryError(format!(
+                "Directory document exceeds {} bytes",
+                MAX_DIRECTORY_BYTES
+            ))
+        };
+        if response
+            .content_length()
+            .is_some_and(|len| len > MAX_DIRECTORY_BYTES as u64)
+        {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| TransportError::NetworkError(e.to_string()))?
+        {
+            if body.len() + chunk.len() > MAX_DIRECTORY_BYTES {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let signed: SignedDirectory = serde_json::from_slice(&body)
+            .map_err(|e| TransportError::DirectoryError(e.to_string()))?;
+
+        signed.verify(&self.config.authority_key, current_timestamp())
+    }
+
+    /// Fetch the directory and replace the client's topology with it.
+    ///
+    /// Returns the number of nodes loaded. The topology is left untouched
+    /// if the document cannot be fetched or fails verification.
+    pub async fn refresh(&self, client: &MixClient) -> Result<usize> {
+        let nodes = self.fetch().await?;
+        let count = nodes.len();
+        client.update_topology(nodes).await;
+        Ok(count)
+    }
+
+    /// Get configuration.
+    pub fn config(&self) -> &DirectoryConfig {
+        &self.config
+    }
+}
+
+fn decode_key(value: &str) -> Result<[u8; 32]> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| TransportError::DirectoryError(format!("Invalid key: {}", value)))
+}
+
+fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mixnet::MixClientConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn test_document(expires_at: i64) -> DirectoryDocument {
+        let nodes = (1..=3u8)
+            .map(|i| DirectoryNode {
+                id: hex::encode([i; 32]),
+                public_key: hex::encode([i + 10; 32]),
+                address: format!("127.0.0.1:900{}", i),
+                layer: i,
+                bandwidth_weight: 1,
+            })
+            .collect();
+
+        DirectoryDocument { nodes, expires_at }
+    }
+
+    /// Serve a single HTTP response with the given body.
+    async fn serve_once(body: String) -> String {
+        serve_response(body, true).await
+    }
+
+    /// Serve a single HTTP response, announcing the body length only if
+    /// `content_length` is set.
+    async fn serve_response(body: String, content_length: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+
+            let length = if content_length {
+                format!("Content-Length: {}\r\n", body.len())
+            } else {
+                String::new()
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n{}Connection: close\r\n\r\n{}",
+                length, body
+            );
+            // The client may hang up once it has read enough
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+
+        format!("http://{}/topology.json", addr)
+    }
+
+    fn client_for(url: String, authority_key: [u8; 32]) -> DirectoryClient {
+        DirectoryClient::new(DirectoryConfig {
+            authority_url: url,
+            authority_key,
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refresh_populates_topology() {
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let signed =
+            SignedDirectory::sign(&test_document(current_timestamp() + 3600), &authority).unwrap();
+        let url = serve_once(serde_json::to_string(&signed).unwrap()).await;
+
+        let directory = client_for(url, authority.verifying_key().to_bytes());
+        let mix_client = MixClient::new(MixClientConfig::default());
+
+        let loaded = directory.refresh(&mix_client).await.unwrap();
+        assert_eq!(loaded, 3);
+
+        let stats = mix_client.stats().await;
+        assert_eq!(stats.known_gateways, 1);
+        assert_eq!(stats.known_mixes, 1);
+        assert_eq!(stats.known_providers, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bad_signature_rejected() {
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let impostor = SigningKey::from_bytes(&[8u8; 32]);
+        let signed =
+            SignedDirectory::sign(&test_document(current_timestamp() + 3600), &impostor).unwrap();
+        let url = serve_once(serde_json::to_string(&signed).unwrap()).await;
+
+        let directory = client_for(url, authority.verifying_key().to_bytes());
+        let mix_client = MixClient::new(MixClientConfig::default());
+
+        let result = directory.refresh(&mix_client).await;
+        assert!(matches!(result, Err(TransportError::DirectoryError(_))));
+
+        let stats = mix_client.stats().await;
+        assert_eq!(stats.known_gateways, 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_document_rejected() {
+        let body = " ".repeat(MAX_DIRECTORY_BYTES + 1);
+        for content_length in [true, false] {
+            let url = serve_response(body.clone(), content_length).await;
+            let directory = client_for(url, [0u8; 32]);
+            assert!(matches!(
+                directory.fetch().await,
+                Err(TransportError::DirectoryError(msg)) if msg.contains("exceeds")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_expired_document_rejected() {
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let signed = SignedDirectory::sign(&test_document(1_000), &authority).unwrap();
+        let key = authority.verifying_key().to_bytes();
+
+        assert!(signed.verify(&key, 999).is_ok());
+        assert!(matches!(
+            signed.verify(&key, 1_000),
+            Err(TransportError::DirectoryError(_))
+        ));
+    }
+
+    #[test]
+    fn test_tampered_document_rejected() {
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let mut signed = SignedDirectory::sign(&test_document(1_000), &authority).unwrap();
+        signed.document = signed.document.replace("127.0.0.1:9001", "10.0.0.66:9001");
+
+        let result = signed.verify(&authority.verifying_key().to_bytes(), 0);
+        assert!(matches!(result, Err(TransportError::DirectoryError(_))));
+    }
+}
@@ -0,0 +1,182 @@
+//! # Message Envelope
+//!
+//! Wraps an end-to-end ciphertext for delivery through the mixnet together
+//! with an optional reply SURB, so an anonymous sender can still be answered.
+//!
+//! ## Wire Format
+//!
+//! ```text
+//! [version: u8][flags: u8][ct_len: u32 LE][ciphertext][surb_len: u32 LE][surb]?
+//! ```
+//!
+//! The SURB length and bytes are only present when flag bit 0 is set.
+
+use crate::mixnet::Surb;
+use crate::{Result, TransportError};
+
+/// Current envelope format version.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Flag bit indicating an embedded reply SURB.
+const FLAG_HAS_SURB: u8 = 0b0000_0001;
+
+/// A ciphertext with an optional embedded reply SURB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// End-to-end encrypted message (opaque to the transport).
+    pub ciphertext: Vec<u8>,
+    /// Serialized [`Surb`] the recipient can use for a single reply.
+    pub reply_surb: Option<Vec<u8>>,
+}
+
+impl Envelope {
+    /// Create an envelope without a reply SURB.
+    pub fn new(ciphertext: Vec<u8>) -> Self {
+        Self {
+            ciphertext,
+            reply_surb: None,
+        }
+    }
+
+    /// Create an envelope carrying a reply SURB.
+    pub fn with_surb(ciphertext: Vec<u8>, surb: &Surb) -> Self {
+        Self {
+            ciphertext,
+            reply_surb: Some(surb.to_bytes()),
+        }
+    }
+
+    /// Serialize the envelope to bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let surb_len = self.reply_surb.as_ref().map(|s| 4 + s.len()).unwrap_or(0);
+        let mut bytes = Vec::with_capacity(2 + 4 + self.ciphertext.len() + surb_len);
+
+        bytes.push(ENVELOPE_VERSION);
+        bytes.push(if self.reply_surb.is_some() {
+            FLAG_HAS_SURB
+        } else {
+            0
+        });
+        bytes.extend_from_slice(&(self.ciphertext.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.ciphertext);
+
+        if let Some(surb) = &self.reply_surb {
+            bytes.extend_from_slice(&(surb.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(surb);
+        }
+
+        bytes
+    }
+
+    /// Parse an envelope from bytes.
+    ///
+    /// Trailing bytes (e.g. Sphinx payload padding) are ignored.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        let version = reader.take(1)?[0];
+        if version != ENVELOPE_VERSION {
+            return Err(TransportError::EnvelopeError(format!(
+                "Unsupported envelope version {}",
+                version
+            )));
+        }
+        let flags = reader.take(1)?[0];
+
+        let ct_len = reader.take_u32()? as usize;
+        let ciphertext = reader.take(ct_len)?.to_vec();
+
+        let reply_surb = if flags & FLAG_HAS_SURB != 0 {
+            let surb_len = reader.take_u32()? as usize;
+            Some(reader.take(surb_len)?.to_vec())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            ciphertext,
+            reply_surb,
+        })
+    }
+
+    /// Decode the embedded reply SURB, if any.
+    pub fn decode_surb(&self) -> Result<Option<Surb>> {
+        self.reply_surb.as_deref().map(Surb::from_bytes).transpose()
+    }
+}
+
+/// Bounds-checked cursor over a byte slice.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| TransportError::EnvelopeError("Envelope truncated".into()))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn take_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_surb() -> Surb {
+        Surb {
+            header_bytes: vec![9u8; 512],
+            first_hop: "127.0.0.1:9001".into(),
+            reply_key: [7u8; 32],
+        }
+    }
+
+    #[test]
+    fn test_envelope_roundtrip_without_surb() {
+        let envelope = Envelope::new(b"ciphertext".to_vec());
+        let parsed = Envelope::deserialize(&envelope.serialize()).unwrap();
+
+        assert_eq!(parsed, envelope);
+        assert!(parsed.decode_surb().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_envelope_roundtrip_with_surb() {
+        let surb = test_surb();
+        let envelope = Envelope::with_surb(b"ciphertext".to_vec(), &surb);
+
+        // Padding after the envelope (as in a fixed-size Sphinx payload) is ignored
+        let mut bytes = envelope.serialize();
+        bytes.extend_from_slice(&[0u8; 64]);
+
+        let parsed = Envelope::deserialize(&bytes).unwrap();
+        assert_eq!(parsed, envelope);
+
+        let decoded = parsed.decode_surb().unwrap().unwrap();
+        assert_eq!(decoded.header_bytes, surb.header_bytes);
+        assert_eq!(decoded.first_hop, surb.first_hop);
+        assert_eq!(decoded.reply_key, surb.reply_key);
+    }
+
+    #[test]
+    fn test_truncated_envelope_rejected() {
+        let bytes = Envelope::with_surb(b"ciphertext".to_vec(), &test_surb()).serialize();
+
+        for len in [0, 1, 5, 10, bytes.len() - 1] {
+            assert!(matches!(
+                Envelope::deserialize(&bytes[..len]),
+                Err(TransportError::EnvelopeError(_))
+            ));
+        }
+    }
+}
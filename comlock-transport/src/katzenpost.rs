@@ -2,13 +2,38 @@
 //!
 //! Integration with the Katzenpost mixnet for anonymous message transport.
 //! Uses the thin client library to communicate with kpclientd daemon.
+//!
+//! ## Wire protocol
+//!
+//! Messages travel over the daemon's TCP socket as length-prefixed frames:
+//!
+//! ```text
+//! [length: u32 BE][bincode-encoded MixnetMessage]
+//! ```
+//!
+//! The daemon delivers inbound messages in the same format, with
+//! `recipient_id` naming our queue; the sender stays anonymous.
 
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
 
 use crate::{Result, TransportError};
 
+/// Upper bound on the delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Size of the frame length prefix in bytes.
+const FRAME_HEADER_SIZE: usize = 4;
+
+/// Largest frame body accepted in either direction.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
 /// Connection status for the Katzenpost client.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ConnectionStatus {
@@ -69,6 +94,8 @@ pub struct ReceivedMixnetMessage {
 ///
 /// This client communicates with the kpclientd daemon which handles
 /// the actual mixnet protocol (Sphinx packets, routing, timing).
+/// Clones share the same status and queues.
+#[derive(Clone)]
 pub struct KatzenpostClient {
     config: KatzenpostConfig,
     status: Arc<RwLock<ConnectionStatus>>,
@@ -76,6 +103,10 @@ pub struct KatzenpostClient {
     outgoing_queue: Arc<RwLock<Vec<MixnetMessage>>>,
     /// Received messages buffer.
     received_messages: Arc<RwLock<Vec<ReceivedMixnetMessage>>>,
+    /// Write half of the daemon connection while connected.
+    writer: Arc<Mutex<Option<OwnedWriteHalf>>>,
+    /// Task reading inbound frames from the daemon.
+    reader: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl KatzenpostClient {
@@ -86,6 +117,8 @@ impl KatzenpostClient {
             status: Arc::new(RwLock::new(ConnectionStatus::Disconnected)),
             outgoing_queue: Arc::new(RwLock::new(Vec::new())),
             received_messages: Arc::new(RwLock::new(Vec::new())),
+            writer: Arc::new(Mutex::new(None)),
+            reader: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -103,43 +136,211 @@ impl KatzenpostClient {
     ///
     /// This checks if the daemon is available and establishes communication.
     pub async fn connect(&self) -> Result<()> {
+        // Don't fail - queue messages for later delivery
+        self.try_connect().await;
+        Ok(())
+    }
+
+    /// Connect, retrying with exponential backoff and jitter.
+    ///
+    /// The delay before attempt `n + 1` is `base_delay * 2^(n - 1)` plus up
+    /// to 50% random jitter, capped at 30 seconds. On success the outgoing
+    /// queue is flushed and the number of attempts used is returned.
+    pub async fn connect_with_retry(&self, max_attempts: u32, base_delay: Duration) -> Result<u32> {
+        for attempt in 1..=max_attempts {
+            if self.try_connect().await {
+                let flushed = self.flush_queue().await?;
+                if flushed > 0 {
+                    tracing::info!("Flushed {} queued messages after reconnect", flushed);
+                }
+                return Ok(attempt);
+            }
+
+            if attempt < max_attempts {
+                let delay = Self::backoff_delay(base_delay, attempt);
+                tracing::debug!(
+                    "Reconnect attempt {}/{} failed, retrying in {:?}",
+                    attempt,
+                    max_attempts,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        Err(TransportError::NetworkError(format!(
+            "Failed to connect to kpclientd after {} attempts",
+            max_attempts
+        )))
+    }
+
+    /// Spawn a background task that keeps the daemon connection alive.
+    ///
+    /// Every `check_interval` the task probes the daemon while connected and
+    /// marks the status as `Error` if it is unreachable. From `Error` it goes
+    /// back to `Connecting` and reconnects via [`Self::connect_with_retry`].
+    /// The task ends once the client is explicitly disconnected.
+    pub fn spawn_keepalive(
+        &self,
+        check_interval: Duration,
+        max_attempts: u32,
+        base_delay: Duration,
+    ) -> JoinHandle<()> {
+        let client = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(check_interval).await;
+
+                match client.status().await {
+                    ConnectionStatus::Disconnected => break,
+                    ConnectionStatus::Connected => {
+                        if tokio::net::TcpStream::connect(&client.config.daemon_address)
+                            .await
+                            .is_err()
+                        {
+                            tracing::warn!("Lost connection to kpclientd");
+                            *client.status.write().await =
+                                ConnectionStatus::Error("Keepalive probe failed".into());
+                        }
+                    }
+                    ConnectionStatus::Error(_) => {
+                        if let Err(e) = client.connect_with_retry(max_attempts, base_delay).await {
+                            tracing::warn!("{}", e);
+                        }
+                    }
+                    ConnectionStatus::Connecting => {}
+                }
+            }
+        })
+    }
+
+    /// Make a single connection attempt, updating the status.
+    async fn try_connect(&self) -> bool {
         *self.status.write().await = ConnectionStatus::Connecting;
 
         // Try to connect to the daemon via TCP
         match tokio::net::TcpStream::connect(&self.config.daemon_address).await {
-            Ok(_stream) => {
+            Ok(stream) => {
                 tracing::info!("Connected to kpclientd at {}", self.config.daemon_address);
+                let (read_half, write_half) = stream.into_split();
+                *self.writer.lock().await = Some(write_half);
+
+                let read_loop = tokio::spawn(self.clone().read_loop(read_half));
+                if let Some(previous) = self.reader.lock().await.replace(read_loop) {
+                    previous.abort();
+                }
+
                 *self.status.write().await = ConnectionStatus::Connected;
-                Ok(())
+                true
             }
             Err(e) => {
                 let error_msg = format!("Failed to connect to kpclientd: {}", e);
                 tracing::warn!("{}", error_msg);
-                *self.status.write().await = ConnectionStatus::Error(error_msg.clone());
+                *self.status.write().await = ConnectionStatus::Error(error_msg);
+                false
+            }
+        }
+    }
 
-                // Don't fail - queue messages for later delivery
-                Ok(())
+    /// Read inbound frames into the received buffer until the connection
+    /// ends or a malformed frame arrives.
+    async fn read_loop(self, mut read_half: OwnedReadHalf) {
+        loop {
+            match read_frame(&mut read_half).await {
+                Ok(Some(message)) => {
+                    tracing::debug!(size = message.payload.len(), "Received mixnet message");
+                    self.received_messages
+                        .write()
+                        .await
+                        .push(ReceivedMixnetMessage {
+                            sender_id: None,
+                            payload: message.payload,
+                            received_at: std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map(|d| d.as_secs() as i64)
+                                .unwrap_or(0),
+                        });
+                }
+                Ok(None) => {
+                    self.connection_lost("kpclientd closed the connection")
+                        .await;
+                    break;
+                }
+                Err(e) => {
+                    self.connection_lost(&e.to_string()).await;
+                    break;
+                }
             }
         }
     }
 
+    /// Drop the connection and mark it failed, unless the client has been
+    /// disconnected meanwhile.
+    async fn connection_lost(&self, reason: &str) {
+        self.writer.lock().await.take();
+        let mut status = self.status.write().await;
+        if *status == ConnectionStatus::Connected {
+            tracing::warn!("Lost connection to kpclientd: {}", reason);
+            *status = ConnectionStatus::Error(reason.into());
+        }
+    }
+
+    /// Write one encoded frame to the daemon connection.
+    async fn write_frame(&self, frame: &[u8]) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        let stream = writer
+            .as_mut()
+            .ok_or_else(|| TransportError::NetworkError("Not connected to kpclientd".into()))?;
+
+        if let Err(e) = stream.write_all(frame).await {
+            writer.take();
+            return Err(TransportError::NetworkError(format!(
+                "Failed to write to kpclientd: {}",
+                e
+            )));
+        }
+        Ok(())
+    }
+
+    /// Backoff before the attempt following `attempt` (1-based).
+    fn backoff_delay(base_delay: Duration, attempt: u32) -> Duration {
+        let exponential = base_delay
+            .saturating_mul(1u32 << (attempt - 1).min(16))
+            .min(MAX_BACKOFF);
+        let jitter_ms = exponential.as_millis() as u64 / 2;
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms));
+        (exponential + jitter).min(MAX_BACKOFF)
+    }
+
     /// Disconnect from the daemon.
     pub async fn disconnect(&self) {
         *self.status.write().await = ConnectionStatus::Disconnected;
+        self.writer.lock().await.take();
+        if let Some(read_loop) = self.reader.lock().await.take() {
+            read_loop.abort();
+        }
     }
 
     /// Send a message through the mixnet.
     ///
-    /// If not connected, the message is queued for later delivery.
+    /// If not connected, or the write to the daemon fails, the message is
+    /// queued for later delivery. Messages that cannot fit in a frame are
+    /// rejected.
     pub async fn send_message(&self, message: MixnetMessage) -> Result<String> {
+        let frame = encode_frame(&message)?;
         let status = self.status.read().await.clone();
 
         match status {
             ConnectionStatus::Connected => {
-                // In production, this would use the thin client API:
-                // client.send(recipient_id, message, surb)
+                if let Err(e) = self.write_frame(&frame).await {
+                    self.connection_lost(&e.to_string()).await;
+                    self.outgoing_queue.write().await.push(message);
+                    let message_id = format!("queued_{}", rand::random::<u64>());
+                    tracing::debug!("Message {} queued after failed write", message_id);
+                    return Ok(message_id);
+                }
 
-                // For now, simulate successful send
                 let message_id = format!("kp_{}", rand::random::<u64>());
                 tracing::info!("Sent message {} via mixnet", message_id);
                 Ok(message_id)
@@ -156,21 +357,11 @@ impl KatzenpostClient {
 
     /// Poll for received messages.
     ///
-    /// Returns all messages received since last poll.
+    /// Returns all messages received since last poll. Messages read before
+    /// a disconnect are still returned.
     pub async fn receive_messages(&self) -> Result<Vec<ReceivedMixnetMessage>> {
-        let status = self.status.read().await.clone();
-
-        if status != ConnectionStatus::Connected {
-            // Return buffered messages
-            let messages = self.received_messages.write().await.drain(..).collect();
-            return Ok(messages);
-        }
-
-        // In production, this would poll the thin client:
-        // client.receive() -> Vec<Message>
-
-        // For now, return empty (no daemon polling implemented)
-        Ok(Vec::new())
+        let messages = self.received_messages.write().await.drain(..).collect();
+        Ok(messages)
     }
 
     /// Get the number of queued outgoing messages.
@@ -187,14 +378,20 @@ impl KatzenpostClient {
         }
 
         let mut queue = self.outgoing_queue.write().await;
-        let count = queue.len();
-
-        // In production, send each queued message
-        for _message in queue.drain(..) {
-            // client.send(message.recipient_id, message.payload, message.surb)
+        let mut sent = 0;
+
+        // Stop at the first failure, keeping it and the rest queued
+        while let Some(message) = queue.first() {
+            if let Err(e) = self.write_frame(&encode_frame(message)?).await {
+                drop(queue);
+                self.connection_lost(&e.to_string()).await;
+                return Err(e);
+            }
+            queue.remove(0);
+            sent += 1;
         }
 
-        Ok(count)
+        Ok(sent)
     }
 
     /// Get configuration.
@@ -203,6 +400,59 @@ impl KatzenpostClient {
     }
 }
 
+/// Encode a message as a length-prefixed frame.
+fn encode_frame(message: &MixnetMessage) -> Result<Vec<u8>> {
+    let body = bincode::serialize(message)
+        .map_err(|e| TransportError::NetworkError(format!("Failed to encode frame: {}", e)))?;
+    if body.len() > MAX_FRAME_SIZE {
+        return Err(TransportError::NetworkError(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            body.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Read one frame, waiting for partial reads to complete.
+///
+/// Returns `None` if the stream ends cleanly before a new frame starts.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<MixnetMessage>> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => {
+            return Err(TransportError::NetworkError(format!(
+                "Failed to read frame: {}",
+                e
+            )));
+        }
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(TransportError::NetworkError(format!(
+            "Frame of {} bytes exceeds the {} byte limit",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+
+    let mut body = vec![0u8; len];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| TransportError::NetworkError(format!("Truncated frame: {}", e)))?;
+
+    bincode::deserialize(&body)
+        .map(Some)
+        .map_err(|e| TransportError::NetworkError(format!("Malformed frame: {}", e)))
+}
+
 /// Builder for KatzenpostClient.
 pub struct KatzenpostClientBuilder {
     config: KatzenpostConfig,
@@ -273,6 +523,174 @@ mod tests {
         assert_eq!(client.queued_count().await, 1);
     }
 
+    /// An address on which nothing is listening (yet).
+    async fn unused_address() -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_with_retry_succeeds_on_third_attempt() {
+        let addr = unused_address().await;
+        let client = KatzenpostClientBuilder::new()
+            .daemon_address(addr.to_string())
+            .build();
+
+        client
+            .send_message(MixnetMessage {
+                recipient_id: vec![1],
+                payload: b"queued".to_vec(),
+                surb: None,
+            })
+            .await
+            .unwrap();
+
+        // Attempts land at ~0ms, 100-150ms and 300-450ms; the daemon comes
+        // up in between the second and third
+        let listener = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(220)).await;
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(listener);
+        });
+
+        let attempts = client
+            .connect_with_retry(5, Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        assert_eq!(attempts, 3);
+        assert_eq!(client.status().await, ConnectionStatus::Connected);
+        assert_eq!(client.queued_count().await, 0);
+        listener.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_with_retry_gives_up() {
+        let addr = unused_address().await;
+        let client = KatzenpostClientBuilder::new()
+            .daemon_address(addr.to_string())
+            .build();
+
+        let result = client.connect_with_retry(2, Duration::from_millis(5)).await;
+        assert!(matches!(result, Err(TransportError::NetworkError(_))));
+        assert!(matches!(client.status().await, ConnectionStatus::Error(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_reconnects_from_error() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = KatzenpostClientBuilder::new()
+            .daemon_address(listener.local_addr().unwrap().to_string())
+            .build();
+        *client.status.write().await = ConnectionStatus::Error("dropped".into());
+
+        let keepalive =
+            client.spawn_keepalive(Duration::from_millis(10), 3, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(client.status().await, ConnectionStatus::Connected);
+
+        // Disconnecting stops the task
+        client.disconnect().await;
+        tokio::time::timeout(Duration::from_secs(1), keepalive)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_frames_roundtrip_through_mock_daemon() {
+        // A mock daemon that echoes every frame back, split across writes
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let daemon = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; FRAME_HEADER_SIZE];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut body = vec![0u8; u32::from_be_bytes(header) as usize];
+            stream.read_exact(&mut body).await.unwrap();
+
+            let mut frame = header.to_vec();
+            frame.extend_from_slice(&body);
+            let (first, rest) = frame.split_at(3);
+            stream.write_all(first).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            stream.write_all(rest).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+
+        let client = KatzenpostClientBuilder::new()
+            .daemon_address(addr.to_string())
+            .build();
+        client.connect().await.unwrap();
+        assert_eq!(client.status().await, ConnectionStatus::Connected);
+
+        let message_id = client
+            .send_message(MixnetMessage {
+                recipient_id: vec![7; 32],
+                payload: b"echo me".to_vec(),
+                surb: None,
+            })
+            .await
+            .unwrap();
+        assert!(message_id.starts_with("kp_"));
+
+        let mut received = Vec::new();
+        for _ in 0..50 {
+            received.extend(client.receive_messages().await.unwrap());
+            if !received.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].payload, b"echo me");
+        assert!(received[0].sender_id.is_none());
+
+        client.disconnect().await;
+        daemon.abort();
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_oversized_and_truncated() {
+        let oversized = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes();
+        assert!(read_frame(&mut &oversized[..]).await.is_err());
+
+        let mut truncated = 10u32.to_be_bytes().to_vec();
+        truncated.extend_from_slice(&[0; 4]);
+        assert!(read_frame(&mut &truncated[..]).await.is_err());
+
+        assert!(read_frame(&mut &[][..]).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_is_rejected() {
+        let client = KatzenpostClient::with_defaults();
+        *client.status.write().await = ConnectionStatus::Connected;
+
+        let result = client
+            .send_message(MixnetMessage {
+                recipient_id: vec![1],
+                payload: vec![0; MAX_FRAME_SIZE + 1],
+                surb: None,
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(client.queued_count().await, 0);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let base = Duration::from_millis(100);
+        for attempt in 1..=4 {
+            let expected = base * (1 << (attempt - 1));
+            let delay = KatzenpostClient::backoff_delay(base, attempt);
+            assert!(delay >= expected && delay <= expected + expected / 2);
+        }
+        assert_eq!(KatzenpostClient::backoff_delay(base, 30), MAX_BACKOFF);
+    }
+
     #[tokio::test]
     async fn test_builder() {
         let client = KatzenpostClientBuilder::new()
@@ -26,15 +26,28 @@
 #![warn(clippy::all)]
 
 pub mod cover;
+pub mod directory;
+pub mod envelope;
 pub mod katzenpost;
 pub mod mixnet;
+pub mod session;
 pub mod sphinx;
 
-pub use cover::{AnonymityBudget, CoverTrafficGenerator};
+pub use cover::{
+    AdaptiveBudget, AnonymityBudget, BatterySource, CoverTrafficGenerator, LoopTracker,
+    ManualBatterySource, TrafficShape,
+};
+pub use directory::{DirectoryClient, DirectoryConfig};
+pub use envelope::Envelope;
 pub use katzenpost::{ConnectionStatus, KatzenpostClient, KatzenpostConfig, MixnetMessage};
-pub use mixnet::{Mailbox, MixClient, MixClientConfig};
-pub use sphinx::{SphinxHeader, SphinxPacket, PACKET_SIZE};
+pub use mixnet::{
+    MAILBOX_EPOCH_SECS, MAILBOX_EPOCH_WINDOW, Mailbox, MailboxCursor, MixClient, MixClientConfig,
+    mailbox_epoch, mailbox_id_for_epoch, mailbox_ids_around_epoch,
+};
+pub use session::SessionManager;
+pub use sphinx::{PACKET_SIZE, SphinxHeader, SphinxPacket, self_test};
 
+use sphinx::MAX_HOPS;
 use thiserror::Error;
 
 /// Errors that can occur in the transport layer.
@@ -67,6 +80,18 @@ pub enum TransportError {
     /// Mailbox polling failed.
     #[error("Mailbox error: {0}")]
     MailboxError(String),
+
+    /// Directory document could not be verified.
+    #[error("Directory error: {0}")]
+    DirectoryError(String),
+
+    /// Message envelope is malformed.
+    #[error("Envelope error: {0}")]
+    EnvelopeError(String),
+
+    /// No session with the given ID.
+    #[error("Unknown session: {0}")]
+    UnknownSession(String),
 }
 
 /// Result type for transport operations.
@@ -88,6 +113,13 @@ impl NodeId {
     }
 }
 
+/// Short hex form (first 8 bytes) for logs.
+impl std::fmt::Display for NodeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(&self.0[..8]))
+    }
+}
+
 /// A node in the mixnet with its public key and address.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MixNode {
@@ -99,6 +131,14 @@ pub struct MixNode {
     pub address: String,
     /// Layer in the stratified topology (1=Gateway, 2=Mix, 3=Exit).
     pub layer: u8,
+    /// Relative capacity used to weight route selection within a layer.
+    #[serde(default = "default_bandwidth_weight")]
+    pub bandwidth_weight: u32,
+}
+
+/// Weight assumed for nodes that do not report a bandwidth.
+pub(crate) fn default_bandwidth_weight() -> u32 {
+    1
 }
 
 /// A route through the mixnet.
@@ -108,9 +148,75 @@ pub struct Route {
     pub nodes: Vec<MixNode>,
 }
 
+/// Layer of gateway (entry) nodes.
+const GATEWAY_LAYER: u8 = 1;
+
+/// Layer of exit (provider) nodes.
+const EXIT_LAYER: u8 = 3;
+
 impl Route {
     /// Create a new route from a list of nodes.
+    ///
+    /// The route must have between 3 and [`MAX_HOPS`] nodes, start at a
+    /// gateway, end at an exit node, and never step back to a lower layer.
     pub fn new(nodes: Vec<MixNode>) -> Result<Self> {
+        Self::with_max_hops(nodes, MAX_HOPS)
+    }
+
+    /// Create a new route, allowing at most `max_hops` nodes.
+    ///
+    /// `max_hops` is capped at [`MAX_HOPS`], the most a Sphinx header can
+    /// carry. Layer ordering is validated as in [`Route::new`].
+    pub fn with_max_hops(nodes: Vec<MixNode>, max_hops: usize) -> Result<Self> {
+        Self::check_length(&nodes, max_hops.min(MAX_HOPS))?;
+
+        if nodes[0].layer != GATEWAY_LAYER {
+            return Err(TransportError::InvalidRoute(format!(
+                "First hop must be a gateway (layer {}), got layer {}",
+                GATEWAY_LAYER, nodes[0].layer
+            )));
+        }
+        let last = &nodes[nodes.len() - 1];
+        if last.layer != EXIT_LAYER {
+            return Err(TransportError::InvalidRoute(format!(
+                "Last hop must be an exit node (layer {}), got layer {}",
+                EXIT_LAYER, last.layer
+            )));
+        }
+        if let Some(i) = nodes
+            .windows(2)
+            .position(|pair| pair[1].layer < pair[0].layer)
+        {
+            return Err(TransportError::InvalidRoute(format!(
+                "Hop {} (layer {}) follows hop {} (layer {}); layers must not decrease",
+                i + 1,
+                nodes[i + 1].layer,
+                i,
+                nodes[i].layer
+            )));
+        }
+
+        Ok(Self { nodes })
+    }
+
+    /// Create a loop route that leaves from and returns to a gateway.
+    ///
+    /// Used for cover loops, which come back to the sender instead of
+    /// ending at an exit node.
+    pub fn new_loop(nodes: Vec<MixNode>) -> Result<Self> {
+        Self::check_length(&nodes, MAX_HOPS)?;
+
+        if nodes[0].layer != GATEWAY_LAYER || nodes[nodes.len() - 1].layer != GATEWAY_LAYER {
+            return Err(TransportError::InvalidRoute(
+                "Loop route must start and end at a gateway".into(),
+            ));
+        }
+
+        Ok(Self { nodes })
+    }
+
+    /// Check the hop count is within `3..=max_hops`.
+    fn check_length(nodes: &[MixNode], max_hops: usize) -> Result<()> {
         if nodes.is_empty() {
             return Err(TransportError::InvalidRoute("Route cannot be empty".into()));
         }
@@ -119,7 +225,14 @@ impl Route {
                 "Route must have at least 3 hops (L1→L2→L3)".into(),
             ));
         }
-        Ok(Self { nodes })
+        if nodes.len() > max_hops {
+            return Err(TransportError::InvalidRoute(format!(
+                "Route has {} hops, maximum is {}",
+                nodes.len(),
+                max_hops
+            )));
+        }
+        Ok(())
     }
 
     /// Get the entry (gateway) node.
@@ -143,23 +256,66 @@ mod tests {
         assert_eq!(id.as_bytes(), &[42u8; 32]);
     }
 
+    fn node(layer: u8) -> MixNode {
+        MixNode {
+            id: NodeId::new([layer; 32]),
+            public_key: [layer + 1; 32],
+            address: format!("127.0.0.1:900{}", layer),
+            layer,
+            bandwidth_weight: 1,
+        }
+    }
+
     #[test]
     fn test_route_validation() {
-        let node = MixNode {
-            id: NodeId::new([1u8; 32]),
-            public_key: [2u8; 32],
-            address: "127.0.0.1:9000".into(),
-            layer: 1,
-        };
-
         // Empty route should fail
         assert!(Route::new(vec![]).is_err());
 
         // Single node should fail
-        assert!(Route::new(vec![node.clone()]).is_err());
+        assert!(Route::new(vec![node(1)]).is_err());
 
         // 3 nodes should succeed
-        let route = Route::new(vec![node.clone(), node.clone(), node.clone()]);
+        let route = Route::new(vec![node(1), node(2), node(3)]);
         assert!(route.is_ok());
     }
+
+    #[test]
+    fn test_route_rejects_too_many_hops() {
+        let nodes = vec![node(1), node(2), node(2), node(2), node(2), node(3)];
+        assert!(matches!(
+            Route::new(nodes),
+            Err(TransportError::InvalidRoute(msg)) if msg.contains("maximum is 5")
+        ));
+
+        let nodes = vec![node(1), node(2), node(2), node(3)];
+        assert!(Route::with_max_hops(nodes, 3).is_err());
+    }
+
+    #[test]
+    fn test_route_rejects_out_of_order_layers() {
+        for nodes in [
+            vec![node(2), node(1), node(3)],
+            vec![node(1), node(3), node(2)],
+            vec![node(1), node(3), node(2), node(3)],
+        ] {
+            assert!(matches!(
+                Route::new(nodes),
+                Err(TransportError::InvalidRoute(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_four_hop_route() {
+        let route = Route::new(vec![node(1), node(2), node(2), node(3)]).unwrap();
+        assert_eq!(route.nodes.len(), 4);
+        assert_eq!(route.entry().layer, 1);
+        assert_eq!(route.exit().layer, 3);
+    }
+
+    #[test]
+    fn test_loop_route_returns_to_gateway() {
+        assert!(Route::new_loop(vec![node(1), node(2), node(1)]).is_ok());
+        assert!(Route::new_loop(vec![node(1), node(2), node(3)]).is_err());
+    }
 }
@@ -3,13 +3,19 @@
 //! Implements the Loopix-style mixnet client for anonymous message delivery.
 //! Handles routing through the stratified topology and mailbox polling.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
+use hkdf::Hkdf;
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use sha2::{Digest, Sha256};
 use tokio::sync::{RwLock, mpsc};
 use tokio::time::{Duration, Instant};
 
-use crate::sphinx::SphinxPacket;
+use crate::cover::LoopTracker;
+use crate::envelope::Envelope;
+use crate::sphinx::{PACKET_SIZE, SphinxPacket};
 use crate::{MixNode, NodeId, Result, Route, TransportError};
 
 /// Configuration for the mix client.
@@ -25,6 +31,10 @@ pub struct MixClientConfig {
     pub poll_interval: Duration,
     /// Maximum retries for failed sends.
     pub max_retries: u32,
+    /// How long a send waits for room in a full outgoing queue.
+    pub queue_timeout: Duration,
+    /// Upper bound on any single node's selection probability within a layer.
+    pub max_selection_probability: f64,
 }
 
 impl Default for MixClientConfig {
@@ -36,23 +46,106 @@ impl Default for MixClientConfig {
                 public_key: [0u8; 32],
                 address: "127.0.0.1:9000".into(),
                 layer: 1,
+                bandwidth_weight: 1,
             },
             timeout: Duration::from_secs(30),
             poll_interval: Duration::from_secs(5),
             max_retries: 3,
+            queue_timeout: Duration::from_secs(2),
+            max_selection_probability: 0.9,
         }
     }
 }
 
+/// Capacity of the outgoing packet queue.
+pub const OUTGOING_QUEUE_CAPACITY: usize = 100;
+
+/// Most message IDs remembered for de-duplication; the oldest are forgotten
+/// first.
+pub const MAX_SEEN_MESSAGES: usize = 4096;
+
 /// A mailbox for receiving messages.
 #[derive(Debug, Clone)]
 pub struct Mailbox {
-    /// Unique mailbox identifier.
+    /// Unique mailbox identifier; see [`mailbox_id_for_epoch`] for
+    /// rotating IDs.
     pub id: [u8; 32],
     /// Exit node hosting this mailbox.
     pub provider: MixNode,
 }
 
+/// Length of one mailbox epoch in seconds.
+pub const MAILBOX_EPOCH_SECS: u64 = 3600;
+
+/// Epochs on either side of the current one still accepted for clock skew.
+pub const MAILBOX_EPOCH_WINDOW: u64 = 1;
+
+/// HKDF info prefix for per-epoch mailbox IDs.
+const MAILBOX_EPOCH_INFO: &[u8] = b"ComLock-Mailbox-Epoch-v1";
+
+/// Epoch number containing `unix_secs`.
+pub fn mailbox_epoch(unix_secs: u64) -> u64 {
+    unix_secs / MAILBOX_EPOCH_SECS
+}
+
+/// Mailbox ID for `epoch`, derived from a long-term mailbox seed.
+///
+/// Sender and receiver share the seed and compute the same ID, while the
+/// provider sees an unrelated pseudonym in every epoch.
+pub fn mailbox_id_for_epoch(seed: &[u8; 32], epoch: u64) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(None, seed);
+    let mut info = Vec::with_capacity(MAILBOX_EPOCH_INFO.len() + 8);
+    info.extend_from_slice(MAILBOX_EPOCH_INFO);
+    info.extend_from_slice(&epoch.to_le_bytes());
+
+    let mut id = [0u8; 32];
+    hk.expand(&info, &mut id)
+        .expect("32 bytes is a valid HKDF output length");
+    id
+}
+
+/// Mailbox IDs for `epoch` and the [`MAILBOX_EPOCH_WINDOW`] epochs around it.
+///
+/// A receiver fetches from all of them so messages addressed by a sender
+/// whose clock is slightly off still arrive.
+pub fn mailbox_ids_around_epoch(seed: &[u8; 32], epoch: u64) -> Vec<(u64, [u8; 32])> {
+    let first = epoch.saturating_sub(MAILBOX_EPOCH_WINDOW);
+    let last = epoch.saturating_add(MAILBOX_EPOCH_WINDOW);
+    (first..=last)
+        .map(|e| (e, mailbox_id_for_epoch(seed, e)))
+        .collect()
+}
+
+impl Mailbox {
+    /// Mailbox at `provider` addressed by the pseudonym for `epoch`.
+    pub fn for_epoch(seed: &[u8; 32], epoch: u64, provider: MixNode) -> Self {
+        Self {
+            id: mailbox_id_for_epoch(seed, epoch),
+            provider,
+        }
+    }
+}
+
+/// Continuation point for [`MixClient::poll_mailbox_paged`].
+///
+/// The cursor is a random token naming the last message handed out, so it
+/// reveals nothing about message order, count or arrival time. It can be
+/// persisted with [`MailboxCursor::to_bytes`] to resume after a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxCursor([u8; 16]);
+
+impl MailboxCursor {
+    /// Opaque byte form of the cursor.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Restore a cursor from [`MailboxCursor::to_bytes`].
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+}
+
 /// Single Use Reply Block for anonymous responses.
 #[derive(Debug, Clone)]
 pub struct Surb {
@@ -64,9 +157,52 @@ pub struct Surb {
     pub reply_key: [u8; 32],
 }
 
+impl Surb {
+    /// Serialize the SURB for embedding in an [`Envelope`].
+    ///
+    /// Format: `[first_hop_len: u16 LE][first_hop][reply_key: 32][header_bytes]`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hop = self.first_hop.as_bytes();
+        let mut bytes = Vec::with_capacity(2 + hop.len() + 32 + self.header_bytes.len());
+        bytes.extend_from_slice(&(hop.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(hop);
+        bytes.extend_from_slice(&self.reply_key);
+        bytes.extend_from_slice(&self.header_bytes);
+        bytes
+    }
+
+    /// Parse a SURB from bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || TransportError::EnvelopeError("Invalid SURB".into());
+
+        let hop_len = bytes
+            .get(0..2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(invalid)?;
+        let key_start = 2 + hop_len;
+        let header_start = key_start + 32;
+        if bytes.len() < header_start {
+            return Err(invalid());
+        }
+
+        let first_hop = String::from_utf8(bytes[2..key_start].to_vec()).map_err(|_| invalid())?;
+        let reply_key: [u8; 32] = bytes[key_start..header_start]
+            .try_into()
+            .map_err(|_| invalid())?;
+
+        Ok(Self {
+            header_bytes: bytes[header_start..].to_vec(),
+            first_hop,
+            reply_key,
+        })
+    }
+}
+
 /// Message received from the mixnet.
 #[derive(Debug, Clone)]
 pub struct ReceivedMessage {
+    /// Unique message ID, see [`message_id`].
+    pub id: [u8; 32],
     /// Decrypted payload.
     pub payload: Vec<u8>,
     /// Optional SURB for replying.
@@ -75,18 +211,82 @@ pub struct ReceivedMessage {
     pub received_at: Instant,
 }
 
+impl ReceivedMessage {
+    /// Build a received message from delivered envelope bytes.
+    pub fn from_envelope_bytes(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::deserialize(bytes)?;
+        let reply_surb = envelope.decode_surb()?;
+
+        Ok(Self {
+            id: message_id(&envelope.ciphertext),
+            payload: envelope.ciphertext,
+            reply_surb,
+            received_at: Instant::now(),
+        })
+    }
+
+    /// Take the reply SURB; it can only be used for a single reply.
+    pub fn take_reply_surb(&mut self) -> Option<Surb> {
+        self.reply_surb.take()
+    }
+}
+
+/// ID of a message with end-to-end ciphertext `ciphertext`: its SHA-256.
+///
+/// Every ciphertext is encrypted under a fresh key and nonce, so a
+/// retried delivery has the same ID and distinct messages do not.
+pub fn message_id(ciphertext: &[u8]) -> [u8; 32] {
+    Sha256::digest(ciphertext).into()
+}
+
+/// IDs of recently delivered messages, capped at [`MAX_SEEN_MESSAGES`].
+#[derive(Debug, Default)]
+struct SeenMessages {
+    /// IDs in the order they were first seen
+    order: VecDeque<[u8; 32]>,
+    ids: HashSet<[u8; 32]>,
+}
+
+impl SeenMessages {
+    /// Record `id`, returning whether it is new.
+    fn insert(&mut self, id: [u8; 32]) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > MAX_SEEN_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
 /// The mixnet client for sending and receiving anonymous messages.
 pub struct MixClient {
     /// Client configuration.
     config: MixClientConfig,
     /// Known mix nodes by layer.
-    topology: Arc<RwLock<HashMap<u8, Vec<MixNode>>>>,
+    topology: Arc<RwLock<Topology>>,
     /// Our mailboxes.
     mailboxes: Arc<RwLock<Vec<Mailbox>>>,
     /// Channel for outgoing packets.
     outgoing_tx: mpsc::Sender<SphinxPacket>,
+    /// Receiving side of the outgoing channel, for the gateway connection.
+    #[allow(dead_code)]
+    outgoing_rx: mpsc::Receiver<SphinxPacket>,
+    /// Sender side of the incoming channel, for the provider connection.
+    #[allow(dead_code)]
+    incoming_tx: mpsc::Sender<ReceivedMessage>,
     /// Channel for incoming messages.
     incoming_rx: mpsc::Receiver<ReceivedMessage>,
+    /// Fetched messages not yet acknowledged, each tagged with a random token.
+    backlog: VecDeque<([u8; 16], ReceivedMessage)>,
+    /// Messages already handed to the backlog, to drop redeliveries.
+    seen: SeenMessages,
+    /// Cover loops we are waiting for, if cover traffic is running.
+    loop_tracker: Option<LoopTracker>,
     /// Our X25519 secret key for decryption.
     #[allow(dead_code)]
     our_secret: x25519_dalek::StaticSecret,
@@ -95,17 +295,22 @@ pub struct MixClient {
 impl MixClient {
     /// Create a new mixnet client.
     pub fn new(config: MixClientConfig) -> Self {
-        let (outgoing_tx, _outgoing_rx) = mpsc::channel(100);
-        let (_incoming_tx, incoming_rx) = mpsc::channel(100);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(OUTGOING_QUEUE_CAPACITY);
+        let (incoming_tx, incoming_rx) = mpsc::channel(100);
 
-        let our_secret = x25519_dalek::StaticSecret::random_from_rng(&mut rand::thread_rng());
+        let our_secret = x25519_dalek::StaticSecret::random_from_rng(rand::thread_rng());
 
         Self {
             config,
-            topology: Arc::new(RwLock::new(HashMap::new())),
+            topology: Arc::new(RwLock::new(Topology::default())),
             mailboxes: Arc::new(RwLock::new(Vec::new())),
             outgoing_tx,
+            outgoing_rx,
+            incoming_tx,
             incoming_rx,
+            backlog: VecDeque::new(),
+            seen: SeenMessages::default(),
+            loop_tracker: None,
             our_secret,
         }
     }
@@ -115,14 +320,51 @@ impl MixClient {
     /// The message is wrapped in a Sphinx packet and routed through
     /// randomly selected nodes in each layer.
     pub async fn send_message(&self, payload: &[u8], recipient_mailbox: &Mailbox) -> Result<()> {
-        // Select a random route
-        let route = self.select_route(recipient_mailbox).await?;
+        self.send_envelope(&Envelope::new(payload.to_vec()), recipient_mailbox)
+            .await
+    }
 
-        // Create Sphinx packet
-        let packet = SphinxPacket::create(payload, &route, recipient_mailbox.id)?;
+    /// Send the same message to several mailboxes.
+    ///
+    /// The topology is read once for the whole batch, but every recipient
+    /// gets its own route and its own onion, so the packets cannot be
+    /// linked to each other. All packets are built before any is queued.
+    /// Returns one result per recipient, in order.
+    #[tracing::instrument(name = "send_multicast", level = "debug", skip_all)]
+    pub async fn send_multicast(&self, payload: &[u8], recipients: &[Mailbox]) -> Vec<Result<()>> {
+        let routes: Vec<Result<Route>> = {
+            let topology = self.topology.read().await;
+            let mut rng = rand::thread_rng();
+            recipients
+                .iter()
+                .map(|recipient| self.route_from(&topology, recipient, &mut rng))
+                .collect()
+        };
 
-        // Send to gateway
-        self.send_to_gateway(packet).await
+        let bytes = Envelope::new(payload.to_vec()).serialize();
+        let packets: Vec<Result<SphinxPacket>> = routes
+            .into_iter()
+            .zip(recipients)
+            .map(|(route, recipient)| SphinxPacket::create(&bytes, &route?, recipient.id))
+            .collect();
+        tracing::debug!(
+            recipients = recipients.len(),
+            envelope_size = bytes.len(),
+            "Created multicast Sphinx packets"
+        );
+
+        let mut results = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let result = match packet {
+                Ok(packet) => self.send_to_gateway(packet).await,
+                Err(e) => Err(e),
+            };
+            if let Err(ref e) = result {
+                tracing::warn!(error = %e, "Failed to send multicast packet");
+            }
+            results.push(result);
+        }
+        results
     }
 
     /// Send a message with a SURB for anonymous reply.
@@ -134,30 +376,87 @@ impl MixClient {
         // Create return route SURB
         let surb = self.create_surb().await?;
 
-        // Combine payload with SURB
-        let mut combined = payload.to_vec();
-        combined.extend_from_slice(&surb.header_bytes);
+        // Embed the SURB alongside the payload
+        let envelope = Envelope::with_surb(payload.to_vec(), &surb);
 
         // Send the message
-        self.send_message(&combined, recipient_mailbox).await?;
+        self.send_envelope(&envelope, recipient_mailbox).await?;
 
         Ok(surb)
     }
 
+    /// Count returned cover loops with `tracker` and keep them out of the
+    /// messages handed to the caller.
+    pub fn set_loop_tracker(&mut self, tracker: LoopTracker) {
+        self.loop_tracker = Some(tracker);
+    }
+
     /// Poll our mailbox for incoming messages.
+    ///
+    /// Each message is returned once, however often it is delivered; see
+    /// [`MixClient::poll_mailbox_paged`] for the ordering.
+    #[tracing::instrument(name = "poll_mailbox", level = "trace", skip_all)]
     pub async fn poll_mailbox(&mut self) -> Result<Option<ReceivedMessage>> {
         // In a real implementation, this would:
         // 1. Connect to our mailbox provider
         // 2. Send an anonymous fetch request
         // 3. Decrypt and return any waiting messages
 
-        match self.incoming_rx.try_recv() {
-            Ok(msg) => Ok(Some(msg)),
-            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
-            Err(mpsc::error::TryRecvError::Disconnected) => {
-                Err(TransportError::MailboxError("Channel closed".into()))
+        self.fetch_incoming()?;
+        let msg = self.backlog.pop_front().map(|(_, msg)| msg);
+        if let Some(msg) = &msg {
+            tracing::debug!(size = msg.payload.len(), "Received mailbox message");
+        }
+        Ok(msg)
+    }
+
+    /// Fetch waiting messages a page at a time.
+    ///
+    /// Returns up to `max_items` messages following `cursor` (or from the
+    /// start when `None`), plus a cursor for the next page. Passing a cursor
+    /// back acknowledges every message up to it, so they are dropped;
+    /// messages after it are kept until acknowledged. An interrupted fetch
+    /// is resumed by retrying with the last cursor received, which returns
+    /// the same page again. The returned cursor is `None` once no messages
+    /// are left to hand out.
+    ///
+    /// A message delivered again (e.g. by a provider retrying) is dropped
+    /// if it was seen among the last [`MAX_SEEN_MESSAGES`]. Ratchet messages
+    /// that arrive together are handed out in message number order within
+    /// each sending chain, see [`order_by_message_number`].
+    #[tracing::instrument(name = "poll_mailbox_paged", level = "trace", skip_all)]
+    pub async fn poll_mailbox_paged(
+        &mut self,
+        cursor: Option<MailboxCursor>,
+        max_items: usize,
+    ) -> Result<(Vec<ReceivedMessage>, Option<MailboxCursor>)> {
+        self.fetch_incoming()?;
+
+        // Acknowledge everything up to the cursor; an unknown cursor means
+        // those messages were already acknowledged
+        if let Some(MailboxCursor(token)) = cursor {
+            if let Some(position) = self.backlog.iter().position(|(t, _)| *t == token) {
+                self.backlog.drain(..=position);
             }
         }
+
+        let page: Vec<ReceivedMessage> = self
+            .backlog
+            .iter()
+            .take(max_items)
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        let next = match page.len() {
+            0 => None,
+            len => Some(MailboxCursor(self.backlog[len - 1].0)),
+        };
+
+        tracing::debug!(
+            count = page.len(),
+            remaining = self.backlog.len() - page.len(),
+            "Fetched mailbox page"
+        );
+        Ok((page, next))
     }
 
     /// Register a new mailbox with a provider.
@@ -173,59 +472,224 @@ impl MixClient {
         Ok(mailbox)
     }
 
-    /// Update the network topology.
+    /// Register a new mailbox with a provider picked at random from layer 3.
+    ///
+    /// Providers are chosen by bandwidth weight under the same cap as route
+    /// selection, so which provider holds a mailbox reveals nothing about
+    /// the client that registered it.
+    pub async fn register_mailbox_random(&self) -> Result<Mailbox> {
+        let mut mailboxes = self.register_mailboxes_random(1).await?;
+        Ok(mailboxes.remove(0))
+    }
+
+    /// Register mailboxes with up to `count` distinct random providers.
+    ///
+    /// Spreading mailboxes over several providers keeps messages reachable
+    /// when one of them is down. If fewer than `count` providers are known,
+    /// every known provider gets a mailbox.
+    pub async fn register_mailboxes_random(&self, count: usize) -> Result<Vec<Mailbox>> {
+        let providers = {
+            let topology = self.topology.read().await;
+            let mut candidates = topology.layers.get(&3).cloned().unwrap_or_default();
+            if candidates.is_empty() {
+                return Err(TransportError::MailboxError(
+                    "No providers available".into(),
+                ));
+            }
+
+            let mut rng = rand::thread_rng();
+            let cap = self.config.max_selection_probability;
+            let mut providers = Vec::with_capacity(count.min(candidates.len()));
+            while providers.len() < count {
+                let Some(index) = choose_weighted(&candidates, cap, &mut rng)
+                    .and_then(|chosen| candidates.iter().position(|node| node.id == chosen.id))
+                else {
+                    break;
+                };
+                providers.push(candidates.swap_remove(index));
+            }
+            providers
+        };
+
+        let mut mailboxes = Vec::with_capacity(providers.len());
+        for provider in providers {
+            mailboxes.push(self.register_mailbox(provider).await?);
+        }
+        Ok(mailboxes)
+    }
+
+    /// Replace the network topology with `nodes`.
+    ///
+    /// Every previously known node is forgotten; prefer
+    /// [`merge_topology`](Self::merge_topology) for directory fetches that
+    /// may be partial.
     pub async fn update_topology(&self, nodes: Vec<MixNode>) {
         let mut topology = self.topology.write().await;
-        topology.clear();
+        *topology = Topology::default();
 
+        let now = Instant::now();
         for node in nodes {
-            topology.entry(node.layer).or_default().push(node);
+            topology.upsert(node, now);
         }
     }
 
+    /// Add or update `nodes`, keeping every other known node.
+    ///
+    /// Nodes are matched by [`NodeId`]; a node that moved layer is moved
+    /// with it. Each merged node is marked as seen now.
+    pub async fn merge_topology(&self, nodes: Vec<MixNode>) {
+        let mut topology = self.topology.write().await;
+        let now = Instant::now();
+        for node in nodes {
+            topology.upsert(node, now);
+        }
+    }
+
+    /// Forget nodes not seen in a merge or update for `max_age`.
+    ///
+    /// Returns the number of nodes removed.
+    pub async fn prune_topology(&self, max_age: Duration) -> usize {
+        self.topology.write().await.prune(Instant::now(), max_age)
+    }
+
     /// Get statistics about the client.
     pub async fn stats(&self) -> ClientStats {
         let topology = self.topology.read().await;
         let mailboxes = self.mailboxes.read().await;
 
         ClientStats {
-            known_gateways: topology.get(&1).map(|v| v.len()).unwrap_or(0),
-            known_mixes: topology.get(&2).map(|v| v.len()).unwrap_or(0),
-            known_providers: topology.get(&3).map(|v| v.len()).unwrap_or(0),
+            known_gateways: topology.layers.get(&1).map(|v| v.len()).unwrap_or(0),
+            known_mixes: topology.layers.get(&2).map(|v| v.len()).unwrap_or(0),
+            known_providers: topology.layers.get(&3).map(|v| v.len()).unwrap_or(0),
             registered_mailboxes: mailboxes.len(),
+            queued_packets: self.outgoing_tx.max_capacity() - self.outgoing_tx.capacity(),
         }
     }
 
     // === Private methods ===
 
+    /// Move newly arrived messages into the backlog, dropping cover loops
+    /// and messages already seen, and ordering the batch by message number.
+    fn fetch_incoming(&mut self) -> Result<()> {
+        let mut arrived = Vec::new();
+        loop {
+            match self.incoming_rx.try_recv() {
+                Ok(msg) if self.is_returned_loop(&msg) => continue,
+                Ok(msg) if !self.seen.insert(msg.id) => {
+                    tracing::trace!("Dropped duplicate mailbox message");
+                }
+                Ok(msg) => arrived.push(msg),
+                Err(mpsc::error::TryRecvError::Empty) => break,
+                Err(mpsc::error::TryRecvError::Disconnected) => {
+                    if self.backlog.is_empty() && arrived.is_empty() {
+                        tracing::warn!("Mailbox channel closed");
+                        return Err(TransportError::MailboxError("Channel closed".into()));
+                    }
+                    break;
+                }
+            }
+        }
+
+        for msg in order_by_message_number(arrived) {
+            let mut token = [0u8; 16];
+            rand::thread_rng().fill(&mut token);
+            self.backlog.push_back((token, msg));
+        }
+        Ok(())
+    }
+
+    /// Whether `msg` is one of our cover loops coming back.
+    fn is_returned_loop(&self, msg: &ReceivedMessage) -> bool {
+        self.loop_tracker
+            .as_ref()
+            .is_some_and(|tracker| tracker.observe(&msg.payload))
+    }
+
+    #[tracing::instrument(name = "send_message", level = "debug", skip_all)]
+    async fn send_envelope(&self, envelope: &Envelope, recipient_mailbox: &Mailbox) -> Result<()> {
+        // Select a random route
+        let route = self.select_route(recipient_mailbox).await?;
+
+        // Create Sphinx packet
+        let bytes = envelope.serialize();
+        let packet = SphinxPacket::create(&bytes, &route, recipient_mailbox.id)?;
+        tracing::debug!(
+            envelope_size = bytes.len(),
+            packet_size = PACKET_SIZE,
+            has_surb = envelope.reply_surb.is_some(),
+            "Created Sphinx packet"
+        );
+
+        // Send to gateway
+        let result = self.send_to_gateway(packet).await;
+        if let Err(ref e) = result {
+            tracing::warn!(error = %e, "Failed to hand packet to gateway");
+        }
+        result
+    }
+
+    #[tracing::instrument(name = "select_route", level = "debug", skip_all)]
     async fn select_route(&self, recipient_mailbox: &Mailbox) -> Result<Route> {
         let topology = self.topology.read().await;
+        self.route_from(&topology, recipient_mailbox, &mut rand::thread_rng())
+    }
+
+    /// Pick a route to `recipient_mailbox` from `topology`.
+    fn route_from<R: Rng + ?Sized>(
+        &self,
+        topology: &Topology,
+        recipient_mailbox: &Mailbox,
+        rng: &mut R,
+    ) -> Result<Route> {
+        let cap = self.config.max_selection_probability;
 
-        // Select one node from each layer
+        // Select one node from each layer, weighted by bandwidth
         let gateway = topology
+            .layers
             .get(&1)
-            .and_then(|nodes| nodes.first())
+            .and_then(|nodes| choose_weighted(nodes, cap, rng))
             .ok_or_else(|| TransportError::InvalidRoute("No gateways available".into()))?
             .clone();
 
         let mix = topology
+            .layers
             .get(&2)
-            .and_then(|nodes| nodes.first())
+            .and_then(|nodes| choose_weighted(nodes, cap, rng))
             .ok_or_else(|| TransportError::InvalidRoute("No mix nodes available".into()))?
             .clone();
 
         let exit = recipient_mailbox.provider.clone();
 
+        tracing::debug!(
+            gateway = %gateway.id,
+            mix = %mix.id,
+            exit = %exit.id,
+            "Selected route"
+        );
+
         Route::new(vec![gateway, mix, exit])
     }
 
     async fn send_to_gateway(&self, packet: SphinxPacket) -> Result<()> {
         // In a real implementation, this would open a connection to the gateway
-        // and send the packet bytes. For now, we just queue it.
-        self.outgoing_tx
-            .send(packet)
-            .await
-            .map_err(|_| TransportError::NetworkError("Failed to queue packet".into()))
+        // and send the packet bytes. For now, we just queue it, waiting a
+        // bounded time for room so a stalled consumer cannot wedge the caller.
+        let packet = match self.outgoing_tx.try_send(packet) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(packet)) => packet,
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                return Err(TransportError::NetworkError(
+                    "Failed to queue packet".into(),
+                ));
+            }
+        };
+        match tokio::time::timeout(self.config.queue_timeout, self.outgoing_tx.send(packet)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(TransportError::NetworkError(
+                "Failed to queue packet".into(),
+            )),
+            Err(_) => Err(TransportError::NetworkError("queue full".into())),
+        }
     }
 
     async fn create_surb(&self) -> Result<Surb> {
@@ -244,6 +708,135 @@ impl MixClient {
     }
 }
 
+/// Known mix nodes by layer, with when each was last seen.
+#[derive(Debug, Default)]
+struct Topology {
+    /// Nodes by layer.
+    layers: HashMap<u8, Vec<MixNode>>,
+    /// When each node last appeared in a topology update.
+    last_seen: HashMap<NodeId, Instant>,
+}
+
+impl Topology {
+    /// Insert `node`, replacing any node with the same ID in any layer.
+    fn upsert(&mut self, node: MixNode, now: Instant) {
+        for nodes in self.layers.values_mut() {
+            nodes.retain(|known| known.id != node.id);
+        }
+        self.last_seen.insert(node.id.clone(), now);
+        self.layers.entry(node.layer).or_default().push(node);
+    }
+
+    /// Remove nodes last seen more than `max_age` before `now`.
+    fn prune(&mut self, now: Instant, max_age: Duration) -> usize {
+        let before = self.last_seen.len();
+        self.last_seen
+            .retain(|_, seen| now.duration_since(*seen) <= max_age);
+        let last_seen = &self.last_seen;
+        for nodes in self.layers.values_mut() {
+            nodes.retain(|node| last_seen.contains_key(&node.id));
+        }
+        self.layers.retain(|_, nodes| !nodes.is_empty());
+        before - self.last_seen.len()
+    }
+}
+
+/// Pick a node with probability proportional to its bandwidth weight,
+/// with no node exceeding `cap` probability.
+fn choose_weighted<'a, R: Rng + ?Sized>(
+    nodes: &'a [MixNode],
+    cap: f64,
+    rng: &mut R,
+) -> Option<&'a MixNode> {
+    let weights: Vec<u32> = nodes.iter().map(|node| node.bandwidth_weight).collect();
+    let probabilities = capped_probabilities(&weights, cap);
+    let index = WeightedIndex::new(&probabilities).ok()?;
+    nodes.get(index.sample(rng))
+}
+
+/// Turn weights into selection probabilities, capping each at `cap` and
+/// redistributing the excess proportionally among the remaining nodes.
+///
+/// If the cap cannot be met (fewer than `1 / cap` nodes), or every weight
+/// is zero, selection falls back to uniform.
+fn capped_probabilities(weights: &[u32], cap: f64) -> Vec<f64> {
+    let count = weights.len();
+    let total: f64 = weights.iter().map(|&w| w as f64).sum();
+    if count == 0 || total == 0.0 || cap * (count as f64) < 1.0 {
+        return vec![1.0; count];
+    }
+
+    let mut probabilities: Vec<f64> = weights.iter().map(|&w| w as f64 / total).collect();
+    let mut capped = vec![false; count];
+
+    // Water-filling: each pass caps at least one more node, so this terminates
+    loop {
+        let mut excess = 0.0;
+        for (p, is_capped) in probabilities.iter_mut().zip(capped.iter_mut()) {
+            if !*is_capped && *p > cap {
+                excess += *p - cap;
+                *p = cap;
+                *is_capped = true;
+            }
+        }
+        if excess <= f64::EPSILON {
+            break;
+        }
+
+        let free: f64 = probabilities
+            .iter()
+            .zip(&capped)
+            .filter(|(_, c)| !**c)
+            .map(|(p, _)| p)
+            .sum();
+        let uncapped = capped.iter().filter(|c| !**c).count();
+        if uncapped == 0 {
+            break;
+        }
+
+        for (p, _) in probabilities.iter_mut().zip(&capped).filter(|(_, c)| !**c) {
+            *p += if free > 0.0 {
+                excess * *p / free
+            } else {
+                excess / uncapped as f64
+            };
+        }
+    }
+
+    probabilities
+}
+
+/// Sort the ratchet messages of `batch` into message number order within
+/// each sending chain, leaving every other message where it is.
+///
+/// The chain key and message number come from the ratchet header inside
+/// the end-to-end ciphertext. The header is authenticated when the message
+/// decrypts, so a relay that rewrites it to reorder messages only gets them
+/// rejected.
+fn order_by_message_number(batch: Vec<ReceivedMessage>) -> Vec<ReceivedMessage> {
+    let mut chains: HashMap<[u8; 32], Vec<(usize, u32)>> = HashMap::new();
+    for (i, msg) in batch.iter().enumerate() {
+        if let Ok(header) = comlock_crypto::peek_header(&msg.payload) {
+            chains
+                .entry(header.classical_pubkey)
+                .or_default()
+                .push((i, header.message_number));
+        }
+    }
+
+    let mut batch: Vec<Option<ReceivedMessage>> = batch.into_iter().map(Some).collect();
+    for positions in chains.into_values() {
+        let slots: Vec<usize> = positions.iter().map(|&(i, _)| i).collect();
+        let mut by_number = positions;
+        by_number.sort_by_key(|&(_, number)| number);
+        let sorted: Vec<_> = by_number.iter().map(|&(i, _)| batch[i].take()).collect();
+        for (slot, msg) in slots.into_iter().zip(sorted) {
+            batch[slot] = msg;
+        }
+    }
+    batch.into_iter().flatten().collect()
+}
+
 /// Client statistics.
 #[derive(Debug, Clone)]
 pub struct ClientStats {
@@ -255,12 +848,44 @@ pub struct ClientStats {
     pub known_providers: usize,
     /// Number of registered mailboxes.
     pub registered_mailboxes: usize,
+    /// Packets waiting in the outgoing queue.
+    pub queued_packets: usize,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_mailbox_ids_rotate_per_epoch() {
+        let seed = [0x5c; 32];
+        let epoch = mailbox_epoch(1_700_000_000);
+
+        assert_ne!(
+            mailbox_id_for_epoch(&seed, epoch),
+            mailbox_id_for_epoch(&seed, epoch + 1)
+        );
+        assert_ne!(
+            mailbox_id_for_epoch(&seed, epoch),
+            mailbox_id_for_epoch(&[0x5d; 32], epoch)
+        );
+    }
+
+    #[test]
+    fn test_sender_and_receiver_agree_on_epoch_id() {
+        let seed = [0x5c; 32];
+        let provider = MixClientConfig::default().gateway;
+
+        // The sender's clock runs half an epoch ahead of the receiver's
+        let receiver_now = 1_700_000_000;
+        let sender_now = receiver_now + MAILBOX_EPOCH_SECS / 2;
+        let sender_box = Mailbox::for_epoch(&seed, mailbox_epoch(sender_now), provider);
+
+        let receiver_ids = mailbox_ids_around_epoch(&seed, mailbox_epoch(receiver_now));
+        assert_eq!(receiver_ids.len(), 2 * MAILBOX_EPOCH_WINDOW as usize + 1);
+        assert!(receiver_ids.iter().any(|(_, id)| *id == sender_box.id));
+    }
+
     #[tokio::test]
     async fn test_client_creation() {
         let config = MixClientConfig::default();
@@ -271,6 +896,278 @@ mod tests {
         assert_eq!(stats.registered_mailboxes, 0);
     }
 
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_route_selection_is_traced() {
+        let client = MixClient::new(MixClientConfig::default());
+        let nodes = (1..=3u8)
+            .map(|i| MixNode {
+                id: NodeId::new([i; 32]),
+                public_key: [i; 32],
+                address: format!("127.0.0.1:900{}", i),
+                layer: i,
+                bandwidth_weight: 1,
+            })
+            .collect::<Vec<_>>();
+        let mailbox = Mailbox {
+            id: [7u8; 32],
+            provider: nodes[2].clone(),
+        };
+        client.update_topology(nodes).await;
+
+        client.select_route(&mailbox).await.unwrap();
+
+        assert!(logs_contain("select_route"));
+        assert!(logs_contain("Selected route"));
+        // Node IDs are logged in short form, never keys
+        assert!(logs_contain(&hex::encode([1u8; 8])));
+        assert!(!logs_contain(&hex::encode([1u8; 32])));
+    }
+
+    #[tokio::test]
+    async fn test_full_outgoing_queue_times_out() {
+        let client = MixClient::new(MixClientConfig {
+            queue_timeout: Duration::from_millis(50),
+            ..Default::default()
+        });
+        let nodes = (1..=3u8)
+            .map(|i| MixNode {
+                id: NodeId::new([i; 32]),
+                public_key: [i; 32],
+                address: format!("127.0.0.1:900{}", i),
+                layer: i,
+                bandwidth_weight: 1,
+            })
+            .collect::<Vec<_>>();
+        let mailbox = Mailbox {
+            id: [7u8; 32],
+            provider: nodes[2].clone(),
+        };
+        client.update_topology(nodes).await;
+
+        // Nothing drains the queue, so it fills up
+        for _ in 0..OUTGOING_QUEUE_CAPACITY {
+            client.send_message(b"hello", &mailbox).await.unwrap();
+        }
+        assert_eq!(client.stats().await.queued_packets, OUTGOING_QUEUE_CAPACITY);
+
+        let result = client.send_message(b"hello", &mailbox).await;
+        assert!(matches!(result, Err(TransportError::NetworkError(ref e)) if e == "queue full"));
+    }
+
+    #[tokio::test]
+    async fn test_multicast_builds_independent_packets() {
+        let mut client = MixClient::new(MixClientConfig::default());
+        let nodes = (1..=3u8)
+            .map(|i| MixNode {
+                id: NodeId::new([i; 32]),
+                public_key: [i; 32],
+                address: format!("127.0.0.1:900{}", i),
+                layer: i,
+                bandwidth_weight: 1,
+            })
+            .collect::<Vec<_>>();
+        let mut recipients = (7..=9u8)
+            .map(|i| Mailbox {
+                id: [i; 32],
+                provider: nodes[2].clone(),
+            })
+            .collect::<Vec<_>>();
+        client.update_topology(nodes).await;
+
+        let results = client.send_multicast(b"hello all", &recipients).await;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let mut ephemeral_keys = HashSet::new();
+        while let Ok(packet) = client.outgoing_rx.try_recv() {
+            ephemeral_keys.insert(packet.header.ephemeral_key);
+        }
+        assert_eq!(ephemeral_keys.len(), 3);
+
+        // One bad recipient does not hold up the others
+        recipients[1].provider.address = "not an address".into();
+        let results = client.send_multicast(b"hello again", &recipients).await;
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert_eq!(client.stats().await.queued_packets, 2);
+    }
+
+    fn queued_message(n: u8) -> ReceivedMessage {
+        ReceivedMessage {
+            id: message_id(&[n]),
+            payload: vec![n],
+            reply_surb: None,
+            received_at: Instant::now(),
+        }
+    }
+
+    /// The first `count` messages of one ratchet sending chain
+    fn ratchet_messages(count: usize) -> Vec<ReceivedMessage> {
+        let mut alice = comlock_crypto::RatchetState::new([7u8; 32], true).unwrap();
+        (0..count)
+            .map(|n| {
+                let ciphertext = comlock_crypto::encrypt_message(&[n as u8], &mut alice).unwrap();
+                ReceivedMessage::from_envelope_bytes(&Envelope::new(ciphertext).serialize())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn ids(messages: &[ReceivedMessage]) -> Vec<[u8; 32]> {
+        messages.iter().map(|msg| msg.id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_and_shuffled_batch_delivered_once_in_order() {
+        let messages = ratchet_messages(6);
+        let mut client = MixClient::new(MixClientConfig::default());
+        // Retries deliver 0 and 3 twice, and the network shuffled the batch
+        for n in [3, 0, 4, 0, 1, 3, 2] {
+            client.incoming_tx.send(messages[n].clone()).await.unwrap();
+        }
+
+        let (page, cursor) = client.poll_mailbox_paged(None, 10).await.unwrap();
+        assert_eq!(ids(&page), ids(&messages[..5]));
+
+        // A late retry of an acknowledged message is not handed out again
+        client.incoming_tx.send(messages[2].clone()).await.unwrap();
+        let (page, _) = client.poll_mailbox_paged(cursor, 10).await.unwrap();
+        assert!(page.is_empty());
+
+        client.incoming_tx.send(messages[5].clone()).await.unwrap();
+        client.incoming_tx.send(messages[5].clone()).await.unwrap();
+        assert_eq!(
+            client.poll_mailbox().await.unwrap().unwrap().id,
+            messages[5].id
+        );
+        assert!(client.poll_mailbox().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_non_ratchet_messages_keep_their_place() {
+        let messages = ratchet_messages(3);
+        let batch = vec![
+            messages[2].clone(),
+            queued_message(10),
+            messages[1].clone(),
+            queued_message(11),
+            messages[0].clone(),
+        ];
+        let ordered = order_by_message_number(batch);
+        assert_eq!(
+            ids(&ordered),
+            vec![
+                messages[0].id,
+                message_id(&[10]),
+                messages[1].id,
+                message_id(&[11]),
+                messages[2].id,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seen_set_is_bounded() {
+        let mut seen = SeenMessages::default();
+        let id = |n: usize| message_id(&n.to_le_bytes());
+
+        assert!(seen.insert(id(0)));
+        assert!(!seen.insert(id(0)));
+        for n in 1..=MAX_SEEN_MESSAGES {
+            assert!(seen.insert(id(n)));
+        }
+        assert_eq!(seen.ids.len(), MAX_SEEN_MESSAGES);
+        assert_eq!(seen.order.len(), MAX_SEEN_MESSAGES);
+
+        // The oldest ID was forgotten, the newest are still known
+        assert!(seen.insert(id(0)));
+        assert!(!seen.insert(id(MAX_SEEN_MESSAGES)));
+    }
+
+    #[tokio::test]
+    async fn test_paged_poll_yields_backlog_once() {
+        let mut client = MixClient::new(MixClientConfig::default());
+        for n in 0..10 {
+            client.incoming_tx.send(queued_message(n)).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let (page, next) = client.poll_mailbox_paged(cursor, 3).await.unwrap();
+            if page.is_empty() {
+                assert!(next.is_none());
+                break;
+            }
+            assert!(page.len() <= 3);
+            seen.extend(page.into_iter().map(|msg| msg.payload[0]));
+            cursor = next;
+            pages += 1;
+        }
+
+        assert_eq!(pages, 4);
+        assert_eq!(seen, (0..10).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_paged_poll_resumes_after_interruption() {
+        let mut client = MixClient::new(MixClientConfig::default());
+        for n in 0..5 {
+            client.incoming_tx.send(queued_message(n)).await.unwrap();
+        }
+
+        let (first, cursor) = client.poll_mailbox_paged(None, 2).await.unwrap();
+        assert_eq!(first.len(), 2);
+
+        // The next page was lost in transit; retrying with the same cursor
+        // returns it again
+        let (lost, _) = client.poll_mailbox_paged(cursor, 2).await.unwrap();
+        let (retried, _) = client.poll_mailbox_paged(cursor, 2).await.unwrap();
+        let payloads = |page: &[ReceivedMessage]| -> Vec<u8> {
+            page.iter().map(|msg| msg.payload[0]).collect()
+        };
+        assert_eq!(payloads(&lost), vec![2, 3]);
+        assert_eq!(payloads(&retried), vec![2, 3]);
+
+        // The cursor round-trips through its opaque byte form
+        let restored = cursor.map(|c| MailboxCursor::from_bytes(c.to_bytes()));
+        assert_eq!(restored, cursor);
+    }
+
+    #[tokio::test]
+    async fn test_returned_cover_loop_is_counted_and_filtered() {
+        let mut client = MixClient::new(MixClientConfig::default());
+        let tracker = LoopTracker::new(Duration::from_secs(60));
+        client.set_loop_tracker(tracker.clone());
+
+        // Simulated echo: the network hands the loop packet back to us
+        let sent = tracker.start_loop(&mut rand::thread_rng());
+        let echo = |bytes: &[u8]| ReceivedMessage::from_envelope_bytes(bytes).unwrap();
+
+        // Another client's loop is opaque to us: it passes through like any
+        // ciphertext and is not counted
+        let foreign = LoopTracker::new(Duration::from_secs(60)).start_loop(&mut rand::thread_rng());
+        client.incoming_tx.send(echo(&foreign)).await.unwrap();
+        assert!(client.poll_mailbox().await.unwrap().is_some());
+        assert_eq!(tracker.completed(), 0);
+
+        client.incoming_tx.send(echo(&sent)).await.unwrap();
+        client.incoming_tx.send(queued_message(7)).await.unwrap();
+        let message = client.poll_mailbox().await.unwrap().unwrap();
+        assert_eq!(message.payload[0], 7);
+        assert_eq!(tracker.completed(), 1);
+        assert_eq!(tracker.in_flight(), 0);
+
+        // A replayed loop does not count twice
+        client.incoming_tx.send(echo(&sent)).await.unwrap();
+        let (page, _) = client.poll_mailbox_paged(None, 10).await.unwrap();
+        assert!(page.is_empty());
+        assert_eq!(tracker.completed(), 1);
+    }
+
     #[tokio::test]
     async fn test_topology_update() {
         let config = MixClientConfig::default();
@@ -282,12 +1179,14 @@ mod tests {
                 public_key: [1u8; 32],
                 address: "127.0.0.1:9001".into(),
                 layer: 1,
+                bandwidth_weight: 1,
             },
             MixNode {
                 id: NodeId::new([2u8; 32]),
                 public_key: [2u8; 32],
                 address: "127.0.0.1:9002".into(),
                 layer: 2,
+                bandwidth_weight: 1,
             },
         ];
 
@@ -298,6 +1197,110 @@ mod tests {
         assert_eq!(stats.known_mixes, 1);
     }
 
+    #[tokio::test]
+    async fn test_merge_topology_keeps_other_nodes() {
+        let client = MixClient::new(MixClientConfig::default());
+        let node = |id: u8, layer: u8| MixNode {
+            id: NodeId::new([id; 32]),
+            public_key: [id; 32],
+            address: format!("127.0.0.1:90{id:02}"),
+            layer,
+            bandwidth_weight: 1,
+        };
+        client
+            .update_topology(vec![node(1, 1), node(2, 2), node(3, 3)])
+            .await;
+
+        // A partial fetch with a single updated mix node
+        let mut updated = node(2, 2);
+        updated.bandwidth_weight = 5;
+        client.merge_topology(vec![updated, node(4, 2)]).await;
+
+        let stats = client.stats().await;
+        assert_eq!(stats.known_gateways, 1);
+        assert_eq!(stats.known_mixes, 2);
+        assert_eq!(stats.known_providers, 1);
+        let topology = client.topology.read().await;
+        let mixes = &topology.layers[&2];
+        assert_eq!(
+            mixes
+                .iter()
+                .filter(|n| n.id == NodeId::new([2; 32]))
+                .count(),
+            1
+        );
+        assert!(mixes.iter().any(|n| n.bandwidth_weight == 5));
+        drop(topology);
+
+        // Nodes missing from recent merges age out
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        client.merge_topology(vec![node(1, 1)]).await;
+        assert_eq!(client.prune_topology(Duration::from_millis(20)).await, 3);
+        let stats = client.stats().await;
+        assert_eq!(stats.known_gateways, 1);
+        assert_eq!(stats.known_mixes, 0);
+        assert_eq!(stats.known_providers, 0);
+    }
+
+    #[test]
+    fn test_received_message_exposes_surb_once() {
+        let surb = Surb {
+            header_bytes: vec![1u8; 64],
+            first_hop: "127.0.0.1:9001".into(),
+            reply_key: [2u8; 32],
+        };
+        let bytes = Envelope::with_surb(b"hello".to_vec(), &surb).serialize();
+
+        let mut message = ReceivedMessage::from_envelope_bytes(&bytes).unwrap();
+        assert_eq!(message.payload, b"hello");
+
+        let reply = message.take_reply_surb().unwrap();
+        assert_eq!(reply.first_hop, surb.first_hop);
+        assert!(message.take_reply_surb().is_none());
+    }
+
+    fn weighted_layer(weights: &[u32]) -> Vec<MixNode> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| MixNode {
+                id: NodeId::new([i as u8; 32]),
+                public_key: [i as u8; 32],
+                address: format!("127.0.0.1:900{}", i),
+                layer: 2,
+                bandwidth_weight: weight,
+            })
+            .collect()
+    }
+
+    fn selection_share(nodes: &[MixNode], cap: f64, target: usize) -> f64 {
+        let mut rng = rand::thread_rng();
+        let samples = 20_000;
+        let hits = (0..samples)
+            .filter(|_| choose_weighted(nodes, cap, &mut rng).unwrap().id == nodes[target].id)
+            .count();
+        hits as f64 / samples as f64
+    }
+
+    #[test]
+    fn test_weighted_selection_follows_bandwidth() {
+        let nodes = weighted_layer(&[1, 1, 8]);
+        let share = selection_share(&nodes, 0.9, 2);
+        assert!((0.77..0.83).contains(&share), "heavy node share {}", share);
+    }
+
+    #[test]
+    fn test_weighted_selection_is_capped() {
+        let probabilities = capped_probabilities(&[1, 1, 100], 0.5);
+        assert!((probabilities[2] - 0.5).abs() < 1e-9);
+        assert!((probabilities[0] - 0.25).abs() < 1e-9);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let nodes = weighted_layer(&[1, 1, 100]);
+        let share = selection_share(&nodes, 0.5, 2);
+        assert!((0.47..0.53).contains(&share), "capped node share {}", share);
+    }
+
     #[tokio::test]
     async fn test_mailbox_registration() {
         let config = MixClientConfig::default();
@@ -308,6 +1311,7 @@ mod tests {
             public_key: [3u8; 32],
             address: "127.0.0.1:9003".into(),
             layer: 3,
+            bandwidth_weight: 1,
         };
 
         let mailbox = client.register_mailbox(provider).await.unwrap();
@@ -316,4 +1320,51 @@ mod tests {
         let stats = client.stats().await;
         assert_eq!(stats.registered_mailboxes, 1);
     }
+
+    fn provider_layer(count: u8) -> Vec<MixNode> {
+        (0..count)
+            .map(|i| MixNode {
+                id: NodeId::new([30 + i; 32]),
+                public_key: [30 + i; 32],
+                address: format!("127.0.0.1:930{}", i),
+                layer: 3,
+                bandwidth_weight: 1,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_random_mailbox_spreads_across_providers() {
+        let client = MixClient::new(MixClientConfig::default());
+        let providers = provider_layer(4);
+        client.update_topology(providers.clone()).await;
+
+        let mut used = std::collections::HashSet::new();
+        for _ in 0..200 {
+            let mailbox = client.register_mailbox_random().await.unwrap();
+            used.insert(mailbox.provider.id);
+        }
+        assert_eq!(used.len(), providers.len());
+
+        let redundant = client.register_mailboxes_random(3).await.unwrap();
+        let distinct: std::collections::HashSet<_> =
+            redundant.iter().map(|m| m.provider.id.clone()).collect();
+        assert_eq!(distinct.len(), 3);
+
+        let all = client.register_mailboxes_random(10).await.unwrap();
+        assert_eq!(all.len(), providers.len());
+    }
+
+    #[tokio::test]
+    async fn test_random_mailbox_requires_providers() {
+        let client = MixClient::new(MixClientConfig::default());
+        client.update_topology(weighted_layer(&[1, 1])).await;
+
+        assert!(matches!(
+            client.register_mailbox_random().await,
+            Err(TransportError::MailboxError(_))
+        ));
+        assert!(client.register_mailboxes_random(2).await.is_err());
+        assert_eq!(client.stats().await.registered_mailboxes, 0);
+    }
 }